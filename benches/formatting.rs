@@ -0,0 +1,79 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use norg_fmt::{context::FormatCtx, converter::format_nodes, Config};
+use rust_norg::{parse, NorgASTFlat};
+
+/// A paragraph-heavy document: long lines of prose that all need to be reflowed.
+fn prose(paragraphs: usize) -> String {
+    let sentence = "The quick brown fox jumps over the lazy dog while *bold* and /italic/ \
+                    markup is sprinkled across the line. ";
+
+    (0..paragraphs)
+        .map(|_| sentence.repeat(6).trim_end().to_string() + "\n\n")
+        .collect()
+}
+
+/// A document made up of deeply nested lists and quotes, exercising indentation.
+fn nesting(items: usize) -> String {
+    (0..items)
+        .map(|item| {
+            let level = item % 6 + 1;
+            let marker = if item % 3 == 0 { ">" } else { "-" };
+
+            format!(
+                "{} item number {item} at level {level}\n",
+                marker.repeat(level)
+            )
+        })
+        .collect()
+}
+
+/// A document mixing every construct the formatter knows about.
+fn document(sections: usize) -> String {
+    (0..sections)
+        .map(|section| {
+            format!(
+                "* Section {section}\n{}- ( ) a task\n- (x) a finished task\n~ first\n~ second\n\
+                 $ term\ndefinition of the term\n@code lua\nprint(\"hello\")\n@end\n\n",
+                prose(2)
+            )
+        })
+        .collect()
+}
+
+fn parsed(source: &str) -> Vec<NorgASTFlat> {
+    parse(source).expect("benchmark corpus should parse")
+}
+
+fn bench_format(c: &mut Criterion, name: &str, source: &str) {
+    let config = Config::default();
+    let ast = parsed(source);
+
+    c.bench_function(name, |b| {
+        b.iter_batched(
+            || ast.clone(),
+            |ast| format_nodes(ast, &FormatCtx::new(&config)).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+fn reflow(c: &mut Criterion) {
+    bench_format(c, "paragraph reflow", &prose(200));
+}
+
+fn indentation(c: &mut Criterion) {
+    bench_format(c, "nested indentation", &nesting(1000));
+}
+
+fn full_document(c: &mut Criterion) {
+    let source = document(50);
+
+    bench_format(c, "full document", &source);
+
+    c.bench_function("parse and format", |b| {
+        b.iter(|| format_nodes(parsed(&source), &FormatCtx::new(&Config::default())).unwrap())
+    });
+}
+
+criterion_group!(benches, reflow, indentation, full_document);
+criterion_main!(benches);
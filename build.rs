@@ -0,0 +1,31 @@
+//! Records the version of the grammar (`rust-norg`) the formatter is built against, so that it can
+//! be checked against the range the formatter supports at runtime.
+
+use std::path::Path;
+
+fn main() {
+    let manifest = Path::new(&std::env::var("CARGO_MANIFEST_DIR").unwrap()).join("Cargo.toml");
+
+    println!("cargo:rerun-if-changed={}", manifest.display());
+
+    // The grammar is pinned to a revision in the manifest, which is always there, unlike the
+    // lockfile, which isn't published and belongs to the crate depending on the formatter when it
+    // is built as a dependency.
+    let manifest = std::fs::read_to_string(manifest).unwrap();
+    let dependency = manifest
+        .lines()
+        .find(|line| line.starts_with("rust-norg = "))
+        .unwrap_or_default();
+    let field = |name: &str| {
+        dependency
+            .split_once(&format!("{name} = \""))
+            .and_then(|(_, rest)| rest.split_once('"'))
+            .map_or("unknown", |(value, _)| value)
+    };
+
+    println!(
+        "cargo:rustc-env=NORG_FMT_GRAMMAR_VERSION={}",
+        field("version")
+    );
+    println!("cargo:rustc-env=NORG_FMT_GRAMMAR_REVISION={}", field("rev"));
+}
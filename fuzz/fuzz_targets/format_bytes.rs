@@ -0,0 +1,13 @@
+//! Formats arbitrary input, which must never panic: input that isn't valid UTF-8 or can't be parsed
+//! is skipped, but anything that parses has to be formatted without crashing.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use norg_fmt::formatter::Formatter;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = Formatter::default().format(source);
+    }
+});
@@ -0,0 +1,28 @@
+//! Formats valid documents generated from the input and checks that formatting preserves their AST
+//! and that formatting the output again doesn't change it.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use norg_fmt::{
+    testing::{
+        assert_format_is_idempotent_with, assert_format_preserves_ast_with, document_from_seed,
+    },
+    Config,
+};
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the line length, so that wrapping is exercised as well.
+    let Some((&line_length, seed)) = data.split_first() else {
+        return;
+    };
+
+    let config = Config {
+        line_length: 10 + usize::from(line_length) % 110,
+        ..Config::default()
+    };
+    let document = document_from_seed(seed);
+
+    assert_format_preserves_ast_with(&document, &config);
+    assert_format_is_idempotent_with(&document, &config);
+});
@@ -0,0 +1,451 @@
+//! A stable JSON serialization of the AST of a formatted document, for exporters, static site
+//! generators and search indexers that want structured Norg without parsing it themselves.
+//!
+//! Unlike [`dump`](crate::dump), which mirrors the parser for debugging, the schema is versioned
+//! with [`SCHEMA_VERSION`], which is increased whenever it changes incompatibly. A document is
+//! serialized as `{ "schema": 1, "blocks": [...] }`:
+//!
+//! - Every block and inline node is an object with a `type`, e.g.
+//!   `{ "type": "heading", "level": 1, "title": [...], "extensions": [], "span": {...} }`.
+//! - Blocks are `paragraph`, `heading`, `nestable` (lists and quotes), `rangeable` (definitions,
+//!   footnotes and tables), `ranged_tag`, `verbatim_ranged_tag`, `infirm_tag` and `carryover_tag`.
+//! - Inline nodes are `text`, `whitespace`, `special`, `escape`, `markup`, `verbatim`, `link`,
+//!   `anchor`, `anchor_definition`, `inline_link_target` and `unsupported`.
+//! - Top-level blocks carry the `span` of lines they occupy in the formatted document, zero-based
+//!   and end exclusive, e.g. `{ "start": 0, "end": 3 }`. The parser doesn't report spans, so they
+//!   are recovered from the formatted text and left out if that fails.
+
+use rust_norg::{
+    DetachedModifierExtension, LinkTarget, NorgASTFlat, ParagraphSegment, ParagraphSegmentToken,
+    TodoStatus,
+};
+use serde::Serialize;
+
+use crate::source_map::block_ranges;
+
+/// The version of the schema, increased whenever it changes incompatibly.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct Document {
+    pub schema: u32,
+    pub blocks: Vec<Block>,
+}
+
+/// The lines a top-level block occupies, zero-based and end exclusive.
+#[derive(Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Serialize)]
+pub struct Block {
+    #[serde(flatten)]
+    pub node: Node,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub span: Option<Span>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Node {
+    Paragraph {
+        content: Vec<Inline>,
+    },
+    Heading {
+        level: usize,
+        title: Vec<Inline>,
+        extensions: Vec<Extension>,
+    },
+    /// A list item or quote, e.g. `kind: "unordered_list"`.
+    Nestable {
+        kind: &'static str,
+        level: usize,
+        extensions: Vec<Extension>,
+        content: Box<Node>,
+    },
+    /// A definition, footnote or table cell, e.g. `kind: "definition"`.
+    Rangeable {
+        kind: &'static str,
+        title: Vec<Inline>,
+        extensions: Vec<Extension>,
+        content: Vec<Node>,
+    },
+    RangedTag {
+        name: Vec<String>,
+        parameters: Vec<String>,
+        content: Vec<Node>,
+    },
+    VerbatimRangedTag {
+        name: Vec<String>,
+        parameters: Vec<String>,
+        content: String,
+    },
+    InfirmTag {
+        name: Vec<String>,
+        parameters: Vec<String>,
+    },
+    /// An attribute (`+name`) or macro (`#name`) applying to the next node.
+    CarryoverTag {
+        kind: &'static str,
+        name: Vec<String>,
+        parameters: Vec<String>,
+        next: Box<Node>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Inline {
+    Text {
+        text: String,
+    },
+    Whitespace,
+    Special {
+        character: char,
+    },
+    Escape {
+        character: char,
+    },
+    /// Attached modifiers, e.g. `modifier: '*'` for bold text.
+    Markup {
+        modifier: char,
+        content: Vec<Inline>,
+    },
+    /// Inline verbatim text, e.g. `` `code` ``, without its delimiters.
+    Verbatim {
+        text: String,
+    },
+    Link {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        file: Option<String>,
+        targets: Vec<Target>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        description: Option<Vec<Inline>>,
+    },
+    Anchor {
+        content: Vec<Inline>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        description: Option<Vec<Inline>>,
+    },
+    AnchorDefinition {
+        content: Vec<Inline>,
+        target: Box<Inline>,
+    },
+    InlineLinkTarget {
+        content: Vec<Inline>,
+    },
+    /// A construct the schema doesn't describe yet, with the parser's representation of it.
+    Unsupported {
+        debug: String,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Target {
+    Heading { level: usize, title: Vec<Inline> },
+    Footnote { title: Vec<Inline> },
+    Definition { title: Vec<Inline> },
+    Generic { title: Vec<Inline> },
+    Wiki { title: Vec<Inline> },
+    Extendable { title: Vec<Inline> },
+    Path { path: String },
+    Url { url: String },
+    Timestamp { timestamp: String },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Extension {
+    /// A task status, e.g. `status: "done"`, with the recurrence of `recurring` tasks.
+    Todo {
+        status: &'static str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        recurrence: Option<String>,
+    },
+    Priority {
+        priority: String,
+    },
+    DueDate {
+        date: String,
+    },
+    StartDate {
+        date: String,
+    },
+    Timestamp {
+        timestamp: String,
+    },
+}
+
+fn inlines(segments: &[ParagraphSegment]) -> Vec<Inline> {
+    segments.iter().map(inline).collect()
+}
+
+fn inline(segment: &ParagraphSegment) -> Inline {
+    match segment {
+        ParagraphSegment::Token(ParagraphSegmentToken::Text(text)) => {
+            Inline::Text { text: text.clone() }
+        }
+        ParagraphSegment::Token(ParagraphSegmentToken::Whitespace) => Inline::Whitespace,
+        ParagraphSegment::Token(ParagraphSegmentToken::Special(character)) => Inline::Special {
+            character: *character,
+        },
+        ParagraphSegment::Token(ParagraphSegmentToken::Escape(character)) => Inline::Escape {
+            character: *character,
+        },
+        ParagraphSegment::AttachedModifier {
+            modifier_type,
+            content,
+        } => Inline::Markup {
+            modifier: *modifier_type,
+            content: inlines(content),
+        },
+        ParagraphSegment::InlineVerbatim(content) => Inline::Verbatim {
+            text: content.iter().map(ToString::to_string).collect(),
+        },
+        ParagraphSegment::Link {
+            filepath,
+            targets,
+            description,
+        } => Inline::Link {
+            file: filepath.clone(),
+            targets: targets.iter().map(target).collect(),
+            description: description.as_deref().map(inlines),
+        },
+        ParagraphSegment::Anchor {
+            content,
+            description,
+        } => Inline::Anchor {
+            content: inlines(content),
+            description: description.as_deref().map(inlines),
+        },
+        ParagraphSegment::AnchorDefinition { content, target } => Inline::AnchorDefinition {
+            content: inlines(content),
+            target: Box::new(inline(target)),
+        },
+        ParagraphSegment::InlineLinkTarget(content) => Inline::InlineLinkTarget {
+            content: inlines(content),
+        },
+        segment => Inline::Unsupported {
+            debug: format!("{segment:?}"),
+        },
+    }
+}
+
+fn target(target: &LinkTarget) -> Target {
+    match target {
+        LinkTarget::Heading { level, title } => Target::Heading {
+            level: usize::from(*level),
+            title: inlines(title),
+        },
+        LinkTarget::Footnote(title) => Target::Footnote {
+            title: inlines(title),
+        },
+        LinkTarget::Definition(title) => Target::Definition {
+            title: inlines(title),
+        },
+        LinkTarget::Generic(title) => Target::Generic {
+            title: inlines(title),
+        },
+        LinkTarget::Wiki(title) => Target::Wiki {
+            title: inlines(title),
+        },
+        LinkTarget::Extendable(title) => Target::Extendable {
+            title: inlines(title),
+        },
+        LinkTarget::Path(path) => Target::Path { path: path.clone() },
+        LinkTarget::Url(url) => Target::Url { url: url.clone() },
+        LinkTarget::Timestamp(timestamp) => Target::Timestamp {
+            timestamp: timestamp.clone(),
+        },
+    }
+}
+
+fn extension(extension: &DetachedModifierExtension) -> Extension {
+    match extension {
+        DetachedModifierExtension::Todo(status) => {
+            let (status, recurrence) = match status {
+                TodoStatus::Undone => ("undone", None),
+                TodoStatus::Done => ("done", None),
+                TodoStatus::NeedsClarification => ("needs_clarification", None),
+                TodoStatus::Paused => ("paused", None),
+                TodoStatus::Urgent => ("urgent", None),
+                TodoStatus::Recurring(recurrence) => ("recurring", recurrence.clone()),
+                TodoStatus::Pending => ("pending", None),
+                TodoStatus::Canceled => ("canceled", None),
+            };
+
+            Extension::Todo { status, recurrence }
+        }
+        DetachedModifierExtension::Priority(priority) => Extension::Priority {
+            priority: priority.trim().to_string(),
+        },
+        DetachedModifierExtension::DueDate(date) => Extension::DueDate {
+            date: date.trim().to_string(),
+        },
+        DetachedModifierExtension::StartDate(date) => Extension::StartDate {
+            date: date.trim().to_string(),
+        },
+        DetachedModifierExtension::Timestamp(timestamp) => Extension::Timestamp {
+            timestamp: timestamp.trim().to_string(),
+        },
+    }
+}
+
+fn extensions(extensions: &[DetachedModifierExtension]) -> Vec<Extension> {
+    extensions.iter().map(extension).collect()
+}
+
+pub fn node(node: &NorgASTFlat) -> Node {
+    match node {
+        NorgASTFlat::Paragraph(content) => Node::Paragraph {
+            content: inlines(content),
+        },
+        NorgASTFlat::Heading {
+            level,
+            title,
+            extensions: heading_extensions,
+        } => Node::Heading {
+            level: usize::from(*level),
+            title: inlines(title),
+            extensions: extensions(heading_extensions),
+        },
+        NorgASTFlat::NestableDetachedModifier {
+            modifier_type,
+            level,
+            extensions: modifier_extensions,
+            content,
+        } => Node::Nestable {
+            kind: match modifier_type.to_string().as_str() {
+                ">" => "quote",
+                "~" => "ordered_list",
+                _ => "unordered_list",
+            },
+            level: usize::from(*level),
+            extensions: extensions(modifier_extensions),
+            content: Box::new(self::node(content)),
+        },
+        NorgASTFlat::RangeableDetachedModifier {
+            modifier_type,
+            title,
+            extensions: modifier_extensions,
+            content,
+        } => Node::Rangeable {
+            kind: match modifier_type.to_string().as_str() {
+                "^" => "footnote",
+                ":" => "table",
+                _ => "definition",
+            },
+            title: inlines(title),
+            extensions: extensions(modifier_extensions),
+            content: content.iter().map(self::node).collect(),
+        },
+        NorgASTFlat::RangedTag {
+            name,
+            parameters,
+            content,
+        } => Node::RangedTag {
+            name: name.clone(),
+            parameters: parameters.clone(),
+            content: content.iter().map(self::node).collect(),
+        },
+        NorgASTFlat::VerbatimRangedTag {
+            name,
+            parameters,
+            content,
+        } => Node::VerbatimRangedTag {
+            name: name.clone(),
+            parameters: parameters.clone(),
+            content: content.clone(),
+        },
+        NorgASTFlat::InfirmTag { name, parameters } => Node::InfirmTag {
+            name: name.clone(),
+            parameters: parameters.clone(),
+        },
+        NorgASTFlat::CarryoverTag {
+            tag_type,
+            name,
+            parameters,
+            next_object,
+        } => Node::CarryoverTag {
+            kind: match tag_type {
+                rust_norg::CarryoverTag::Attribute => "attribute",
+                rust_norg::CarryoverTag::Macro => "macro",
+            },
+            name: name.clone(),
+            parameters: parameters.clone(),
+            next: Box::new(self::node(next_object)),
+        },
+    }
+}
+
+/// Describes the AST of a formatted document, with `formatted` being the text it was parsed from,
+/// which the spans of its blocks are recovered from.
+pub fn document(ast: &[NorgASTFlat], formatted: &str) -> Document {
+    let mut spans = block_ranges(formatted, ast.len())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|range| Span {
+            start: range.start,
+            end: range.end,
+        });
+
+    Document {
+        schema: SCHEMA_VERSION,
+        blocks: ast
+            .iter()
+            .map(|block| Block {
+                node: node(block),
+                span: spans.next(),
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_norg::parse;
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn describes_blocks_with_their_spans() {
+        let formatted = "* ( ) Heading\n- {https://neorg.org}[Neorg]\n";
+        let ast = parse(formatted).unwrap();
+        let document = serde_json::to_value(document(&ast, formatted)).unwrap();
+
+        assert_eq!(
+            document,
+            json!({
+                "schema": SCHEMA_VERSION,
+                "blocks": [
+                    {
+                        "type": "heading",
+                        "level": 1,
+                        "title": [{ "type": "text", "text": "Heading" }],
+                        "extensions": [{ "type": "todo", "status": "undone" }],
+                        "span": { "start": 0, "end": 1 },
+                    },
+                    {
+                        "type": "nestable",
+                        "kind": "unordered_list",
+                        "level": 1,
+                        "extensions": [],
+                        "content": {
+                            "type": "paragraph",
+                            "content": [{
+                                "type": "link",
+                                "targets": [{ "type": "url", "url": "https://neorg.org" }],
+                                "description": [{ "type": "text", "text": "Neorg" }],
+                            }],
+                        },
+                        "span": { "start": 1, "end": 2 },
+                    },
+                ],
+            })
+        );
+    }
+}
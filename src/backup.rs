@@ -0,0 +1,35 @@
+use std::{
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+};
+
+use eyre::{Result, WrapErr};
+
+/// Saves a copy of the file's original content before it is overwritten.
+///
+/// Without a backup directory the copy is placed next to the file as `<file>.orig`. With one, the
+/// file's path is mirrored inside of it so that equally named files in different directories
+/// don't overwrite each other's backups.
+pub fn backup(file: &Path, content: &[u8], directory: Option<&Path>) -> Result<PathBuf> {
+    let mut name = OsString::from(file.as_os_str());
+    name.push(".orig");
+
+    let destination = match directory {
+        None => PathBuf::from(name),
+        Some(directory) => directory.join(
+            Path::new(&name)
+                .components()
+                .filter(|component| matches!(component, Component::Normal(_)))
+                .collect::<PathBuf>(),
+        ),
+    };
+
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    std::fs::write(&destination, content)
+        .wrap_err_with(|| format!("unable to write backup {}", destination.display()))?;
+
+    Ok(destination)
+}
@@ -0,0 +1,219 @@
+use std::{
+    collections::BTreeSet,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
+
+use eyre::Result;
+use norg_fmt::{stable_hash::StableHasher, Config};
+
+/// The first line of the cache file. Caches written by other versions are discarded, as their
+/// output may differ.
+const HEADER: &str = concat!("norg-fmt ", env!("CARGO_PKG_VERSION"));
+
+/// The amount of entries above which only the entries used by the current run are kept.
+const MAX_ENTRIES: usize = 100_000;
+
+/// Remembers which file contents are already formatted under a given configuration, so that
+/// repeated runs over unchanged files can skip formatting them.
+///
+/// The cache lives in `$XDG_CACHE_HOME/norg-fmt` (`~/.cache/norg-fmt` by default) and only stores
+/// hashes, never file contents or paths.
+pub struct Cache {
+    path: Option<PathBuf>,
+    settings: u64,
+    entries: BTreeSet<u64>,
+    used: BTreeSet<u64>,
+    modified: bool,
+}
+
+/// Where the cache is kept, if there is a cache directory at all.
+pub fn cache_path() -> Option<PathBuf> {
+    let directory = std::env::var_os("XDG_CACHE_HOME")
+        .filter(|directory| !directory.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+
+    Some(directory.join("norg-fmt").join("formatted"))
+}
+
+impl Cache {
+    /// Opens the cache for the given settings, which must cover everything that influences the
+    /// formatted output. Starts out empty if there is no usable cache yet.
+    pub fn open(settings: &impl Hash) -> Self {
+        Self::open_at(cache_path(), settings)
+    }
+
+    fn open_at(path: Option<PathBuf>, settings: &impl Hash) -> Self {
+        let entries = path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .filter(|content| content.lines().next() == Some(HEADER))
+            .map(|content| {
+                content
+                    .lines()
+                    .skip(1)
+                    .filter_map(|line| u64::from_str_radix(line, 16).ok())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut hasher = StableHasher::default();
+        settings.hash(&mut hasher);
+
+        Self {
+            path,
+            settings: hasher.finish(),
+            entries,
+            used: BTreeSet::new(),
+            modified: false,
+        }
+    }
+
+    /// A cache that never remembers anything.
+    pub fn disabled() -> Self {
+        Self {
+            path: None,
+            settings: 0,
+            entries: BTreeSet::new(),
+            used: BTreeSet::new(),
+            modified: false,
+        }
+    }
+
+    fn key(&self, content: &[u8], config: &Config) -> u64 {
+        let mut hasher = StableHasher::default();
+        self.settings.hash(&mut hasher);
+        config.hash(&mut hasher);
+        content.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Whether the content is known to be formatted already with the configuration of its file,
+    /// which may differ from file to file.
+    pub fn is_formatted(&mut self, content: &[u8], config: &Config) -> bool {
+        if self.path.is_none() {
+            return false;
+        }
+
+        let key = self.key(content, config);
+        let is_formatted = self.entries.contains(&key);
+
+        if is_formatted {
+            self.used.insert(key);
+        }
+
+        is_formatted
+    }
+
+    /// Records that the content is formatted.
+    pub fn insert(&mut self, content: &[u8], config: &Config) {
+        if self.path.is_none() {
+            return;
+        }
+
+        let key = self.key(content, config);
+
+        self.used.insert(key);
+        self.modified |= self.entries.insert(key);
+    }
+
+    /// Writes the cache back to disk if anything was added to it.
+    pub fn save(mut self) -> Result<()> {
+        let Some(path) = self.path.take().filter(|_| self.modified) else {
+            return Ok(());
+        };
+
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries = std::mem::take(&mut self.used);
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let content = std::iter::once(HEADER.to_string())
+            .chain(self.entries.iter().map(|key| format!("{key:016x}")))
+            .map(|line| line + "\n")
+            .collect::<String>();
+
+        // Writing to a temporary file first keeps concurrent runs from reading a partial cache.
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+
+        std::fs::write(&temporary, content)?;
+        std::fs::rename(&temporary, &path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("norg-fmt-cache-{name}-{}", std::process::id()))
+    }
+
+    #[test]
+    fn remembers_formatted_contents_across_runs() -> Result<()> {
+        let path = path("runs");
+        let config = Config::default();
+        let wide = Config {
+            line_length: 100,
+            ..Config::default()
+        };
+        let mut cache = Cache::open_at(Some(path.clone()), &"settings");
+
+        assert!(!cache.is_formatted(b"* Heading\n", &config));
+
+        cache.insert(b"* Heading\n", &config);
+
+        assert!(cache.is_formatted(b"* Heading\n", &config));
+        assert!(!cache.is_formatted(b"* Heading\n", &wide));
+        assert!(!cache.is_formatted(b"* Other\n", &config));
+
+        cache.save()?;
+
+        let reopened =
+            Cache::open_at(Some(path.clone()), &"settings").is_formatted(b"* Heading\n", &config);
+        let changed =
+            Cache::open_at(Some(path.clone()), &"other").is_formatted(b"* Heading\n", &config);
+        std::fs::remove_file(&path)?;
+
+        assert!(reopened);
+        assert!(!changed);
+
+        Ok(())
+    }
+
+    #[test]
+    fn discards_caches_of_other_versions() -> Result<()> {
+        let path = path("versions");
+        let config = Config::default();
+        let mut cache = Cache::open_at(Some(path.clone()), &());
+
+        cache.insert(b"text\n", &config);
+        cache.save()?;
+
+        let content = std::fs::read_to_string(&path)?;
+        std::fs::write(&path, content.replace(HEADER, "norg-fmt 0.0.0"))?;
+
+        let outdated = Cache::open_at(Some(path.clone()), &()).is_formatted(b"text\n", &config);
+        std::fs::remove_file(&path)?;
+
+        assert!(!outdated);
+
+        Ok(())
+    }
+
+    #[test]
+    fn never_remembers_anything_when_disabled() {
+        let config = Config::default();
+        let mut cache = Cache::disabled();
+
+        cache.insert(b"text\n", &config);
+
+        assert!(!cache.is_formatted(b"text\n", &config));
+    }
+}
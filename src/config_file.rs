@@ -0,0 +1,680 @@
+//! Reading formatting options from a configuration file and the environment.
+
+use std::{
+    borrow::Cow,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use ariadne::{Config as ReportConfig, Label, Report, ReportKind, Source};
+use eyre::{bail, eyre, Result, WrapErr};
+use itertools::Itertools;
+use norg_fmt::{
+    options::{suggest, FormatOptions, Problem},
+    refactor::normalize_path,
+    Config,
+};
+use serde_json::{Map, Value};
+
+use crate::{editorconfig::EditorConfigs, workspace::Glob};
+
+/// The prefix of the environment variables holding options, e.g. `NORG_FMT_LINE_LENGTH`.
+pub const ENV_PREFIX: &str = "NORG_FMT_";
+
+/// The environment variable holding the path of the configuration file to use when `--config`
+/// isn't given.
+pub const CONFIG_VAR: &str = "NORG_FMT_CONFIG";
+
+/// Finds the span of the line setting the given option, with the keys of maps separated by dots.
+/// Falls back to the line setting the map itself if the key can't be found within it.
+fn span_of(content: &str, key: &str) -> Option<Range<usize>> {
+    let mut table = String::new();
+    let mut offset = 0;
+
+    for line in content.split_inclusive('\n') {
+        let trimmed = line.trim();
+        let start = offset + (line.len() - line.trim_start().len());
+        let span = start..start + trimmed.len();
+
+        offset += line.len();
+
+        let full_key = if let Some(header) = trimmed
+            .strip_prefix('[')
+            .and_then(|header| header.strip_suffix(']'))
+        {
+            table = header.trim().replace('"', "");
+            table.clone()
+        } else if let Some((name, _)) = trimmed.split_once('=') {
+            let name = name.trim().trim_matches('"');
+
+            if table.is_empty() {
+                name.to_string()
+            } else {
+                format!("{table}.{name}")
+            }
+        } else {
+            continue;
+        };
+
+        if full_key == key {
+            return Some(span);
+        }
+    }
+
+    span_of(content, key.rsplit_once('.')?.0)
+}
+
+/// Renders a problem in a configuration file as a report pointing at where it is.
+fn report(
+    kind: ReportKind,
+    path: &Path,
+    content: &str,
+    span: Range<usize>,
+    message: &str,
+    help: Option<&str>,
+) -> String {
+    let id = path.display().to_string();
+    let mut report = Report::build(kind, id.clone(), span.start)
+        .with_config(ReportConfig::default().with_color(false))
+        .with_message(message)
+        .with_label(Label::new((id.clone(), span)));
+
+    if let Some(help) = help {
+        report = report.with_help(help);
+    }
+
+    let mut output = Vec::new();
+
+    // Writing to a vector can't fail.
+    let _ = report
+        .finish()
+        .write((id, Source::from(content)), &mut output);
+
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+fn report_problem(kind: ReportKind, path: &Path, content: &str, problem: &Problem) -> String {
+    let span = problem
+        .key
+        .as_deref()
+        .and_then(|key| span_of(content, key))
+        .unwrap_or(0..0);
+
+    report(
+        kind,
+        path,
+        content,
+        span,
+        &problem.message,
+        problem.help.as_deref(),
+    )
+}
+
+/// Reads the options of a TOML configuration file, using the same keys as [`FormatOptions`], e.g.
+/// `line_length = 100`. Warnings about ignored options are printed, whereas invalid options fail
+/// with an error pointing at the offending line.
+pub fn load(path: &Path) -> Result<FormatOptions> {
+    let content = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("unable to read {}", path.display()))?;
+
+    let options = toml::from_str::<FormatOptions>(&content).map_err(|err| {
+        eyre!(report(
+            ReportKind::Error,
+            path,
+            &content,
+            err.span().unwrap_or(0..0),
+            &format!("invalid configuration: {}", err.message()),
+            None,
+        ))
+    })?;
+
+    for warning in options.warnings() {
+        eprint!(
+            "{}",
+            report_problem(ReportKind::Warning, path, &content, &warning)
+        );
+    }
+
+    let errors = options.errors();
+
+    if !errors.is_empty() {
+        bail!(errors
+            .iter()
+            .map(|error| report_problem(ReportKind::Error, path, &content, error))
+            .collect::<String>());
+    }
+
+    Ok(options)
+}
+
+/// Parses the value of an environment variable the way it would be written in a configuration file.
+/// Options holding maps are given as comma-separated `key=value` pairs, e.g. `quotes=72,lists=80`.
+fn env_value(key: &str, value: &str) -> Value {
+    match key {
+        "construct_line_length" | "tag_handling" | "language_aliases" => Value::Object(
+            value
+                .split(',')
+                .filter_map(|entry| entry.split_once('='))
+                .map(|(key, value)| (key.trim().to_string(), env_value("", value.trim())))
+                .collect(),
+        ),
+        "heading_annotation_markers" | "template_placeholders" => Value::Array(
+            value
+                .split(',')
+                .map(|marker| Value::String(marker.trim().to_string()))
+                .collect(),
+        ),
+        // Anything that isn't a number or a boolean is a string, without needing quotes.
+        _ => match value.parse() {
+            Ok(value @ (Value::Number(_) | Value::Bool(_))) => value,
+            _ => Value::String(value.to_string()),
+        },
+    }
+}
+
+/// Reads the options given as `NORG_FMT_<OPTION>` environment variables, e.g.
+/// `NORG_FMT_LINE_LENGTH=100`, which take precedence over the configuration file but not over the
+/// command line.
+///
+/// Variables sharing the prefix without naming an option, such as `NORG_FMT_CONFIG`, are left
+/// alone, apart from those close enough to an option to be a misspelling of it, which are reported
+/// as unknown options.
+pub fn from_env() -> Result<FormatOptions> {
+    from_vars(std::env::vars())
+}
+
+/// Reads the options given by the environment variables `vars`, see [`from_env`].
+fn from_vars(vars: impl IntoIterator<Item = (String, String)>) -> Result<FormatOptions> {
+    let mut options = Map::new();
+
+    for (name, value) in vars {
+        let Some(key) = name.strip_prefix(ENV_PREFIX).map(str::to_ascii_lowercase) else {
+            continue;
+        };
+
+        if name == CONFIG_VAR || suggest(&key).is_none() {
+            continue;
+        }
+
+        let parsed = env_value(&key, &value);
+
+        // Checked one at a time, so that an invalid value can be traced back to its variable.
+        serde_json::from_value::<FormatOptions>(serde_json::json!({ &key: parsed }))
+            .wrap_err_with(|| format!("invalid value `{value}` in {name}"))?;
+
+        options.insert(key, parsed);
+    }
+
+    let options = serde_json::from_value::<FormatOptions>(Value::Object(options))?;
+
+    if let Some(error) = options.errors().first() {
+        // Keys within maps are only named by the variable holding the whole map.
+        match error.key.as_deref().and_then(|key| key.split('.').next()) {
+            Some(key) => bail!("invalid {ENV_PREFIX}{}: {error}", key.to_ascii_uppercase()),
+            None => bail!("invalid options in {ENV_PREFIX}* environment variables: {error}"),
+        }
+    }
+
+    Ok(options)
+}
+
+/// Options that only apply to the files matching a glob.
+struct Scope {
+    /// What the options are called in `--why`, e.g. `profile "journal/**"`.
+    name: String,
+    glob: Glob,
+    /// The directory the glob is relative to, or the working directory if not set.
+    root: Option<PathBuf>,
+    options: FormatOptions,
+}
+
+impl Scope {
+    fn matches(&self, file: &Path) -> bool {
+        let Some(root) = &self.root else {
+            return self.glob.matches_path(file);
+        };
+
+        std::path::absolute(file)
+            .map(|file| normalize_path(&file))
+            .is_ok_and(|file| {
+                file.strip_prefix(root)
+                    .is_ok_and(|file| self.glob.matches_path(file))
+            })
+    }
+}
+
+/// The configuration of every file: the options of the configuration file with those of the
+/// profiles and `paths` entries matching the file on top, overridden by the environment and the
+/// command line. The `.editorconfig` files of a file make up the lowest layer, if enabled.
+pub struct Configs {
+    default: Config,
+    base: FormatOptions,
+    overrides: FormatOptions,
+    scopes: Vec<Scope>,
+    include: Vec<Glob>,
+    exclude: Vec<Glob>,
+    editorconfig: Option<EditorConfigs>,
+    /// The configuration file the `base` options were read from, if any.
+    path: Option<PathBuf>,
+}
+
+impl Configs {
+    /// Resolves the profiles and `paths` entries of the `base` options read from the configuration
+    /// file at `path`, with the globs of profiles relative to its directory.
+    pub fn new(
+        mut base: FormatOptions,
+        overrides: FormatOptions,
+        path: Option<&Path>,
+    ) -> Result<Self> {
+        let root = path
+            .and_then(Path::parent)
+            .filter(|root| !root.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let root = normalize_path(&std::path::absolute(root)?);
+        let profiles = base
+            .profile
+            .take()
+            .into_iter()
+            .flatten()
+            .sorted_by_key(|(glob, _)| glob.len())
+            .map(|(glob, options)| {
+                let name = format!("profile \"{glob}\"");
+
+                (name, glob, Some(root.clone()), options)
+            });
+        let paths = base
+            .paths
+            .take()
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .map(|(index, path)| {
+                let name = format!("paths[{index}] \"{}\"", path.glob);
+
+                (name, path.glob, None, path.options)
+            });
+
+        let scopes = profiles
+            .chain(paths)
+            .map(|(name, glob, root, options)| {
+                let merged = base.clone().merge(options.clone()).merge(overrides.clone());
+
+                if let Some(error) = merged.errors().first() {
+                    bail!("invalid options for {glob}: {error}");
+                }
+
+                Ok(Scope {
+                    name,
+                    glob: glob
+                        .parse()
+                        .map_err(|err| eyre!("invalid glob `{glob}`: {err}"))?,
+                    root,
+                    options: FormatOptions {
+                        include: None,
+                        exclude: None,
+                        paths: None,
+                        profile: None,
+                        ..options
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let merged = base.clone().merge(overrides.clone());
+        let globs = |globs: Option<Vec<String>>| {
+            globs
+                .into_iter()
+                .flatten()
+                .map(|glob| {
+                    glob.parse()
+                        .map_err(|err| eyre!("invalid glob `{glob}`: {err}"))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            default: merged.config(),
+            include: globs(merged.include)?,
+            exclude: globs(merged.exclude)?,
+            base,
+            overrides,
+            scopes,
+            editorconfig: None,
+            path: path.map(Path::to_path_buf),
+        })
+    }
+
+    /// Uses the `.editorconfig` files of every file beneath the other options.
+    pub fn with_editorconfig(mut self) -> Self {
+        self.editorconfig = Some(EditorConfigs::default());
+        self
+    }
+
+    /// The globs of the `include` option: if there are any, only the files matching one of them are
+    /// formatted.
+    pub fn include(&self) -> &[Glob] {
+        &self.include
+    }
+
+    /// The globs of the `exclude` option, from every layer.
+    pub fn exclude(&self) -> &[Glob] {
+        &self.exclude
+    }
+
+    /// The configuration of files that no profile or `paths` entry matches.
+    pub fn default_config(&self) -> &Config {
+        &self.default
+    }
+
+    pub fn for_file(&self, file: &Path) -> Cow<'_, Config> {
+        let mut matching = self
+            .scopes
+            .iter()
+            .filter(|scope| scope.matches(file))
+            .peekable();
+
+        let editorconfig = self
+            .editorconfig
+            .as_ref()
+            .map(|editorconfig| editorconfig.options_for(file))
+            .filter(|options| *options != FormatOptions::default());
+
+        if matching.peek().is_none() && editorconfig.is_none() {
+            return Cow::Borrowed(&self.default);
+        }
+
+        let base = editorconfig.unwrap_or_default().merge(self.base.clone());
+        let options = matching.fold(base, |options, scope| options.merge(scope.options.clone()));
+
+        Cow::Owned(options.merge(self.overrides.clone()).config())
+    }
+
+    /// The layers of options that apply to `file` beneath the environment and the command line,
+    /// named and in order of precedence: its `.editorconfig` files, the configuration file and the
+    /// matching profiles and `paths` entries.
+    pub fn layers(&self, file: &Path) -> Vec<(String, FormatOptions)> {
+        let mut layers = Vec::new();
+
+        if let Some(editorconfig) = &self.editorconfig {
+            layers.push((".editorconfig".to_string(), editorconfig.options_for(file)));
+        }
+
+        if let Some(path) = &self.path {
+            layers.push((
+                format!("configuration file {}", path.display()),
+                self.base.clone(),
+            ));
+        }
+
+        layers.extend(
+            self.scopes
+                .iter()
+                .filter(|scope| scope.matches(file))
+                .map(|scope| (scope.name.clone(), scope.options.clone())),
+        );
+
+        layers
+    }
+}
+
+/// Explains which of the given layers of options, from the lowest to the highest precedence,
+/// determined the value of `option` for a document, and which `+norgfmt` attributes in its
+/// `content` override it for parts of it.
+pub fn why(option: &str, layers: &[(String, FormatOptions)], content: &str) -> Result<String> {
+    let key = option
+        .trim_start_matches("--")
+        .replace('-', "_")
+        .to_ascii_lowercase();
+    let probe = serde_json::from_value::<FormatOptions>(serde_json::json!({ &key: null }))?;
+
+    if !probe.unknown.is_empty() || matches!(key.as_str(), "version" | "paths" | "profile") {
+        match suggest(&key) {
+            Some(suggestion) => bail!("`{option}` is not an option, did you mean `{suggestion}`?"),
+            None => bail!("`{option}` is not an option"),
+        }
+    }
+
+    let value = |options: &FormatOptions| -> Result<Option<Value>> {
+        Ok(serde_json::to_value(options)?
+            .get(&key)
+            .filter(|value| !value.is_null())
+            .cloned())
+    };
+    let mut lines = Vec::new();
+    let mut determined_by = None;
+
+    for (name, options) in layers {
+        match value(options)? {
+            Some(value) => {
+                lines.push(format!("  {name}: {value}"));
+                determined_by = Some(name);
+            }
+            None => lines.push(format!("  {name}: not set")),
+        }
+    }
+
+    let merged = layers
+        .iter()
+        .fold(FormatOptions::default(), |merged, (_, options)| {
+            merged.merge(options.clone())
+        });
+    let mut output = match (value(&merged)?, determined_by) {
+        (Some(value), Some(name)) => format!("{key} = {value}, determined by the {name}\n"),
+        _ => format!("{key} is not set\n"),
+    };
+
+    for line in lines {
+        output += &line;
+        output.push('\n');
+    }
+
+    for (number, line) in content.lines().enumerate() {
+        let Some(parameters) = line.trim_start().strip_prefix("+norgfmt") else {
+            continue;
+        };
+
+        for parameter in parameters.split_whitespace() {
+            let Some((name, value)) = parameter.split_once('=') else {
+                continue;
+            };
+            let name = name.replace('-', "_");
+            let overrides = name == key
+                || (key == "line_length" && name == "wrap")
+                || (key == "construct_line_length" && name.starts_with("line_length."));
+
+            if overrides {
+                output += &format!(
+                    "  overridden by `+norgfmt {name}={value}` on line {} for the block or section \
+                     it is attached to\n",
+                    number + 1
+                );
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(vars: &[(&str, &str)]) -> Result<FormatOptions> {
+        from_vars(
+            vars.iter()
+                .map(|(name, value)| (name.to_string(), value.to_string())),
+        )
+    }
+
+    #[test]
+    fn parses_environment_values_like_the_configuration_file() {
+        assert_eq!(env_value("line_length", "100"), serde_json::json!(100));
+        assert_eq!(env_value("wrap_comments", "true"), serde_json::json!(true));
+        assert_eq!(env_value("indent_style", "tabs"), serde_json::json!("tabs"));
+        assert_eq!(
+            env_value("construct_line_length", "quotes=72, lists = 80"),
+            serde_json::json!({ "quotes": 72, "lists": 80 })
+        );
+        assert_eq!(
+            env_value("heading_annotation_markers", "{{{, }}}"),
+            serde_json::json!(["{{{", "}}}"])
+        );
+    }
+
+    #[test]
+    fn reads_only_option_variables() {
+        let options = vars(&[
+            ("NORG_FMT_LINE_LENGTH", "100"),
+            ("NORG_FMT_CONFIG", "norg-fmt.toml"),
+            ("NORG_FMT_BLESS", "1"),
+            ("LINE_LENGTH", "60"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            options,
+            FormatOptions {
+                line_length: Some(100),
+                ..FormatOptions::default()
+            }
+        );
+    }
+
+    #[test]
+    fn names_the_variable_of_invalid_values() {
+        let error = vars(&[("NORG_FMT_LINE_LENGTH", "wide")]).unwrap_err();
+
+        assert!(
+            error.to_string().contains("NORG_FMT_LINE_LENGTH"),
+            "{error}"
+        );
+
+        let error = vars(&[("NORG_FMT_CONSTRUCT_LINE_LENGTH", "quotes=5")]).unwrap_err();
+
+        assert!(
+            error
+                .to_string()
+                .starts_with("invalid NORG_FMT_CONSTRUCT_LINE_LENGTH: "),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn layers_profiles_paths_and_overrides() {
+        let base = serde_json::from_value::<FormatOptions>(serde_json::json!({
+            "line_length": 80,
+            "profile": { "journal/**": { "line_length": 60, "wrap_comments": true } },
+            "paths": [{ "glob": "journal/work/**", "line_length": 70 }],
+        }))
+        .unwrap();
+        let overrides = FormatOptions {
+            indent_width: Some(2),
+            ..FormatOptions::default()
+        };
+        let configs = Configs::new(base, overrides.clone(), None).unwrap();
+        let config = |file: &str| configs.for_file(Path::new(file)).into_owned();
+
+        assert_eq!(config("notes.norg").line_length, 80);
+        assert_eq!(config("journal/day.norg").line_length, 60);
+        assert_eq!(config("journal/work/day.norg").line_length, 70);
+        assert!(config("journal/work/day.norg").wrap_comments);
+        assert!(!config("notes.norg").wrap_comments);
+        assert_eq!(config("journal/day.norg").indent_width, 2);
+        assert_eq!(
+            configs
+                .layers(Path::new("journal/work/day.norg"))
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>(),
+            ["profile \"journal/**\"", "paths[0] \"journal/work/**\""]
+        );
+
+        let base = serde_json::from_value::<FormatOptions>(serde_json::json!({
+            "profile": { "journal/**": { "line_length": 60 } },
+        }))
+        .unwrap();
+        let overrides = FormatOptions {
+            line_length: Some(100),
+            ..overrides
+        };
+        let overridden = Configs::new(base, overrides, None).unwrap();
+
+        assert_eq!(
+            overridden
+                .for_file(Path::new("journal/day.norg"))
+                .line_length,
+            100
+        );
+    }
+
+    #[test]
+    fn replaces_included_and_combines_excluded_globs() {
+        let base = serde_json::from_value::<FormatOptions>(serde_json::json!({
+            "include": ["notes/**"],
+            "exclude": ["**/archive/**"],
+        }))
+        .unwrap();
+        let matching = |globs: &[Glob], file: &str| {
+            globs
+                .iter()
+                .filter(|glob| glob.matches_path(Path::new(file)))
+                .count()
+        };
+
+        let configs = Configs::new(base.clone(), FormatOptions::default(), None).unwrap();
+
+        assert_eq!(matching(configs.include(), "notes/a.norg"), 1);
+        assert_eq!(matching(configs.exclude(), "notes/archive/a.norg"), 1);
+
+        let overrides = FormatOptions {
+            include: Some(vec!["journal/**".to_string()]),
+            exclude: Some(vec!["**/drafts/**".to_string()]),
+            ..FormatOptions::default()
+        };
+        let configs = Configs::new(base.clone(), overrides, None).unwrap();
+
+        assert_eq!(matching(configs.include(), "notes/a.norg"), 0);
+        assert_eq!(matching(configs.include(), "journal/a.norg"), 1);
+        assert_eq!(matching(configs.exclude(), "notes/archive/a.norg"), 1);
+        assert_eq!(matching(configs.exclude(), "journal/drafts/a.norg"), 1);
+
+        let overrides = FormatOptions {
+            exclude: Some(vec!["[]".to_string()]),
+            ..FormatOptions::default()
+        };
+
+        assert!(Configs::new(base, overrides, None).is_err());
+    }
+
+    #[test]
+    fn explains_which_layer_determines_an_option() {
+        let layer = |line_length| FormatOptions {
+            line_length,
+            ..FormatOptions::default()
+        };
+        let layers = [
+            ("configuration file".to_string(), layer(Some(80))),
+            ("profile \"journal/**\"".to_string(), layer(Some(60))),
+            ("command line".to_string(), layer(None)),
+        ];
+        let why = why(
+            "--line-length",
+            &layers,
+            "* Day\n+norgfmt line_length=100\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            why,
+            "line_length = 60, determined by the profile \"journal/**\"\n  \
+             configuration file: 80\n  profile \"journal/**\": 60\n  command line: not set\n  \
+             overridden by `+norgfmt line_length=100` on line 2 for the block or section it is \
+             attached to\n"
+        );
+        assert_eq!(
+            super::why("line-lenght", &layers, "")
+                .unwrap_err()
+                .to_string(),
+            "`line-lenght` is not an option, did you mean `line_length`?"
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    cell::{OnceCell, RefCell},
+    collections::{BTreeMap, BTreeSet, HashSet, VecDeque},
+    fmt,
+    ops::Range,
+    rc::Rc,
+    sync::Arc,
+    time::Instant,
+};
+
+use rust_norg::NorgASTFlat;
+
+use crate::{
+    profile::Profile,
+    prose::ProseInspector,
+    registry::Registry,
+    source_map::{block_ranges, heading_annotations, tag_bodies},
+    tags::common_indent,
+    Config, Construct, TagHandling,
+};
+
+/// The kinds of top-level nodes the formatter dispatches on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NodeKind {
+    Paragraph,
+    NestableDetachedModifier,
+    RangeableDetachedModifier,
+    Heading,
+    CarryoverTag,
+    VerbatimRangedTag,
+    RangedTag,
+    InfirmTag,
+}
+
+impl NodeKind {
+    pub fn of(node: &NorgASTFlat) -> Self {
+        match node {
+            NorgASTFlat::Paragraph(_) => Self::Paragraph,
+            NorgASTFlat::NestableDetachedModifier { .. } => Self::NestableDetachedModifier,
+            NorgASTFlat::RangeableDetachedModifier { .. } => Self::RangeableDetachedModifier,
+            NorgASTFlat::Heading { .. } => Self::Heading,
+            NorgASTFlat::CarryoverTag { .. } => Self::CarryoverTag,
+            NorgASTFlat::VerbatimRangedTag { .. } => Self::VerbatimRangedTag,
+            NorgASTFlat::RangedTag { .. } => Self::RangedTag,
+            NorgASTFlat::InfirmTag { .. } => Self::InfirmTag,
+        }
+    }
+}
+
+impl fmt::Display for NodeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Self::Paragraph => "Paragraph",
+            Self::NestableDetachedModifier => "NestableDetachedModifier",
+            Self::RangeableDetachedModifier => "RangeableDetachedModifier",
+            Self::Heading => "Heading",
+            Self::CarryoverTag => "CarryoverTag",
+            Self::VerbatimRangedTag => "VerbatimRangedTag",
+            Self::RangedTag => "RangedTag",
+            Self::InfirmTag => "InfirmTag",
+        })
+    }
+}
+
+/// The top-level nodes of the document being formatted, for looking up what they were written as.
+/// Nodes are identified by their position, as equal nodes may have been written differently.
+#[derive(Default)]
+struct Document {
+    ast: Vec<NorgASTFlat>,
+    /// The source text of every top-level node, recovered from the source the first time it is
+    /// needed, as that takes parsing the source again.
+    written: OnceCell<Vec<String>>,
+    /// The positions of the top-level nodes that are kept exactly as they were written instead of
+    /// being formatted.
+    kept: RefCell<HashSet<usize>>,
+}
+
+impl Document {
+    fn written(&self, source: &str, ranges: &[Range<usize>]) -> &[String] {
+        self.written.get_or_init(|| {
+            let lines = source.lines().collect::<Vec<_>>();
+
+            ranges
+                .iter()
+                .map(|range| {
+                    let text = lines[range.clone()]
+                        .iter()
+                        .map(|line| format!("{line}\n"))
+                        .collect::<String>();
+                    // The indentation of the block is that of the section it is in, which is
+                    // applied again when the section is formatted.
+                    let indent = common_indent(&text);
+
+                    text.lines()
+                        .map(|line| format!("{}\n", line.strip_prefix(indent).unwrap_or(line)))
+                        .collect()
+                })
+                .collect()
+        })
+    }
+}
+
+/// Something the formatter noticed that the user should know about, without it stopping the
+/// document from being formatted.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub message: String,
+}
+
+/// The state every formatting function receives.
+///
+/// Nested content is formatted with a context derived through [`FormatCtx::nested`], which keeps
+/// track of where in the document the formatter is while sharing the diagnostics of the whole run.
+#[derive(Clone)]
+pub struct FormatCtx<'a> {
+    pub config: &'a Config,
+    /// The source the document was parsed from, if it is available.
+    pub source: Option<&'a str>,
+    /// The number of blocks the content being formatted is nested in.
+    pub depth: usize,
+    /// The kind of block the content being formatted is nested in, if any.
+    pub parent: Option<NodeKind>,
+    /// The number of columns every line of the content being formatted is indented by, e.g. within
+    /// an indented section, which count towards its line length.
+    pub indentation: usize,
+    /// The construct the content being formatted is part of, if it has a line length of its own.
+    pub construct: Option<Construct>,
+    /// The formatters used for every kind of node.
+    pub registry: Arc<Registry>,
+    diagnostics: Rc<RefCell<Vec<Diagnostic>>>,
+    /// The source text of the tags whose content is not formatted as Norg, queued by tag name in
+    /// the order they appear in.
+    tag_bodies: Rc<RefCell<BTreeMap<String, VecDeque<String>>>>,
+    /// The trailing annotations of the headings in the source, along with the heading they belong
+    /// to, queued in the order they appear in.
+    heading_annotations: Rc<RefCell<VecDeque<(String, String)>>>,
+    /// The document being formatted, if it is formatted as a whole.
+    document: Rc<Document>,
+    /// The lines every top-level block occupies in the source, recovered the first time they are
+    /// needed and shared by everything looking them up for the same source.
+    block_ranges: Rc<OnceCell<Option<Vec<Range<usize>>>>>,
+    /// Where the time spent formatting is recorded, if it is being measured.
+    profile: Option<Rc<RefCell<Profile>>>,
+    /// The inspectors the prose of the document is checked by.
+    inspectors: Vec<Rc<dyn ProseInspector>>,
+    /// Where the rules applied while formatting are recorded, if they are being recorded.
+    rules: Option<Rc<RefCell<BTreeSet<&'static str>>>>,
+}
+
+impl<'a> FormatCtx<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Self {
+            config,
+            source: None,
+            depth: 0,
+            parent: None,
+            indentation: 0,
+            construct: None,
+            registry: Arc::default(),
+            diagnostics: Rc::default(),
+            tag_bodies: Rc::default(),
+            heading_annotations: Rc::default(),
+            document: Rc::default(),
+            block_ranges: Rc::default(),
+            profile: None,
+            inspectors: Vec::new(),
+            rules: None,
+        }
+    }
+
+    pub fn with_source(self, source: &'a str) -> Self {
+        let handling = &self.config.tag_handling;
+        let mut bodies = BTreeMap::<String, VecDeque<String>>::new();
+
+        for (name, body) in tag_bodies(source, |name| {
+            handling
+                .get(name)
+                .is_some_and(|handling| handling != &TagHandling::Norg)
+        }) {
+            bodies.entry(name).or_default().push_back(body);
+        }
+
+        let annotations = heading_annotations(source, &self.config.heading_annotation_markers);
+
+        Self {
+            source: Some(source),
+            block_ranges: Rc::default(),
+            tag_bodies: Rc::new(RefCell::new(bodies)),
+            heading_annotations: Rc::new(RefCell::new(annotations.into())),
+            ..self
+        }
+    }
+
+    pub fn with_registry(self, registry: Arc<Registry>) -> Self {
+        Self { registry, ..self }
+    }
+
+    /// Records the time spent formatting in the given profile.
+    pub fn with_profile(self, profile: Rc<RefCell<Profile>>) -> Self {
+        Self {
+            profile: Some(profile),
+            ..self
+        }
+    }
+
+    /// Records the ids of the rules applied while formatting in the given set, see
+    /// [`crate::explain`].
+    pub fn with_rules(self, rules: Rc<RefCell<BTreeSet<&'static str>>>) -> Self {
+        Self {
+            rules: Some(rules),
+            ..self
+        }
+    }
+
+    /// Checks the prose of the document with the given inspector as well, see [`crate::prose`].
+    pub fn with_inspector(mut self, inspector: Rc<dyn ProseInspector>) -> Self {
+        self.inspectors.push(inspector);
+        self
+    }
+
+    pub(crate) fn inspectors(&self) -> &[Rc<dyn ProseInspector>] {
+        &self.inspectors
+    }
+
+    /// Derives a context formatting with another configuration, e.g. one overridden for a section
+    /// of the document.
+    pub fn with_config<'b>(&self, config: &'b Config) -> FormatCtx<'b>
+    where
+        'a: 'b,
+    {
+        FormatCtx {
+            config,
+            ..self.clone()
+        }
+    }
+
+    /// Derives the context for the content of a block of the given kind.
+    pub fn nested(&self, parent: NodeKind) -> Self {
+        Self {
+            depth: self.depth + 1,
+            parent: Some(parent),
+            ..self.clone()
+        }
+    }
+
+    /// Derives the context for content indented by another `columns` columns.
+    pub fn indented(&self, columns: usize) -> Self {
+        Self {
+            indentation: self.indentation + columns,
+            ..self.clone()
+        }
+    }
+
+    /// Derives the context for the content of a construct with a line length of its own.
+    pub fn within(&self, construct: Construct) -> Self {
+        Self {
+            construct: Some(construct),
+            ..self.clone()
+        }
+    }
+
+    /// The length the lines of the content being formatted are wrapped at, its indentation
+    /// included. Lines are never wrapped where the line breaks they were written with are kept.
+    pub fn line_length(&self) -> usize {
+        if self.config.preserve_line_breaks {
+            return usize::MAX;
+        }
+
+        let line_length = match self.construct {
+            Some(construct) => self.config.line_length_of(construct),
+            None => self.config.line_length,
+        };
+
+        line_length.saturating_sub(self.indentation)
+    }
+
+    pub fn diagnose(&self, message: impl Into<String>) {
+        self.diagnostics.borrow_mut().push(Diagnostic {
+            message: message.into(),
+        });
+    }
+
+    /// Takes the source text of the next tag called `name` that has not been formatted yet. Only
+    /// available for tags that are not formatted as Norg, and only if the source is known.
+    pub(crate) fn take_tag_body(&self, name: &str) -> Option<String> {
+        self.tag_bodies.borrow_mut().get_mut(name)?.pop_front()
+    }
+
+    /// Takes the trailing annotation of the heading (whitespace normalized) from the source, along
+    /// with the whitespace in front of it. Annotations of headings skipped over are dropped, as
+    /// they belong to headings that won't be formatted anymore.
+    pub(crate) fn take_heading_annotation(&self, heading: &str) -> Option<String> {
+        let mut annotations = self.heading_annotations.borrow_mut();
+        let index = annotations.iter().position(|(other, _)| other == heading)?;
+
+        annotations
+            .drain(..=index)
+            .last()
+            .map(|(_, annotation)| annotation)
+    }
+
+    /// Derives the context for formatting a whole document made up of the given top-level nodes.
+    pub(crate) fn with_document(&self, ast: Vec<NorgASTFlat>) -> Self {
+        Self {
+            document: Rc::new(Document {
+                ast,
+                ..Document::default()
+            }),
+            ..self.clone()
+        }
+    }
+
+    /// The source text of the top-level node at `index` without its indentation, if the content
+    /// being formatted is the top level of the document and the source is known.
+    pub(crate) fn written(&self, index: usize) -> Option<String> {
+        if self.depth > 0 {
+            return None;
+        }
+
+        let ranges = self.block_ranges(self.document.ast.len())?;
+
+        self.document
+            .written(self.source?, ranges)
+            .get(index)
+            .cloned()
+    }
+
+    /// The lines (zero-based, end exclusive) occupied by each of the `block_count` top-level blocks
+    /// of the source, if the source is known and its blocks can be located, see
+    /// [`block_ranges`].
+    pub(crate) fn block_ranges(&self, block_count: usize) -> Option<&[Range<usize>]> {
+        let source = self.source?;
+
+        self.block_ranges
+            .get_or_init(|| block_ranges(source, block_count))
+            .as_deref()
+    }
+
+    /// Keeps the top-level node at `index` exactly as it was written instead of formatting it,
+    /// which requires its source text to be known.
+    pub(crate) fn keep_as_written(&self, index: usize) {
+        self.document.kept.borrow_mut().insert(index);
+    }
+
+    /// Whether the node at `index` is a top-level node that is kept as it was written.
+    pub(crate) fn is_kept(&self, index: usize) -> bool {
+        self.depth == 0 && self.document.kept.borrow().contains(&index)
+    }
+
+    /// Runs `f`, adding the time it took to the named profile entry if time is being measured.
+    pub(crate) fn profiled<T>(&self, name: impl FnOnce() -> String, f: impl FnOnce() -> T) -> T {
+        let Some(profile) = &self.profile else {
+            return f();
+        };
+
+        let start = Instant::now();
+        let result = f();
+
+        // Not measured through the profile itself, as `f` records its own nested entries in it.
+        profile.borrow_mut().record(&name(), start.elapsed());
+
+        result
+    }
+
+    /// Records that the rule with the given id was applied, if rules are being recorded.
+    pub(crate) fn apply_rule(&self, rule: &'static str) {
+        if let Some(rules) = &self.rules {
+            rules.borrow_mut().insert(rule);
+        }
+    }
+
+    /// Every diagnostic reported so far, by this context or any context derived from it.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.diagnostics.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::converter::format_document;
+
+    #[test]
+    fn tells_equal_nodes_written_differently_apart() {
+        let source = "-  Item\n\n- Item\n";
+        let ast = rust_norg::parse(source).unwrap();
+
+        assert_eq!(ast.len(), 2);
+        assert_eq!(ast[0], ast[1]);
+
+        let config = Config::default();
+        let ctx = FormatCtx::new(&config)
+            .with_source(source)
+            .with_document(ast.clone());
+
+        assert_eq!(ctx.written(0).as_deref(), Some("-  Item\n"));
+        assert_eq!(ctx.written(1).as_deref(), Some("- Item\n"));
+
+        ctx.keep_as_written(1);
+
+        assert!(!ctx.is_kept(0));
+        assert!(ctx.is_kept(1));
+
+        let config = Config {
+            preserve_line_breaks: true,
+            ..Config::default()
+        };
+        let formatted = format_document(ast, &FormatCtx::new(&config).with_source(source));
+
+        assert_eq!(formatted.unwrap().concat(), "-  Item\n- Item\n");
+    }
+}
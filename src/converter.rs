@@ -1,18 +1,50 @@
-use chumsky::{select, Parser};
+use std::ops::Range;
+
+use chumsky::{
+    error::Simple,
+    prelude::{end, filter, BoxedParser},
+    Parser,
+};
+use eyre::{eyre, Result};
 use itertools::Itertools as _;
-use regex::Regex;
-use rust_norg::{LinkTarget, NorgASTFlat, ParagraphSegment};
+use rust_norg::{
+    DetachedModifierExtension, LinkTarget, NorgASTFlat, ParagraphSegment, ParagraphSegmentToken,
+    TodoStatus,
+};
+use unicode_width::UnicodeWidthStr as _;
+
+use crate::{
+    context::{FormatCtx, NodeKind},
+    grammar,
+    indent::Indent,
+    overrides, slug,
+    source_map::split_heading_annotation,
+    tags::{tag_content, verbatim_content},
+    typography::is_breaking_whitespace,
+    Config, Construct, ContinuationIndent, FreeFormConversion,
+};
+
+/// Stands in for a space that must never become a line break during reflow, such as the spaces
+/// within inline verbatim. It is only turned back into a space once a whole paragraph is formatted,
+/// so that markup-aware decisions made for nested text aren't undone by the text around it.
+const GLUE: char = '\u{E000}';
 
-fn format_link_target(input: LinkTarget) -> String {
+/// Formats a link target, collapsing the whitespace of titles but keeping their case, as described
+/// in [`crate::slug`].
+fn format_link_target(input: LinkTarget, ctx: &FormatCtx) -> String {
     match input {
         LinkTarget::Heading { level, title } => {
-            format!("{} {}", "*".repeat(level.into()), format_paragraph(title))
+            format!(
+                "{} {}",
+                "*".repeat(level.into()),
+                format_inline_text(title, ctx)
+            )
         }
-        LinkTarget::Footnote(title) => format!("^ {}", format_paragraph(title)),
-        LinkTarget::Definition(title) => format!("$ {}", format_paragraph(title)),
-        LinkTarget::Generic(title) => format!("# {}", format_paragraph(title)),
-        LinkTarget::Wiki(title) => format!("? {}", format_paragraph(title)),
-        LinkTarget::Extendable(title) => format!("= {}", format_paragraph(title)),
+        LinkTarget::Footnote(title) => format!("^ {}", format_inline_text(title, ctx)),
+        LinkTarget::Definition(title) => format!("$ {}", format_inline_text(title, ctx)),
+        LinkTarget::Generic(title) => format!("# {}", format_inline_text(title, ctx)),
+        LinkTarget::Wiki(title) => format!("? {}", format_inline_text(title, ctx)),
+        LinkTarget::Extendable(title) => format!("= {}", format_inline_text(title, ctx)),
         LinkTarget::Path(path) => format!("/ {path}"),
         LinkTarget::Url(url) => url,
         LinkTarget::Timestamp(timestamp) => format!("@ {timestamp}"),
@@ -23,166 +55,1237 @@ fn format_link(
     filepath: Option<String>,
     targets: Vec<LinkTarget>,
     description: Option<Vec<ParagraphSegment>>,
+    ctx: &FormatCtx,
 ) -> String {
-    let filepath = filepath.unwrap_or_default();
-    let targets = targets.into_iter().map(format_link_target).join(" : ");
+    let filepath = filepath
+        .map(|filepath| format!(":{filepath}:"))
+        .unwrap_or_default();
+    let targets = targets
+        .into_iter()
+        .map(|target| format_link_target(target, ctx))
+        .join(" : ");
 
-    if let Some(description) = description.map(format_paragraph) {
+    if let Some(description) = description.map(|description| format_inline_text(description, ctx)) {
         format!("{{{filepath}{targets}}}[{description}]")
     } else {
         format!("{{{filepath}{targets}}}")
     }
 }
 
-fn format_paragraph_segment(input: ParagraphSegment) -> String {
+/// Formats an inline comment (`%comment%`).
+///
+/// Unless comment wrapping is enabled the comment is kept on a single line, as reflowing
+/// commented-out text tends to mangle it.
+fn format_inline_comment(content: Vec<ParagraphSegment>, ctx: &FormatCtx) -> String {
+    if ctx.config.wrap_comments {
+        return format!("%{}%", format_paragraph(content, ctx));
+    }
+
+    ctx.apply_rule("comment-preservation");
+
+    let content = content
+        .into_iter()
+        .map(|segment| format_paragraph_segment(segment, ctx))
+        .collect::<String>();
+
+    format!(
+        "%{}%",
+        content
+            .trim_matches(is_breaking_whitespace)
+            .replace(is_breaking_whitespace, &GLUE.to_string())
+    )
+}
+
+/// Returns the content of a free-form attached modifier (`*|text|*`) without its pipes, or `None`
+/// if the modifier was written in its regular form.
+fn strip_free_form(content: &[ParagraphSegment]) -> Option<&[ParagraphSegment]> {
+    use rust_norg::{ParagraphSegment::Token, ParagraphSegmentToken::Special};
+
+    match content {
+        [Token(Special('|')), inner @ .., Token(Special('|'))] => Some(inner),
+        _ => None,
+    }
+}
+
+/// Whether the content of an attached modifier can only be written in free-form markup: any
+/// backslash or modifier character in it would have to be escaped in regular form. Nested markup is
+/// considered as it ends up formatted, so escapes within it don't count, as it is made free-form
+/// itself, but the characters they stand for do.
+fn needs_free_form(content: &[ParagraphSegment], modifier_type: char, nested: bool) -> bool {
+    use rust_norg::ParagraphSegmentToken::{Escape, Special};
+
+    content.iter().any(|segment| match segment {
+        ParagraphSegment::Token(Special(c)) => *c == modifier_type || *c == '\\',
+        ParagraphSegment::Token(Escape(c)) => !nested || *c == modifier_type,
+        // Verbatim, comments, math and variables are written as they are.
+        ParagraphSegment::AttachedModifier {
+            modifier_type: '`' | '%' | '$' | '&',
+            ..
+        } => false,
+        ParagraphSegment::AttachedModifier {
+            modifier_type: nested_type,
+            content,
+        } => {
+            *nested_type == modifier_type
+                || needs_free_form(
+                    strip_free_form(content).unwrap_or(content),
+                    modifier_type,
+                    true,
+                )
+        }
+        _ => false,
+    })
+}
+
+/// Formats an attached modifier, converting between regular and free-form markup according to
+/// the configured [`FreeFormConversion`].
+fn format_attached_modifier(
+    modifier_type: char,
+    content: Vec<ParagraphSegment>,
+    ctx: &FormatCtx,
+) -> String {
+    use rust_norg::ParagraphSegmentToken::{Escape, Special};
+
+    let free_form = strip_free_form(&content).map(<[_]>::to_vec);
+
+    match (ctx.config.free_form_conversion, free_form) {
+        (FreeFormConversion::Never, _) => {
+            format!(
+                "{modifier_type}{}{modifier_type}",
+                format_paragraph(content, ctx)
+            )
+        }
+        (FreeFormConversion::Auto, free_form) => {
+            let is_free_form = free_form.is_some();
+            let inner = free_form.unwrap_or(content);
+
+            if !needs_free_form(&inner, modifier_type, false) {
+                if is_free_form {
+                    ctx.apply_rule("free-form-decay");
+                }
+
+                return format!(
+                    "{modifier_type}{}{modifier_type}",
+                    format_paragraph(inner, ctx)
+                );
+            }
+
+            // Escapes are unnecessary in free-form markup, where backslashes are literal.
+            let inner = if is_free_form {
+                inner
+            } else {
+                ctx.apply_rule("free-form-decay");
+
+                inner
+                    .into_iter()
+                    .map(|segment| match segment {
+                        ParagraphSegment::Token(Escape(c)) => ParagraphSegment::Token(Special(c)),
+                        segment => segment,
+                    })
+                    .collect()
+            };
+
+            format!(
+                "{modifier_type}|{}|{modifier_type}",
+                format_paragraph(inner, ctx)
+            )
+        }
+        (FreeFormConversion::AlwaysPreferEscapes, None) => {
+            format!(
+                "{modifier_type}{}{modifier_type}",
+                format_paragraph(content, ctx)
+            )
+        }
+        (FreeFormConversion::AlwaysPreferEscapes, Some(inner)) => {
+            ctx.apply_rule("free-form-decay");
+
+            // Backslashes are literal inside free-form markup, so they must be escaped themselves.
+            let escaped = inner
+                .into_iter()
+                .flat_map(|segment| match segment {
+                    ParagraphSegment::Token(Special(c)) if c == modifier_type || c == '\\' => {
+                        vec![ParagraphSegment::Token(Escape(c))]
+                    }
+                    ParagraphSegment::Token(Escape(c)) if c == modifier_type || c == '\\' => vec![
+                        ParagraphSegment::Token(Escape('\\')),
+                        ParagraphSegment::Token(Escape(c)),
+                    ],
+                    ParagraphSegment::Token(Escape(c)) => vec![
+                        ParagraphSegment::Token(Escape('\\')),
+                        ParagraphSegment::Token(Special(c)),
+                    ],
+                    segment => vec![segment],
+                })
+                .collect();
+
+            format!(
+                "{modifier_type}{}{modifier_type}",
+                format_paragraph(escaped, ctx)
+            )
+        }
+    }
+}
+
+/// Formats inline verbatim (`` `code` ``). Its content is significant down to the whitespace, so it
+/// is kept exactly as written and never broken across lines.
+fn format_inline_verbatim(content: Vec<ParagraphSegmentToken>) -> String {
+    let content = content
+        .into_iter()
+        .map(|token| token.to_string())
+        .collect::<String>()
+        .replace(' ', &GLUE.to_string());
+
+    format!("`{content}`")
+}
+
+fn format_paragraph_segment(input: ParagraphSegment, ctx: &FormatCtx) -> String {
     use ParagraphSegment::*;
 
     match input {
         Token(token) => token.to_string(),
-        //AttachedModifierCandidate { modifier_type, content, closer } => todo!(),
+        // Modifier characters the parser could not pair up are plain text and are kept as such.
+        AttachedModifierOpener((modifier_type, free_form))
+        | AttachedModifierOpenerFail((modifier_type, free_form)) => {
+            modifier_type.to_string() + &free_form.map(String::from).unwrap_or_default()
+        }
+        AttachedModifierCloserCandidate(modifier_type) | AttachedModifierCloser(modifier_type) => {
+            modifier_type.to_string()
+        }
+        AttachedModifierCandidate {
+            modifier_type,
+            content,
+            closer,
+        } => {
+            let content = content
+                .into_iter()
+                .map(|segment| format_paragraph_segment(segment, ctx))
+                .collect::<String>();
+
+            format!(
+                "{modifier_type}{content}{}",
+                closer.map(String::from).unwrap_or_default()
+            )
+        }
+        AttachedModifier {
+            modifier_type: '%',
+            content,
+        } => format_inline_comment(content, ctx),
         AttachedModifier {
             modifier_type,
             content,
-        } => format!(
-            "{modifier_type}{}{modifier_type}",
-            format_paragraph(content)
+        } => ctx.profiled(
+            || "attached-modifier".into(),
+            || format_attached_modifier(modifier_type, content, ctx),
         ),
         Link {
             filepath,
             targets,
             description,
-        } => format_link(filepath, targets, description),
+        } => ctx.profiled(
+            || "link".into(),
+            || format_link(filepath, targets, description, ctx),
+        ),
         AnchorDefinition { content, target } => {
-            let content = format_paragraph(content);
+            let content = format_inline_text(content, ctx);
+            let target = format_paragraph_segment(*target, ctx);
 
-            match *target {
-                Link {
-                    filepath,
-                    targets,
-                    description,
-                } => {
-                    let link = format_link(filepath, targets, description);
-
-                    format!("[{content}]{link}")
-                }
-                _ => unreachable!(),
-            }
+            format!("[{content}]{target}")
         }
         Anchor {
             content,
             description,
         } => {
-            let content = format_paragraph(content);
+            let content = format_inline_text(content, ctx);
 
-            if let Some(description) = description.map(format_paragraph) {
+            if let Some(description) =
+                description.map(|description| format_inline_text(description, ctx))
+            {
                 format!("[{content}][{description}]")
             } else {
                 format!("[{content}]")
             }
         }
-        InlineLinkTarget(content) => format!("<{}>", format_paragraph(content)),
-        _ => unreachable!(),
+        InlineLinkTarget(content) => format!("<{}>", format_inline_text(content, ctx)),
+        InlineVerbatim(content) => format_inline_verbatim(content),
     }
 }
 
-fn reflow_paragraph(input: Vec<String>) -> String {
-    let whitespace_regex = Regex::new(r"\s+").unwrap();
-    let mergables = ["{", "[", "<"];
+/// The number of columns text takes up in a terminal or editor.
+///
+/// Measured with the Unicode East Asian Width rules, treating ambiguous characters as narrow, so
+/// that the result never depends on the platform or locale the formatter runs in.
+pub fn text_width(text: &str) -> usize {
+    text.width()
+}
 
-    whitespace_regex
-        .split(&input.join(""))
-        .map_into()
-        .coalesce(|first: String, second: String| {
-            if mergables
-                .iter()
-                .any(|possibility| first.starts_with(possibility))
-            {
-                Ok(first.to_string() + " " + &second)
-            } else {
-                Err((first, second))
+/// A formatted paragraph segment as far as wrapping is concerned.
+enum Fragment {
+    /// Whitespace between words, where the paragraph may be broken.
+    Space,
+    /// Text that may be broken wherever it contains whitespace, e.g. the content of markup.
+    Text(String),
+    /// Text that is never broken, e.g. a link.
+    Atom(String),
+}
+
+fn fragment(segment: ParagraphSegment, ctx: &FormatCtx) -> Fragment {
+    use ParagraphSegment::*;
+
+    match segment {
+        Token(ParagraphSegmentToken::Whitespace) => Fragment::Space,
+        Link { .. }
+        | Anchor { .. }
+        | AnchorDefinition { .. }
+        | InlineLinkTarget(_)
+        | InlineVerbatim(_) => Fragment::Atom(format_paragraph_segment(segment, ctx)),
+        segment => Fragment::Text(format_paragraph_segment(segment, ctx)),
+    }
+}
+
+/// Splits a paragraph into the words it is wrapped by: runs of text between break opportunities.
+fn words(fragments: Vec<Fragment>) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+
+    for fragment in fragments {
+        match fragment {
+            Fragment::Space => words.push(std::mem::take(&mut current)),
+            Fragment::Atom(atom) => current.push_str(&atom),
+            Fragment::Text(text) => {
+                // Non-breaking spaces stay within the word they glue together.
+                let mut parts = text.split(is_breaking_whitespace);
+
+                current.push_str(parts.next().unwrap_or_default());
+
+                for part in parts {
+                    words.push(std::mem::replace(&mut current, part.to_string()));
+                }
             }
+        }
+    }
+
+    words.push(current);
+    words.retain(|word| !word.is_empty());
+    words
+}
+
+/// Fills lines with as many words as fit, building the output in a single buffer and keeping
+/// track of the width of the current line rather than measuring it again for every word.
+fn reflow_paragraph(words: Vec<String>, line_length: usize) -> String {
+    let mut output = String::with_capacity(words.iter().map(|word| word.len() + 1).sum());
+    let mut width = 0;
+
+    for word in words {
+        let word_width = text_width(&word);
+
+        // This odd-looking less than operation is intentional, as we are also taking into account
+        // the space that will be inserted.
+        if width + word_width < line_length {
+            if !output.is_empty() {
+                output.push(' ');
+            }
+
+            width += word_width + 1;
+        } else {
+            if !output.is_empty() {
+                output.push('\n');
+            }
+
+            width = word_width;
+        }
+
+        output.push_str(&word);
+    }
+
+    output
+}
+
+/// Formats text within a paragraph, such as the content of markup. Spaces that must not be broken
+/// at are kept as [`GLUE`], so that they survive the paragraph the text is part of being reflowed.
+fn format_paragraph(input: Vec<ParagraphSegment>, ctx: &FormatCtx) -> String {
+    format_paragraph_at(input, ctx.line_length(), ctx)
+}
+
+/// Formats a paragraph standing on its own to the given line length, turning [`GLUE`] back into
+/// regular spaces.
+fn format_block_paragraph(
+    input: Vec<ParagraphSegment>,
+    line_length: usize,
+    ctx: &FormatCtx,
+) -> String {
+    format_paragraph_at(input, line_length, ctx).replace(GLUE, " ")
+}
+
+/// Formats the text inside an inline construct such as an anchor or an inline link target: runs of
+/// whitespace are collapsed into a single space and whitespace around the text is removed, but the
+/// text is never wrapped, as the construct is kept on a single line.
+fn format_inline_text(input: Vec<ParagraphSegment>, ctx: &FormatCtx) -> String {
+    format_paragraph_at(input, usize::MAX, ctx)
+}
+
+/// Returns the slide (`:`) or indent segment (`::`) marker a paragraph consists of, if any.
+fn segment_marker(content: &[ParagraphSegment]) -> Option<&'static str> {
+    use rust_norg::ParagraphSegmentToken::{Special, Whitespace};
+
+    let colons = content
+        .iter()
+        .filter(|segment| !matches!(segment, ParagraphSegment::Token(Whitespace)))
+        .map(|segment| matches!(segment, ParagraphSegment::Token(Special(':'))).then_some(()))
+        .collect::<Option<Vec<_>>>()?
+        .len();
+
+    match colons {
+        1 => Some(":"),
+        2 => Some("::"),
+        _ => None,
+    }
+}
+
+/// Finds a slide (`:`) or indent segment (`::`) marker ending the content of a detached modifier,
+/// returning its position and the number of colons it is made up of. Colons standing on their own
+/// elsewhere in the text, as in `3 : 4`, are plain punctuation.
+fn find_segment_marker(content: &[ParagraphSegment]) -> Option<(usize, usize)> {
+    use rust_norg::ParagraphSegmentToken::{Special, Whitespace};
+
+    let end = content
+        .iter()
+        .rposition(|segment| !matches!(segment, ParagraphSegment::Token(Whitespace)))?
+        + 1;
+    let colons = content[..end]
+        .iter()
+        .rev()
+        .take_while(|segment| matches!(segment, ParagraphSegment::Token(Special(':'))))
+        .count();
+    let start = end - colons;
+
+    ((1..=2).contains(&colons)
+        && start > 0
+        && matches!(content[start - 1], ParagraphSegment::Token(Whitespace)))
+    .then_some((start, colons))
+}
+
+/// Formats the paragraph making up the content of a detached modifier. A slide or indent segment
+/// marker ending it is kept on a line of its own, so that reflow doesn't absorb it into the text
+/// before it.
+fn format_modifier_paragraph(mut content: Vec<ParagraphSegment>, ctx: &FormatCtx) -> String {
+    let Some((start, colons)) = find_segment_marker(&content) else {
+        return format_block_paragraph(content, ctx.line_length(), ctx);
+    };
+
+    content.truncate(start);
+
+    [
+        format_block_paragraph(content, ctx.line_length(), ctx),
+        ":".repeat(colons),
+    ]
+    .into_iter()
+    .filter(|part| !part.is_empty())
+    .join("\n")
+}
+
+/// Formats a paragraph, reflowing it to the given line length instead of the configured one.
+fn format_paragraph_at(
+    input: Vec<ParagraphSegment>,
+    line_length: usize,
+    ctx: &FormatCtx,
+) -> String {
+    let is_space = |segment: Option<&ParagraphSegment>| {
+        segment.is_none_or(|segment| {
+            matches!(
+                segment,
+                ParagraphSegment::Token(ParagraphSegmentToken::Whitespace)
+            )
         })
-        .fold::<Vec<String>, _>(vec![String::default()], |mut lines, word| {
-            let current_line = lines.last_mut().unwrap();
-            let new_len = word.len();
-
-            // This odd-looking less than operation is intentional, as we are also taking into
-            // account the space that will be inserted.
-            if current_line.len() + new_len < 80 {
-                current_line.push_str(&(" ".to_string() + &word));
-            } else {
-                *current_line = current_line.trim().to_string();
-                lines.push(word.to_string());
+    };
+    // Markup attached to the text around it, as in `anti*bold*ism` or `anti:*bold*:ism`, must stay
+    // on a single line together with it, or it would no longer parse as markup.
+    let bound = (0..input.len())
+        .map(|i| {
+            matches!(input[i], ParagraphSegment::AttachedModifier { .. })
+                && !(is_space(i.checked_sub(1).map(|i| &input[i])) && is_space(input.get(i + 1)))
+        })
+        .collect::<Vec<_>>();
+    let fragments = input
+        .into_iter()
+        .zip(bound)
+        .map(|(segment, bound)| match fragment(segment, ctx) {
+            Fragment::Text(text) if bound => Fragment::Atom(
+                text.split(is_breaking_whitespace)
+                    .filter(|word| !word.is_empty())
+                    .join(&GLUE.to_string()),
+            ),
+            fragment => fragment,
+        })
+        .collect();
+
+    ctx.apply_rule("reflow");
+    ctx.profiled(
+        || "reflow".into(),
+        || reflow_paragraph(words(fragments), line_length),
+    )
+}
+
+/// The position of an extension in the canonical order: status, priority, due date, start date
+/// and finally any other timestamps.
+fn extension_rank(extension: &DetachedModifierExtension) -> u8 {
+    match extension {
+        DetachedModifierExtension::Todo(_) => 0,
+        DetachedModifierExtension::Priority(_) => 1,
+        DetachedModifierExtension::DueDate(_) => 2,
+        DetachedModifierExtension::StartDate(_) => 3,
+        DetachedModifierExtension::Timestamp(_) => 4,
+    }
+}
+
+fn format_extension(extension: DetachedModifierExtension) -> String {
+    match extension {
+        DetachedModifierExtension::Todo(status) => match status {
+            TodoStatus::Undone => " ".to_string(),
+            TodoStatus::Done => "x".to_string(),
+            TodoStatus::NeedsClarification => "?".to_string(),
+            TodoStatus::Paused => "=".to_string(),
+            TodoStatus::Urgent => "!".to_string(),
+            TodoStatus::Recurring(None) => "+".to_string(),
+            TodoStatus::Recurring(Some(recurrence)) => format!("+ {}", recurrence.trim()),
+            TodoStatus::Pending => "-".to_string(),
+            TodoStatus::Canceled => "_".to_string(),
+        },
+        DetachedModifierExtension::Priority(priority) => format!("# {}", priority.trim()),
+        DetachedModifierExtension::DueDate(date) => format!("< {}", date.trim()),
+        DetachedModifierExtension::StartDate(date) => format!("> {}", date.trim()),
+        DetachedModifierExtension::Timestamp(timestamp) => format!("@ {}", timestamp.trim()),
+    }
+}
+
+/// Formats the extensions of a detached modifier in their canonical order, including the space
+/// that separates them from the rest of the line. Returns an empty string if there are none.
+pub fn format_extensions(mut extensions: Vec<DetachedModifierExtension>) -> String {
+    if extensions.is_empty() {
+        return String::new();
+    }
+
+    extensions.sort_by_key(extension_rank);
+
+    format!(
+        "({}) ",
+        extensions.into_iter().map(format_extension).join("|")
+    )
+}
+
+/// Formats the extensions of a detached modifier like [`format_extensions`], recording whether
+/// they had to be reordered.
+fn format_ordered_extensions(
+    extensions: Vec<DetachedModifierExtension>,
+    ctx: &FormatCtx,
+) -> String {
+    if extensions
+        .windows(2)
+        .any(|pair| extension_rank(&pair[0]) > extension_rank(&pair[1]))
+    {
+        ctx.apply_rule("extension-ordering");
+    }
+
+    format_extensions(extensions)
+}
+
+fn is_single_line_definition(node: &NorgASTFlat) -> bool {
+    matches!(
+        node,
+        NorgASTFlat::RangeableDetachedModifier {
+            modifier_type: rust_norg::RangeableDetachedModifier::Definition,
+            content,
+            ..
+        } if content.len() == 1 && matches!(content[0], NorgASTFlat::Paragraph(_))
+    )
+}
+
+/// Formats a group of consecutive single-line definitions, aligning the content of every
+/// definition to a common column so that the group reads like a table.
+fn format_aligned_definitions(definitions: Vec<NorgASTFlat>, ctx: &FormatCtx) -> String {
+    let definitions = definitions
+        .into_iter()
+        .filter_map(|definition| match definition {
+            NorgASTFlat::RangeableDetachedModifier {
+                title,
+                mut content,
+                extensions,
+                ..
+            } => {
+                let title = format_ordered_extensions(extensions, ctx)
+                    + &title.into_iter().map_into::<String>().collect::<String>();
+
+                match content.pop() {
+                    Some(NorgASTFlat::Paragraph(content)) => Some((title, content)),
+                    _ => None,
+                }
             }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    // Past half of the line length the alignment would squash the content too much to be useful.
+    let column = definitions
+        .iter()
+        .map(|(title, _)| "$ ".len() + text_width(title) + 1)
+        .max()
+        .unwrap_or_default()
+        .min(ctx.line_length() / 2);
+    let indent = Indent {
+        levels: 0,
+        align: column,
+    };
 
-            lines
+    definitions
+        .into_iter()
+        .map(|(title, content)| {
+            let content = format_block_paragraph(content, ctx.line_length() - column, ctx);
+
+            format!(
+                "$ {title}\n{}{}\n",
+                indent.render(ctx.config),
+                indent.apply(&content, ctx.config)
+            )
         })
-        .join("\n")
-        .trim()
-        .to_string()
+        .collect()
 }
 
-fn format_paragraph(input: Vec<ParagraphSegment>) -> String {
-    reflow_paragraph(input.into_iter().map(format_paragraph_segment).collect())
+/// Formats the body of a `|comment` ranged tag.
+///
+/// Comment paragraphs are only rewrapped to the line length when comment wrapping is enabled.
+/// Otherwise each paragraph is emitted as-is on its own line.
+fn format_comment_body(content: Vec<NorgASTFlat>, ctx: &FormatCtx) -> String {
+    let nested = ctx.nested(NodeKind::RangedTag);
+
+    if ctx.config.wrap_comments {
+        return format_nested(content, &nested);
+    }
+
+    ctx.apply_rule("comment-preservation");
+
+    content
+        .into_iter()
+        .map(|node| match node {
+            NorgASTFlat::Paragraph(content) => {
+                let content = content
+                    .into_iter()
+                    .map(|segment| format_paragraph_segment(segment, ctx))
+                    .collect::<String>();
+
+                content
+                    .trim_matches(is_breaking_whitespace)
+                    .replace(GLUE, " ")
+                    + "\n"
+            }
+            node => format_nested(vec![node], &nested),
+        })
+        .collect()
 }
 
-pub fn format() -> impl Parser<NorgASTFlat, Vec<String>, Error = chumsky::error::Simple<NorgASTFlat>>
-{
+/// The kind of a block as far as the blank line policy is concerned.
+#[derive(Clone, PartialEq)]
+enum BlockKind {
+    Paragraph,
+    /// A paragraph made up of just a slide (`:`) or indent segment (`::`) marker.
+    SegmentMarker,
+    Heading,
+    /// A list or quote, identified by its modifier character.
+    Nestable(String),
+    Other,
+}
+
+fn block_kind(node: &NorgASTFlat) -> BlockKind {
+    match node {
+        NorgASTFlat::Paragraph(content) if segment_marker(content).is_some() => {
+            BlockKind::SegmentMarker
+        }
+        NorgASTFlat::Paragraph(_) => BlockKind::Paragraph,
+        NorgASTFlat::Heading { .. } => BlockKind::Heading,
+        NorgASTFlat::NestableDetachedModifier { modifier_type, .. } => {
+            BlockKind::Nestable(modifier_type.to_string())
+        }
+        _ => BlockKind::Other,
+    }
+}
+
+/// Whether a block of the given kind is separated by a blank line from the sibling block before it:
+/// a paragraph and a following list or quote are, as are lists and quotes of different types, if
+/// the configuration requires it. A paragraph is always separated from a preceding block other
+/// than a heading, as it would otherwise be read as a continuation of that block. Slide and indent
+/// segment markers are never separated from the blocks around them, as that would detach them from
+/// their modifier.
+fn needs_blank_line(previous: Option<&BlockKind>, current: &BlockKind, config: &Config) -> bool {
+    match (previous, current) {
+        (Some(BlockKind::SegmentMarker), _) | (_, BlockKind::SegmentMarker) => false,
+        (Some(previous), BlockKind::Paragraph) => previous != &BlockKind::Heading,
+        (Some(BlockKind::Paragraph), BlockKind::Nestable(_)) => config.blank_line_before_lists,
+        (Some(BlockKind::Nestable(previous)), BlockKind::Nestable(current)) => {
+            config.blank_line_between_lists && previous != current
+        }
+        _ => false,
+    }
+}
+
+/// Whether `node` is separated by a blank line from the sibling node before it, see
+/// [`needs_blank_line`].
+pub(crate) fn is_separated(previous: &NorgASTFlat, node: &NorgASTFlat, config: &Config) -> bool {
+    needs_blank_line(Some(&block_kind(previous)), &block_kind(node), config)
+}
+
+/// Inserts blank lines between sibling blocks where they are needed, see [`needs_blank_line`].
+fn apply_blank_line_policy(blocks: Vec<(BlockKind, String)>, ctx: &FormatCtx) -> Vec<String> {
+    let mut output = Vec::with_capacity(blocks.len());
+    let mut previous = None;
+
+    for (kind, block) in blocks {
+        if needs_blank_line(previous.as_ref(), &kind, ctx.config) {
+            ctx.apply_rule("blank-line-policy");
+            output.push("\n".to_string());
+        }
+
+        output.push(block);
+        previous = Some(kind);
+    }
+
+    output
+}
+
+fn is_override_attribute(tag_type: &rust_norg::CarryoverTag, name: &[String]) -> bool {
+    matches!(tag_type, rust_norg::CarryoverTag::Attribute) && name == [overrides::ATTRIBUTE]
+}
+
+/// Applies the overrides of a `+norgfmt` attribute to the configuration, reporting them if they
+/// are invalid.
+fn override_config(parameters: &[String], ctx: &FormatCtx) -> Option<Config> {
+    overrides::apply(ctx.config, parameters)
+        .map_err(|err| {
+            ctx.diagnose(format!(
+                "ignoring `+{} {}`: {err}",
+                overrides::ATTRIBUTE,
+                parameters.join(" ")
+            ))
+        })
+        .ok()
+}
+
+/// The level of a heading, looking through any carryover tags attached to it.
+fn heading_level(node: &NorgASTFlat) -> Option<usize> {
+    match node {
+        NorgASTFlat::Heading { level, .. } => Some((*level).into()),
+        NorgASTFlat::CarryoverTag { next_object, .. } => heading_level(next_object),
+        _ => None,
+    }
+}
+
+/// The level of the heading a `+norgfmt` attribute is attached to, if it is attached to one.
+fn overridden_heading_level(node: &NorgASTFlat) -> Option<usize> {
+    match node {
+        NorgASTFlat::CarryoverTag {
+            tag_type,
+            name,
+            next_object,
+            ..
+        } if is_override_attribute(tag_type, name) => heading_level(next_object),
+        _ => None,
+    }
+}
+
+/// The level of the heading starting a section that is formatted as a whole, if the node is one:
+/// a heading with a `+norgfmt` attribute, or any heading if the content of headings is indented or
+/// separated from them.
+fn section_level(node: &NorgASTFlat, config: &Config) -> Option<usize> {
+    heading_level(node)
+        .filter(|_| config.indent_headings || config.newline_after_headings)
+        .or_else(|| overridden_heading_level(node))
+}
+
+/// Formats a heading together with the rest of its section, all with the configuration overridden
+/// by a `+norgfmt` attribute on the heading, if any. The content of the section, subsections
+/// included, is separated from the heading by a blank line with `newline_after_headings` and
+/// indented by an indent unit with `indent_headings`, so that every level of the hierarchy is
+/// indented by one more unit.
+fn format_section(
+    (index, heading): (usize, NorgASTFlat),
+    section: Vec<Group>,
+    ctx: &FormatCtx,
+) -> String {
+    // The attribute reports invalid overrides itself when its heading is formatted.
+    let config = match &heading {
+        NorgASTFlat::CarryoverTag { parameters, .. } => {
+            overrides::apply(ctx.config, parameters).ok()
+        }
+        _ => None,
+    };
+    let ctx = &config
+        .as_ref()
+        .map_or_else(|| ctx.clone(), |config| ctx.with_config(config));
+    let heading = format_block(index, heading, ctx);
+
+    if section.is_empty() {
+        return heading;
+    }
+
+    let separator = if ctx.config.newline_after_headings {
+        "\n"
+    } else {
+        ""
+    };
+
+    if !ctx.config.indent_headings {
+        return heading + separator + &format_groups(section, ctx).concat();
+    }
+
+    let indent = Indent {
+        levels: 1,
+        align: 0,
+    };
+    let content = format_groups(section, &ctx.indented(indent.width(ctx.config))).concat();
+
+    ctx.apply_rule("indentation");
+
+    heading + separator + &indent.render(ctx.config) + &indent.apply(&content, ctx.config)
+}
+
+/// Formats a single node with its built-in formatter, regardless of the formatters registered in
+/// the context's [`Registry`](crate::registry::Registry).
+pub fn format_node(node: NorgASTFlat, ctx: &FormatCtx) -> String {
     use NorgASTFlat::*;
 
-    let formatter = select! {
-        // TODO: Format attached modifier extensions.
-        // TODO: Find way to appropriately propagate error messages.
-        Heading { level, title, extensions: _ } => {
-            format!("{} {}\n", "*".repeat(level.into()), title.into_iter().map_into::<String>().collect::<String>())
-        },
-        NestableDetachedModifier { modifier_type, level, content, extensions: _ } => {
-            let content = format().parse(vec![*content]).unwrap().join("").replace("\n", &format!("\n{}", " ".repeat(level as usize + 1)));
+    match node {
+        Heading {
+            level,
+            title,
+            extensions,
+        } => {
+            let heading = format!(
+                "{} {}{}",
+                "*".repeat(level.into()),
+                format_ordered_extensions(extensions, ctx),
+                title.into_iter().map_into::<String>().collect::<String>()
+            );
+            // A trailing annotation, such as a fold marker, is kept as written and doesn't count
+            // towards the length of the heading.
+            let (heading, annotation) =
+                match split_heading_annotation(&heading, &ctx.config.heading_annotation_markers) {
+                    Some((title, annotation)) => {
+                        let annotation = ctx
+                            .take_heading_annotation(&slug::normalize(title))
+                            .unwrap_or_else(|| annotation.to_string());
 
-            format!("{} {content}", modifier_type.to_string().repeat(level.into()))
-        },
-        RangeableDetachedModifier { modifier_type, title, content, extensions: _ } => {
+                        (title.to_string(), annotation)
+                    }
+                    None => (heading, String::new()),
+                };
+
+            // Headings are never wrapped, so an overly long one can only be pointed out.
+            let headings = ctx.config.construct_line_length.get(&Construct::Headings);
+
+            if let Some(line_length) = headings.filter(|&&length| text_width(&heading) > length) {
+                ctx.diagnose(format!(
+                    "the heading `{heading}` is longer than {line_length} columns"
+                ));
+            }
+
+            heading + &annotation + "\n"
+        }
+        NestableDetachedModifier {
+            modifier_type,
+            level,
+            content,
+            extensions,
+        } => {
+            let construct = match modifier_type {
+                rust_norg::NestableDetachedModifier::Quote => Construct::Quotes,
+                _ => Construct::Lists,
+            };
+            let prefix = format!(
+                "{} {}",
+                modifier_type.to_string().repeat(level.into()),
+                format_ordered_extensions(extensions, ctx)
+            );
+
+            let indent = match ctx.config.continuation_indent {
+                ContinuationIndent::Text => Indent {
+                    levels: 0,
+                    align: level as usize + 1,
+                },
+                ContinuationIndent::Marker => Indent {
+                    levels: 1,
+                    align: 0,
+                },
+            };
+
+            // The content is wrapped to fit both next to the prefix on the first line and after
+            // the continuation indent on the others.
+            let nested = ctx
+                .within(construct)
+                .indented(prefix.width().max(indent.width(ctx.config)))
+                .nested(NodeKind::NestableDetachedModifier);
+            let content = format_nested(vec![*content], &nested);
+
+            if content.trim_end_matches('\n').contains('\n') {
+                ctx.apply_rule("indentation");
+            }
+
+            // Only indent the lines *inside* the content, otherwise the trailing newline drags the
+            // next block (e.g. a comment preceding it) into this one.
+            let content = indent.apply(content.trim_end_matches('\n'), ctx.config);
+
+            format!("{prefix}{content}\n")
+        }
+        RangeableDetachedModifier {
+            modifier_type,
+            title,
+            content,
+            extensions,
+        } => {
             let is_single_line = content.len() == 1 && matches!(content[0], Paragraph(_));
+            let extensions = format_ordered_extensions(extensions, ctx);
+            let content = format_nested(content, &ctx.nested(NodeKind::RangeableDetachedModifier));
 
             if is_single_line {
-                format!("{modifier_type} {}\n{}", title.into_iter().map_into::<String>().collect::<String>(), format().parse(content).unwrap().join(""))
+                format!(
+                    "{modifier_type} {extensions}{}\n{}",
+                    title.into_iter().map_into::<String>().collect::<String>(),
+                    content
+                )
             } else {
-                format!("{modifier_type}{modifier_type} {}\n{}\n$$\n", title.into_iter().map_into::<String>().collect::<String>(), format().parse(content).unwrap().join(""))
+                format!(
+                    "{modifier_type}{modifier_type} {extensions}{}\n{}\n$$\n",
+                    title.into_iter().map_into::<String>().collect::<String>(),
+                    content
+                )
             }
-        },
-        CarryoverTag { tag_type, name, parameters, next_object } =>  {
+        }
+        CarryoverTag {
+            tag_type,
+            name,
+            parameters,
+            next_object,
+        } => {
+            let scoped_config = is_override_attribute(&tag_type, &name)
+                .then(|| override_config(&parameters, ctx))
+                .flatten();
+            let ctx = &scoped_config
+                .as_ref()
+                .map_or_else(|| ctx.clone(), |config| ctx.with_config(config));
             let tag_type = match tag_type {
                 rust_norg::CarryoverTag::Attribute => "+",
                 rust_norg::CarryoverTag::Macro => "#",
             };
             let name = name.join(".");
             let parameters = parameters.join(" ");
-            let next_object = format().parse(vec![*next_object]).unwrap().join("");
+            let next_object = tag_content(&name, ctx, || {
+                format_nested(vec![*next_object], &ctx.nested(NodeKind::CarryoverTag))
+            });
 
             format!("{tag_type}{name} {parameters}\n{next_object}")
-        },
+        }
         InfirmTag { name, parameters } => {
             let name = name.join(".");
             let parameters = parameters.join(" ");
 
             format!(".{name} {parameters}")
-        },
-        VerbatimRangedTag { name, parameters, content } => {
+        }
+        VerbatimRangedTag {
+            name,
+            parameters,
+            content,
+        } => {
             let name = name.join(".");
+            let content = verbatim_content(&name, &parameters, content, ctx);
             let parameters = parameters.join(" ");
 
             // TODO: Make `content` respect indentation
             format!("@{name} {parameters}\n{content}@end\n")
-        },
-        RangedTag { name, parameters, content } => {
+        }
+        RangedTag {
+            name,
+            parameters,
+            content,
+        } => {
             let name = name.join(".");
             let parameters = parameters.join(" ");
-            let content = format().parse(content).unwrap().join("");
+            let content = tag_content(&name, ctx, || {
+                if name == "comment" {
+                    format_comment_body(content, ctx)
+                } else {
+                    format_nested(content, &ctx.nested(NodeKind::RangedTag))
+                }
+            });
 
             format!("|{name} {parameters}\n{content}|end\n")
-        },
-        Paragraph(content) => format_paragraph(content) + "\n",
+        }
+        Paragraph(content)
+            if matches!(
+                ctx.parent,
+                Some(NodeKind::NestableDetachedModifier | NodeKind::RangeableDetachedModifier)
+            ) =>
+        {
+            format_modifier_paragraph(content, ctx) + "\n"
+        }
+        Paragraph(content) => format_block_paragraph(content, ctx.line_length(), ctx) + "\n",
+    }
+}
+
+/// Formats nodes nested in another node. Should the nodes fail to format as a whole, e.g. because
+/// of a node the grammar doesn't expect there, the failure is reported as a diagnostic and the
+/// nodes are formatted one at a time instead, so that nothing is lost.
+fn format_nested(nodes: Vec<NorgASTFlat>, ctx: &FormatCtx) -> String {
+    match format_nodes(nodes.clone(), ctx) {
+        Ok(blocks) => blocks.concat(),
+        Err(_) => {
+            ctx.diagnose(format!(
+                "unable to format the content of a {}, formatting its nodes one at a time",
+                ctx.parent
+                    .map_or("document".to_string(), |kind| kind.to_string())
+            ));
+
+            nodes
+                .into_iter()
+                .map(|node| ctx.registry.format(node, ctx))
+                .collect()
+        }
+    }
+}
+
+/// Whether a node is a paragraph, list item or quote that keeps the line breaks it was written
+/// with, either because of the configuration or because of a `+norgfmt` attribute attached to it.
+fn preserves_line_breaks(node: &NorgASTFlat, config: &Config) -> bool {
+    match node {
+        NorgASTFlat::Paragraph(_) | NorgASTFlat::NestableDetachedModifier { .. } => {
+            config.preserve_line_breaks
+        }
+        NorgASTFlat::CarryoverTag {
+            tag_type,
+            name,
+            parameters,
+            next_object,
+        } if is_override_attribute(tag_type, name) => overrides::apply(config, parameters)
+            .is_ok_and(|config| preserves_line_breaks(next_object, &config)),
+        _ => false,
+    }
+}
+
+/// Formats the node at `index` among its siblings with the formatter registered for its kind,
+/// unless it is kept as it was written: because it couldn't be formatted, or because its line
+/// breaks are preserved.
+fn format_block(index: usize, node: NorgASTFlat, ctx: &FormatCtx) -> String {
+    let keep = ctx.is_kept(index) || preserves_line_breaks(&node, ctx.config);
+
+    match ctx.written(index).filter(|_| keep) {
+        Some(written) => written,
+        None => ctx.registry.format(node, ctx),
+    }
+}
+
+/// Nodes grouped to be formatted together, each along with its position among its siblings, which
+/// identifies the top-level nodes of a document even if some of them are equal.
+enum Group {
+    /// A heading along with the rest of its section, grouped itself.
+    Section((usize, NorgASTFlat), Vec<Group>),
+    /// Consecutive single-line definitions, which may be aligned.
+    Definitions(Vec<(usize, NorgASTFlat)>),
+    Node((usize, NorgASTFlat)),
+}
+
+type GroupParser = BoxedParser<'static, NorgASTFlat, Vec<Group>, Simple<NorgASTFlat>>;
+
+/// Groups the nodes within a section of the given level, or of a whole document for level 0. A
+/// section ends at the next heading of the same or a higher level.
+fn groups(level: usize) -> GroupParser {
+    fn indexed(node: NorgASTFlat, span: Range<usize>) -> (usize, NorgASTFlat) {
+        (span.start, node)
+    }
+
+    let section = |level| {
+        filter(move |node: &NorgASTFlat| heading_level(node) == Some(level))
+            .map_with_span(indexed)
+            .then(groups(level))
+            .map(|(heading, section)| Group::Section(heading, section))
     };
+    let definitions = filter(is_single_line_definition)
+        .map_with_span(indexed)
+        .repeated()
+        .at_least(2)
+        .map(Group::Definitions);
+    let node =
+        filter(move |node: &NorgASTFlat| !heading_level(node).is_some_and(|other| other <= level))
+            .map_with_span(indexed)
+            .map(Group::Node);
+
+    (level + 1..=6)
+        .fold(definitions.or(node).boxed(), |groups, level| {
+            section(level).or(groups).boxed()
+        })
+        .repeated()
+        .boxed()
+}
+
+thread_local! {
+    /// The parser grouping the nodes of a document. Grouping doesn't depend on how the nodes are
+    /// formatted, so the parser is built only once per thread.
+    static GROUPS: GroupParser = groups(0).then_ignore(end()).boxed();
+}
+
+/// Formats grouped nodes, inserting blank lines between them where the configuration requires them.
+fn format_groups(groups: Vec<Group>, ctx: &FormatCtx) -> Vec<String> {
+    fn push(groups: Vec<Group>, ctx: &FormatCtx, blocks: &mut Vec<(BlockKind, String)>) {
+        for group in groups {
+            match group {
+                Group::Section(heading, section)
+                    if section_level(&heading.1, ctx.config).is_some() =>
+                {
+                    let kind = block_kind(&heading.1);
+
+                    blocks.push((kind, format_section(heading, section, ctx)));
+                }
+                // Nothing applies to the section as a whole, so its nodes are formatted in turn.
+                Group::Section((index, heading), section) => {
+                    blocks.push((block_kind(&heading), format_block(index, heading, ctx)));
+                    push(section, ctx, blocks);
+                }
+                // Aligned definitions are formatted as a group, which would bypass a registered
+                // formatter.
+                Group::Definitions(definitions)
+                    if ctx.config.align_definitions
+                        && !ctx.registry.overrides(NodeKind::RangeableDetachedModifier) =>
+                {
+                    // Definitions kept as written split the group.
+                    for (kept, definitions) in &definitions
+                        .into_iter()
+                        .group_by(|(index, _)| ctx.is_kept(*index))
+                    {
+                        let definitions = definitions.collect::<Vec<_>>();
+
+                        if kept || definitions.len() < 2 {
+                            push(
+                                definitions.into_iter().map(Group::Node).collect(),
+                                ctx,
+                                blocks,
+                            );
+                        } else {
+                            let kind = block_kind(&definitions[0].1);
+                            let definitions = definitions.into_iter().map(|(_, node)| node);
+
+                            ctx.apply_rule("definition-alignment");
+
+                            let block = ctx.profiled(
+                                || "aligned-definitions".into(),
+                                || format_aligned_definitions(definitions.collect(), ctx),
+                            );
+
+                            blocks.push((kind, block));
+                        }
+                    }
+                }
+                Group::Definitions(definitions) => {
+                    push(
+                        definitions.into_iter().map(Group::Node).collect(),
+                        ctx,
+                        blocks,
+                    );
+                }
+                Group::Node((index, node)) => {
+                    blocks.push((block_kind(&node), format_block(index, node, ctx)));
+                }
+            }
+        }
+    }
+
+    let mut blocks = Vec::new();
+
+    push(groups, ctx, &mut blocks);
+    apply_blank_line_policy(blocks, ctx)
+}
+
+/// Formats a sequence of sibling nodes, such as the top-level nodes of a document, into blocks.
+/// Failures are returned as errors pointing at the nodes they occurred at.
+#[allow(clippy::result_large_err)]
+pub fn format_nodes(
+    nodes: Vec<NorgASTFlat>,
+    ctx: &FormatCtx,
+) -> Result<Vec<String>, Vec<Simple<NorgASTFlat>>> {
+    let groups = GROUPS.with(|parser| parser.parse(nodes))?;
+
+    Ok(format_groups(groups, ctx))
+}
+
+/// The attached modifiers the formatter knows how to write back.
+const ATTACHED_MODIFIERS: [char; 11] = ['*', '/', '_', '-', '!', '^', ',', '`', '%', '$', '&'];
+
+/// Describes the first construct within a node that the formatter doesn't know how to write back,
+/// such as markup added to the grammar after the formatter was written, if there is one.
+fn unsupported_construct(node: &NorgASTFlat) -> Option<String> {
+    fn in_segments(segments: &[ParagraphSegment]) -> Option<String> {
+        use ParagraphSegment::*;
+
+        segments.iter().find_map(|segment| match segment {
+            AttachedModifier { modifier_type, .. }
+            | AttachedModifierCandidate { modifier_type, .. }
+                if !ATTACHED_MODIFIERS.contains(modifier_type) =>
+            {
+                Some(format!("`{modifier_type}` markup"))
+            }
+            AttachedModifier { content, .. }
+            | AttachedModifierCandidate { content, .. }
+            | InlineLinkTarget(content)
+            | AnchorDefinition { content, .. } => in_segments(content),
+            Link {
+                description: Some(description),
+                ..
+            } => in_segments(description),
+            Anchor {
+                content,
+                description,
+            } => in_segments(content).or_else(|| in_segments(description.as_deref()?)),
+            _ => None,
+        })
+    }
+
+    match node {
+        NorgASTFlat::Paragraph(content) => in_segments(content),
+        NorgASTFlat::Heading { title, .. } => in_segments(title),
+        NorgASTFlat::NestableDetachedModifier { content, .. } => unsupported_construct(content),
+        NorgASTFlat::RangeableDetachedModifier { title, content, .. } => {
+            in_segments(title).or_else(|| content.iter().find_map(unsupported_construct))
+        }
+        NorgASTFlat::RangedTag { content, .. } => content.iter().find_map(unsupported_construct),
+        NorgASTFlat::CarryoverTag { next_object, .. } => unsupported_construct(next_object),
+        NorgASTFlat::VerbatimRangedTag { .. } | NorgASTFlat::InfirmTag { .. } => None,
+    }
+}
+
+/// Formats a whole document into its top-level blocks.
+///
+/// Nodes containing constructs the formatter doesn't support are reported as diagnostics and kept
+/// exactly as they were written, which requires the context to know the source, while the nodes
+/// around them are formatted as usual.
+pub fn format_document(ast: Vec<NorgASTFlat>, ctx: &FormatCtx) -> Result<Vec<String>> {
+    let ctx = &ctx.with_document(ast.clone());
+
+    for (index, node) in ast.iter().enumerate() {
+        let Some(construct) = unsupported_construct(node) else {
+            continue;
+        };
+        let kind = NodeKind::of(node);
+
+        if ctx.written(index).is_some() {
+            ctx.diagnose(format!(
+                "unsupported construct ({construct}) in the {kind} at block #{index}, keeping it \
+                 as written; the grammar (rust-norg {}) may be newer than norg-fmt",
+                grammar::VERSION
+            ));
+            ctx.keep_as_written(index);
+        } else {
+            ctx.diagnose(format!(
+                "unsupported construct ({construct}) in the {kind} at block #{index}, \
+                 formatting it as well as possible as its source is unknown; the grammar \
+                 (rust-norg {}) may be newer than norg-fmt",
+                grammar::VERSION
+            ));
+        }
+    }
 
-    formatter.repeated().at_least(1)
+    // Every node is grouped into a block at the top level, so none of them can be left out.
+    format_nodes(ast, ctx).map_err(|_| eyre!("unable to format the document"))
 }
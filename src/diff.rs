@@ -0,0 +1,360 @@
+use std::{collections::BTreeMap, path::Path};
+
+use serde::Serialize;
+use similar::{udiff::UnifiedDiffHunk, ChangeTag, DiffOp, DiffTag, TextDiff};
+
+use crate::{encoding::Encoding, term::Style};
+
+/// Renders a unified diff between the original and the formatted content of a file.
+pub fn unified_diff(file: &Path, original: &str, formatted: &str, style: Style) -> String {
+    let diff = TextDiff::from_lines(original, formatted);
+    let path = file.display();
+    let mut output = style.bold(&format!("--- {path}\n+++ {path}\n"));
+
+    for hunk in diff.unified_diff().context_radius(3).iter_hunks() {
+        output.push_str(&render_hunk(&hunk, style));
+    }
+
+    output
+}
+
+fn render_hunk(hunk: &UnifiedDiffHunk<'_, '_, '_, '_, str>, style: Style) -> String {
+    let mut output = style.cyan(&hunk.header().to_string());
+    output.push('\n');
+
+    for change in hunk.iter_changes() {
+        let line = format!("{}{}", change.tag(), change.value());
+        let line = match change.tag() {
+            ChangeTag::Delete => style.red(&line),
+            ChangeTag::Insert => style.green(&line),
+            ChangeTag::Equal => line,
+        };
+
+        output.push_str(&line);
+
+        if change.missing_newline() {
+            output.push_str("\n\\ No newline at end of file\n");
+        }
+    }
+
+    output
+}
+
+/// The replacement of a range of bytes of the original content.
+#[derive(Serialize)]
+pub struct Edit {
+    pub start: usize,
+    pub end: usize,
+    pub replacement: String,
+}
+
+/// The byte offset of the start of every line, followed by the length of the content.
+fn line_offsets(lines: &[&str]) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(lines.iter().scan(0, |offset, line| {
+            *offset += line.len();
+
+            Some(*offset)
+        }))
+        .collect()
+}
+
+/// The edit making the change of `op`, given the offsets of the original lines and the formatted
+/// lines.
+fn edit(op: &DiffOp, offsets: &[usize], new_lines: &[&str]) -> Edit {
+    Edit {
+        start: offsets[op.old_range().start],
+        end: offsets[op.old_range().end],
+        replacement: new_lines[op.new_range()].concat(),
+    }
+}
+
+/// Determines the line-based edits turning the original content of a file into the formatted one,
+/// so that editors can apply them without replacing the whole document. Adjacent changes are
+/// merged into a single edit.
+///
+/// The offsets are those of the file the original content was decoded from, i.e. in bytes of the
+/// given encoding and counting its byte order mark.
+pub fn edits(original: &str, formatted: &str, encoding: Encoding) -> Vec<Edit> {
+    let diff = TextDiff::from_lines(original, formatted);
+    let offsets = line_offsets(diff.old_slices());
+    let mut edits = Vec::<Edit>::new();
+
+    for op in diff.ops() {
+        if op.tag() == DiffTag::Equal {
+            continue;
+        }
+
+        let Edit {
+            start,
+            end,
+            replacement,
+        } = edit(op, &offsets, diff.new_slices());
+
+        match edits.last_mut() {
+            Some(edit) if edit.end == start => {
+                edit.end = end;
+                edit.replacement.push_str(&replacement);
+            }
+            _ => edits.push(Edit {
+                start,
+                end,
+                replacement,
+            }),
+        }
+    }
+
+    // The edits are in order, so the original is only encoded once while their offsets are mapped.
+    let mut decoded = 0;
+    let mut encoded = encoding.bom().len();
+    let mut map = |offset: usize| {
+        encoded += encoding.encoded_len(&original[decoded..offset]);
+        decoded = offset;
+
+        encoded
+    };
+
+    for edit in &mut edits {
+        edit.start = map(edit.start);
+        edit.end = map(edit.end);
+    }
+
+    edits
+}
+
+/// Renders the edits turning the original content of a file into the formatted one as a line of
+/// JSON: `{"file": ..., "edits": [{"start": ..., "end": ..., "replacement": ...}]}`, with byte
+/// offsets into the file as it is stored in `encoding` (see [`edits`]).
+pub fn patch(file: &Path, original: &str, formatted: &str, encoding: Encoding) -> String {
+    serde_json::json!({
+        "file": file.display().to_string(),
+        "edits": edits(original, formatted, encoding),
+    })
+    .to_string()
+        + "\n"
+}
+
+/// A group of changes close enough to each other to be shown together, as in a unified diff.
+pub struct Hunk {
+    /// The hunk as part of a unified diff, starting with its `@@` header.
+    pub diff: String,
+    edits: Vec<Edit>,
+}
+
+/// Splits the changes turning the original content of a file into the formatted one into hunks,
+/// so that they can be accepted or declined one by one.
+pub fn hunks(original: &str, formatted: &str, style: Style) -> Vec<Hunk> {
+    let diff = TextDiff::from_lines(original, formatted);
+    let offsets = line_offsets(diff.old_slices());
+
+    diff.unified_diff()
+        .context_radius(3)
+        .iter_hunks()
+        .map(|hunk| Hunk {
+            diff: render_hunk(&hunk, style),
+            edits: hunk
+                .ops()
+                .iter()
+                .filter(|op| op.tag() != DiffTag::Equal)
+                .map(|op| edit(op, &offsets, diff.new_slices()))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Applies the changes of the given hunks, in order, to the original content of a file, which is
+/// kept as it was everywhere else.
+pub fn apply<'a>(original: &str, hunks: impl IntoIterator<Item = &'a Hunk>) -> String {
+    let mut output = String::with_capacity(original.len());
+    let mut position = 0;
+
+    for edit in hunks.into_iter().flat_map(|hunk| &hunk.edits) {
+        output += &original[position..edit.start];
+        output += &edit.replacement;
+        position = edit.end;
+    }
+
+    output + &original[position..]
+}
+
+/// What a change between the original and the formatted content of a file amounts to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Category {
+    /// The same words, broken into lines differently.
+    Reflow,
+    /// The same lines, indented differently.
+    Indentation,
+    /// The same lines, spaced differently, or blank lines added or removed.
+    Whitespace,
+    /// Anything else, e.g. changed markers, escapes or link targets.
+    Content,
+}
+
+impl std::fmt::Display for Category {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Category::Reflow => "reflow",
+            Category::Indentation => "indentation",
+            Category::Whitespace => "whitespace",
+            Category::Content => "content",
+        })
+    }
+}
+
+/// How much formatting a file would change it.
+#[derive(Default)]
+pub struct Stats {
+    /// The number of lines removed, replaced or added.
+    pub lines: usize,
+    /// The number of bytes removed and added.
+    pub bytes: usize,
+    /// The number of changed lines, by the category of the change they are part of.
+    pub categories: BTreeMap<Category, usize>,
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} line(s), {} byte(s)", self.lines, self.bytes)?;
+
+        if !self.categories.is_empty() {
+            let categories = self
+                .categories
+                .iter()
+                .map(|(category, lines)| format!("{category} {lines}"))
+                .collect::<Vec<_>>();
+
+            write!(f, " ({})", categories.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Determines the category of a change replacing the `old` lines with the `new` ones.
+fn categorize(old: &[&str], new: &[&str]) -> Category {
+    let words = |lines: &[&str]| {
+        lines
+            .iter()
+            .flat_map(|line| line.split_whitespace())
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+
+    if words(old) != words(new) {
+        return Category::Content;
+    }
+
+    let non_blank = |lines: &[&str]| {
+        lines
+            .iter()
+            .map(|line| line.split_whitespace().collect::<Vec<_>>())
+            .filter(|words| !words.is_empty())
+            .collect::<Vec<_>>()
+    };
+
+    if non_blank(old) != non_blank(new) {
+        Category::Reflow
+    } else if old.len() == new.len()
+        && old
+            .iter()
+            .zip(new)
+            .all(|(old, new)| old.trim_start() == new.trim_start())
+    {
+        Category::Indentation
+    } else {
+        Category::Whitespace
+    }
+}
+
+/// Measures the changes turning the original content of a file into the formatted one.
+pub fn stats(original: &str, formatted: &str) -> Stats {
+    let diff = TextDiff::from_lines(original, formatted);
+    let mut stats = Stats::default();
+
+    for op in diff.ops() {
+        if op.tag() == DiffTag::Equal {
+            continue;
+        }
+
+        let old = &diff.old_slices()[op.old_range()];
+        let new = &diff.new_slices()[op.new_range()];
+        let lines = old.len().max(new.len());
+
+        stats.lines += lines;
+        stats.bytes += old.concat().len() + new.concat().len();
+        *stats.categories.entry(categorize(old, new)).or_default() += lines;
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::ColorChoice;
+
+    fn offsets(original: &str, formatted: &str, encoding: Encoding) -> Vec<(usize, usize, String)> {
+        edits(original, formatted, encoding)
+            .into_iter()
+            .map(|edit| (edit.start, edit.end, edit.replacement))
+            .collect()
+    }
+
+    #[test]
+    fn edits_replace_changed_lines() {
+        assert_eq!(
+            offsets("a\nb\nc\nd\n", "a\nB\nc\nD\n", Encoding::Utf8),
+            [(2, 4, "B\n".to_string()), (6, 8, "D\n".to_string())]
+        );
+    }
+
+    #[test]
+    fn edit_offsets_count_the_encoding_and_byte_order_mark() {
+        let (original, formatted) = ("é\nb\n", "é\nB\n");
+
+        assert_eq!(
+            offsets(original, formatted, Encoding::Utf8Bom),
+            [(6, 8, "B\n".to_string())]
+        );
+        assert_eq!(
+            offsets(original, formatted, Encoding::Utf16Le),
+            [(6, 10, "B\n".to_string())]
+        );
+        assert_eq!(
+            offsets(original, formatted, Encoding::Latin1),
+            [(2, 4, "B\n".to_string())]
+        );
+    }
+
+    #[test]
+    fn applies_accepted_hunks_only() {
+        let original = (1..=20).map(|line| format!("{line}\n")).collect::<String>();
+        let formatted = original
+            .replace("\n2\n", "\ntwo\n")
+            .replace("\n19\n", "\nnineteen\n");
+        let hunks = hunks(&original, &formatted, Style::stdout(ColorChoice::Never));
+
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(apply(&original, &hunks), formatted);
+        assert_eq!(apply(&original, []), original);
+        assert_eq!(
+            apply(&original, &hunks[..1]),
+            original.replace("\n2\n", "\ntwo\n")
+        );
+    }
+
+    #[test]
+    fn categorizes_changes() {
+        let category = |original: &str, formatted: &str| {
+            stats(original, formatted)
+                .categories
+                .into_keys()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(category("a b\nc\n", "a\nb c\n"), [Category::Reflow]);
+        assert_eq!(category("  a\n", "a\n"), [Category::Indentation]);
+        assert_eq!(category("a\nb\n", "a\n\nb\n"), [Category::Whitespace]);
+        assert_eq!(category("a\n", "b\n"), [Category::Content]);
+    }
+}
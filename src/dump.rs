@@ -0,0 +1,341 @@
+use std::collections::BTreeMap;
+
+use itertools::Itertools as _;
+use rust_norg::{LinkTarget, NorgASTFlat, ParagraphSegment, ParagraphSegmentToken};
+use serde::Serialize;
+
+use crate::{ast_json::Span, converter::format_extensions, source_map::block_ranges};
+
+#[derive(Clone, Copy, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum DumpFormat {
+    #[default]
+    Json,
+    Sexpr,
+}
+
+/// A generic view of a node of the AST, used to print the tree the formatter operates on.
+///
+/// Every node is located by its `path`: the indices leading to it from the root of the document,
+/// separated by dots. The parser does not report spans, so only top-level nodes carry the `span` of
+/// lines they occupy in the source, recovered by parsing it again; nested nodes have none.
+#[derive(Serialize)]
+pub struct DumpNode {
+    kind: &'static str,
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    span: Option<Span>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    attributes: BTreeMap<&'static str, String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<DumpNode>,
+}
+
+impl DumpNode {
+    fn new(kind: &'static str, path: String) -> Self {
+        Self {
+            kind,
+            path,
+            span: None,
+            attributes: BTreeMap::new(),
+            children: Vec::new(),
+        }
+    }
+
+    fn attribute(mut self, name: &'static str, value: impl ToString) -> Self {
+        self.attributes.insert(name, value.to_string());
+        self
+    }
+
+    fn children<T>(mut self, children: &[T], f: impl Fn(&T, String) -> DumpNode) -> Self {
+        let path = self.path.clone();
+
+        self.children.extend(
+            children
+                .iter()
+                .enumerate()
+                .map(|(i, child)| f(child, format!("{path}.{i}"))),
+        );
+        self
+    }
+
+    fn to_sexpr(&self, depth: usize, output: &mut String) {
+        output.push_str(&"  ".repeat(depth));
+        output.push('(');
+        output.push_str(self.kind);
+        output.push_str(&format!(" :path {:?}", self.path));
+
+        if let Some(span) = &self.span {
+            output.push_str(&format!(" :span ({} {})", span.start, span.end));
+        }
+
+        for (name, value) in &self.attributes {
+            output.push_str(&format!(" :{name} {value:?}"));
+        }
+
+        for child in &self.children {
+            output.push('\n');
+            child.to_sexpr(depth + 1, output);
+        }
+
+        output.push(')');
+    }
+}
+
+fn from_token(token: &ParagraphSegmentToken, path: String) -> DumpNode {
+    match token {
+        ParagraphSegmentToken::Text(text) => DumpNode::new("Text", path).attribute("text", text),
+        ParagraphSegmentToken::Whitespace => DumpNode::new("Whitespace", path),
+        ParagraphSegmentToken::Special(c) => DumpNode::new("Special", path).attribute("char", c),
+        ParagraphSegmentToken::Escape(c) => DumpNode::new("Escape", path).attribute("char", c),
+    }
+}
+
+fn from_link_target(target: &LinkTarget, path: String) -> DumpNode {
+    let node = DumpNode::new("LinkTarget", path);
+
+    match target {
+        LinkTarget::Heading { level, title } => node
+            .attribute("type", "heading")
+            .attribute("level", level)
+            .children(title, from_segment),
+        LinkTarget::Footnote(title) => node
+            .attribute("type", "footnote")
+            .children(title, from_segment),
+        LinkTarget::Definition(title) => node
+            .attribute("type", "definition")
+            .children(title, from_segment),
+        LinkTarget::Generic(title) => node
+            .attribute("type", "generic")
+            .children(title, from_segment),
+        LinkTarget::Wiki(title) => node.attribute("type", "wiki").children(title, from_segment),
+        LinkTarget::Extendable(title) => node
+            .attribute("type", "extendable")
+            .children(title, from_segment),
+        LinkTarget::Path(target) => node.attribute("type", "path").attribute("target", target),
+        LinkTarget::Url(target) => node.attribute("type", "url").attribute("target", target),
+        LinkTarget::Timestamp(target) => node
+            .attribute("type", "timestamp")
+            .attribute("target", target),
+    }
+}
+
+fn from_segment(segment: &ParagraphSegment, path: String) -> DumpNode {
+    match segment {
+        ParagraphSegment::Token(token) => from_token(token, path),
+        ParagraphSegment::AttachedModifier {
+            modifier_type,
+            content,
+        } => DumpNode::new("AttachedModifier", path)
+            .attribute("modifier", modifier_type)
+            .children(content, from_segment),
+        ParagraphSegment::Link {
+            filepath,
+            targets,
+            description,
+        } => {
+            let mut node = DumpNode::new("Link", path).children(targets, from_link_target);
+
+            if let Some(filepath) = filepath {
+                node = node.attribute("filepath", filepath);
+            }
+
+            if let Some(description) = description {
+                let description = DumpNode::new("Description", format!("{}.d", node.path))
+                    .children(description, from_segment);
+                node.children.push(description);
+            }
+
+            node
+        }
+        ParagraphSegment::AnchorDefinition { content, target } => {
+            let node = DumpNode::new("AnchorDefinition", path).children(content, from_segment);
+            let target = from_segment(target, format!("{}.t", node.path));
+
+            DumpNode {
+                children: node.children.into_iter().chain([target]).collect(),
+                ..node
+            }
+        }
+        ParagraphSegment::Anchor {
+            content,
+            description,
+        } => {
+            let mut node = DumpNode::new("Anchor", path).children(content, from_segment);
+
+            if let Some(description) = description {
+                let description = DumpNode::new("Description", format!("{}.d", node.path))
+                    .children(description, from_segment);
+                node.children.push(description);
+            }
+
+            node
+        }
+        ParagraphSegment::InlineLinkTarget(content) => {
+            DumpNode::new("InlineLinkTarget", path).children(content, from_segment)
+        }
+        segment => DumpNode::new("Unsupported", path).attribute("debug", format!("{segment:?}")),
+    }
+}
+
+fn from_node(node: &NorgASTFlat, path: String) -> DumpNode {
+    match node {
+        NorgASTFlat::Paragraph(content) => {
+            DumpNode::new("Paragraph", path).children(content, from_segment)
+        }
+        NorgASTFlat::NestableDetachedModifier {
+            modifier_type,
+            level,
+            extensions,
+            content,
+        } => DumpNode::new("NestableDetachedModifier", path)
+            .attribute("modifier", modifier_type)
+            .attribute("level", level)
+            .attribute(
+                "extensions",
+                format_extensions(extensions.clone()).trim_end(),
+            )
+            .children(std::slice::from_ref(content.as_ref()), from_node),
+        NorgASTFlat::RangeableDetachedModifier {
+            modifier_type,
+            title,
+            extensions,
+            content,
+        } => DumpNode::new("RangeableDetachedModifier", path)
+            .attribute("modifier", modifier_type)
+            .attribute("title", title.iter().join(""))
+            .attribute(
+                "extensions",
+                format_extensions(extensions.clone()).trim_end(),
+            )
+            .children(content, from_node),
+        NorgASTFlat::Heading {
+            level,
+            title,
+            extensions,
+        } => DumpNode::new("Heading", path)
+            .attribute("level", level)
+            .attribute("title", title.iter().join(""))
+            .attribute(
+                "extensions",
+                format_extensions(extensions.clone()).trim_end(),
+            ),
+        NorgASTFlat::CarryoverTag {
+            tag_type,
+            name,
+            parameters,
+            next_object,
+        } => DumpNode::new("CarryoverTag", path)
+            .attribute(
+                "type",
+                match tag_type {
+                    rust_norg::CarryoverTag::Attribute => "attribute",
+                    rust_norg::CarryoverTag::Macro => "macro",
+                },
+            )
+            .attribute("name", name.join("."))
+            .attribute("parameters", parameters.join(" "))
+            .children(std::slice::from_ref(next_object.as_ref()), from_node),
+        NorgASTFlat::VerbatimRangedTag {
+            name,
+            parameters,
+            content,
+        } => DumpNode::new("VerbatimRangedTag", path)
+            .attribute("name", name.join("."))
+            .attribute("parameters", parameters.join(" "))
+            .attribute("content", content),
+        NorgASTFlat::RangedTag {
+            name,
+            parameters,
+            content,
+        } => DumpNode::new("RangedTag", path)
+            .attribute("name", name.join("."))
+            .attribute("parameters", parameters.join(" "))
+            .children(content, from_node),
+        NorgASTFlat::InfirmTag { name, parameters } => DumpNode::new("InfirmTag", path)
+            .attribute("name", name.join("."))
+            .attribute("parameters", parameters.join(" ")),
+    }
+}
+
+/// Renders the AST of a document parsed from `source` in the given format.
+pub fn dump(source: &str, ast: &[NorgASTFlat], format: DumpFormat) -> String {
+    let mut spans = block_ranges(source, ast.len())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|range| Span {
+            start: range.start,
+            end: range.end,
+        });
+    let nodes = ast
+        .iter()
+        .enumerate()
+        .map(|(i, node)| DumpNode {
+            span: spans.next(),
+            ..from_node(node, i.to_string())
+        })
+        .collect::<Vec<_>>();
+
+    match format {
+        DumpFormat::Json => serde_json::to_string_pretty(&nodes).unwrap_or_default(),
+        DumpFormat::Sexpr => nodes
+            .iter()
+            .map(|node| {
+                let mut output = String::new();
+                node.to_sexpr(0, &mut output);
+                output
+            })
+            .join("\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_norg::parse;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    const SOURCE: &str = "* ( ) Heading\n\nSome *bold* text.\n";
+
+    #[test]
+    fn dumps_nodes_with_their_paths_and_spans() {
+        let ast = parse(SOURCE).unwrap();
+        let nodes = serde_json::from_str::<Value>(&dump(SOURCE, &ast, DumpFormat::Json)).unwrap();
+        let bold = json!({ "kind": "Text", "path": "1.2.0", "attributes": { "text": "bold" } });
+
+        assert_eq!(
+            nodes[0],
+            json!({
+                "kind": "Heading",
+                "path": "0",
+                "span": { "start": 0, "end": 1 },
+                "attributes": { "extensions": "( )", "level": "1", "title": "Heading" },
+            })
+        );
+        assert_eq!(nodes[1]["span"], json!({ "start": 2, "end": 3 }));
+        assert_eq!(
+            nodes[1]["children"][2],
+            json!({
+                "kind": "AttachedModifier",
+                "path": "1.2",
+                "attributes": { "modifier": "*" },
+                "children": [bold],
+            })
+        );
+    }
+
+    #[test]
+    fn dumps_s_expressions() {
+        let ast = parse(SOURCE).unwrap();
+        let dumped = dump(SOURCE, &ast, DumpFormat::Sexpr);
+
+        assert!(dumped.starts_with(
+            "(Heading :path \"0\" :span (0 1) :extensions \"( )\" :level \"1\" \
+             :title \"Heading\")\n(Paragraph :path \"1\" :span (2 3)\n  \
+             (Text :path \"1.0\" :text \"Some\")\n  (Whitespace :path \"1.1\")\n  \
+             (AttachedModifier :path \"1.2\" :modifier \"*\"\n    \
+             (Text :path \"1.2.0\" :text \"bold\"))"
+        ));
+    }
+}
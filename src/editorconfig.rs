@@ -0,0 +1,343 @@
+//! Reading whitespace settings from `.editorconfig` files (see <https://editorconfig.org>), which
+//! make up the lowest layer of options, beneath the configuration file.
+//!
+//! `indent_style`, `indent_size`, `max_line_length` and `end_of_line` are understood. Formatted
+//! documents always end with a newline and never have trailing whitespace, which is what
+//! `insert_final_newline` and `trim_trailing_whitespace` ask for already.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use norg_fmt::{
+    options::{FormatOptions, MIN_LINE_LENGTH},
+    refactor::normalize_path,
+    IndentStyle, LineEnding,
+};
+
+use crate::workspace::Glob;
+
+/// A section of an `.editorconfig` file, e.g. `[*.norg]`, with the properties it sets.
+struct Section {
+    globs: Vec<Glob>,
+    properties: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+struct EditorConfig {
+    /// Whether the `.editorconfig` files of parent directories are ignored.
+    root: bool,
+    sections: Vec<Section>,
+}
+
+/// Expands the braces of a section name, e.g. `*.{md,norg}` into `*.md` and `*.norg`.
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let Some(open) = pattern.find('{') else {
+        return vec![pattern.to_string()];
+    };
+
+    let mut depth = 0;
+    let close = pattern[open..].char_indices().find_map(|(index, c)| {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 1 => return Some(open + index),
+            '}' => depth -= 1,
+            _ => {}
+        }
+
+        None
+    });
+    let Some(close) = close else {
+        return vec![pattern.to_string()];
+    };
+
+    let inner = &pattern[open + 1..close];
+    let mut alternatives = Vec::new();
+    let (mut depth, mut start) = (0, 0);
+
+    for (index, c) in inner.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ',' if depth == 0 => {
+                alternatives.push(&inner[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+
+    alternatives.push(&inner[start..]);
+
+    let rest = expand_braces(&pattern[close + 1..]);
+
+    alternatives
+        .into_iter()
+        .flat_map(expand_braces)
+        .flat_map(|alternative| {
+            rest.iter()
+                .map(move |rest| format!("{}{alternative}{rest}", &pattern[..open]))
+        })
+        .collect()
+}
+
+/// The globs a section name matches files with, relative to the directory of its file. Names
+/// without a `/` match files in any directory beneath it.
+fn section_globs(name: &str) -> Vec<Glob> {
+    expand_braces(name)
+        .into_iter()
+        .filter_map(|pattern| {
+            let pattern = match pattern.strip_prefix('/') {
+                Some(pattern) => pattern.to_string(),
+                None if pattern.contains('/') => pattern,
+                None => format!("**/{pattern}"),
+            };
+
+            pattern.parse().ok()
+        })
+        .collect()
+}
+
+fn parse(content: &str) -> EditorConfig {
+    let mut config = EditorConfig::default();
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+
+        if let Some(name) = line
+            .strip_prefix('[')
+            .and_then(|line| line.strip_suffix(']'))
+        {
+            config.sections.push(Section {
+                globs: section_globs(name),
+                properties: Vec::new(),
+            });
+        } else if let Some((key, value)) = line.split_once('=') {
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim().to_ascii_lowercase();
+
+            match config.sections.last_mut() {
+                Some(section) => section.properties.push((key, value)),
+                None if key == "root" => config.root = value == "true",
+                None => {}
+            }
+        }
+    }
+
+    config
+}
+
+/// Turns the properties that apply to a file into options.
+fn options(properties: &BTreeMap<&str, &str>) -> FormatOptions {
+    let indent_style = match properties.get("indent_style") {
+        Some(&"space") => Some(IndentStyle::Spaces),
+        Some(&"tab") => Some(IndentStyle::Tabs),
+        _ => None,
+    };
+
+    let size = |key| {
+        properties
+            .get(key)
+            .and_then(|size| size.parse().ok())
+            .filter(|&size| size > 0)
+    };
+    // A tab counts as `tab_width` columns, which defaults to `indent_size`.
+    let indent_width = match (indent_style, properties.get("indent_size")) {
+        (Some(IndentStyle::Tabs), _) | (_, Some(&"tab")) => {
+            size("tab_width").or_else(|| size("indent_size"))
+        }
+        _ => size("indent_size"),
+    };
+
+    FormatOptions {
+        indent_width,
+        indent_style,
+        line_length: properties
+            .get("max_line_length")
+            .and_then(|length| length.parse().ok())
+            .filter(|&length| length >= MIN_LINE_LENGTH),
+        line_ending: match properties.get("end_of_line") {
+            Some(&"lf") => Some(LineEnding::Lf),
+            Some(&"crlf") => Some(LineEnding::Crlf),
+            _ => None,
+        },
+        ..FormatOptions::default()
+    }
+}
+
+/// The `.editorconfig` files of the directories files are formatted in, each read once.
+#[derive(Default)]
+pub struct EditorConfigs(RefCell<HashMap<PathBuf, Rc<EditorConfig>>>);
+
+impl EditorConfigs {
+    fn in_directory(&self, directory: &Path) -> Rc<EditorConfig> {
+        self.0
+            .borrow_mut()
+            .entry(directory.to_path_buf())
+            .or_insert_with(|| {
+                let content = std::fs::read_to_string(directory.join(".editorconfig"));
+
+                Rc::new(content.map(|content| parse(&content)).unwrap_or_default())
+            })
+            .clone()
+    }
+
+    /// The `.editorconfig` files that apply to the files in `directory`, from the closest one up to
+    /// the first one marked as `root = true`.
+    pub fn paths(&self, directory: &Path) -> Vec<PathBuf> {
+        let Ok(directory) = std::path::absolute(directory).map(|path| normalize_path(&path)) else {
+            return Vec::new();
+        };
+        let mut paths = Vec::new();
+
+        for directory in directory.ancestors() {
+            let path = directory.join(".editorconfig");
+
+            if path.is_file() {
+                paths.push(path);
+            }
+
+            if self.in_directory(directory).root {
+                break;
+            }
+        }
+
+        paths
+    }
+
+    /// The options the `.editorconfig` files of the directory of `file` and its parents set for
+    /// it, up to the first one marked as `root = true`. Closer files take precedence, and so do
+    /// later sections within a file.
+    pub fn options_for(&self, file: &Path) -> FormatOptions {
+        let Ok(file) = std::path::absolute(file).map(|file| normalize_path(&file)) else {
+            return FormatOptions::default();
+        };
+        let mut configs = Vec::new();
+
+        for directory in file.ancestors().skip(1) {
+            let config = self.in_directory(directory);
+            let is_root = config.root;
+
+            configs.push((directory, config));
+
+            if is_root {
+                break;
+            }
+        }
+
+        let mut properties = BTreeMap::new();
+
+        for (directory, config) in configs.iter().rev() {
+            let Ok(relative) = file.strip_prefix(directory) else {
+                continue;
+            };
+
+            for section in &config.sections {
+                if !section.globs.iter().any(|glob| glob.matches_path(relative)) {
+                    continue;
+                }
+
+                for (key, value) in &section.properties {
+                    if value == "unset" {
+                        properties.remove(key.as_str());
+                    } else {
+                        properties.insert(key.as_str(), value.as_str());
+                    }
+                }
+            }
+        }
+
+        options(&properties)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_braces() {
+        assert_eq!(expand_braces("*.{md,norg}"), ["*.md", "*.norg"]);
+        assert_eq!(
+            expand_braces("{a,{b,c}}/*.norg"),
+            ["a/*.norg", "b/*.norg", "c/*.norg"]
+        );
+        assert_eq!(expand_braces("{a"), ["{a"]);
+    }
+
+    #[test]
+    fn parses_sections_relative_to_the_file() {
+        let config =
+            parse("root = true\n\n[*.norg]\nindent_size = 2\n[/docs/*]\nEND_OF_LINE = CRLF\n");
+        let matches = |section: usize, path: &str| {
+            config.sections[section]
+                .globs
+                .iter()
+                .any(|glob| glob.matches_path(Path::new(path)))
+        };
+
+        assert!(config.root);
+        assert!(matches(0, "notes/index.norg"));
+        assert!(!matches(0, "notes/index.md"));
+        assert!(matches(1, "docs/index.norg"));
+        assert!(!matches(1, "notes/docs/index.norg"));
+        assert_eq!(
+            config.sections[1].properties,
+            [("end_of_line".to_string(), "crlf".to_string())]
+        );
+    }
+
+    #[test]
+    fn turns_properties_into_options() {
+        let options = |properties: &[(&'static str, &'static str)]| {
+            super::options(&properties.iter().copied().collect())
+        };
+
+        assert_eq!(
+            options(&[
+                ("indent_style", "space"),
+                ("indent_size", "2"),
+                ("max_line_length", "100"),
+                ("end_of_line", "crlf"),
+            ]),
+            FormatOptions {
+                indent_style: Some(IndentStyle::Spaces),
+                indent_width: Some(2),
+                line_length: Some(100),
+                line_ending: Some(LineEnding::Crlf),
+                ..FormatOptions::default()
+            }
+        );
+        // Options that can't be formatted with are left to the other layers.
+        assert_eq!(
+            options(&[
+                ("indent_style", "tab"),
+                ("indent_size", "2"),
+                ("max_line_length", "off"),
+            ]),
+            FormatOptions {
+                indent_style: Some(IndentStyle::Tabs),
+                indent_width: Some(2),
+                ..FormatOptions::default()
+            }
+        );
+        assert_eq!(
+            options(&[
+                ("indent_style", "tab"),
+                ("indent_size", "2"),
+                ("tab_width", "8")
+            ])
+            .indent_width,
+            Some(8)
+        );
+        assert_eq!(
+            options(&[("indent_size", "tab"), ("tab_width", "3")]).indent_width,
+            Some(3)
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use clap::ValueEnum;
+use eyre::{bail, Result};
+
+/// The text encodings files can be read and written in.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Encoding {
+    #[value(name = "utf-8")]
+    Utf8,
+    /// UTF-8 prefixed with a byte order mark.
+    #[value(name = "utf-8-bom")]
+    Utf8Bom,
+    #[value(name = "utf-16le")]
+    Utf16Le,
+    #[value(name = "utf-16be")]
+    Utf16Be,
+    #[value(name = "latin-1")]
+    Latin1,
+}
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16_LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16_BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+impl Encoding {
+    /// The byte order mark files written in this encoding start with, if any.
+    pub fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8Bom => UTF8_BOM,
+            Encoding::Utf16Le => UTF16_LE_BOM,
+            Encoding::Utf16Be => UTF16_BE_BOM,
+            Encoding::Utf8 | Encoding::Latin1 => &[],
+        }
+    }
+
+    /// The number of bytes `text` takes up when encoded, without the byte order mark.
+    pub fn encoded_len(self, text: &str) -> usize {
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => text.len(),
+            Encoding::Utf16Le | Encoding::Utf16Be => text.encode_utf16().count() * 2,
+            Encoding::Latin1 => text.chars().count(),
+        }
+    }
+}
+
+/// Reads a file and decodes it, returning its content along with the encoding it was stored in.
+pub fn read(file: &Path, latin1: bool) -> Result<(String, Encoding)> {
+    decode(&std::fs::read(file)?, latin1)
+}
+
+/// Decodes the raw content of a file. UTF-8 and UTF-16 inputs are recognised by their byte order
+/// mark, everything else has to be valid UTF-8, unless `latin1` is set in which case invalid UTF-8
+/// is decoded as Latin-1 instead.
+pub fn decode(bytes: &[u8], latin1: bool) -> Result<(String, Encoding)> {
+    if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+        return Ok((String::from_utf8(rest.to_vec())?, Encoding::Utf8Bom));
+    }
+
+    if let Some(rest) = bytes.strip_prefix(UTF16_LE_BOM) {
+        return Ok((decode_utf16(rest, u16::from_le_bytes)?, Encoding::Utf16Le));
+    }
+
+    if let Some(rest) = bytes.strip_prefix(UTF16_BE_BOM) {
+        return Ok((decode_utf16(rest, u16::from_be_bytes)?, Encoding::Utf16Be));
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(content) => Ok((content.to_string(), Encoding::Utf8)),
+        Err(_) if latin1 => Ok((
+            bytes.iter().map(|&byte| byte as char).collect(),
+            Encoding::Latin1,
+        )),
+        Err(err) => bail!("file is not valid UTF-8 ({err}), pass --latin1 to read it as Latin-1"),
+    }
+}
+
+fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Result<String> {
+    let chunks = bytes.chunks_exact(2);
+
+    if !chunks.remainder().is_empty() {
+        bail!("file is not valid UTF-16: odd number of bytes");
+    }
+
+    let units = chunks.map(|chunk| from_bytes([chunk[0], chunk[1]]));
+
+    Ok(char::decode_utf16(units).collect::<Result<String, _>>()?)
+}
+
+/// Ensures that the content can be represented in the given encoding, so that a file is never
+/// left half-written because of a character that can't be encoded.
+pub fn check(content: &str, encoding: Encoding) -> Result<()> {
+    match (
+        encoding,
+        content.chars().find(|&char| u8::try_from(char).is_err()),
+    ) {
+        (Encoding::Latin1, Some(char)) => {
+            bail!("character '{char}' can not be represented in Latin-1")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Encodes the blocks of a formatted document into the sink one at a time. UTF-16 output is always
+/// prefixed with a byte order mark so that it can be detected again. The blocks must have passed
+/// [`check`] beforehand.
+pub fn write(
+    sink: &mut impl Write,
+    blocks: impl IntoIterator<Item = String>,
+    encoding: Encoding,
+) -> io::Result<()> {
+    sink.write_all(encoding.bom())?;
+
+    for block in blocks {
+        let bytes: Vec<u8> = match encoding {
+            Encoding::Utf8 | Encoding::Utf8Bom => block.into_bytes(),
+            Encoding::Utf16Le => block.encode_utf16().flat_map(u16::to_le_bytes).collect(),
+            Encoding::Utf16Be => block.encode_utf16().flat_map(u16::to_be_bytes).collect(),
+            Encoding::Latin1 => block.chars().map(|char| char as u8).collect(),
+        };
+
+        sink.write_all(&bytes)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODINGS: [Encoding; 5] = [
+        Encoding::Utf8,
+        Encoding::Utf8Bom,
+        Encoding::Utf16Le,
+        Encoding::Utf16Be,
+        Encoding::Latin1,
+    ];
+
+    #[test]
+    fn decodes_what_it_writes_in_every_encoding() {
+        let blocks = ["* Café\n", "Text.\n"];
+        let text = blocks.concat();
+
+        for encoding in ENCODINGS {
+            let mut bytes = Vec::new();
+
+            write(&mut bytes, blocks.map(String::from), encoding).unwrap();
+
+            let (decoded, detected) = decode(&bytes, true).unwrap();
+
+            assert_eq!(decoded, text);
+            assert!(detected == encoding);
+            assert_eq!(
+                bytes.len(),
+                encoding.bom().len() + encoding.encoded_len(&text)
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_what_it_can_not_decode_or_encode() {
+        let error = decode(&[b'a', 0xE9], false).unwrap_err();
+
+        assert!(error.to_string().contains("--latin1"), "{error}");
+        assert!(decode(&[0xFF, 0xFE, b'a'], true).is_err());
+        assert!(decode(&[0xFF, 0xFE, 0x00, 0xD8], true).is_err());
+
+        assert!(check("café", Encoding::Latin1).is_ok());
+        assert!(check("5 €", Encoding::Latin1).is_err());
+        assert!(check("5 €", Encoding::Utf16Le).is_ok());
+    }
+}
@@ -0,0 +1,249 @@
+use std::{cell::RefCell, collections::BTreeSet, ops::Range, rc::Rc, str::FromStr};
+
+use eyre::{eyre, Result};
+use rust_norg::NorgASTFlat;
+
+use crate::{
+    context::FormatCtx,
+    converter::{format_nodes, is_separated},
+    source_map::block_ranges,
+    Config,
+};
+
+/// A `line:col` position in a document, both one-based.
+#[derive(Clone, Copy)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (line, column) = s.split_once(':').unwrap_or((s, "1"));
+        let parse = |value: &str| {
+            value
+                .parse::<usize>()
+                .ok()
+                .filter(|value| *value > 0)
+                .ok_or_else(|| format!("`{value}` is not a valid one-based line or column"))
+        };
+
+        Ok(Self {
+            line: parse(line)?,
+            column: parse(column)?,
+        })
+    }
+}
+
+/// A transformation the formatter applied to a block, identified by a stable rule id.
+pub struct Transformation {
+    pub rule: &'static str,
+    pub options: String,
+}
+
+impl Transformation {
+    fn new(rule: &'static str, options: impl Into<String>) -> Self {
+        Self {
+            rule,
+            options: options.into(),
+        }
+    }
+}
+
+/// A top-level block of a document alongside its formatted counterpart.
+pub struct Block {
+    pub index: usize,
+    /// The zero-based, end-exclusive range of lines the block occupies in the source.
+    pub lines: Range<usize>,
+    pub before: String,
+    pub after: String,
+    /// The ids of the rules the formatter applied while formatting the block, including the blank
+    /// line policy if it separates the block from the one before it.
+    pub rules: BTreeSet<&'static str>,
+}
+
+/// Pairs every top-level block of the source with its formatted output.
+pub fn blocks(source: &str, ast: &[NorgASTFlat], config: &Config) -> Result<Vec<Block>> {
+    let ranges = block_ranges(source, ast.len())
+        .ok_or_else(|| eyre!("unable to map the document's blocks back to its source"))?;
+    let source_lines = source.lines().collect::<Vec<_>>();
+
+    ranges
+        .into_iter()
+        .zip(ast)
+        .enumerate()
+        .map(|(index, (lines, node))| {
+            let rules = Rc::new(RefCell::new(BTreeSet::new()));
+            let ctx = FormatCtx::new(config).with_rules(rules.clone());
+            let after = format_nodes(vec![node.clone()], &ctx)
+                .map(|output| output.join(""))
+                .map_err(|_| eyre!("block #{index} could not be formatted"))?;
+            let mut rules = rules.take();
+
+            // Blocks are formatted one at a time, so the blank line before the block is decided
+            // here, the same way as when the whole document is formatted.
+            if index > 0 && is_separated(&ast[index - 1], node, config) {
+                rules.insert("blank-line-policy");
+            }
+
+            Ok(Block {
+                index,
+                before: source_lines[lines.clone()].join("\n"),
+                after: after.trim_end_matches('\n').to_string(),
+                lines,
+                rules,
+            })
+        })
+        .collect()
+}
+
+/// The configuration values involved in a rule applied to `node`, preceded by `previous`.
+fn options(
+    rule: &str,
+    node: &NorgASTFlat,
+    previous: Option<&NorgASTFlat>,
+    config: &Config,
+) -> String {
+    match rule {
+        "reflow" => format!(
+            "line_length = {}{}",
+            config.line_length,
+            config
+                .construct_line_length
+                .iter()
+                .map(|(construct, length)| format!(", line_length.{construct} = {length}"))
+                .collect::<String>()
+        ),
+        "indentation" => format!(
+            "indent_style = {}, indent_width = {}, continuation_indent = {}, indent_headings = {}",
+            config.indent_style,
+            config.indent_width,
+            config.continuation_indent,
+            config.indent_headings
+        ),
+        "free-form-decay" => format!("free_form_conversion = {}", config.free_form_conversion),
+        "comment-preservation" => format!("wrap_comments = {}", config.wrap_comments),
+        "definition-alignment" => "align_definitions = true".to_string(),
+        "embedded-norg" => {
+            "(applied to `@norg`, `@code norg` and tags configured as `norg`)".to_string()
+        }
+        "blank-line-policy" => match (previous, node) {
+            (Some(NorgASTFlat::Paragraph(_)), NorgASTFlat::NestableDetachedModifier { .. }) => {
+                format!(
+                    "blank_line_before_lists = {}",
+                    config.blank_line_before_lists
+                )
+            }
+            (
+                Some(NorgASTFlat::NestableDetachedModifier { .. }),
+                NorgASTFlat::NestableDetachedModifier { .. },
+            ) => format!(
+                "blank_line_between_lists = {}",
+                config.blank_line_between_lists
+            ),
+            (Some(_), NorgASTFlat::Paragraph(_)) => {
+                "paragraphs are always separated from the preceding block".to_string()
+            }
+            _ => format!(
+                "blank_line_before_lists = {}, blank_line_between_lists = {}",
+                config.blank_line_before_lists, config.blank_line_between_lists
+            ),
+        },
+        _ => "(always applied)".to_string(),
+    }
+}
+
+/// The transformations the formatter applied to a block and the configuration values involved.
+pub fn transformations(block: &Block, ast: &[NorgASTFlat], config: &Config) -> Vec<Transformation> {
+    let node = &ast[block.index];
+    let previous = block.index.checked_sub(1).map(|index| &ast[index]);
+
+    block
+        .rules
+        .iter()
+        .map(|&rule| Transformation::new(rule, options(rule, node, previous, config)))
+        .collect()
+}
+
+/// Describes which rules the formatter applied to the block at `position` and the configuration
+/// values that were involved.
+pub fn explain(
+    source: &str,
+    ast: &[NorgASTFlat],
+    position: Position,
+    config: &Config,
+) -> Result<String> {
+    let line = position.line - 1;
+    let block = blocks(source, ast, config)?
+        .into_iter()
+        .find(|block| block.lines.contains(&line))
+        .ok_or_else(|| eyre!("there is no block at line {}", position.line))?;
+
+    let mut explanation = format!(
+        "{}:{} is inside block #{} (lines {}-{})\n",
+        position.line,
+        position.column,
+        block.index,
+        block.lines.start + 1,
+        block.lines.end
+    );
+
+    if block.before == block.after {
+        explanation.push_str("the block is left unchanged by the formatter\n");
+    }
+
+    let transformations = transformations(&block, ast, config);
+
+    if transformations.is_empty() {
+        explanation.push_str("no rules were applied to this block\n");
+    }
+
+    for transformation in transformations {
+        explanation.push_str(&format!(
+            "- {}: {}\n",
+            transformation.rule, transformation.options
+        ));
+    }
+
+    Ok(explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_norg::parse;
+
+    use super::*;
+
+    #[test]
+    fn parses_one_based_positions() {
+        let position = "3:5".parse::<Position>().unwrap();
+
+        assert_eq!((position.line, position.column), (3, 5));
+        assert_eq!("3".parse::<Position>().unwrap().column, 1);
+        assert!("0:1".parse::<Position>().is_err());
+        assert!("3:x".parse::<Position>().is_err());
+    }
+
+    #[test]
+    fn explains_the_block_at_a_position() {
+        let source = "*  Heading\nText.\n";
+        let ast = parse(source).unwrap();
+        let config = Config::default();
+        let at = |position: &str| explain(source, &ast, position.parse().unwrap(), &config);
+
+        let heading = at("1:2").unwrap();
+        let text = at("2:1").unwrap();
+
+        assert!(heading.starts_with("1:2 is inside block #0 (lines 1-1)\n"));
+        assert!(!heading.contains("left unchanged"));
+        assert!(text.starts_with(
+            "2:1 is inside block #1 (lines 2-2)\nthe block is left unchanged by the formatter\n"
+        ));
+        assert!(at("9")
+            .unwrap_err()
+            .to_string()
+            .contains("no block at line 9"));
+    }
+}
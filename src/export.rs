@@ -0,0 +1,39 @@
+//! Exporting documents to other formats, formatted first so that what is exported is what the
+//! formatter would write.
+
+use std::path::Path;
+
+use eyre::{eyre, Result};
+use itertools::Itertools;
+use norg_fmt::{
+    exporter::{self, ExportOptions, Exporters},
+    formatter::{Fingerprint, Formatter},
+    Config,
+};
+use rust_norg::parse;
+
+use crate::{encoding, status::ParseError};
+
+/// Formats the document at `file` and renders it in the format registered as `format`.
+pub fn export(
+    file: &Path,
+    format: &str,
+    options: &ExportOptions,
+    config: &Config,
+) -> Result<String> {
+    let exporters = Exporters::default();
+    let mut exporter = exporters.create(format, options).ok_or_else(|| {
+        eyre!(
+            "unknown export format `{format}`, expected one of: {}",
+            exporters.names().join(", ")
+        )
+    })?;
+    let (content, _) = encoding::read(file, false)?;
+    let formatted = Formatter::new(config.clone())
+        .with_fingerprint(Fingerprint::Remove)
+        .format(&content)?
+        .output();
+    let ast = parse(&formatted).map_err(|err| ParseError(format!("{err:?}")))?;
+
+    Ok(exporter::export(exporter.as_mut(), &ast))
+}
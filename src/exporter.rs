@@ -0,0 +1,443 @@
+//! Exporting formatted documents to other formats, e.g. [HTML](crate::html) or
+//! [Pandoc's JSON](crate::pandoc).
+//!
+//! An [`Exporter`] is visited with every node of a document in turn, and renders the whole of it
+//! when [finished](Exporter::finish). Exporters are looked up by name in [`Exporters`], which other
+//! crates can register their own formats with, e.g. org-mode or AsciiDoc, while reusing the parsing
+//! and formatting of this crate:
+//!
+//! ```no_run
+//! # use norg_fmt::exporter::{Exporter, Exporters, Nestable, Rangeable};
+//! # use rust_norg::{DetachedModifierExtension, NorgASTFlat, ParagraphSegment};
+//! #[derive(Default)]
+//! struct Plain(String);
+//!
+//! impl Exporter for Plain {
+//!     fn paragraph(&mut self, content: &[ParagraphSegment]) {
+//!         self.0 += &content.iter().cloned().map(String::from).collect::<String>();
+//!         self.0 += "\n";
+//!     }
+//!
+//!     fn heading(
+//!         &mut self,
+//!         _: usize,
+//!         title: &[ParagraphSegment],
+//!         _: &[DetachedModifierExtension],
+//!     ) {
+//!         self.paragraph(title);
+//!     }
+//!
+//!     fn nestable(
+//!         &mut self,
+//!         _: Nestable,
+//!         _: usize,
+//!         _: &[DetachedModifierExtension],
+//!         _: &NorgASTFlat,
+//!     ) {
+//!     }
+//!
+//!     fn rangeable(
+//!         &mut self,
+//!         _: Rangeable,
+//!         _: &[ParagraphSegment],
+//!         _: &[DetachedModifierExtension],
+//!         _: &[NorgASTFlat],
+//!     ) {
+//!     }
+//!
+//!     fn verbatim_tag(&mut self, _: &[String], _: &[String], _: &str) {}
+//!
+//!     fn finish(&mut self) -> String {
+//!         std::mem::take(&mut self.0)
+//!     }
+//! }
+//!
+//! let mut exporters = Exporters::default();
+//!
+//! exporters.register("plain", |_| Box::new(Plain::default()));
+//! ```
+//!
+//! Exporters share the rules by which links find headings, so that a link exported by any of them
+//! points where it does in Norg.
+
+use std::{collections::BTreeMap, rc::Rc};
+
+use rust_norg::{
+    CarryoverTag, DetachedModifierExtension, LinkTarget, NorgASTFlat, ParagraphSegment,
+};
+
+use crate::{html::Html, pandoc::PandocJson, slug::slug_segments};
+
+/// The kinds of nestable detached modifiers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Nestable {
+    /// `-`
+    UnorderedList,
+    /// `~`
+    OrderedList,
+    /// `>`
+    Quote,
+}
+
+impl Nestable {
+    fn of(marker: &str) -> Self {
+        match marker {
+            ">" => Self::Quote,
+            "~" => Self::OrderedList,
+            _ => Self::UnorderedList,
+        }
+    }
+}
+
+/// The kinds of rangeable detached modifiers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rangeable {
+    /// `$`
+    Definition,
+    /// `^`
+    Footnote,
+    /// `:`
+    Table,
+}
+
+impl Rangeable {
+    fn of(marker: &str) -> Self {
+        match marker {
+            "^" => Self::Footnote,
+            ":" => Self::Table,
+            _ => Self::Definition,
+        }
+    }
+}
+
+/// Renders a document in another format, node by node. Documents are expected to be formatted
+/// already, so that what is exported matches what the formatter writes.
+///
+/// Nodes are visited in order with the method for their kind. Nodes holding others, e.g. ranged
+/// tags, are visited with the others by default, so that exporters only need to implement the
+/// kinds they render differently.
+pub trait Exporter {
+    fn paragraph(&mut self, content: &[ParagraphSegment]);
+
+    fn heading(
+        &mut self,
+        level: usize,
+        title: &[ParagraphSegment],
+        extensions: &[DetachedModifierExtension],
+    );
+
+    /// A list item or quote, which is followed by those nested in it (with a greater `level`).
+    fn nestable(
+        &mut self,
+        kind: Nestable,
+        level: usize,
+        extensions: &[DetachedModifierExtension],
+        content: &NorgASTFlat,
+    );
+
+    fn rangeable(
+        &mut self,
+        kind: Rangeable,
+        title: &[ParagraphSegment],
+        extensions: &[DetachedModifierExtension],
+        content: &[NorgASTFlat],
+    );
+
+    /// A verbatim ranged tag, e.g. `@code`, including the `@document.meta` block.
+    fn verbatim_tag(&mut self, name: &[String], parameters: &[String], content: &str);
+
+    /// A ranged tag, e.g. `|example`. Its content is visited unless it is a comment by default.
+    fn ranged_tag(&mut self, name: &[String], parameters: &[String], content: &[NorgASTFlat]) {
+        let _ = parameters;
+
+        if name != ["comment"] {
+            for node in content {
+                visit(self, node);
+            }
+        }
+    }
+
+    /// An infirm tag, e.g. `.image`, which is left out by default.
+    fn infirm_tag(&mut self, name: &[String], parameters: &[String]) {
+        let _ = (name, parameters);
+    }
+
+    /// An attribute or macro applying to the node after it, which is visited by default.
+    fn carryover_tag(
+        &mut self,
+        kind: &CarryoverTag,
+        name: &[String],
+        parameters: &[String],
+        next: &NorgASTFlat,
+    ) {
+        let _ = (kind, name, parameters);
+
+        visit(self, next);
+    }
+
+    /// Renders the document visited so far, leaving the exporter ready for the next one.
+    fn finish(&mut self) -> String;
+}
+
+/// Visits a node with the method of `exporter` for its kind.
+pub fn visit<E: Exporter + ?Sized>(exporter: &mut E, node: &NorgASTFlat) {
+    match node {
+        NorgASTFlat::Paragraph(content) => exporter.paragraph(content),
+        NorgASTFlat::Heading {
+            level,
+            title,
+            extensions,
+        } => exporter.heading(usize::from(*level), title, extensions),
+        NorgASTFlat::NestableDetachedModifier {
+            modifier_type,
+            level,
+            extensions,
+            content,
+        } => exporter.nestable(
+            Nestable::of(&modifier_type.to_string()),
+            usize::from(*level),
+            extensions,
+            content,
+        ),
+        NorgASTFlat::RangeableDetachedModifier {
+            modifier_type,
+            title,
+            extensions,
+            content,
+        } => exporter.rangeable(
+            Rangeable::of(&modifier_type.to_string()),
+            title,
+            extensions,
+            content,
+        ),
+        NorgASTFlat::VerbatimRangedTag {
+            name,
+            parameters,
+            content,
+        } => exporter.verbatim_tag(name, parameters, content),
+        NorgASTFlat::RangedTag {
+            name,
+            parameters,
+            content,
+        } => exporter.ranged_tag(name, parameters, content),
+        NorgASTFlat::InfirmTag { name, parameters } => exporter.infirm_tag(name, parameters),
+        NorgASTFlat::CarryoverTag {
+            tag_type,
+            name,
+            parameters,
+            next_object,
+        } => exporter.carryover_tag(tag_type, name, parameters, next_object),
+    }
+}
+
+/// Visits every node of a document and renders it.
+pub fn export(exporter: &mut dyn Exporter, ast: &[NorgASTFlat]) -> String {
+    for node in ast {
+        visit(exporter, node);
+    }
+
+    exporter.finish()
+}
+
+/// What every exporter is created with, e.g. from the command line.
+#[derive(Clone, Debug, Default)]
+pub struct ExportOptions {
+    /// Whether to give headings, footnotes and definitions ids derived from their titles, where
+    /// the format makes that optional.
+    pub heading_ids: bool,
+}
+
+/// A function creating an exporter.
+pub type ExporterFactory = Rc<dyn Fn(&ExportOptions) -> Box<dyn Exporter>>;
+
+/// Maps the names of formats to the functions creating their exporters, starting with the
+/// built-in `html` and `pandoc-json`.
+#[derive(Clone)]
+pub struct Exporters {
+    factories: BTreeMap<String, ExporterFactory>,
+}
+
+impl Default for Exporters {
+    fn default() -> Self {
+        let mut exporters = Self {
+            factories: BTreeMap::new(),
+        };
+
+        exporters
+            .register("html", |options| Box::new(Html::new(options.heading_ids)))
+            .register("pandoc-json", |_| Box::new(PandocJson::default()));
+
+        exporters
+    }
+}
+
+impl Exporters {
+    /// Registers an exporter for a format, replacing the one registered before, if any.
+    pub fn register(
+        &mut self,
+        name: &str,
+        factory: impl Fn(&ExportOptions) -> Box<dyn Exporter> + 'static,
+    ) -> &mut Self {
+        self.factories.insert(name.to_string(), Rc::new(factory));
+        self
+    }
+
+    /// The names of the formats registered, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.factories.keys().map(String::as_str)
+    }
+
+    /// Creates an exporter for a format, if one is registered.
+    pub fn create(&self, name: &str, options: &ExportOptions) -> Option<Box<dyn Exporter>> {
+        self.factories.get(name).map(|factory| factory(options))
+    }
+}
+
+/// The id of a heading (or footnote, or definition) titled `title`: its
+/// [`slug`](crate::slug::slug) with spaces replaced by dashes.
+pub fn heading_id(title: &[ParagraphSegment]) -> String {
+    slug_segments(title).replace(' ', "-")
+}
+
+/// Where a link points to once exported: URLs and paths as they are, documents (`{:path:}`) to
+/// their counterpart with `extension` and headings and other titles to their [`heading_id`].
+pub fn link_href(filepath: Option<&str>, targets: &[LinkTarget], extension: &str) -> String {
+    let document = filepath
+        .map(|path| {
+            let path = path.strip_prefix("$/").unwrap_or(path);
+
+            format!("{}.{extension}", path.strip_suffix(".norg").unwrap_or(path))
+        })
+        .unwrap_or_default();
+
+    match targets.first() {
+        Some(LinkTarget::Url(url)) => url.clone(),
+        Some(LinkTarget::Path(path)) => path.clone(),
+        Some(
+            LinkTarget::Heading { title, .. }
+            | LinkTarget::Footnote(title)
+            | LinkTarget::Definition(title)
+            | LinkTarget::Generic(title)
+            | LinkTarget::Wiki(title)
+            | LinkTarget::Extendable(title),
+        ) => format!("{document}#{}", heading_id(title)),
+        Some(LinkTarget::Timestamp(_)) | None => document,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_norg::parse;
+
+    use super::*;
+
+    /// Writes the text of paragraphs and headings on lines of their own, and nothing else.
+    #[derive(Default)]
+    struct Plain(String);
+
+    impl Exporter for Plain {
+        fn paragraph(&mut self, content: &[ParagraphSegment]) {
+            self.0 += &content
+                .iter()
+                .cloned()
+                .map(String::from)
+                .collect::<String>();
+            self.0 += "\n";
+        }
+
+        fn heading(
+            &mut self,
+            _: usize,
+            title: &[ParagraphSegment],
+            _: &[DetachedModifierExtension],
+        ) {
+            self.paragraph(title);
+        }
+
+        fn nestable(
+            &mut self,
+            _: Nestable,
+            _: usize,
+            _: &[DetachedModifierExtension],
+            _: &NorgASTFlat,
+        ) {
+        }
+
+        fn rangeable(
+            &mut self,
+            _: Rangeable,
+            _: &[ParagraphSegment],
+            _: &[DetachedModifierExtension],
+            _: &[NorgASTFlat],
+        ) {
+        }
+
+        fn verbatim_tag(&mut self, _: &[String], _: &[String], _: &str) {}
+
+        fn finish(&mut self) -> String {
+            std::mem::take(&mut self.0)
+        }
+    }
+
+    #[test]
+    fn visits_the_content_of_tags_by_default() {
+        let ast = parse(
+            "* Title\n|example\nInside.\n|end\n|comment\nHidden.\n|end\n+attribute\nAfter.\n\
+             - Skipped.\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            export(&mut Plain::default(), &ast),
+            "Title\nInside.\nAfter.\n"
+        );
+    }
+
+    #[test]
+    fn creates_registered_exporters_by_name() {
+        let mut exporters = Exporters::default();
+
+        exporters.register("plain", |_| Box::new(Plain::default()));
+
+        let ast = parse("* Title\n").unwrap();
+        let mut plain = exporters
+            .create("plain", &ExportOptions::default())
+            .unwrap();
+
+        assert_eq!(
+            exporters.names().collect::<Vec<_>>(),
+            ["html", "pandoc-json", "plain"]
+        );
+        assert_eq!(export(plain.as_mut(), &ast), "Title\n");
+        assert!(exporters.create("org", &ExportOptions::default()).is_none());
+    }
+
+    #[test]
+    fn points_links_to_exported_documents_and_headings() {
+        let href = |source: &str| {
+            let mut href = None;
+
+            for node in parse(source).unwrap() {
+                let NorgASTFlat::Paragraph(segments) = node else {
+                    continue;
+                };
+
+                for segment in segments {
+                    if let ParagraphSegment::Link {
+                        filepath, targets, ..
+                    } = segment
+                    {
+                        href = Some(link_href(filepath.as_deref(), &targets, "html"));
+                    }
+                }
+            }
+
+            href.unwrap()
+        };
+
+        assert_eq!(href("{:$/notes/index:}"), "notes/index.html");
+        assert_eq!(href("{:notes.norg:* Some  Title}"), "notes.html#some-title");
+        assert_eq!(href("{# Target}"), "#target");
+        assert_eq!(href("{https://neorg.org}"), "https://neorg.org");
+    }
+}
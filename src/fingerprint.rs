@@ -0,0 +1,103 @@
+//! An optional fingerprint on the first line of a document (`%norgfmt-hash: …%`), recording the
+//! hash of the formatted content below it. Files whose content still matches their fingerprint can
+//! be skipped without formatting them, where the cache can't be used, e.g. on network mounts or in
+//! CI.
+//!
+//! The fingerprint is followed by a blank line, so that it isn't read as part of the first
+//! paragraph of the document.
+
+use std::hash::{Hash, Hasher as _};
+
+use crate::{stable_hash::StableHasher, Config};
+
+const PREFIX: &str = "%norgfmt-hash: ";
+
+/// Splits a document into the hash of its fingerprint, if it starts with one, and the content
+/// below it and the blank line following it.
+pub fn split(content: &str) -> (Option<&str>, &str) {
+    let (line, rest) = content.split_once('\n').unwrap_or((content, ""));
+    let hash = line
+        .trim_end_matches('\r')
+        .strip_prefix(PREFIX)
+        .and_then(|line| line.strip_suffix('%'));
+
+    match hash {
+        Some(hash) => {
+            let rest = rest
+                .strip_prefix('\n')
+                .or_else(|| rest.strip_prefix("\r\n"))
+                .unwrap_or(rest);
+
+            (Some(hash), rest)
+        }
+        None => (None, content),
+    }
+}
+
+/// The hash of formatted content under the given configuration. Other versions of the formatter
+/// may format differently, so they never share hashes.
+fn hash(content: &str, config: &Config) -> String {
+    let mut hasher = StableHasher::default();
+
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    config.hash(&mut hasher);
+    content.hash(&mut hasher);
+
+    format!("{:016x}", hasher.finish())
+}
+
+/// Whether the document starts with a fingerprint matching the content below it, i.e. it hasn't
+/// changed since it was formatted with the same configuration.
+pub fn is_clean(content: &str, config: &Config) -> bool {
+    match split(content) {
+        (Some(fingerprint), rest) => fingerprint == hash(rest, config),
+        (None, _) => false,
+    }
+}
+
+/// The fingerprint line of formatted content, followed by a blank line, in the configured line
+/// endings.
+pub fn header(formatted: &str, config: &Config) -> String {
+    config
+        .line_ending
+        .apply(format!("{PREFIX}{}%\n\n", hash(formatted, config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_the_fingerprint_from_the_content() {
+        assert_eq!(
+            split("%norgfmt-hash: 0123%\n\n* Heading\n"),
+            (Some("0123"), "* Heading\n")
+        );
+        assert_eq!(
+            split("%norgfmt-hash: 0123%\r\n\r\n* Heading\r\n"),
+            (Some("0123"), "* Heading\r\n")
+        );
+        assert_eq!(split("* Heading\n"), (None, "* Heading\n"));
+        assert_eq!(
+            split("%norgfmt-hash: 0123\n"),
+            (None, "%norgfmt-hash: 0123\n")
+        );
+    }
+
+    #[test]
+    fn is_clean_until_the_content_or_configuration_changes() {
+        let config = Config::default();
+        let content = format!("{}* Heading\n", header("* Heading\n", &config));
+
+        assert!(is_clean(&content, &config));
+        assert!(!is_clean(&content.replace("Heading", "Changed"), &config));
+        assert!(!is_clean(
+            &content,
+            &Config {
+                line_length: 100,
+                ..Config::default()
+            }
+        ));
+        assert!(!is_clean("* Heading\n", &config));
+    }
+}
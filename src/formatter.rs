@@ -0,0 +1,330 @@
+//! A reusable formatter that can be shared across threads.
+
+use std::{
+    ffi::OsStr,
+    fmt,
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use eyre::Result;
+use rust_norg::{parse, NorgASTFlat};
+
+use crate::{
+    context::{Diagnostic, FormatCtx},
+    converter::format_document,
+    fingerprint,
+    lint::lint,
+    metadata::{self, Metadata},
+    placeholders::Masked,
+    refactor::restyle,
+    registry::Registry,
+    source_map::keep_untouched,
+    verify::verify,
+    wikilinks, Config, Lint,
+};
+
+/// An error caused by a document that could not be parsed, as opposed to a problem with the
+/// environment the formatter runs in.
+#[derive(Debug)]
+pub struct ParseError(pub String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unable to parse document: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// What happens to the fingerprint of a document (see [`fingerprint`]) when it is formatted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fingerprint {
+    /// Documents with a fingerprint get an up-to-date one, others don't get any.
+    #[default]
+    Keep,
+    /// Every document gets a fingerprint.
+    Add,
+    /// Fingerprints are removed.
+    Remove,
+}
+
+/// A document parsed by a [`Formatter`], whose AST can still be changed before it is formatted.
+pub struct Parsed {
+    pub ast: Vec<NorgASTFlat>,
+    masked: Masked,
+    /// The document as it was written, without its fingerprint.
+    body: String,
+    fingerprinted: bool,
+}
+
+impl Parsed {
+    /// The source the AST was parsed from, with wikilinks expanded and placeholders masked.
+    pub fn source(&self) -> &str {
+        &self.masked.source
+    }
+}
+
+/// A document formatted by a [`Formatter`], along with everything noticed while formatting it.
+pub struct Formatted {
+    /// The formatted document, split into its top-level blocks, so that it can be compared with
+    /// the original or written out without joining them.
+    pub blocks: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Formatted {
+    /// The formatted document as a whole.
+    pub fn output(&self) -> String {
+        self.blocks.concat()
+    }
+}
+
+/// Formats whole documents with a fixed configuration.
+///
+/// Unlike a [`FormatCtx`], which holds the state of formatting a single document, a formatter holds
+/// no state besides its configuration and the formatters registered for kinds of nodes, and the
+/// parser grouping the nodes of documents is only built once per thread. It is `Send + Sync`, so
+/// that a single instance can be shared by every thread of e.g. a language server and used for any
+/// number of documents.
+///
+/// The command line formats every file through a formatter too, so that documents are formatted
+/// the same way everywhere.
+#[derive(Clone, Default)]
+pub struct Formatter {
+    config: Config,
+    registry: Arc<Registry>,
+    fingerprint: Fingerprint,
+    verify: bool,
+    minimal: bool,
+}
+
+// Sharing a formatter across threads is the point of it, so losing that should fail to compile.
+const _: () = {
+    const fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<Formatter>();
+};
+
+impl Formatter {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            ..Self::default()
+        }
+    }
+
+    /// Formats the nodes of the kinds the registry has formatters for with those.
+    pub fn with_registry(self, registry: Registry) -> Self {
+        Self {
+            registry: Arc::new(registry),
+            ..self
+        }
+    }
+
+    pub fn with_fingerprint(self, fingerprint: Fingerprint) -> Self {
+        Self {
+            fingerprint,
+            ..self
+        }
+    }
+
+    /// Checks that every formatted document parses to the same AST as the original one, failing
+    /// with a [`VerifyError`](crate::verify::VerifyError) otherwise.
+    pub fn with_verification(self, verify: bool) -> Self {
+        Self { verify, ..self }
+    }
+
+    /// Keeps every block formatting leaves untouched exactly as it was written, see
+    /// [`keep_untouched`].
+    pub fn with_minimal_diff(self, minimal: bool) -> Self {
+        Self { minimal, ..self }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Parses and formats a document, running the same lints as the command line.
+    pub fn format(&self, source: &str) -> Result<Formatted> {
+        self.format_parsed(self.parse(source)?, None, |ctx| ctx)
+    }
+
+    /// Parses a document, without its fingerprint, and applies the structural changes the
+    /// configuration asks for (see [`restyle`]). Fails with a [`ParseError`] if the document can't
+    /// be parsed.
+    pub fn parse(&self, source: &str) -> Result<Parsed> {
+        let (existing_fingerprint, body) = fingerprint::split(source);
+        let masked = Masked::new(
+            &wikilinks::expand(body, self.config.wikilink_style),
+            &self.config.template_placeholders,
+        );
+        let mut ast = parse(&masked.source).map_err(|err| ParseError(format!("{err:?}")))?;
+
+        restyle(&mut ast, &self.config);
+
+        Ok(Parsed {
+            ast,
+            masked,
+            body: body.to_string(),
+            fingerprinted: existing_fingerprint.is_some(),
+        })
+    }
+
+    /// Formats a parsed document stored in `file`, if it is stored in one. The context the
+    /// document is formatted with can be extended by `prepare`, e.g. with a profile.
+    pub fn format_parsed(
+        &self,
+        mut parsed: Parsed,
+        file: Option<&Path>,
+        prepare: impl for<'c> FnOnce(FormatCtx<'c>) -> FormatCtx<'c>,
+    ) -> Result<Formatted> {
+        let config = &self.config;
+        let stale_title = stale_metadata_title(&parsed.ast, file, config);
+
+        if let Some(title) = stale_title.as_deref().filter(|_| config.fix_metadata) {
+            metadata::set_title(&mut parsed.ast, title);
+        }
+
+        let ctx = prepare(
+            FormatCtx::new(config)
+                .with_source(&parsed.masked.source)
+                .with_registry(self.registry.clone()),
+        );
+
+        lint(&parsed.ast, &ctx);
+
+        if let Some(title) = stale_title.filter(|_| {
+            !config.fix_metadata && !config.disabled_lints.contains(&Lint::MetadataTitle)
+        }) {
+            ctx.diagnose(format!(
+                "the title in `@document.meta` doesn't match `{title}`; pass --fix to update it"
+            ));
+        }
+
+        let blocks = if self.verify {
+            let blocks = format_document(parsed.ast.clone(), &ctx)?;
+
+            verify(&parsed.ast, &blocks.concat(), config)?;
+
+            blocks
+        } else {
+            format_document(parsed.ast, &ctx)?
+        };
+
+        // Placeholders are swapped back once verified, as they are not Norg.
+        let blocks = blocks
+            .iter()
+            .map(|block| parsed.masked.unmask(block))
+            .collect::<Vec<_>>();
+        let blocks = blocks
+            .into_iter()
+            .map(|block| wikilinks::collapse(block, config.wikilink_style))
+            .collect();
+        let blocks = if self.minimal {
+            keep_untouched(&parsed.body, blocks)
+        } else {
+            blocks
+        };
+        let blocks = blocks
+            .into_iter()
+            .map(|block| config.line_ending.apply(block))
+            .collect::<Vec<_>>();
+        // Compared once the output is final, so that formatting a formatted document again leaves
+        // `updated` as it is.
+        let mut blocks = if config.fix_metadata && !is_unchanged(&blocks, &parsed.body) {
+            bump_updated(blocks)
+        } else {
+            blocks
+        };
+
+        let fingerprinted = match self.fingerprint {
+            Fingerprint::Keep => parsed.fingerprinted,
+            Fingerprint::Add => true,
+            Fingerprint::Remove => false,
+        };
+
+        if fingerprinted {
+            blocks.insert(0, fingerprint::header(&blocks.concat(), config));
+        }
+
+        Ok(Formatted {
+            blocks,
+            diagnostics: ctx.diagnostics(),
+        })
+    }
+}
+
+/// Checks whether the formatted blocks reproduce the content exactly, without joining them.
+pub fn is_unchanged(blocks: &[String], content: &str) -> bool {
+    let mut rest = content;
+
+    for block in blocks {
+        match rest.strip_prefix(block.as_str()) {
+            Some(remainder) => rest = remainder,
+            None => return false,
+        }
+    }
+
+    rest.is_empty()
+}
+
+/// The title the `@document.meta` block of a document should have (see
+/// [`Config::metadata_title`]), if the block has a different one.
+fn stale_metadata_title(
+    ast: &[NorgASTFlat],
+    file: Option<&Path>,
+    config: &Config,
+) -> Option<String> {
+    let current = Metadata::of(ast)?.title()?.to_string();
+    let stem = file.and_then(Path::file_stem).and_then(OsStr::to_str);
+
+    metadata::expected_title(ast, config.metadata_title, stem).filter(|title| *title != current)
+}
+
+/// Sets the `updated` field of the `@document.meta` block of a document that formatting changed
+/// to the current time.
+fn bump_updated(blocks: Vec<String>) -> Vec<String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+
+    match metadata::update_field_in_output(
+        &blocks.concat(),
+        "updated",
+        &metadata::format_timestamp(now),
+    ) {
+        Some(output) => vec![output],
+        None => blocks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{LineEnding, WikilinkStyle};
+
+    #[test]
+    fn leaves_updated_alone_when_nothing_changes() {
+        let formatter = Formatter::new(Config {
+            fix_metadata: true,
+            line_ending: LineEnding::Crlf,
+            wikilink_style: WikilinkStyle::Collapse,
+            ..Config::default()
+        });
+        let source = "@document.meta\ntitle: Notes\nupdated: 2024-05-01T12:30:00+0000\n@end\n\n\
+                      * Notes\n\nSee {:other:} and   {:other:* Heading}.\n";
+        let formatted = formatter.format(source).unwrap().output();
+
+        assert_ne!(formatted, source);
+        assert!(formatted.contains("\r\n"));
+
+        // An old `updated` shows whether formatting again bumps it, even within the same second.
+        let formatted =
+            metadata::update_field_in_output(&formatted, "updated", "2024-05-01T12:30:00+0000")
+                .unwrap();
+
+        assert_eq!(formatter.format(&formatted).unwrap().output(), formatted);
+    }
+}
@@ -0,0 +1,66 @@
+//! The version of the grammar (`rust-norg`) the formatter is built against.
+//!
+//! The formatter writes back whatever the grammar parses, so a grammar it wasn't built for may
+//! produce nodes it formats wrongly or doesn't know at all. The version is recorded at build time
+//! and checked against the range the formatter supports.
+
+/// The version of `rust-norg` the formatter is built against, as pinned in its manifest.
+pub const VERSION: &str = env!("NORG_FMT_GRAMMAR_VERSION");
+
+/// The git revision of `rust-norg` the formatter is built against, as pinned in its manifest.
+pub const REVISION: &str = env!("NORG_FMT_GRAMMAR_REVISION");
+
+/// The revision of the Norg specification the supported versions of `rust-norg` implement.
+pub const SPECIFICATION: &str = "1.1.1";
+
+/// The oldest supported version of `rust-norg`.
+pub const MIN_SUPPORTED: (u64, u64, u64) = (0, 1, 0);
+
+/// The first version of `rust-norg` that is no longer supported.
+pub const MAX_SUPPORTED: (u64, u64, u64) = (0, 2, 0);
+
+/// Parses a `major.minor.patch` version, ignoring any pre-release or build metadata.
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.split('.').map(str::parse);
+
+    match (parts.next()?, parts.next()?, parts.next()?, parts.next()) {
+        (Ok(major), Ok(minor), Ok(patch), None) => Some((major, minor, patch)),
+        _ => None,
+    }
+}
+
+/// Whether the formatter supports the given version of `rust-norg`. Versions that can't be parsed
+/// are given the benefit of the doubt.
+pub fn is_supported(version: &str) -> bool {
+    parse_version(version).is_none_or(|version| (MIN_SUPPORTED..MAX_SUPPORTED).contains(&version))
+}
+
+/// A warning about the grammar the formatter is built against if it is outside of the supported
+/// range.
+pub fn check() -> Option<String> {
+    let (min, max) = (MIN_SUPPORTED, MAX_SUPPORTED);
+
+    (!is_supported(VERSION)).then(|| {
+        format!(
+            "norg-fmt was built against rust-norg {VERSION}, but only supports versions from \
+             {}.{}.{} up to {}.{}.{}; documents may be misformatted",
+            min.0, min.1, min.2, max.0, max.1, max.2
+        )
+    })
+}
+
+/// Describes the grammar the formatter is built against and the range it supports.
+pub fn describe() -> String {
+    let (min, max) = (MIN_SUPPORTED, MAX_SUPPORTED);
+    let status = if is_supported(VERSION) {
+        "supported"
+    } else {
+        "unsupported"
+    };
+
+    format!(
+        "rust-norg {VERSION} (revision {REVISION}, {status}; supported: >={}.{}.{}, <{}.{}.{})",
+        min.0, min.1, min.2, max.0, max.1, max.2
+    )
+}
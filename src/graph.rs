@@ -0,0 +1,485 @@
+//! The graph of the links between the documents of a workspace, and between their headings.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use eyre::Result;
+use itertools::Itertools;
+use norg_fmt::{
+    metadata::Metadata,
+    refactor::{normalize_path, relative_path, resolve_link_path, visit_links_mut},
+    slug::{normalize_segments, slug},
+    wikilinks, WikilinkStyle,
+};
+use rust_norg::{parse, LinkTarget, NorgASTFlat};
+use serde::Serialize;
+
+use crate::{encoding, index::escape_description};
+
+/// A document of the workspace.
+pub struct Document {
+    /// The absolute path of the document.
+    pub path: PathBuf,
+    /// The title of its `@document.meta`, falling back to the name of the file.
+    pub title: String,
+    /// The titles of its headings, in order.
+    pub headings: Vec<String>,
+}
+
+/// A link from one document to another one, or to a heading of a document (possibly the same one).
+pub struct Link {
+    /// The index of the document the link is in.
+    pub from: usize,
+    /// The absolute path of the document the link refers to. The document may not exist.
+    pub to: PathBuf,
+    /// The title of the heading the link refers to, if it refers to one.
+    pub heading: Option<String>,
+}
+
+#[derive(Default)]
+pub struct LinkGraph {
+    pub documents: Vec<Document>,
+    pub links: Vec<Link>,
+}
+
+fn absolute(path: &Path) -> std::io::Result<PathBuf> {
+    Ok(normalize_path(&std::path::absolute(path)?))
+}
+
+fn heading_title(node: &NorgASTFlat) -> Option<String> {
+    match node {
+        NorgASTFlat::Heading { title, .. } => Some(normalize_segments(title)),
+        NorgASTFlat::CarryoverTag { next_object, .. } => heading_title(next_object),
+        _ => None,
+    }
+}
+
+impl LinkGraph {
+    /// Builds the graph of the given files, with `$/` links relative to `root`. Files that can't be
+    /// read or parsed are skipped with a warning, and links into other workspaces (`$name/`) or the
+    /// home directory are left out.
+    pub fn build(files: &[PathBuf], root: &Path) -> Result<Self> {
+        let root = absolute(root)?;
+        let mut graph = Self::default();
+
+        for file in files {
+            let path = absolute(file)?;
+            let Ok((content, _)) = encoding::read(file, false) else {
+                eprintln!("warning: {}: unable to read, skipping it", file.display());
+                continue;
+            };
+            // Shorthand links to documents are only links once expanded.
+            let content = wikilinks::expand(&content, WikilinkStyle::Expand);
+            let Ok(mut ast) = parse(&content) else {
+                eprintln!("warning: {}: unable to parse, skipping it", file.display());
+                continue;
+            };
+
+            let from = graph.documents.len();
+
+            visit_links_mut(&mut ast, &mut |filepath, targets| {
+                let heading = targets.iter().find_map(|target| match target {
+                    LinkTarget::Heading { title, .. } => {
+                        Some(title.iter().cloned().map_into::<String>().collect())
+                    }
+                    _ => None,
+                });
+                let to = match filepath.as_deref() {
+                    None if heading.is_some() => path.clone(),
+                    Some(filepath)
+                        if !filepath.starts_with('~')
+                            && (!filepath.starts_with('$') || filepath.starts_with("$/")) =>
+                    {
+                        normalize_path(&resolve_link_path(&path, &root, filepath))
+                    }
+                    _ => return,
+                };
+
+                graph.links.push(Link { from, to, heading });
+            });
+
+            let title = Metadata::of(&ast)
+                .and_then(|metadata| metadata.title().map(str::to_string))
+                .unwrap_or_else(|| {
+                    path.file_stem()
+                        .unwrap_or_default()
+                        .to_string_lossy()
+                        .into_owned()
+                });
+
+            graph.documents.push(Document {
+                title,
+                headings: ast.iter().filter_map(heading_title).collect(),
+                path,
+            });
+        }
+
+        Ok(graph)
+    }
+
+    /// The documents linking to the document at the (absolute) `path`, other than itself, by
+    /// title.
+    pub fn backlinks(&self, path: &Path) -> Vec<&Document> {
+        self.links
+            .iter()
+            .filter(|link| link.to == path)
+            .map(|link| link.from)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .map(|from| &self.documents[from])
+            .filter(|document| document.path != path)
+            .sorted_by(|a, b| a.title.cmp(&b.title).then_with(|| a.path.cmp(&b.path)))
+            .collect()
+    }
+}
+
+/// The title of the section listing the documents that link to a document.
+const BACKLINKS_TITLE: &str = "Backlinks";
+
+/// Replaces the `* Backlinks` section of the document at `file` with one listing the documents of
+/// the graph that link to it, or removes it if there are none. A new section is added at the end
+/// of the document.
+pub fn update_backlinks(ast: &mut Vec<NorgASTFlat>, file: &Path, graph: &LinkGraph) {
+    let Ok(path) = absolute(file) else {
+        return;
+    };
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let is_top_level = |node: &NorgASTFlat| matches!(node, NorgASTFlat::Heading { level: 1, .. });
+    let is_backlinks = |node: &NorgASTFlat| {
+        is_top_level(node)
+            && heading_title(node).is_some_and(|title| slug(&title) == slug(BACKLINKS_TITLE))
+    };
+
+    let start = ast.iter().position(is_backlinks).unwrap_or(ast.len());
+    let end = ast[start..]
+        .iter()
+        .skip(1)
+        .position(is_top_level)
+        .map_or(ast.len(), |position| start + 1 + position);
+
+    let backlinks = graph.backlinks(&path);
+    let section = if backlinks.is_empty() {
+        Vec::new()
+    } else {
+        let source = format!("* {BACKLINKS_TITLE}\n")
+            + &backlinks
+                .iter()
+                .map(|document| {
+                    format!(
+                        "- {{:{}:}}[{}]\n",
+                        relative_path(directory, &document.path.with_extension("")),
+                        escape_description(&document.title)
+                    )
+                })
+                .collect::<String>();
+
+        parse(&source).unwrap_or_default()
+    };
+
+    ast.splice(start..end, section);
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz, e.g. for `dot -Tsvg`.
+    #[default]
+    Dot,
+    Json,
+}
+
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+enum NodeKind {
+    Document,
+    Heading,
+    /// A document or heading that is linked to but doesn't exist.
+    Missing,
+}
+
+#[derive(Serialize)]
+struct Node {
+    id: String,
+    kind: NodeKind,
+    label: String,
+}
+
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+enum EdgeKind {
+    /// From a document to one of its headings.
+    Contains,
+    Link,
+}
+
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+struct Edge {
+    from: String,
+    to: String,
+    kind: EdgeKind,
+}
+
+#[derive(Serialize)]
+struct Export {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl LinkGraph {
+    /// Renders the graph of documents and their headings, with the links between them. Documents
+    /// are identified by their path relative to `root` without the `.norg` extension, and headings
+    /// by that of their document followed by `#` and their title.
+    pub fn export(&self, root: &Path, format: GraphFormat) -> Result<String> {
+        let root = absolute(root)?;
+        let id = |path: &Path| relative_path(&root, &path.with_extension(""));
+        let mut nodes = Vec::new();
+        let mut edges = BTreeSet::new();
+
+        for document in &self.documents {
+            let document_id = id(&document.path);
+
+            for heading in document.headings.iter().unique_by(|heading| slug(heading)) {
+                let heading_id = format!("{document_id}#{heading}");
+
+                edges.insert(Edge {
+                    from: document_id.clone(),
+                    to: heading_id.clone(),
+                    kind: EdgeKind::Contains,
+                });
+                nodes.push(Node {
+                    id: heading_id,
+                    kind: NodeKind::Heading,
+                    label: heading.clone(),
+                });
+            }
+
+            nodes.push(Node {
+                id: document_id,
+                kind: NodeKind::Document,
+                label: document.title.clone(),
+            });
+        }
+
+        let mut missing = BTreeSet::new();
+
+        for link in &self.links {
+            let target = self
+                .documents
+                .iter()
+                .find(|document| document.path == link.to);
+            let document_id = id(&link.to);
+            let to = match (&link.heading, target) {
+                (None, Some(_)) => document_id,
+                (None, None) => {
+                    missing.insert((document_id.clone(), document_id.clone()));
+                    document_id
+                }
+                (Some(heading), target) => {
+                    let existing = target.and_then(|target| {
+                        target
+                            .headings
+                            .iter()
+                            .find(|other| slug(other) == slug(heading))
+                    });
+                    let heading_id = format!("{document_id}#{}", existing.unwrap_or(heading));
+
+                    if existing.is_none() {
+                        missing.insert((heading_id.clone(), heading.clone()));
+                    }
+
+                    heading_id
+                }
+            };
+
+            edges.insert(Edge {
+                from: id(&self.documents[link.from].path),
+                to,
+                kind: EdgeKind::Link,
+            });
+        }
+
+        nodes.extend(missing.into_iter().map(|(id, label)| Node {
+            id,
+            kind: NodeKind::Missing,
+            label,
+        }));
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let export = Export {
+            nodes,
+            edges: edges.into_iter().collect(),
+        };
+
+        Ok(match format {
+            GraphFormat::Json => serde_json::to_string_pretty(&export)?,
+            GraphFormat::Dot => {
+                let mut dot = String::from("digraph norg {\n");
+
+                for node in &export.nodes {
+                    let attributes = match node.kind {
+                        NodeKind::Document => "shape=box",
+                        NodeKind::Heading => "shape=ellipse",
+                        NodeKind::Missing => "shape=ellipse, style=dashed",
+                    };
+
+                    dot += &format!(
+                        "    \"{}\" [label=\"{}\", {attributes}];\n",
+                        escape_dot(&node.id),
+                        escape_dot(&node.label)
+                    );
+                }
+
+                for edge in &export.edges {
+                    let attributes = match edge.kind {
+                        EdgeKind::Contains => " [style=dotted, arrowhead=none]",
+                        EdgeKind::Link => "",
+                    };
+
+                    dot += &format!(
+                        "    \"{}\" -> \"{}\"{attributes};\n",
+                        escape_dot(&edge.from),
+                        escape_dot(&edge.to)
+                    );
+                }
+
+                dot + "}\n"
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(path: &str, title: &str, headings: &[&str]) -> Document {
+        Document {
+            path: PathBuf::from(path),
+            title: title.to_string(),
+            headings: headings.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    fn link(from: usize, to: &str, heading: Option<&str>) -> Link {
+        Link {
+            from,
+            to: PathBuf::from(to),
+            heading: heading.map(str::to_string),
+        }
+    }
+
+    fn graph() -> LinkGraph {
+        LinkGraph {
+            documents: vec![
+                document("/w/a.norg", "Alpha", &["Intro"]),
+                document("/w/notes/b.norg", "Beta", &[]),
+                document("/w/c.norg", "Gamma", &[]),
+            ],
+            links: vec![
+                link(2, "/w/a.norg", Some("Intro")),
+                link(1, "/w/a.norg", None),
+                link(0, "/w/a.norg", Some("Intro")),
+                link(1, "/w/a.norg", Some("intro")),
+            ],
+        }
+    }
+
+    #[test]
+    fn lists_other_documents_linking_to_a_document() {
+        let graph = graph();
+        let titles = |path: &str| {
+            graph
+                .backlinks(Path::new(path))
+                .iter()
+                .map(|document| document.title.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(titles("/w/a.norg"), ["Beta", "Gamma"]);
+        assert!(titles("/w/notes/b.norg").is_empty());
+    }
+
+    #[test]
+    fn replaces_adds_and_removes_backlinks_sections() {
+        let graph = graph();
+        let update = |file: &str, source: &str| {
+            let mut ast = parse(source).unwrap();
+
+            update_backlinks(&mut ast, Path::new(file), &graph);
+            ast
+        };
+
+        assert_eq!(
+            update(
+                "/w/a.norg",
+                "* Intro\n* Backlinks\n- {:old:}[Old]\n* Later\n"
+            ),
+            parse("* Intro\n* Backlinks\n- {:notes/b:}[Beta]\n- {:c:}[Gamma]\n* Later\n").unwrap()
+        );
+        assert_eq!(
+            update("/w/a.norg", "Text.\n"),
+            parse("Text.\n* Backlinks\n- {:notes/b:}[Beta]\n- {:c:}[Gamma]\n").unwrap()
+        );
+        assert_eq!(
+            update(
+                "/w/c.norg",
+                "* Gamma\n* Backlinks\n** Old\n- {:a:}[Alpha]\n"
+            ),
+            parse("* Gamma\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn exports_documents_headings_and_missing_targets() -> Result<()> {
+        let graph = LinkGraph {
+            documents: vec![
+                document("/w/a.norg", "Alpha", &["Intro", "intro"]),
+                document("/w/notes/b.norg", r#"Say "hi""#, &[]),
+            ],
+            links: vec![
+                link(1, "/w/a.norg", Some("intro")),
+                link(1, "/w/gone.norg", None),
+                link(0, "/w/a.norg", Some("Outro")),
+                link(1, "/w/a.norg", Some("Intro")),
+            ],
+        };
+
+        assert_eq!(
+            graph.export(Path::new("/w"), GraphFormat::Dot)?,
+            r#"digraph norg {
+    "a" [label="Alpha", shape=box];
+    "a#Intro" [label="Intro", shape=ellipse];
+    "a#Outro" [label="Outro", shape=ellipse, style=dashed];
+    "gone" [label="gone", shape=ellipse, style=dashed];
+    "notes/b" [label="Say \"hi\"", shape=box];
+    "a" -> "a#Intro" [style=dotted, arrowhead=none];
+    "a" -> "a#Outro";
+    "notes/b" -> "a#Intro";
+    "notes/b" -> "gone";
+}
+"#
+        );
+
+        let json = serde_json::from_str::<serde_json::Value>(
+            &graph.export(Path::new("/w"), GraphFormat::Json)?,
+        )?;
+
+        assert_eq!(
+            json["nodes"][2],
+            serde_json::json!({ "id": "a#Outro", "kind": "missing", "label": "Outro" })
+        );
+        assert_eq!(
+            json["edges"][0],
+            serde_json::json!({ "from": "a", "to": "a#Intro", "kind": "contains" })
+        );
+        assert_eq!(json["edges"].as_array().map(Vec::len), Some(4));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::{fmt, path::Path};
+
+use eyre::Result;
+
+/// The amount of leading bytes inspected when deciding whether a file is binary.
+const SNIFF_LENGTH: usize = 8000;
+
+/// Why a file was left alone instead of being formatted.
+pub enum SkipReason {
+    TooLarge {
+        size: u64,
+        limit: u64,
+    },
+    Binary,
+    /// Its changes were declined with `--interactive`.
+    Declined,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooLarge { size, limit } => {
+                write!(
+                    f,
+                    "file is {size} bytes, exceeding the limit of {limit} bytes"
+                )
+            }
+            Self::Binary => write!(f, "file appears to be binary"),
+            Self::Declined => write!(f, "changes were declined"),
+        }
+    }
+}
+
+/// Checks the size of a file against the limit before it is read into memory.
+pub fn check_size(file: &Path, limit: u64) -> Result<Option<SkipReason>> {
+    let size = std::fs::metadata(file)?.len();
+
+    Ok((size > limit).then_some(SkipReason::TooLarge { size, limit }))
+}
+
+/// Treats content with a NUL byte near its start as binary. UTF-16 text is naturally full of NUL
+/// bytes, so content starting with a UTF-16 byte order mark is exempt.
+pub fn sniff(bytes: &[u8]) -> Option<SkipReason> {
+    if bytes.starts_with(&[0xFF, 0xFE]) || bytes.starts_with(&[0xFE, 0xFF]) {
+        return None;
+    }
+
+    bytes[..bytes.len().min(SNIFF_LENGTH)]
+        .contains(&0)
+        .then_some(SkipReason::Binary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_content_with_nul_bytes_near_its_start_for_binary() {
+        assert!(matches!(sniff(b"PK\x03\x04\0\0"), Some(SkipReason::Binary)));
+        assert!(sniff(b"* Heading\n").is_none());
+        assert!(sniff(&[0xFF, 0xFE, b'*', 0, b'\n', 0]).is_none());
+
+        let mut late = vec![b'a'; SNIFF_LENGTH];
+        late.push(0);
+
+        assert!(sniff(&late).is_none());
+    }
+
+    #[test]
+    fn skips_files_larger_than_the_limit() -> Result<()> {
+        let file = std::env::temp_dir().join(format!("norg-fmt-guard-{}.norg", std::process::id()));
+
+        std::fs::write(&file, "* Heading\n")?;
+
+        let within = check_size(&file, 10);
+        let beyond = check_size(&file, 9);
+        std::fs::remove_file(&file)?;
+
+        assert!(within?.is_none());
+        assert_eq!(
+            beyond?.map(|reason| reason.to_string()).as_deref(),
+            Some("file is 10 bytes, exceeding the limit of 9 bytes")
+        );
+
+        Ok(())
+    }
+}
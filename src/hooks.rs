@@ -0,0 +1,144 @@
+//! Installing git hooks that keep the documents of a repository formatted.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use eyre::{bail, eyre, Result, WrapErr};
+
+/// Marks hooks installed by norg-fmt, so that they are updated rather than refused.
+const MARKER: &str = "# Installed by `norg-fmt install-hooks`, which updates it when run again.";
+
+#[derive(Clone, Copy)]
+pub enum Hook {
+    /// Runs before every commit, on the staged documents.
+    PreCommit,
+    /// Runs before every push, on every tracked document.
+    PrePush,
+}
+
+impl Hook {
+    fn name(self) -> &'static str {
+        match self {
+            Hook::PreCommit => "pre-commit",
+            Hook::PrePush => "pre-push",
+        }
+    }
+
+    /// The script of the hook. With `write`, the documents are formatted rather than checked, and
+    /// the changes to them are staged again.
+    fn script(self, write: bool) -> String {
+        let command = match (self, write) {
+            (Hook::PreCommit, false) => "norg-fmt --check --changed --staged\n",
+            (Hook::PreCommit, true) => {
+                "norg-fmt --write --quiet --changed --staged || exit\ngit update-index --again\n"
+            }
+            (Hook::PrePush, _) => {
+                "git ls-files -z -- '*.norg' | norg-fmt --check --files-from - -0\n"
+            }
+        };
+
+        format!("#!/bin/sh\n{MARKER}\n\n{command}")
+    }
+}
+
+/// The directory the hooks of the repository in the working directory live in, which may be set
+/// with `core.hooksPath`.
+fn hooks_directory() -> Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--git-path", "hooks"])
+        .output()
+        .wrap_err("unable to run git")?;
+
+    if !output.status.success() {
+        bail!(
+            "unable to find the git hooks: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let directory = String::from_utf8(output.stdout)
+        .map_err(|_| eyre!("the path of the git hooks isn't valid UTF-8"))?;
+
+    Ok(PathBuf::from(directory.trim_end_matches(['\r', '\n'])))
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = std::fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o111);
+
+    Ok(std::fs::set_permissions(path, permissions)?)
+}
+
+#[cfg(not(unix))]
+fn make_executable(_: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Installs the hook in the repository in the working directory, or updates one installed before.
+/// Hooks that weren't installed by norg-fmt are only replaced with `force`.
+pub fn install(hook: Hook, write: bool, force: bool) -> Result<()> {
+    let directory = hooks_directory()?;
+    let path = directory.join(hook.name());
+    let script = hook.script(write);
+
+    let verb = match std::fs::read_to_string(&path) {
+        Ok(existing) if existing == script => {
+            println!("{} is up to date", path.display());
+
+            return Ok(());
+        }
+        Ok(existing) if existing.contains(MARKER) => "updated",
+        Ok(_) if !force => bail!(
+            "{} exists already and wasn't installed by norg-fmt, pass --force to replace it",
+            path.display()
+        ),
+        Ok(_) => "replaced",
+        Err(_) => "installed",
+    };
+
+    std::fs::create_dir_all(&directory)?;
+    std::fs::write(&path, script)
+        .wrap_err_with(|| format!("unable to write {}", path.display()))?;
+    make_executable(&path)?;
+
+    println!("{verb} {}", path.display());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_or_formats_the_staged_documents_before_committing() {
+        let header = format!("#!/bin/sh\n{MARKER}\n\n");
+
+        assert_eq!(
+            Hook::PreCommit.script(false),
+            header.clone() + "norg-fmt --check --changed --staged\n"
+        );
+        assert_eq!(
+            Hook::PreCommit.script(true),
+            header
+                + "norg-fmt --write --quiet --changed --staged || exit\n\
+                   git update-index --again\n"
+        );
+    }
+
+    #[test]
+    fn checks_the_tracked_documents_before_pushing() {
+        assert_eq!(
+            Hook::PrePush.script(false),
+            format!(
+                "#!/bin/sh\n{MARKER}\n\n\
+                 git ls-files -z -- '*.norg' | norg-fmt --check --files-from - -0\n"
+            )
+        );
+    }
+}
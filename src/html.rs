@@ -0,0 +1,408 @@
+//! Rendering documents as semantic HTML, for sharing notes with people who don't use Neorg.
+//!
+//! The HTML is a fragment meant to be embedded in a page: headings become `<h1>` to `<h6>`, lists
+//! `<ul>` and `<ol>` (nested by level), quotes `<blockquote>`, definitions a `<dl>` and code blocks
+//! `<pre><code>`. Nothing is styled, and the `@document.meta` block and comments are left out.
+//! Links to other documents point to their `.html` counterpart, so that a workspace exported file
+//! by file links up.
+
+use std::collections::HashMap;
+
+use rust_norg::{DetachedModifierExtension, LinkTarget, NorgASTFlat, ParagraphSegment, TodoStatus};
+
+use crate::exporter::{export, heading_id, link_href, visit, Exporter, Nestable, Rangeable};
+
+/// Escapes the characters with a meaning in HTML, in text and attribute values alike.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped += "&amp;",
+            '<' => escaped += "&lt;",
+            '>' => escaped += "&gt;",
+            '"' => escaped += "&quot;",
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// The element a nestable detached modifier is rendered in, and whether it holds its items as
+/// `<li>` elements (lists) or as paragraphs (quotes).
+fn container(kind: Nestable) -> (&'static str, bool) {
+    match kind {
+        Nestable::Quote => ("blockquote", false),
+        Nestable::OrderedList => ("ol", true),
+        Nestable::UnorderedList => ("ul", true),
+    }
+}
+
+struct Open {
+    tag: &'static str,
+    level: usize,
+    /// Whether the `<li>` of the last item is still open, so that a nested list can go into it.
+    item: bool,
+}
+
+/// Exports documents as HTML fragments, optionally giving their headings, footnotes and definitions
+/// ids derived from their titles. Headings with the same title get ids suffixed with `-2`, `-3` and
+/// so on.
+#[derive(Default)]
+pub struct Html {
+    html: String,
+    heading_ids: bool,
+    /// The number of times each heading id was used, to keep the ids of headings with the same
+    /// title unique.
+    ids: HashMap<String, usize>,
+    open: Vec<Open>,
+    in_definitions: bool,
+}
+
+impl Html {
+    pub fn new(heading_ids: bool) -> Self {
+        Self {
+            heading_ids,
+            ..Self::default()
+        }
+    }
+
+    fn close_containers(&mut self, level: usize, tag: Option<&str>) {
+        while let Some(top) = self.open.last() {
+            if top.level < level || (top.level == level && Some(top.tag) == tag) {
+                break;
+            }
+
+            if top.item {
+                self.html += "</li>\n";
+            }
+
+            self.html += &format!("</{}>\n", top.tag);
+            self.open.pop();
+        }
+    }
+
+    fn close_all(&mut self) {
+        self.close_containers(0, None);
+
+        if self.in_definitions {
+            self.html += "</dl>\n";
+            self.in_definitions = false;
+        }
+    }
+
+    fn inlines(&self, segments: &[ParagraphSegment]) -> String {
+        segments
+            .iter()
+            .map(|segment| self.inline(segment))
+            .collect()
+    }
+
+    fn inline(&self, segment: &ParagraphSegment) -> String {
+        match segment {
+            ParagraphSegment::AttachedModifier {
+                modifier_type: '%', ..
+            } => String::new(),
+            ParagraphSegment::AttachedModifier {
+                modifier_type,
+                content,
+            } => {
+                let content = self.inlines(content);
+
+                match modifier_type {
+                    '*' => format!("<strong>{content}</strong>"),
+                    '/' => format!("<em>{content}</em>"),
+                    '_' => format!("<u>{content}</u>"),
+                    '-' => format!("<s>{content}</s>"),
+                    '^' => format!("<sup>{content}</sup>"),
+                    ',' => format!("<sub>{content}</sub>"),
+                    '`' => format!("<code>{content}</code>"),
+                    '&' => format!("<var>{content}</var>"),
+                    '$' => format!("<span class=\"math\">{content}</span>"),
+                    '!' => format!("<span class=\"spoiler\">{content}</span>"),
+                    _ => content,
+                }
+            }
+            ParagraphSegment::InlineVerbatim(content) => format!(
+                "<code>{}</code>",
+                escape(&content.iter().map(ToString::to_string).collect::<String>())
+            ),
+            ParagraphSegment::Link {
+                filepath,
+                targets,
+                description,
+            } => {
+                let text = match description {
+                    Some(description) => self.inlines(description),
+                    None => self.link_text(filepath.as_deref(), targets),
+                };
+
+                format!(
+                    "<a href=\"{}\">{text}</a>",
+                    escape(&link_href(filepath.as_deref(), targets, "html"))
+                )
+            }
+            ParagraphSegment::Anchor {
+                content,
+                description,
+            } => self.inlines(description.as_deref().unwrap_or(content)),
+            ParagraphSegment::AnchorDefinition { content, target } => match target.as_ref() {
+                ParagraphSegment::Link {
+                    filepath, targets, ..
+                } => format!(
+                    "<a href=\"{}\">{}</a>",
+                    escape(&link_href(filepath.as_deref(), targets, "html")),
+                    self.inlines(content)
+                ),
+                _ => self.inlines(content),
+            },
+            ParagraphSegment::InlineLinkTarget(content) => format!(
+                "<span id=\"{}\">{}</span>",
+                escape(&heading_id(content)),
+                self.inlines(content)
+            ),
+            segment => escape(&String::from(segment.clone())),
+        }
+    }
+
+    fn link_text(&self, filepath: Option<&str>, targets: &[LinkTarget]) -> String {
+        match targets.first() {
+            Some(
+                LinkTarget::Heading { title, .. }
+                | LinkTarget::Footnote(title)
+                | LinkTarget::Definition(title)
+                | LinkTarget::Generic(title)
+                | LinkTarget::Wiki(title)
+                | LinkTarget::Extendable(title),
+            ) => self.inlines(title),
+            Some(
+                LinkTarget::Path(target) | LinkTarget::Url(target) | LinkTarget::Timestamp(target),
+            ) => escape(target),
+            None => escape(filepath.unwrap_or_default()),
+        }
+    }
+
+    /// Renders the content of a list item or quote, without wrapping a paragraph in `<p>`.
+    fn item_content(&mut self, node: &NorgASTFlat) -> String {
+        match node {
+            NorgASTFlat::Paragraph(content) => self.inlines(content),
+            node => {
+                let mut nested = Html {
+                    ids: std::mem::take(&mut self.ids),
+                    ..Html::new(self.heading_ids)
+                };
+
+                visit(&mut nested, node);
+                nested.close_all();
+                self.ids = nested.ids;
+
+                nested.html
+            }
+        }
+    }
+}
+
+impl Exporter for Html {
+    fn paragraph(&mut self, content: &[ParagraphSegment]) {
+        self.close_all();
+
+        let content = self.inlines(content);
+
+        self.html += &format!("<p>{content}</p>\n");
+    }
+
+    fn heading(
+        &mut self,
+        level: usize,
+        title: &[ParagraphSegment],
+        _extensions: &[DetachedModifierExtension],
+    ) {
+        self.close_all();
+
+        let level = level.clamp(1, 6);
+        let content = self.inlines(title);
+        let id = if self.heading_ids {
+            let id = heading_id(title);
+            let count = self.ids.entry(id.clone()).or_default();
+
+            *count += 1;
+
+            match *count {
+                1 => format!(" id=\"{}\"", escape(&id)),
+                count => format!(" id=\"{}-{count}\"", escape(&id)),
+            }
+        } else {
+            String::new()
+        };
+
+        self.html += &format!("<h{level}{id}>{content}</h{level}>\n");
+    }
+
+    fn nestable(
+        &mut self,
+        kind: Nestable,
+        level: usize,
+        extensions: &[DetachedModifierExtension],
+        content: &NorgASTFlat,
+    ) {
+        let (tag, is_list) = container(kind);
+
+        self.close_containers(level, Some(tag));
+
+        match self.open.last_mut() {
+            Some(top) if top.level == level => {
+                if std::mem::take(&mut top.item) {
+                    self.html += "</li>\n";
+                }
+            }
+            _ => {
+                self.html += &format!("<{tag}>\n");
+                self.open.push(Open {
+                    tag,
+                    level,
+                    item: false,
+                });
+            }
+        }
+
+        let checkbox = extensions.iter().find_map(|extension| match extension {
+            DetachedModifierExtension::Todo(TodoStatus::Done) => {
+                Some("<input type=\"checkbox\" checked disabled> ")
+            }
+            DetachedModifierExtension::Todo(_) => Some("<input type=\"checkbox\" disabled> "),
+            _ => None,
+        });
+        let content = self.item_content(content);
+
+        if is_list {
+            self.html += &format!("<li>{}{content}", checkbox.unwrap_or_default());
+
+            if let Some(top) = self.open.last_mut() {
+                top.item = true;
+            }
+        } else {
+            self.html += &format!("<p>{content}</p>\n");
+        }
+    }
+
+    fn rangeable(
+        &mut self,
+        kind: Rangeable,
+        title: &[ParagraphSegment],
+        _extensions: &[DetachedModifierExtension],
+        content: &[NorgASTFlat],
+    ) {
+        if kind == Rangeable::Definition {
+            self.close_containers(0, None);
+        } else {
+            self.close_all();
+        }
+
+        let id = if self.heading_ids {
+            format!(" id=\"{}\"", escape(&heading_id(title)))
+        } else {
+            String::new()
+        };
+        let title = self.inlines(title);
+        let content = content
+            .iter()
+            .map(|node| self.item_content(node))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        match kind {
+            Rangeable::Definition => {
+                if !self.in_definitions {
+                    self.html += "<dl>\n";
+                    self.in_definitions = true;
+                }
+
+                self.html += &format!("<dt{id}>{title}</dt>\n<dd>{content}</dd>\n");
+            }
+            Rangeable::Footnote => {
+                self.html +=
+                    &format!("<aside class=\"footnote\"{id}>\n<p><strong>{title}</strong></p>\n");
+                self.html += &format!("{content}\n</aside>\n");
+            }
+            Rangeable::Table => self.html += &format!("<p>{title}</p>\n{content}\n"),
+        }
+    }
+
+    fn verbatim_tag(&mut self, name: &[String], parameters: &[String], content: &str) {
+        self.close_all();
+
+        let class = match (name.join(".").as_str(), parameters.first()) {
+            ("document.meta", _) => return,
+            ("code", Some(language)) => format!(" class=\"language-{}\"", escape(language)),
+            _ => String::new(),
+        };
+
+        self.html += &format!("<pre><code{class}>{}</code></pre>\n", escape(content));
+    }
+
+    fn ranged_tag(&mut self, name: &[String], _parameters: &[String], content: &[NorgASTFlat]) {
+        self.close_all();
+
+        if name != ["comment"] {
+            for node in content {
+                visit(self, node);
+            }
+
+            self.close_all();
+        }
+    }
+
+    fn infirm_tag(&mut self, name: &[String], parameters: &[String]) {
+        self.close_all();
+
+        if let Some(source) = parameters.first().filter(|_| name == ["image"]) {
+            self.html += &format!("<img src=\"{}\" alt=\"\">\n", escape(source));
+        }
+    }
+
+    fn finish(&mut self) -> String {
+        self.close_all();
+        self.ids.clear();
+
+        std::mem::take(&mut self.html)
+    }
+}
+
+/// Renders a document as an HTML fragment, as exported by [`Html`].
+pub fn render(ast: &[NorgASTFlat], heading_ids: bool) -> String {
+    export(&mut Html::new(heading_ids), ast)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_norg::parse;
+
+    use super::*;
+
+    fn html(source: &str, heading_ids: bool) -> String {
+        render(&parse(source).unwrap(), heading_ids)
+    }
+
+    #[test]
+    fn nests_lists_by_level() {
+        assert_eq!(
+            html("* Notes\n- one\n-- nested\n- two\n> quoted\n", false),
+            "<h1>Notes</h1>\n<ul>\n<li>one<ul>\n<li>nested</li>\n</ul>\n</li>\n\
+             <li>two</li>\n</ul>\n<blockquote>\n<p>quoted</p>\n</blockquote>\n"
+        );
+    }
+
+    #[test]
+    fn links_to_unique_heading_ids_and_escapes_text() {
+        assert_eq!(
+            html(
+                "@document.meta\ntitle: Notes\n@end\n* Twice\n* Twice\nSee {:other:* Twice} and \
+                 `x<y`.\n",
+                true
+            ),
+            "<h1 id=\"twice\">Twice</h1>\n<h1 id=\"twice-2\">Twice</h1>\n\
+             <p>See <a href=\"other.html#twice\">Twice</a> and <code>x&lt;y</code>.</p>\n"
+        );
+    }
+}
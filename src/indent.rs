@@ -0,0 +1,49 @@
+use crate::{Config, IndentStyle};
+
+/// The indentation preceding a line.
+///
+/// Structural indentation (nesting levels) is rendered in the configured indent unit, whereas
+/// alignment padding (e.g. lining text up under a list item's content) is always rendered as
+/// spaces. This allows tabs to be used for nesting without breaking alignment.
+#[derive(Clone, Copy, Default)]
+pub struct Indent {
+    pub levels: usize,
+    pub align: usize,
+}
+
+impl Indent {
+    pub fn render(self, config: &Config) -> String {
+        let unit = match config.indent_style {
+            IndentStyle::Spaces => " ".repeat(config.indent_width),
+            IndentStyle::Tabs => "\t".to_string(),
+        };
+
+        unit.repeat(self.levels) + &" ".repeat(self.align)
+    }
+
+    /// The number of columns the indentation takes up, counting a tab as `indent_width` columns.
+    pub fn width(self, config: &Config) -> usize {
+        self.levels * config.indent_width + self.align
+    }
+
+    /// Indents every line of `text` apart from the first. Empty lines are left empty.
+    pub fn apply(self, text: &str, config: &Config) -> String {
+        let indent = self.render(config);
+        let mut output =
+            String::with_capacity(text.len() + indent.len() * text.matches('\n').count());
+
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                output.push('\n');
+
+                if !line.is_empty() {
+                    output.push_str(&indent);
+                }
+            }
+
+            output.push_str(line);
+        }
+
+        output
+    }
+}
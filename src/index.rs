@@ -0,0 +1,194 @@
+//! Generating an index of the documents of a workspace, grouped by the categories in their
+//! metadata.
+
+use std::{collections::BTreeMap, path::Path};
+
+use eyre::{bail, eyre, Result, WrapErr};
+use norg_fmt::{
+    context::FormatCtx,
+    converter::format_document,
+    metadata::Metadata,
+    refactor::{normalize_path, relative_path},
+    verify, Config,
+};
+use rust_norg::parse;
+
+use crate::{
+    encoding,
+    persist::{self, Generation},
+    workspace::{self, WalkOptions},
+};
+
+/// A document filed under a category.
+struct Entry {
+    title: String,
+    /// The path of the document relative to the index, without the `.norg` extension.
+    link: String,
+}
+
+/// Escapes the characters that would end a link description early.
+pub fn escape_description(title: &str) -> String {
+    title
+        .chars()
+        .flat_map(|c| match c {
+            '[' | ']' | '\\' => vec!['\\', c],
+            c => vec![c],
+        })
+        .collect()
+}
+
+/// Renders the index: a heading per category, listing links to the documents filed under it.
+fn render(categories: &BTreeMap<String, Vec<Entry>>) -> String {
+    let mut index = String::from("@document.meta\ntitle: Index\n@end\n");
+
+    for (category, entries) in categories {
+        index += &format!("\n* {category}\n");
+
+        for Entry { title, link } in entries {
+            index += &format!("- {{:{link}:}}[{}]\n", escape_description(title));
+        }
+    }
+
+    index
+}
+
+/// Generates the index of the documents below `root` at `output`, formatted with `config`, and
+/// prints whether it changed. Documents without categories are left out, as is the index itself.
+pub fn index(root: &Path, output: &Path, config: &Config, dry_run: bool) -> Result<()> {
+    let absolute =
+        |path: &Path| Ok::<_, std::io::Error>(normalize_path(&std::path::absolute(path)?));
+    let output_path = absolute(output)?;
+    let output_dir = output_path.parent().unwrap_or(Path::new(""));
+    let mut categories = BTreeMap::<String, Vec<Entry>>::new();
+
+    for file in workspace::collect_files(&[root.to_path_buf()], &WalkOptions::default())? {
+        let path = absolute(&file)?;
+
+        if path == output_path {
+            continue;
+        }
+
+        let Ok((content, _)) = encoding::read(&file, false) else {
+            eprintln!("warning: {}: unable to read, skipping it", file.display());
+            continue;
+        };
+        let Ok(ast) = parse(&content) else {
+            eprintln!("warning: {}: unable to parse, skipping it", file.display());
+            continue;
+        };
+        let Some(metadata) = Metadata::of(&ast) else {
+            continue;
+        };
+
+        let link = relative_path(output_dir, &path.with_extension(""));
+        let title = metadata.title().map_or_else(
+            || {
+                path.file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned()
+            },
+            str::to_string,
+        );
+
+        for category in metadata.categories() {
+            categories
+                .entry(category.to_string())
+                .or_default()
+                .push(Entry {
+                    title: title.clone(),
+                    link: link.clone(),
+                });
+        }
+    }
+
+    for entries in categories.values_mut() {
+        entries.sort_by(|a, b| a.title.cmp(&b.title).then_with(|| a.link.cmp(&b.link)));
+    }
+
+    let source = render(&categories);
+    let ast = parse(&source).map_err(|err| eyre!("unable to parse the index: {err:?}"))?;
+    let ctx = FormatCtx::new(config).with_source(&source);
+    let formatted = format_document(ast.clone(), &ctx)?.concat();
+
+    verify::verify(&ast, &formatted, config).wrap_err("unable to format the index")?;
+
+    let existing = std::fs::read(output).ok();
+
+    if existing.as_deref() == Some(formatted.as_bytes()) {
+        println!("{} is up to date", output.display());
+
+        return Ok(());
+    }
+
+    println!(
+        "{}: {} categories, {} entries",
+        output.display(),
+        categories.len(),
+        categories.values().map(Vec::len).sum::<usize>()
+    );
+
+    if dry_run {
+        return Ok(());
+    }
+
+    match existing {
+        Some(existing) => persist::replace(output, Generation::of(&existing), false, |sink| {
+            std::io::Write::write_all(sink, formatted.as_bytes())
+        })
+        .wrap_err_with(|| format!("unable to write {}", output.display())),
+        None if output_dir.is_dir() => std::fs::write(output, formatted)
+            .wrap_err_with(|| format!("unable to write {}", output.display())),
+        None => bail!("{} does not exist", output_dir.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_link_descriptions() {
+        assert_eq!(escape_description(r"a [b] \c"), r"a \[b\] \\c");
+    }
+
+    #[test]
+    fn groups_documents_by_category() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("norg-fmt-index-{}", std::process::id()));
+        let output = root.join("index.norg");
+
+        std::fs::create_dir_all(root.join("notes"))?;
+
+        for (file, content) in [
+            ("index.norg", "@document.meta\ncategories: work\n@end\n"),
+            (
+                "b.norg",
+                "@document.meta\ntitle: Beta\ncategories: [work, home]\n@end\n",
+            ),
+            (
+                "notes/a.norg",
+                "@document.meta\ntitle: Alpha\ncategories: work\n@end\n",
+            ),
+            ("notes/c.norg", "* Uncategorized\n"),
+        ] {
+            std::fs::write(root.join(file), content)?;
+        }
+
+        let indexed = index(&root, &output, &Config::default(), false);
+        let content = std::fs::read_to_string(&output);
+        std::fs::remove_dir_all(&root)?;
+
+        indexed?;
+
+        let content = content?;
+        let home = content.find("* home").unwrap();
+        let work = content.find("* work").unwrap();
+        let links = content[work..].lines().skip(1).collect::<Vec<_>>();
+
+        assert!(content.starts_with("@document.meta\ntitle: Index\n@end\n"));
+        assert!(content[home..work].contains("- {:b:}[Beta]"));
+        assert_eq!(links, ["- {:notes/a:}[Alpha]", "- {:b:}[Beta]"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+//! Describing the build of the formatter and where it looks for its configuration, as JSON for
+//! integration scripts and bug reports.
+
+use std::path::Path;
+
+use norg_fmt::{grammar, options::OPTIONS_VERSION, protocol::PROTOCOL_VERSION};
+use serde_json::{json, Value};
+
+use crate::{cache, config_file, editorconfig::EditorConfigs};
+
+/// The cargo features the formatter was built with.
+fn features() -> Vec<&'static str> {
+    [
+        ("cli", cfg!(feature = "cli")),
+        ("testing", cfg!(feature = "testing")),
+        ("tracing", cfg!(feature = "tracing")),
+        ("spellcheck", cfg!(feature = "spellcheck")),
+    ]
+    .into_iter()
+    .filter_map(|(feature, enabled)| enabled.then_some(feature))
+    .collect()
+}
+
+/// Describes the formatter, with the configuration that applies to the files in `directory`.
+pub fn info(directory: &Path) -> Value {
+    let (min, max) = (grammar::MIN_SUPPORTED, grammar::MAX_SUPPORTED);
+
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "grammar": {
+            "version": grammar::VERSION,
+            "revision": grammar::REVISION,
+            "supported": grammar::is_supported(grammar::VERSION),
+            "supportedRange": format!(
+                ">={}.{}.{}, <{}.{}.{}",
+                min.0, min.1, min.2, max.0, max.1, max.2
+            ),
+        },
+        "specification": grammar::SPECIFICATION,
+        "optionsVersion": OPTIONS_VERSION,
+        "protocolVersion": PROTOCOL_VERSION,
+        "features": features(),
+        "config": {
+            "file": std::env::var_os(config_file::CONFIG_VAR)
+                .map(|path| Path::new(&path).display().to_string()),
+            "fileVariable": config_file::CONFIG_VAR,
+            "optionVariablePrefix": config_file::ENV_PREFIX,
+            "editorconfig": EditorConfigs::default()
+                .paths(directory)
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>(),
+        },
+        "cache": cache::cache_path().map(|path| path.display().to_string()),
+    })
+}
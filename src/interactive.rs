@@ -0,0 +1,269 @@
+//! Asking for approval before writing the changes to each file, for `--interactive`.
+
+use std::{
+    io::{BufRead, IsTerminal, Write},
+    path::Path,
+};
+
+use eyre::{bail, Result};
+use rust_norg::parse;
+
+use crate::{
+    diff::{self, unified_diff},
+    term::Style,
+};
+
+const FILE_HELP: &str = "\
+y - write the changes to this file
+n - leave this file untouched
+s - pick the changes to write hunk by hunk
+a - write the changes to this file and all remaining ones
+q - leave this file and all remaining ones untouched
+";
+
+const HUNK_HELP: &str = "\
+y - write this hunk
+n - leave this hunk out
+a - write this hunk and all remaining hunks of the file
+d - leave this hunk and all remaining hunks of the file out
+q - leave this hunk and all remaining hunks out, and all remaining files untouched
+";
+
+/// Asks whether to write the changes to each file, remembering answers that apply to all of the
+/// remaining ones.
+pub struct Prompt {
+    /// Where answers are read from, a line each.
+    answers: Box<dyn BufRead>,
+    accept_all: bool,
+    quit: bool,
+}
+
+impl Prompt {
+    pub fn new() -> Result<Self> {
+        if !std::io::stdin().is_terminal() {
+            bail!("--interactive needs a terminal to read answers from");
+        }
+
+        Ok(Self {
+            answers: Box::new(std::io::stdin().lock()),
+            accept_all: false,
+            quit: false,
+        })
+    }
+
+    /// Whether every remaining file is to be left untouched.
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Prints `question` and reads the answer, which is `None` at the end of the input.
+    fn ask(&mut self, question: &str, style: Style) -> Result<Option<String>> {
+        print!("{}", style.bold(question));
+        std::io::stdout().flush()?;
+
+        let mut answer = String::new();
+
+        if self.answers.read_line(&mut answer)? == 0 {
+            println!();
+
+            return Ok(None);
+        }
+
+        Ok(Some(answer.trim().to_string()))
+    }
+
+    /// Prints the diff of the changes to `file` and asks whether to write them, returning the
+    /// content to write, or `None` to leave the file untouched. Reaching the end of the input
+    /// counts as quitting.
+    pub fn review(
+        &mut self,
+        file: &Path,
+        original: &str,
+        formatted: &str,
+        style: Style,
+    ) -> Result<Option<String>> {
+        if self.accept_all || self.quit {
+            return Ok(self.accept_all.then(|| formatted.to_string()));
+        }
+
+        print!("{}", unified_diff(file, original, formatted, style));
+
+        let question = format!("Write the changes to {} [y,n,s,a,q,?]? ", file.display());
+
+        loop {
+            match self.ask(&question, style)?.as_deref() {
+                Some("y") => return Ok(Some(formatted.to_string())),
+                Some("n") => return Ok(None),
+                Some("s") => return self.review_hunks(file, original, formatted, style),
+                Some("a") => {
+                    self.accept_all = true;
+
+                    return Ok(Some(formatted.to_string()));
+                }
+                Some("q") | None => {
+                    self.quit = true;
+
+                    return Ok(None);
+                }
+                _ => print!("{FILE_HELP}"),
+            }
+        }
+    }
+
+    /// Asks whether to write each hunk of the changes to `file`, returning the original content
+    /// with the accepted hunks applied. The result is checked to still parse before it is written.
+    fn review_hunks(
+        &mut self,
+        file: &Path,
+        original: &str,
+        formatted: &str,
+        style: Style,
+    ) -> Result<Option<String>> {
+        let hunks = diff::hunks(original, formatted, style);
+        let mut accepted = vec![false; hunks.len()];
+        let mut rest = None;
+
+        for (index, hunk) in hunks.iter().enumerate() {
+            if let Some(accept) = rest {
+                accepted[index] = accept;
+                continue;
+            }
+
+            print!("{}", hunk.diff);
+
+            let question = format!(
+                "Write this hunk ({}/{}) [y,n,a,d,q,?]? ",
+                index + 1,
+                hunks.len()
+            );
+
+            loop {
+                match self.ask(&question, style)?.as_deref() {
+                    Some("y") => accepted[index] = true,
+                    Some("n") => {}
+                    Some("a") => {
+                        accepted[index] = true;
+                        rest = Some(true);
+                    }
+                    Some("d") => rest = Some(false),
+                    Some("q") | None => {
+                        self.quit = true;
+                        rest = Some(false);
+                    }
+                    _ => {
+                        print!("{HUNK_HELP}");
+                        continue;
+                    }
+                }
+
+                break;
+            }
+        }
+
+        if !accepted.contains(&true) {
+            return Ok(None);
+        }
+
+        let content = diff::apply(
+            original,
+            hunks
+                .iter()
+                .zip(&accepted)
+                .filter(|&(_, &is_accepted)| is_accepted)
+                .map(|(hunk, _)| hunk),
+        );
+
+        if let Err(err) = parse(&content) {
+            bail!(
+                "{}: the selected hunks don't parse together ({err:?}), leaving the file untouched",
+                file.display()
+            );
+        }
+
+        Ok(Some(content))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::term::ColorChoice;
+
+    fn prompt(answers: &'static str) -> Prompt {
+        Prompt {
+            answers: Box::new(answers.as_bytes()),
+            accept_all: false,
+            quit: false,
+        }
+    }
+
+    fn review(prompt: &mut Prompt) -> Option<String> {
+        prompt
+            .review(
+                Path::new("notes.norg"),
+                "*  Heading\n",
+                "* Heading\n",
+                Style::stdout(ColorChoice::Never),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn writes_the_files_that_were_accepted() {
+        let mut prompt = prompt("n\n?\ny\n");
+
+        assert_eq!(review(&mut prompt), None);
+        assert_eq!(review(&mut prompt).as_deref(), Some("* Heading\n"));
+        assert!(!prompt.has_quit());
+    }
+
+    #[test]
+    fn remembers_answers_for_all_remaining_files() {
+        let mut accepting = prompt("a\n");
+
+        assert_eq!(review(&mut accepting).as_deref(), Some("* Heading\n"));
+        assert_eq!(review(&mut accepting).as_deref(), Some("* Heading\n"));
+
+        let mut quitting = prompt("q\ny\n");
+
+        assert_eq!(review(&mut quitting), None);
+        assert!(quitting.has_quit());
+        assert_eq!(review(&mut quitting), None);
+
+        let mut ended = prompt("");
+
+        assert_eq!(review(&mut ended), None);
+        assert!(ended.has_quit());
+    }
+
+    #[test]
+    fn writes_the_hunks_that_were_accepted() {
+        let original = format!("*  A\n{}*  B\n", "Text.\n".repeat(10));
+        let formatted = original.replace("*  ", "* ");
+        let review = |prompt: &mut Prompt| {
+            prompt
+                .review(
+                    Path::new("notes.norg"),
+                    &original,
+                    &formatted,
+                    Style::stdout(ColorChoice::Never),
+                )
+                .unwrap()
+        };
+
+        assert_eq!(
+            review(&mut prompt("s\ny\n?\nn\n")),
+            Some(original.replacen("*  ", "* ", 1))
+        );
+        assert_eq!(
+            review(&mut prompt("s\nn\na\n")),
+            Some(original.replace("*  B", "* B"))
+        );
+        assert_eq!(review(&mut prompt("s\nd\n")), None);
+
+        let mut quitting = prompt("s\nq\n");
+
+        assert_eq!(review(&mut quitting), None);
+        assert!(quitting.has_quit());
+    }
+}
@@ -0,0 +1,397 @@
+//! Rules for journals, where every heading is a day: normalizing the dates headings are titled
+//! with to a single format, and sorting the entries of a day by the time they are titled with.
+
+use itertools::Itertools;
+use rust_norg::{parse, NorgASTFlat};
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// The directives a date format may contain.
+pub const DATE_DIRECTIVES: &str = "YmdeBbAa%";
+
+/// A day of the Gregorian calendar.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Date {
+    pub year: u32,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    fn new(year: u32, month: u32, day: u32) -> Option<Self> {
+        let is_leap_year = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let days = match month {
+            2 if is_leap_year => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => return None,
+        };
+
+        (year > 0 && (1..=days).contains(&day)).then_some(Self { year, month, day })
+    }
+
+    /// The day of the week, where 0 is Sunday.
+    fn weekday(self) -> usize {
+        const OFFSETS: [u32; 12] = [0, 3, 2, 5, 0, 3, 5, 1, 4, 6, 2, 4];
+
+        let year = if self.month < 3 {
+            self.year - 1
+        } else {
+            self.year
+        };
+
+        ((year + year / 4 - year / 100 + year / 400 + OFFSETS[self.month as usize - 1] + self.day)
+            % 7) as usize
+    }
+
+    /// Renders the date with a `strftime`-like format: `%Y` is the year, `%m` and `%d` the
+    /// zero-padded month and day, `%e` the day without padding, `%B` and `%b` the full and
+    /// abbreviated name of the month, and `%A` and `%a` those of the weekday.
+    pub fn format(self, format: &str) -> String {
+        let mut output = String::new();
+        let mut chars = format.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                output.push(c);
+                continue;
+            }
+
+            let month = MONTHS[self.month as usize - 1];
+            let weekday = WEEKDAYS[self.weekday()];
+
+            match chars.next() {
+                Some('Y') => output += &self.year.to_string(),
+                Some('m') => output += &format!("{:02}", self.month),
+                Some('d') => output += &format!("{:02}", self.day),
+                Some('e') => output += &self.day.to_string(),
+                Some('B') => output += month,
+                Some('b') => output += &month[..3],
+                Some('A') => output += weekday,
+                Some('a') => output += &weekday[..3],
+                Some('%') => output.push('%'),
+                Some(other) => {
+                    output.push('%');
+                    output.push(other);
+                }
+                None => output.push('%'),
+            }
+        }
+
+        output
+    }
+}
+
+/// The month a word names, in full or abbreviated to three letters, e.g. `May` or `sep`.
+fn month(word: &str) -> Option<u32> {
+    let word = word.trim_end_matches('.').to_lowercase();
+
+    MONTHS
+        .iter()
+        .position(|month| {
+            let month = month.to_lowercase();
+
+            word == month || (word.len() >= 3 && month.starts_with(&word))
+        })
+        .map(|index| index as u32 + 1)
+}
+
+fn is_weekday(word: &str) -> bool {
+    let word = word.trim_end_matches([',', '.']).to_lowercase();
+
+    word.len() >= 3
+        && WEEKDAYS
+            .iter()
+            .any(|weekday| weekday.to_lowercase().starts_with(&word))
+}
+
+/// Parses a date written as `2024-05-01` (or with `/` or `.`), `01.05.2024`, `May 1, 2024` or
+/// `1 May 2024`, optionally preceded by the weekday, e.g. `Wednesday, May 1 2024`.
+pub fn parse_date(text: &str) -> Option<Date> {
+    let number = |word: &str| -> Option<u32> {
+        let word = word.trim_end_matches(',');
+        let digits = ["st", "nd", "rd", "th"]
+            .iter()
+            .find_map(|suffix| word.strip_suffix(suffix))
+            .unwrap_or(word);
+
+        (!digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()))
+            .then(|| digits.parse().ok())
+            .flatten()
+    };
+    let mut words = text.split_whitespace().collect::<Vec<_>>();
+
+    if words.first().is_some_and(|word| is_weekday(word)) {
+        words.remove(0);
+    }
+
+    match words[..] {
+        [date] => {
+            let parts = date.split(['-', '/', '.']).collect::<Vec<_>>();
+
+            match parts[..] {
+                [year, month, day] if year.len() == 4 => {
+                    Date::new(number(year)?, number(month)?, number(day)?)
+                }
+                [day, month, year] if year.len() == 4 && date.contains('.') => {
+                    Date::new(number(year)?, number(month)?, number(day)?)
+                }
+                _ => None,
+            }
+        }
+        [first, second, year] => {
+            let (month, day) = match month(first) {
+                Some(month) => (month, number(second)?),
+                None => (month(second)?, number(first)?),
+            };
+
+            Date::new(number(year)?, month, day)
+        }
+        _ => None,
+    }
+}
+
+/// Parses the time an entry is titled with, e.g. `09:30`, `9:30 pm` or `14:00 Standup`, as the
+/// number of minutes since midnight.
+pub fn parse_time(text: &str) -> Option<u32> {
+    let mut words = text.split_whitespace();
+    let time = words.next()?;
+    let is_pm = |suffix: &str| match suffix.to_ascii_lowercase().as_str() {
+        "am" => Some(false),
+        "pm" => Some(true),
+        _ => None,
+    };
+    let (time, suffix) = match time.char_indices().rev().nth(1) {
+        Some((index, _)) if is_pm(&time[index..]).is_some() => {
+            (&time[..index], is_pm(&time[index..]))
+        }
+        _ => (time, words.next().and_then(is_pm)),
+    };
+    let (hours, minutes) = time.split_once(':')?;
+    let is_number =
+        |part: &str| (1..=2).contains(&part.len()) && part.chars().all(|c| c.is_ascii_digit());
+
+    if !is_number(hours) || minutes.len() != 2 || !is_number(minutes) {
+        return None;
+    }
+
+    let (hours, minutes) = (hours.parse::<u32>().ok()?, minutes.parse::<u32>().ok()?);
+    let hours = match suffix {
+        Some(_) if !(1..=12).contains(&hours) => return None,
+        Some(false) => hours % 12,
+        Some(true) => hours % 12 + 12,
+        None => hours,
+    };
+
+    (hours < 24 && minutes < 60).then_some(hours * 60 + minutes)
+}
+
+fn heading_level(node: &NorgASTFlat) -> Option<u16> {
+    match node {
+        NorgASTFlat::Heading { level, .. } => Some(*level),
+        NorgASTFlat::CarryoverTag { next_object, .. } => heading_level(next_object),
+        _ => None,
+    }
+}
+
+fn heading_title(node: &NorgASTFlat) -> Option<String> {
+    match node {
+        NorgASTFlat::Heading { title, .. } => {
+            Some(title.iter().cloned().map_into::<String>().collect())
+        }
+        NorgASTFlat::CarryoverTag { next_object, .. } => heading_title(next_object),
+        _ => None,
+    }
+}
+
+/// Rewrites every heading titled with a date in the given format, e.g. `%Y-%m-%d`.
+pub fn normalize_dates(ast: &mut [NorgASTFlat], format: &str) {
+    fn visit(node: &mut NorgASTFlat, format: &str) {
+        match node {
+            NorgASTFlat::Heading { level, title, .. } => {
+                let text = title
+                    .iter()
+                    .cloned()
+                    .map_into::<String>()
+                    .collect::<String>();
+                let Some(date) = parse_date(&text) else {
+                    return;
+                };
+                let normalized = date.format(format);
+
+                if normalized == text.trim() {
+                    return;
+                }
+
+                // Parsed again, so that the title is made up of the same segments as when the
+                // formatted document is parsed.
+                let heading = format!("{} {normalized}\n", "*".repeat((*level).into()));
+
+                if let Ok([NorgASTFlat::Heading { title: parsed, .. }]) = parse(&heading).as_deref()
+                {
+                    *title = parsed.clone();
+                }
+            }
+            NorgASTFlat::CarryoverTag { next_object, .. } => visit(next_object, format),
+            _ => {}
+        }
+    }
+
+    for node in ast {
+        visit(node, format);
+    }
+}
+
+/// Sorts the entries of every day, i.e. the sections directly beneath a heading titled with a
+/// date, by the time they are titled with. Entries without a time stay where they are, as does
+/// everything before the first entry.
+pub fn sort_entries(ast: &mut [NorgASTFlat]) {
+    let mut index = 0;
+
+    while index < ast.len() {
+        let (Some(level), Some(title)) = (heading_level(&ast[index]), heading_title(&ast[index]))
+        else {
+            index += 1;
+            continue;
+        };
+        let end = ast[index + 1..]
+            .iter()
+            .position(|node| heading_level(node).is_some_and(|other| other <= level))
+            .map_or(ast.len(), |position| index + 1 + position);
+
+        if parse_date(&title).is_none() {
+            index += 1;
+            continue;
+        }
+
+        // The entries, each starting at a heading one level below the day.
+        let starts = (index + 1..end)
+            .filter(|&start| heading_level(&ast[start]) == Some(level + 1))
+            .collect::<Vec<_>>();
+        let mut entries = starts
+            .iter()
+            .enumerate()
+            .map(|(i, &start)| {
+                let end = starts.get(i + 1).copied().unwrap_or(end);
+
+                ast[start..end].to_vec()
+            })
+            .collect::<Vec<_>>();
+        let times = entries
+            .iter()
+            .map(|entry| heading_title(&entry[0]).and_then(|title| parse_time(&title)))
+            .collect::<Vec<_>>();
+
+        // Only the slots of timed entries are refilled, in order of their times.
+        let slots = (0..entries.len())
+            .filter(|&i| times[i].is_some())
+            .collect::<Vec<_>>();
+        let sorted = slots
+            .iter()
+            .copied()
+            .sorted_by_key(|&i| times[i])
+            .map(|i| entries[i].clone())
+            .collect::<Vec<_>>();
+
+        for (slot, entry) in slots.into_iter().zip(sorted) {
+            entries[slot] = entry;
+        }
+
+        // Entries are only reordered, so they fill exactly the range they came from.
+        if let Some(&first) = starts.first() {
+            for (node, entry) in ast[first..end]
+                .iter_mut()
+                .zip(entries.into_iter().flatten())
+            {
+                *node = entry;
+            }
+        }
+
+        index = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAY_FIRST: Date = Date {
+        year: 2024,
+        month: 5,
+        day: 1,
+    };
+
+    #[test]
+    fn parses_dates() {
+        for text in [
+            "2024-05-01",
+            "2024/5/1",
+            "01.05.2024",
+            "May 1, 2024",
+            "1st may 2024",
+            "Wednesday, May 1 2024",
+        ] {
+            assert_eq!(parse_date(text), Some(MAY_FIRST), "{text}");
+        }
+
+        assert!(parse_date("2024-02-29").is_some());
+        assert_eq!(parse_date("2023-02-29"), None);
+        assert_eq!(parse_date("05/01/2024"), None);
+        assert_eq!(parse_date("Meeting notes"), None);
+    }
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(parse_time("09:30"), Some(9 * 60 + 30));
+        assert_eq!(parse_time("9:30 pm"), Some(21 * 60 + 30));
+        assert_eq!(parse_time("12:15am Late"), Some(15));
+        assert_eq!(parse_time("14:00 Standup"), Some(14 * 60));
+        assert_eq!(parse_time("13:00 pm"), None);
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("9:5"), None);
+        assert_eq!(parse_time("Standup"), None);
+    }
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(MAY_FIRST.format("%Y-%m-%d"), "2024-05-01");
+        assert_eq!(MAY_FIRST.format("%A, %B %e %Y"), "Wednesday, May 1 2024");
+        assert_eq!(MAY_FIRST.format("%a %b %d %% %q"), "Wed May 01 % %q");
+    }
+
+    #[test]
+    fn sorts_timed_entries_only() {
+        let mut ast = parse("* 2024-05-01\n** 14:00 Standup\n** Notes\n** 09:30 Coffee\n").unwrap();
+
+        sort_entries(&mut ast);
+
+        assert_eq!(
+            ast,
+            parse("* 2024-05-01\n** 09:30 Coffee\n** Notes\n** 14:00 Standup\n").unwrap()
+        );
+    }
+}
@@ -0,0 +1,466 @@
+//! The formatting core of norg-fmt: converts parsed Norg documents back into formatted text.
+//!
+//! Documents are formatted by [`converter::format_document`] with a [`context::FormatCtx`], or by
+//! [`tree::format_tree`] when they were parsed into a tree. The formatter
+//! used for each kind of node can be replaced through the context's [`registry::Registry`].
+//! Parts of a document can override the configuration with a `+norgfmt` attribute, see
+//! [`overrides`].
+//!
+//! Applications formatting many documents, possibly from several threads, can use a
+//! [`formatter::Formatter`] instead, which parses and formats whole documents the way the command
+//! line does. Documents that may have unsaved changes in an editor are read through a
+//! [`vfs::SourceProvider`]. Options can be read from configuration files or sent by editors as
+//! [`options::FormatOptions`].
+
+pub mod ast_json;
+pub mod context;
+pub mod converter;
+pub mod dump;
+pub mod explain;
+pub mod exporter;
+pub mod fingerprint;
+pub mod formatter;
+pub mod grammar;
+pub mod html;
+mod indent;
+pub mod journal;
+pub mod lint;
+pub mod metadata;
+pub mod options;
+pub mod overrides;
+pub mod pandoc;
+pub mod placeholders;
+pub mod profile;
+pub mod prose;
+pub mod protocol;
+pub mod refactor;
+pub mod registry;
+pub mod slug;
+pub mod source_map;
+pub mod stable_hash;
+mod tags;
+pub mod tasks;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod trace;
+pub mod tree;
+pub mod typography;
+pub mod verify;
+pub mod vfs;
+pub mod wikilinks;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    str::FromStr,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Implements `FromStr` and `Display` for an enum of option values, using the names the values
+/// have on the command line. Names are matched case-insensitively.
+macro_rules! option_values {
+    ($type:ty { $($variant:ident => $name:literal),* $(,)? }) => {
+        impl FromStr for $type {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s.to_ascii_lowercase().as_str() {
+                    $($name => Ok(Self::$variant),)*
+                    _ => Err(format!(
+                        "`{s}` is not one of {}",
+                        [$(concat!("`", $name, "`")),*].join(", ")
+                    )),
+                }
+            }
+        }
+
+        impl fmt::Display for $type {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(match self {
+                    $(Self::$variant => $name,)*
+                })
+            }
+        }
+    };
+}
+
+/// The unit used to render structural indentation. Alignment padding is always rendered as spaces.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum IndentStyle {
+    #[default]
+    Spaces,
+    Tabs,
+}
+
+/// The line endings formatted documents are written with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// Converts formatted text, which is written with `\n` line endings, to these line endings.
+    pub fn apply(self, text: String) -> String {
+        match self {
+            Self::Lf => text,
+            Self::Crlf => text.replace("\r\n", "\n").replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// Determines where the wrapped lines of a list item or quote start.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ContinuationIndent {
+    /// Align wrapped lines under the first character of the content.
+    #[default]
+    Text,
+    /// Indent wrapped lines by a single indent unit past the marker, however long it is.
+    Marker,
+}
+
+/// Controls the conversion between regular (`*text*`) and free-form (`*|text|*`) markup.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum FreeFormConversion {
+    /// Use free-form markup only where it allows escape sequences to be dropped.
+    #[default]
+    Auto,
+    /// Never convert; markup is kept in the form it was written in.
+    Never,
+    /// Never emit free-form markup, escaping characters where necessary instead.
+    AlwaysPreferEscapes,
+}
+
+/// Determines whether links repeated within a document are written inline or as anchors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum LinkStyle {
+    /// Keep links and anchors as they were written.
+    #[default]
+    Preserve,
+    /// Turn links to a target that is linked to repeatedly into an anchor definition and anchors
+    /// referring to it.
+    Anchors,
+    /// Turn every anchor with a definition in the document into an inline link.
+    Inline,
+}
+
+/// Determines the form links to other documents are written in, see [`wikilinks`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum WikilinkStyle {
+    /// Keep links to documents as they were written.
+    #[default]
+    Preserve,
+    /// Write every link to a document in full, e.g. `{:note}` as `{:note:}`.
+    Expand,
+    /// Write links to a whole document in shorthand, e.g. `{:note:}` as `{:note}`.
+    Collapse,
+}
+
+/// Determines how the blank lines at the start and end of verbatim tags such as `@code` are
+/// handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum VerbatimBlankLines {
+    /// Keep the content exactly as it was written.
+    #[default]
+    Preserve,
+    /// Remove leading and trailing blank lines, so that `@end` sits flush against the last line.
+    Flush,
+    /// Remove leading blank lines and leave exactly one blank line before `@end`.
+    Spaced,
+}
+
+/// Determines what the title in the `@document.meta` block of a document has to match.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum MetadataTitle {
+    /// The title of the first level-1 heading.
+    #[default]
+    Heading,
+    /// The name of the file, without the `.norg` extension.
+    Filename,
+}
+
+/// A check run on every document, whose findings are reported as warnings (see [`lint`]).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Lint {
+    /// Headings sharing a title under the same parent, which makes links to them ambiguous.
+    DuplicateHeadings,
+    /// Footnotes and definitions that are never referenced, and links to ones that don't exist.
+    References,
+    /// Recurrences, dates and timestamps of tasks that can't be understood.
+    TaskDates,
+    /// A title in `@document.meta` that doesn't match the one it has to match.
+    MetadataTitle,
+}
+
+/// The typographic conventions prose follows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Locale {
+    /// No space before any punctuation.
+    #[default]
+    #[serde(rename = "en")]
+    #[cfg_attr(feature = "cli", value(name = "en"))]
+    English,
+    /// A space before `;`, `:`, `!` and `?`.
+    #[serde(rename = "fr")]
+    #[cfg_attr(feature = "cli", value(name = "fr"))]
+    French,
+}
+
+/// The constructs whose line length can be configured apart from the rest of the document.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Construct {
+    /// Quotes (`>`), whose content is wrapped to the line length.
+    Quotes,
+    /// Ordered and unordered lists, whose content is wrapped to the line length.
+    Lists,
+    /// Headings, which are never wrapped, so exceeding the line length only emits a warning.
+    Headings,
+}
+
+option_values!(IndentStyle {
+    Spaces => "spaces",
+    Tabs => "tabs",
+});
+
+option_values!(LineEnding {
+    Lf => "lf",
+    Crlf => "crlf",
+});
+
+option_values!(ContinuationIndent {
+    Text => "text",
+    Marker => "marker",
+});
+
+option_values!(FreeFormConversion {
+    Auto => "auto",
+    Never => "never",
+    AlwaysPreferEscapes => "always-prefer-escapes",
+});
+
+option_values!(LinkStyle {
+    Preserve => "preserve",
+    Anchors => "anchors",
+    Inline => "inline",
+});
+
+option_values!(WikilinkStyle {
+    Preserve => "preserve",
+    Expand => "expand",
+    Collapse => "collapse",
+});
+
+option_values!(VerbatimBlankLines {
+    Preserve => "preserve",
+    Flush => "flush",
+    Spaced => "spaced",
+});
+
+option_values!(MetadataTitle {
+    Heading => "heading",
+    Filename => "filename",
+});
+
+option_values!(Locale {
+    English => "en",
+    French => "fr",
+});
+
+option_values!(Construct {
+    Quotes => "quotes",
+    Lists => "lists",
+    Headings => "headings",
+});
+
+/// Determines how the content of a specific tag is formatted.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TagHandling {
+    /// Leave the content exactly as it was written.
+    Verbatim,
+    /// Format the content as Norg, like any other content.
+    Norg,
+    /// Pipe the content through an external program and use its output instead.
+    Command(String),
+}
+
+impl FromStr for TagHandling {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "verbatim" => Ok(Self::Verbatim),
+            "norg" => Ok(Self::Norg),
+            _ => match s.strip_prefix("command:") {
+                Some(command) if !command.trim().is_empty() => {
+                    Ok(Self::Command(command.trim().to_string()))
+                }
+                _ => Err(format!(
+                    "`{s}` is not one of `verbatim`, `norg` or `command:<program>`"
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for TagHandling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Verbatim => f.write_str("verbatim"),
+            Self::Norg => f.write_str("norg"),
+            Self::Command(command) => write!(f, "command:{command}"),
+        }
+    }
+}
+
+// Tag handling is written the same way everywhere, e.g. `"command:prettier --parser=json"`.
+impl Serialize for TagHandling {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TagHandling {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// The options the converter formats a document with.
+#[derive(Clone, Hash)]
+pub struct Config {
+    /// Whether the content of a heading is separated from it by a blank line.
+    pub newline_after_headings: bool,
+    /// Whether the content of a heading, subheadings included, is indented by an indent unit.
+    pub indent_headings: bool,
+    /// Whether paragraphs, list items and quotes keep the line breaks they were written with
+    /// instead of being wrapped, as set for a part of a document with `+norgfmt wrap=preserve`.
+    pub preserve_line_breaks: bool,
+    pub line_length: usize,
+    /// Line lengths used for specific constructs instead of `line_length`.
+    pub construct_line_length: BTreeMap<Construct, usize>,
+    pub wrap_comments: bool,
+    pub free_form_conversion: FreeFormConversion,
+    pub indent_style: IndentStyle,
+    /// The number of spaces per indentation level, or the number of columns a tab counts as towards
+    /// the line length when indenting with tabs.
+    pub indent_width: usize,
+    pub line_ending: LineEnding,
+    pub continuation_indent: ContinuationIndent,
+    pub align_definitions: bool,
+    pub blank_line_before_lists: bool,
+    pub blank_line_between_lists: bool,
+    pub link_style: LinkStyle,
+    pub wikilink_style: WikilinkStyle,
+    /// Whether whitespace before punctuation is removed and a space after it ensured.
+    pub punctuation_spacing: bool,
+    pub locale: Locale,
+    /// Whether the no-break spaces the typography of the locale requires are inserted, e.g. before
+    /// `;`, `:`, `!` and `?` in French.
+    pub non_breaking_spaces: bool,
+    /// Whether dashes, ellipses and quotes are replaced by their typographic counterparts.
+    pub typographic_substitution: bool,
+    pub verbatim_blank_lines: VerbatimBlankLines,
+    /// Markers starting a trailing annotation of a heading, e.g. a `{{{` fold marker, which is kept
+    /// as written and doesn't count towards the length of the heading.
+    pub heading_annotation_markers: Vec<String>,
+    /// Patterns of template placeholders, e.g. `{{*}}` where `*` stands for any text within a
+    /// line, which are kept exactly as written (see [`placeholders`]).
+    pub template_placeholders: Vec<String>,
+    /// Whether headings are annotated with the number of tasks beneath them that are done, e.g.
+    /// `%3/7%`.
+    pub task_roll_up: bool,
+    /// The `strftime`-like format headings titled with a date are rewritten in, e.g. `%Y-%m-%d`
+    /// (see [`journal::Date::format`]). Dates are left as written if not set.
+    pub journal_date_format: Option<String>,
+    /// Whether the entries beneath a heading titled with a date are sorted by their time.
+    pub sort_journal_entries: bool,
+    /// What the title in the `@document.meta` block of a document has to match.
+    pub metadata_title: MetadataTitle,
+    /// Whether the metadata is kept in sync: the title is set to the one it has to match, and
+    /// `updated` is bumped whenever formatting changes a document.
+    pub fix_metadata: bool,
+    /// The lints that are not run, e.g. because their findings are expected in some documents.
+    pub disabled_lints: BTreeSet<Lint>,
+    /// Whether the languages of `@code` blocks are lowercased and resolved through aliases.
+    pub normalize_languages: bool,
+    /// Aliases of `@code` languages and the names they are normalized to, on top of the built-in
+    /// ones such as `js` for `javascript`. Keys are lowercase.
+    pub language_aliases: BTreeMap<String, String>,
+    /// How the content of tags is handled, by tag name. Tags that aren't listed are formatted as
+    /// Norg, apart from verbatim tags, which are only formatted if they embed Norg.
+    pub tag_handling: BTreeMap<String, TagHandling>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            newline_after_headings: false,
+            indent_headings: false,
+            preserve_line_breaks: false,
+            line_length: 80,
+            construct_line_length: BTreeMap::new(),
+            wrap_comments: false,
+            free_form_conversion: FreeFormConversion::Auto,
+            indent_style: IndentStyle::Spaces,
+            indent_width: 4,
+            line_ending: LineEnding::Lf,
+            continuation_indent: ContinuationIndent::Text,
+            align_definitions: false,
+            blank_line_before_lists: false,
+            blank_line_between_lists: false,
+            link_style: LinkStyle::Preserve,
+            wikilink_style: WikilinkStyle::Preserve,
+            punctuation_spacing: false,
+            locale: Locale::English,
+            non_breaking_spaces: false,
+            typographic_substitution: false,
+            verbatim_blank_lines: VerbatimBlankLines::Preserve,
+            heading_annotation_markers: Vec::new(),
+            template_placeholders: Vec::new(),
+            task_roll_up: false,
+            journal_date_format: None,
+            sort_journal_entries: false,
+            metadata_title: MetadataTitle::Heading,
+            fix_metadata: false,
+            disabled_lints: BTreeSet::new(),
+            normalize_languages: false,
+            language_aliases: BTreeMap::new(),
+            tag_handling: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// The line length of the given construct, falling back to the general line length.
+    pub fn line_length_of(&self, construct: Construct) -> usize {
+        self.construct_line_length
+            .get(&construct)
+            .copied()
+            .unwrap_or(self.line_length)
+    }
+}
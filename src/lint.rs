@@ -0,0 +1,404 @@
+//! Checks for problems in a document that formatting can not fix, reported as diagnostics.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use rust_norg::{
+    DetachedModifierExtension, LinkTarget, NorgASTFlat, ParagraphSegment,
+    RangeableDetachedModifier, TodoStatus,
+};
+
+use crate::{
+    context::FormatCtx,
+    prose::{plain_text, visit_prose, Prose},
+    slug::{normalize_segments as normalize, slug},
+    tasks::{is_valid_when, visit_extensions},
+    Lint,
+};
+
+/// The level and title of a heading, looking through any carryover tags attached to it.
+fn heading(node: &NorgASTFlat) -> Option<(u16, &[ParagraphSegment])> {
+    match node {
+        NorgASTFlat::Heading { level, title, .. } => Some((*level, title)),
+        NorgASTFlat::CarryoverTag { next_object, .. } => heading(next_object),
+        _ => None,
+    }
+}
+
+/// Describes where the top-level blocks of a document are, by line if the source is known.
+///
+/// Locating blocks means parsing the source again, so it is only done once there is a finding, and
+/// only once per document, as the formatter looks them up through the same context.
+struct Locations<'a> {
+    ast: &'a [NorgASTFlat],
+    ctx: &'a FormatCtx<'a>,
+}
+
+impl<'a> Locations<'a> {
+    fn new(ast: &'a [NorgASTFlat], ctx: &'a FormatCtx<'a>) -> Self {
+        Self { ast, ctx }
+    }
+
+    fn describe(&self, index: usize) -> String {
+        match self.ctx.block_ranges(self.ast.len()) {
+            Some(ranges) => format!("line {}", ranges[index].start + 1),
+            None => format!("block #{index}"),
+        }
+    }
+}
+
+/// Calls `visit` for every paragraph segment of a node, including those nested in other segments.
+fn visit_segments(node: &NorgASTFlat, visit: &mut impl FnMut(&ParagraphSegment)) {
+    fn visit_all(segments: &[ParagraphSegment], visit: &mut impl FnMut(&ParagraphSegment)) {
+        for segment in segments {
+            visit(segment);
+
+            match segment {
+                ParagraphSegment::AttachedModifier { content, .. }
+                | ParagraphSegment::AttachedModifierCandidate { content, .. }
+                | ParagraphSegment::InlineLinkTarget(content) => visit_all(content, visit),
+                ParagraphSegment::Link {
+                    description: Some(description),
+                    ..
+                } => visit_all(description, visit),
+                ParagraphSegment::AnchorDefinition { content, target } => {
+                    visit_all(content, visit);
+                    visit_all(std::slice::from_ref(target), visit);
+                }
+                ParagraphSegment::Anchor {
+                    content,
+                    description,
+                } => {
+                    visit_all(content, visit);
+                    visit_all(description.as_deref().unwrap_or_default(), visit);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    match node {
+        NorgASTFlat::Paragraph(content) => visit_all(content, visit),
+        NorgASTFlat::Heading { title, .. } => visit_all(title, visit),
+        NorgASTFlat::NestableDetachedModifier { content, .. } => visit_segments(content, visit),
+        NorgASTFlat::RangeableDetachedModifier { title, content, .. } => {
+            visit_all(title, visit);
+            content.iter().for_each(|node| visit_segments(node, visit));
+        }
+        NorgASTFlat::RangedTag { content, .. } => {
+            content.iter().for_each(|node| visit_segments(node, visit))
+        }
+        NorgASTFlat::CarryoverTag { next_object, .. } => visit_segments(next_object, visit),
+        NorgASTFlat::VerbatimRangedTag { .. } | NorgASTFlat::InfirmTag { .. } => {}
+    }
+}
+
+/// Calls `visit` for every footnote and definition declared in a node.
+fn visit_declarations(node: &NorgASTFlat, visit: &mut impl FnMut(&'static str, String)) {
+    match node {
+        NorgASTFlat::RangeableDetachedModifier {
+            modifier_type,
+            title,
+            content,
+            ..
+        } => {
+            if matches!(modifier_type, RangeableDetachedModifier::Footnote) {
+                visit("footnote", normalize(title));
+            } else if matches!(modifier_type, RangeableDetachedModifier::Definition) {
+                visit("definition", normalize(title));
+            }
+
+            content
+                .iter()
+                .for_each(|node| visit_declarations(node, visit));
+        }
+        NorgASTFlat::NestableDetachedModifier { content, .. } => visit_declarations(content, visit),
+        NorgASTFlat::RangedTag { content, .. } => content
+            .iter()
+            .for_each(|node| visit_declarations(node, visit)),
+        NorgASTFlat::CarryoverTag { next_object, .. } => visit_declarations(next_object, visit),
+        _ => {}
+    }
+}
+
+/// Reports footnotes and definitions no link refers to, as well as links within the document
+/// referring to footnotes and definitions that don't exist. Generic links (`{# title}`) may refer
+/// to anything with their title, so they count as references to footnotes and definitions but are
+/// never reported themselves.
+fn check_references(ast: &[NorgASTFlat], locations: &Locations, ctx: &FormatCtx) {
+    let mut declarations = Vec::new();
+    let mut references = Vec::new();
+
+    for (index, node) in ast.iter().enumerate() {
+        visit_declarations(node, &mut |kind, title| {
+            declarations.push((index, kind, title))
+        });
+        visit_segments(node, &mut |segment| {
+            let ParagraphSegment::Link {
+                filepath: None,
+                targets,
+                ..
+            } = segment
+            else {
+                return;
+            };
+
+            for target in targets {
+                match target {
+                    LinkTarget::Footnote(title) => {
+                        references.push((index, "footnote", normalize(title)))
+                    }
+                    LinkTarget::Definition(title) => {
+                        references.push((index, "definition", normalize(title)))
+                    }
+                    LinkTarget::Generic(title) => {
+                        references.push((index, "generic", normalize(title)))
+                    }
+                    _ => {}
+                }
+            }
+        });
+    }
+
+    // Links match titles regardless of case, so only the slugs are compared.
+    let declared = declarations
+        .iter()
+        .map(|(_, kind, title)| (*kind, slug(title)))
+        .collect::<BTreeSet<_>>();
+    let referenced = references
+        .iter()
+        .map(|(_, kind, title)| (*kind, slug(title)))
+        .collect::<BTreeSet<_>>();
+
+    for (index, kind, title) in &declarations {
+        let is_referenced = [*kind, "generic"]
+            .into_iter()
+            .any(|kind| referenced.contains(&(kind, slug(title))));
+
+        if !is_referenced {
+            ctx.diagnose(format!(
+                "the {kind} `{title}` at {} is never referenced",
+                locations.describe(*index)
+            ));
+        }
+    }
+
+    for (index, kind, title) in references.iter().filter(|(_, kind, _)| *kind != "generic") {
+        if !declared.contains(&(*kind, slug(title))) {
+            ctx.diagnose(format!(
+                "a link at {} refers to the {kind} `{title}`, which does not exist",
+                locations.describe(*index)
+            ));
+        }
+    }
+}
+
+/// Finds headings with the same level and title (ignoring case) under the same parent heading,
+/// which make links to them ambiguous. Returns the indices of the first heading and of its
+/// duplicate with their title.
+fn duplicate_headings(ast: &[NorgASTFlat]) -> Vec<(usize, usize, String)> {
+    let mut parents = Vec::<(u16, usize)>::new();
+    let mut seen = BTreeMap::new();
+    let mut duplicates = Vec::new();
+
+    for (index, (level, title)) in ast
+        .iter()
+        .enumerate()
+        .filter_map(|(index, node)| Some((index, heading(node)?)))
+    {
+        while parents.last().is_some_and(|&(parent, _)| parent >= level) {
+            parents.pop();
+        }
+
+        let title = normalize(title);
+        let parent = parents.last().map(|&(_, parent)| parent);
+
+        match seen.get(&(parent, level, slug(&title))) {
+            Some(&first) => duplicates.push((first, index, title)),
+            None => {
+                seen.insert((parent, level, slug(&title)), index);
+            }
+        }
+
+        parents.push((level, index));
+    }
+
+    duplicates
+}
+
+/// Checks the top-level blocks of a document, reporting any problems to the context.
+pub fn lint(ast: &[NorgASTFlat], ctx: &FormatCtx) {
+    let locations = Locations::new(ast, ctx);
+    let enabled = |lint| !ctx.config.disabled_lints.contains(&lint);
+
+    for (first, duplicate, title) in duplicate_headings(ast)
+        .into_iter()
+        .filter(|_| enabled(Lint::DuplicateHeadings))
+    {
+        ctx.diagnose(format!(
+            "the headings at {} and {} are both titled `{title}` under the same parent, which \
+             makes links to them ambiguous; consider making their titles unique",
+            locations.describe(first),
+            locations.describe(duplicate)
+        ));
+    }
+
+    if enabled(Lint::References) {
+        check_references(ast, &locations, ctx);
+    }
+
+    if enabled(Lint::TaskDates) {
+        check_extensions(ast, &locations, ctx);
+    }
+
+    inspect_prose(ast, &locations, ctx);
+}
+
+/// Reports recurrences, dates and timestamps of detached modifiers that can't be understood, e.g.
+/// `(+ evry monday)`, as tools acting on them would skip or misread the task.
+fn check_extensions(ast: &[NorgASTFlat], locations: &Locations, ctx: &FormatCtx) {
+    for (index, node) in ast.iter().enumerate() {
+        visit_extensions(node, &mut |extensions| {
+            for extension in extensions {
+                let (kind, when) = match extension {
+                    DetachedModifierExtension::Todo(TodoStatus::Recurring(Some(when))) => {
+                        ("recurrence", when)
+                    }
+                    DetachedModifierExtension::DueDate(when) => ("due date", when),
+                    DetachedModifierExtension::StartDate(when) => ("start date", when),
+                    DetachedModifierExtension::Timestamp(when) => ("timestamp", when),
+                    _ => continue,
+                };
+
+                if !is_valid_when(when) {
+                    ctx.diagnose(format!(
+                        "the {kind} `{}` at {} can't be understood",
+                        when.trim(),
+                        locations.describe(index)
+                    ));
+                }
+            }
+        });
+    }
+}
+
+/// Runs the prose inspectors registered on the context over every paragraph and heading title.
+fn inspect_prose(ast: &[NorgASTFlat], locations: &Locations, ctx: &FormatCtx) {
+    if ctx.inspectors().is_empty() {
+        return;
+    }
+
+    for (index, node) in ast.iter().enumerate() {
+        visit_prose(node, &mut |segments| {
+            let text = plain_text(segments);
+            let prose = Prose {
+                text: &text,
+                lines: locations.ranges().map(|ranges| ranges[index].clone()),
+            };
+
+            for inspector in ctx.inspectors() {
+                for finding in inspector.inspect(&prose) {
+                    ctx.diagnose(format!(
+                        "{}: {}",
+                        locations.describe(index),
+                        finding.message
+                    ));
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_norg::parse;
+
+    use super::*;
+    use crate::{formatter::Formatter, Config};
+
+    fn diagnostics(source: &str, config: &Config) -> Vec<String> {
+        let ast = parse(source).unwrap();
+        let ctx = FormatCtx::new(config).with_source(source);
+
+        lint(&ast, &ctx);
+
+        ctx.diagnostics()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    }
+
+    fn disabled(lint: Lint) -> Config {
+        Config {
+            disabled_lints: [lint].into(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn reports_duplicate_headings_under_the_same_parent() {
+        let source = "* Notes\n** Ideas\n** ideas\n* Other\n** Ideas\n";
+
+        assert_eq!(
+            diagnostics(source, &Config::default()),
+            [
+                "the headings at line 2 and line 3 are both titled `ideas` under the same parent, \
+              which makes links to them ambiguous; consider making their titles unique"
+            ]
+        );
+        assert!(diagnostics(source, &disabled(Lint::DuplicateHeadings)).is_empty());
+    }
+
+    #[test]
+    fn reports_unreferenced_footnotes_and_missing_definitions() {
+        let source = "See {$ term}.\n\n^ note\nThe content of the note.\n";
+
+        assert_eq!(
+            diagnostics(source, &Config::default()),
+            [
+                "the footnote `note` at line 3 is never referenced",
+                "a link at line 1 refers to the definition `term`, which does not exist",
+            ]
+        );
+        assert!(diagnostics(source, &disabled(Lint::References)).is_empty());
+    }
+
+    #[test]
+    fn accepts_referenced_footnotes_regardless_of_case() {
+        let source = "See {^ Note}.\n\n^ note\nThe content of the note.\n";
+
+        assert!(diagnostics(source, &Config::default()).is_empty());
+    }
+
+    #[test]
+    fn counts_generic_links_as_references() {
+        let source = "See {# note} and {# Elsewhere}.\n\n^ note\nThe content of the note.\n";
+
+        assert!(diagnostics(source, &Config::default()).is_empty());
+    }
+
+    #[test]
+    fn reports_task_dates_that_cant_be_understood() {
+        let source = "- (+ evry monday) water the plants\n- (+ every monday) feed the cat\n";
+
+        assert_eq!(
+            diagnostics(source, &Config::default()),
+            ["the recurrence `evry monday` at line 1 can't be understood"]
+        );
+        assert!(diagnostics(source, &disabled(Lint::TaskDates)).is_empty());
+    }
+
+    #[test]
+    fn reports_stale_metadata_titles() {
+        let source = "@document.meta\ntitle: Draft\n@end\n\n* Launch plan\n";
+        let format = |config| Formatter::new(config).format(source).unwrap().diagnostics;
+
+        assert_eq!(
+            format(Config::default())
+                .into_iter()
+                .map(|diagnostic| diagnostic.message)
+                .collect::<Vec<_>>(),
+            ["the title in `@document.meta` doesn't match `Launch plan`; pass --fix to update it"]
+        );
+        assert!(format(disabled(Lint::MetadataTitle)).is_empty());
+    }
+}
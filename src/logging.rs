@@ -0,0 +1,29 @@
+//! Printing of the `tracing` spans the formatter is instrumented with.
+
+use clap::ValueEnum;
+use tracing::Level;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+/// The format log lines are printed in.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
+}
+
+/// Prints every span up to the given level to stderr once it closes, along with the time spent in
+/// it, which is what tracks down slow files and nodes.
+pub fn init(level: Level, format: LogFormat) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr);
+
+    match format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+}
@@ -1,69 +1,1485 @@
-use chumsky::Parser as _;
-use clap::Parser as ClapParser;
-use converter::format;
-use eyre::Result;
+use cache::Cache;
+use clap::{Parser as ClapParser, Subcommand, ValueEnum};
+use config_file::Configs;
+use encoding::Encoding;
+use eyre::{bail, Result};
+use graph::{GraphFormat, LinkGraph};
+use interactive::Prompt;
+use norg_fmt::{
+    ast_json, dump,
+    dump::DumpFormat,
+    explain,
+    explain::Position,
+    exporter::ExportOptions,
+    fingerprint,
+    formatter::{is_unchanged, Fingerprint, Formatter},
+    grammar,
+    options::FormatOptions,
+    profile::Profile,
+    prose::ProseInspector,
+    protocol::Server,
+    trace,
+    verify::VerifyError,
+    vfs::Disk,
+    Config, Construct, ContinuationIndent, FreeFormConversion, IndentStyle, LineEnding, LinkStyle,
+    Lint, Locale, MetadataTitle, TagHandling, VerbatimBlankLines, WikilinkStyle,
+};
+use prefetch::Prefetched;
+use progress::Progress;
+use report::{Outcome, Summary};
 use rust_norg::parse;
-use std::path::PathBuf;
+use self_check::SelfCheck;
+use status::{ParseError, Status};
+use std::{
+    cell::RefCell,
+    io::{BufWriter, Write},
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    rc::Rc,
+    time::Instant,
+};
+use term::{ColorChoice, Style};
+use timings::{Phase, Timings};
+use workspace::WalkOptions;
 
-mod converter;
+mod backup;
+mod cache;
+mod config_file;
+mod diff;
+mod editorconfig;
+mod encoding;
+mod export;
+mod graph;
+mod guard;
+mod hooks;
+mod index;
+mod info;
+mod interactive;
+#[cfg(feature = "tracing")]
+mod logging;
+mod persist;
+mod prefetch;
+mod progress;
+mod report;
+mod rewrite;
+mod self_check;
+mod status;
+mod term;
+mod timings;
+mod workspace;
 
 #[derive(ClapParser)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct NorgFmt {
-    /// The path of the file to format.
-    file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// (todo) Verify the output of the AST after the formatting.
+    /// The paths of the files to format. Directories are searched recursively for `.norg` files.
+    #[arg(required_unless_present_any = ["version", "files_from", "changed"])]
+    files: Vec<PathBuf>,
+
+    /// Also format the files listed in the given file, or on stdin if it is `-`, one per line, e.g.
+    /// the output of `git ls-files` or `fd`. The files are formatted like multiple files are, even
+    /// if only one is listed.
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Separate the files listed by `--files-from` with NUL bytes instead of newlines, e.g. for
+    /// `git ls-files -z` or `fd -0`.
+    #[arg(long, short = '0', requires = "files_from")]
+    null: bool,
+
+    /// Also format the `.norg` files git reports as changed in the working directory: added or
+    /// modified by staged or unstaged changes, or untracked.
+    #[arg(long)]
+    changed: bool,
+
+    /// With `--changed`, only take the files with staged changes, e.g. in a pre-commit hook.
+    #[arg(long, requires = "changed")]
+    staged: bool,
+
+    /// Print the version. With `--verbose`, also print the version of the grammar norg-fmt was
+    /// built against and whether it is supported.
+    #[arg(long, short = 'V')]
+    version: bool,
+
+    /// Follow symbolic links while searching directories. Files reachable through multiple paths
+    /// are only formatted once either way.
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Limits how many levels of directories are searched: 1 only searches the given directories
+    /// themselves, 2 also their immediate subdirectories and so on. Default: unlimited.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_depth: Option<u64>,
+
+    /// Only format files matching the glob, e.g. `'**/journal/**.norg'`. May be given multiple
+    /// times. Applies to files found in directories as well as to the paths given explicitly.
+    /// Replaces the `include` globs of the configuration file.
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Never format files matching the glob, nor search directories matching it, e.g.
+    /// `'**/archive/**'`. May be given multiple times. Takes precedence over `--include`. Adds to
+    /// the `exclude` globs of the configuration file.
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Write the formatted output back to the files instead of printing it.
+    #[arg(long, conflicts_with = "check")]
+    write: bool,
+
+    /// Save the original content of every file rewritten by `--write` as `<file>.orig`.
+    #[arg(long, requires = "write")]
+    backup: bool,
+
+    /// Place backups in the given directory, mirroring the paths of the original files, instead of
+    /// next to them.
+    #[arg(long, value_name = "DIR", requires = "backup")]
+    backup_dir: Option<PathBuf>,
+
+    /// Keep the original modification time of files `--write` rewrites without changing their text,
+    /// e.g. only to convert them to `--output-encoding`. Files whose text does change always get a
+    /// new modification time, so that tools keyed off it notice the change, and files that are
+    /// already formatted are never rewritten at all.
+    #[arg(long, requires = "write")]
+    preserve_mtime: bool,
+
+    /// Show the diff of every file `--write` would change and ask whether to write it: `y` for
+    /// yes, `n` for no, `s` to pick hunk by hunk, `a` for this file and all remaining ones, `q` for
+    /// none of the remaining ones.
+    #[arg(long, requires = "write", conflicts_with_all = ["diff", "output"])]
+    interactive: bool,
+
+    /// Read files that are neither valid UTF-8 nor marked as UTF-16 as Latin-1 instead of
+    /// rejecting them.
+    #[arg(long)]
+    latin1: bool,
+
+    /// Determines the encoding files are written back in. Default: the encoding they were read in.
+    #[arg(long, value_enum)]
+    output_encoding: Option<Encoding>,
+
+    /// Determines the size in bytes above which files are skipped instead of formatted. Default:
+    /// 4194304 (4 MiB).
+    #[arg(long, value_name = "BYTES")]
+    max_file_size: Option<u64>,
+
+    /// Determines how many files are read at once, ahead of the one being formatted, when
+    /// formatting multiple files. Files are still formatted and reported in order. Default: the
+    /// number of CPUs.
+    #[arg(long, short, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Don't write anything, only report which files would be reformatted.
+    #[arg(long)]
+    check: bool,
+
+    /// Don't write anything, only report how many lines and bytes formatting would change in
+    /// every file, and whether the changes are reflowing, indentation, whitespace or content.
+    #[arg(long, conflicts_with_all = ["write", "diff", "output"])]
+    dry_run: bool,
+
+    /// Only print the paths of the files that would be reformatted, one per line, and fail if there
+    /// are any. Files are formatted as with `--check`, but no diffs, warnings or summary are
+    /// printed.
+    #[arg(
+        long,
+        short,
+        conflicts_with_all = [
+            "write", "check", "dry_run", "diff", "verify", "explain", "self_check"
+        ]
+    )]
+    list_different: bool,
+
+    /// Stop at the first file that can't be formatted, leaving the remaining files untouched.
+    #[arg(long, overrides_with = "keep_going")]
+    fail_fast: bool,
+
+    /// Format every file even if some of them can't be formatted, reporting all errors at the end.
+    /// This is the default, which undoes an earlier `--fail-fast`.
+    #[arg(long, overrides_with = "fail_fast")]
+    keep_going: bool,
+
+    /// Don't consult or update the cache of files that are known to be formatted already.
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Don't print a summary after formatting multiple files.
+    #[arg(long, short, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// List the outcome of every file in the summary, not just the ones that errored.
+    #[arg(long, short)]
+    verbose: bool,
+
+    /// Print a diff of the changes instead of the formatted output.
+    #[arg(long)]
+    diff: bool,
+
+    /// Keep every block formatting leaves untouched exactly as it was written, including its line
+    /// endings unless `--line-ending crlf` converts them, and only regenerate the blocks that
+    /// change.
+    #[arg(long)]
+    minimal_diff: bool,
+
+    /// Add a fingerprint comment (`%norgfmt-hash: …%`) to the first line of every formatted file,
+    /// recording the hash of its formatted content for `--assume-clean`.
+    #[arg(long, conflicts_with = "no_fingerprint")]
+    fingerprint: bool,
+
+    /// Remove the fingerprint comment added by `--fingerprint` from every formatted file. Without
+    /// either option, files that have a fingerprint keep it, updated to their formatted content.
+    #[arg(long)]
+    no_fingerprint: bool,
+
+    /// Skip files whose fingerprint comment still matches their content without formatting them,
+    /// for when the cache can't be used, e.g. on network mounts or in CI.
+    #[arg(long)]
+    assume_clean: bool,
+
+    /// Maintain a `* Backlinks` section at the end of every file, listing the files being
+    /// formatted that link to it, and remove it from files nothing links to. `$/` links are
+    /// relative to the working directory.
+    #[arg(long)]
+    backlinks: bool,
+
+    /// Determines what is printed for formatted files: the whole `document`, or a `patch` holding
+    /// the edits that format it as a line of JSON with byte ranges into the original content, or
+    /// the `ast-json` of the formatted document, in the versioned schema of `norg_fmt::ast_json`.
+    /// Default: document.
+    #[arg(long, visible_alias = "emit", value_enum, conflicts_with = "diff")]
+    output: Option<Output>,
+
+    /// Determines whether diffs and diagnostics are colored. `NO_COLOR` is respected in auto mode.
+    /// Default: auto.
+    #[arg(long, value_enum)]
+    color: Option<ColorChoice>,
+
+    /// Run one of the formatter's self-checks over the files instead of formatting them.
+    #[arg(long, value_enum, value_name = "CHECK")]
+    self_check: Option<SelfCheck>,
+
+    /// Print how long parsing, formatting, emitting and verifying took across all files.
+    #[arg(long)]
+    timings: bool,
+
+    /// Print where formatting spent the most time across all files, by node kind and by
+    /// transformation (e.g. reflow or links).
+    #[arg(long)]
+    profile: bool,
+
+    /// Fail if any warnings are reported, e.g. by lints or for content that could not be handled
+    /// as configured.
+    #[arg(long)]
+    error_on_warnings: bool,
+
+    /// Verify that formatting did not change the structure of the document by comparing the AST
+    /// of the output with the AST of the input.
     #[arg(long)]
     verify: bool,
 
+    /// Write a report of every file that failed `--verify` to the given file, as HTML if it ends
+    /// in `.html` and as JSON otherwise, listing the path and an excerpt of each node that changed.
+    #[arg(long, value_name = "FILE", requires = "verify")]
+    verify_report: Option<PathBuf>,
+
+    /// Instead of printing the formatted output, explain which rules the formatter applies to the
+    /// block at the given `line:col` position and which options were involved.
+    #[arg(long, value_name = "LINE:COL")]
+    explain: Option<Position>,
+
+    /// Instead of formatting the file, print which layer of options determined the value of the
+    /// given option for it: the defaults, its `.editorconfig` files, the configuration file, a
+    /// profile or `paths` entry, the environment or the command line, and which `+norgfmt`
+    /// attributes override it for parts of the file.
+    #[arg(long, value_name = "OPTION")]
+    why: Option<String>,
+
+    /// Log every change applied to the document as JSON lines (rule ids, input lines, before and
+    /// after text) to the given file, or to stderr if no file is given.
+    #[arg(
+        long,
+        value_name = "FILE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "-"
+    )]
+    trace_transforms: Option<PathBuf>,
+
+    /// Print the spans formatting goes through (files, phases and nodes) up to the given level to
+    /// stderr as they close, along with the time spent in them. One of `error`, `warn`, `info`,
+    /// `debug` or `trace`.
+    #[cfg(feature = "tracing")]
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<tracing::Level>,
+
+    /// Report words in paragraphs and headings that are not in the given Hunspell dictionary, given
+    /// as its path without an extension, e.g. `/usr/share/hunspell/en_US`.
+    #[cfg(feature = "spellcheck")]
+    #[arg(long, value_name = "DICTIONARY")]
+    spellcheck: Option<PathBuf>,
+
+    /// Determines the format of the lines printed by `--log-level`. Default: text.
+    #[cfg(feature = "tracing")]
+    #[arg(long, value_enum, requires = "log_level")]
+    log_format: Option<logging::LogFormat>,
+
+    /// Read formatting options from a TOML file, with the options below as keys (using
+    /// underscores), e.g. `line_length = 100`. Options given on the command line or as
+    /// `NORG_FMT_<OPTION>` environment variables take precedence. Default: `$NORG_FMT_CONFIG`.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Ignore `.editorconfig` files, whose `indent_style`, `indent_size`, `max_line_length` and
+    /// `end_of_line` are otherwise used beneath the options of the configuration file.
+    #[arg(long)]
+    no_editorconfig: bool,
+
     /// If true will add an extra newline after a heading title to separate the content.
     #[arg(long)]
     newline_after_headings: bool,
 
-    /// If true will not forcefully give heading titles zero indentation.
+    /// If true will indent the content of headings, subheadings included, by an indent unit per
+    /// level of the hierarchy instead of giving everything zero indentation.
     #[arg(long)]
     indent_headings: bool,
 
     /// Determines the maximum length of a paragraph's line. Default: 80.
     #[arg(long)]
     line_length: Option<usize>,
+
+    /// Determines the maximum line length of a specific construct (`quotes`, `lists` or
+    /// `headings`) instead of `--line-length`. Headings are never wrapped, so exceeding their line
+    /// length only emits a warning. May be given multiple times.
+    #[arg(long, value_name = "CONSTRUCT=LENGTH", value_parser = parse_construct_line_length)]
+    construct_line_length: Vec<(Construct, usize)>,
+
+    /// If true will rewrap long comments to the maximum line length instead of leaving them as-is.
+    #[arg(long)]
+    wrap_comments: bool,
+
+    /// Determines when markup is converted to and from free-form markup (`*|text|*`). Default:
+    /// auto.
+    #[arg(long, value_enum)]
+    free_form_conversion: Option<FreeFormConversion>,
+
+    /// Determines whether nesting is indented with spaces or tabs: the content of headings with
+    /// --indent-headings and wrapped lines with --continuation-indent marker. Default: spaces.
+    #[arg(long, value_enum)]
+    indent_style: Option<IndentStyle>,
+
+    /// Determines the amount of spaces a single level of nesting is indented by when indenting
+    /// with spaces, or the number of columns a tab counts as towards the line length when indenting
+    /// with tabs. Default: 4.
+    #[arg(long)]
+    indent_width: Option<usize>,
+
+    /// Determines the line endings formatted files are written with. Default: lf.
+    #[arg(long, value_enum)]
+    line_ending: Option<LineEnding>,
+
+    /// Determines whether the wrapped lines of list items and quotes are aligned under the text or
+    /// indented by a single indent unit past the marker. Default: text.
+    #[arg(long, value_enum)]
+    continuation_indent: Option<ContinuationIndent>,
+
+    /// If true will align the content of consecutive single-line definitions to a common column.
+    #[arg(long)]
+    align_definitions: bool,
+
+    /// If true will separate a paragraph from a list or quote directly following it with a blank
+    /// line.
+    #[arg(long)]
+    blank_line_before_lists: bool,
+
+    /// If true will separate adjacent lists and quotes of different types with a blank line.
+    #[arg(long)]
+    blank_line_between_lists: bool,
+
+    /// Determines whether links to a target that is linked to repeatedly are turned into an anchor
+    /// definition and anchors referring to it (`anchors`), anchors are turned into inline links
+    /// (`inline`), or both are kept as written (`preserve`). Default: preserve.
+    #[arg(long, value_enum)]
+    link_style: Option<LinkStyle>,
+
+    /// Determines whether links to other documents are written in full (`expand`, e.g. `{:note}`
+    /// as `{:note:}`), in shorthand (`collapse`, e.g. `{:note:}` as `{:note}`), or as they were
+    /// written (`preserve`). Both fix the spacing of variants like `{: note :}`. Default: preserve.
+    #[arg(long, value_enum)]
+    wikilink_style: Option<WikilinkStyle>,
+
+    /// If true will remove whitespace before punctuation and add a space after `,`, `;`, `!` and
+    /// `?` where a word follows them directly. Verbatim, math, comments and links are left alone.
+    #[arg(long)]
+    punctuation_spacing: bool,
+
+    /// Determines the typographic conventions of the prose: `fr` keeps the space before `;`, `:`,
+    /// `!` and `?`. Default: en.
+    #[arg(long, value_enum)]
+    locale: Option<Locale>,
+
+    /// If true will insert the no-break spaces the typography of the locale requires: with `fr`, a
+    /// narrow one before `;`, `!` and `?` and a regular one before `:`. Existing no-break spaces
+    /// are always kept and never broken at.
+    #[arg(long)]
+    non_breaking_spaces: bool,
+
+    /// If true will replace `--` and `---` with en and em dashes, `...` with an ellipsis and
+    /// straight quotes with curly ones. Verbatim, math, comments, links and escaped characters are
+    /// left alone.
+    #[arg(long)]
+    typographic_substitution: bool,
+
+    /// Determines the blank lines at the start and end of verbatim tags like `@code` and
+    /// `@example`: `flush` removes them so `@end` sits against the last line, `spaced` leaves a
+    /// single one before `@end`. Tags embedding Norg are left alone. Default: preserve.
+    #[arg(long, value_enum)]
+    verbatim_blank_lines: Option<VerbatimBlankLines>,
+
+    /// Keeps a trailing annotation of a heading starting with the given marker (after whitespace),
+    /// e.g. `{{{` for fold markers, exactly as written and leaves it out of the heading's length.
+    /// May be given multiple times.
+    #[arg(long = "heading-annotation", value_name = "MARKER")]
+    heading_annotation_markers: Vec<String>,
+
+    /// Keeps template placeholders matching the given pattern exactly as written, never wrapping,
+    /// respacing or escaping them, e.g. `{{*}}` (where `*` stands for any text within a line) or
+    /// `<DATE>`. May be given multiple times.
+    #[arg(long = "template-placeholder", value_name = "PATTERN")]
+    template_placeholders: Vec<String>,
+
+    /// If true will annotate every heading with the number of tasks in its section that are done
+    /// out of all of them (leaving out canceled ones) in an inline comment, e.g. `* Launch %3/7%`,
+    /// updating the annotation on every run.
+    #[arg(long)]
+    task_roll_up: bool,
+
+    /// Rewrites headings titled with a date, e.g. `May 1, 2024`, in the given `strftime`-like
+    /// format, e.g. `%Y-%m-%d`. Meant for journals, so best set for their files only, in the
+    /// `paths` of the configuration file.
+    #[arg(long, value_name = "FORMAT")]
+    journal_date_format: Option<String>,
+
+    /// If true will sort the entries beneath a heading titled with a date by the time they are
+    /// titled with, e.g. `** 09:30 Standup`. Entries without a time stay where they are.
+    #[arg(long)]
+    sort_journal_entries: bool,
+
+    /// What the title in the `@document.meta` block of a document has to match: the first level-1
+    /// heading or the name of the file. Titles that don't match are warned about. Default: heading.
+    #[arg(long, value_enum)]
+    metadata_title: Option<MetadataTitle>,
+
+    /// If true will fix the metadata instead of warning about it: the title in `@document.meta` is
+    /// set to the one it has to match, and `updated` is bumped whenever formatting changes a file.
+    #[arg(long = "fix")]
+    fix_metadata: bool,
+
+    /// Disables a lint, so that its findings are no longer reported as warnings. May be given
+    /// multiple times.
+    #[arg(long = "disable-lint", value_enum, value_name = "LINT")]
+    disabled_lints: Vec<Lint>,
+
+    /// If true will lowercase the language of `@code` blocks and resolve common aliases, e.g. `JS`
+    /// and `js` become `javascript` and `rs` becomes `rust`.
+    #[arg(long)]
+    normalize_languages: bool,
+
+    /// Adds an alias for `--normalize-languages`, e.g. `golang=go`. May be given multiple times.
+    #[arg(
+        long = "language-alias",
+        value_name = "ALIAS=LANGUAGE",
+        value_parser = parse_language_alias
+    )]
+    language_aliases: Vec<(String, String)>,
+
+    /// Determines how the content of the named tag is formatted: `verbatim` leaves it untouched,
+    /// `norg` formats it as Norg and `command:<program>` pipes it through an external program. May
+    /// be given multiple times. Default: norg, except for verbatim tags other than `@norg`.
+    #[arg(long = "tag", value_name = "NAME=HANDLING", value_parser = parse_tag_handling)]
+    tag_handling: Vec<(String, TagHandling)>,
 }
 
-pub struct Config {
-    newline_after_headings: bool,
-    indent_headings: bool,
-    line_length: usize,
+/// What is printed for a formatted file.
+#[derive(Clone, Copy, ValueEnum)]
+enum Output {
+    Document,
+    Patch,
+    AstJson,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            newline_after_headings: false,
-            indent_headings: false,
-            line_length: 80,
+#[derive(Subcommand)]
+enum Command {
+    /// Print the AST the formatter operates on for a file, with the lines every top-level node
+    /// spans in it.
+    DebugAst {
+        /// The path of the file to inspect.
+        file: PathBuf,
+
+        /// The format to print the AST in. Default: json.
+        #[arg(long, value_enum)]
+        format: Option<DumpFormat>,
+    },
+    /// Answer JSON-RPC 2.0 formatting requests read from stdin, one per line, until shut down.
+    /// Supports `version`, `initialize`, `formatText`, `formatFile`, `checkText` and `shutdown`.
+    Serve,
+    /// Rename a heading and point every link to it across the workspace to the new title,
+    /// formatting the files that change.
+    RenameHeading {
+        /// The file containing the heading.
+        file: PathBuf,
+
+        /// The current title of the heading. Case and whitespace don't matter.
+        old: String,
+
+        /// The new title of the heading.
+        new: String,
+
+        /// The root of the workspace searched for links, which links starting with `$/` are
+        /// relative to. Default: the current directory.
+        #[arg(long, value_name = "DIR")]
+        workspace: Option<PathBuf>,
+
+        /// Only print the files that would change, without writing them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Update the `{:path:}` and `{/ path}` links across the workspace after a file was moved or
+    /// renamed, formatting the files that change.
+    FixLinks {
+        /// The path the file was moved from, followed by the path it was moved to.
+        #[arg(long, num_args = 2, value_names = ["OLD", "NEW"], required = true)]
+        moved: Vec<PathBuf>,
+
+        /// The root of the workspace searched for links, which links starting with `$/` are
+        /// relative to. Default: the current directory.
+        #[arg(long, value_name = "DIR")]
+        workspace: Option<PathBuf>,
+
+        /// Only print the files that would change, without writing them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the graph of the links between the documents in a directory and their headings.
+    Graph {
+        /// The directory searched for documents, which `$/` links are relative to.
+        dir: PathBuf,
+
+        /// The format to print the graph in. Default: dot.
+        #[arg(long, value_enum)]
+        format: Option<GraphFormat>,
+    },
+    /// Generate (or refresh) an index of the documents in a directory, with a heading per category
+    /// of their `@document.meta` listing links to them.
+    Index {
+        /// The directory searched for documents.
+        dir: PathBuf,
+
+        /// The file the index is written to. Default: `index.norg` in the directory.
+        #[arg(long, value_name = "FILE")]
+        output: Option<PathBuf>,
+
+        /// Only print whether the index would change, without writing it.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Export a document to another format, formatting it first.
+    Export {
+        /// The path of the document to export.
+        file: PathBuf,
+
+        /// The format to export to: `html`, or `pandoc-json` for Pandoc's AST as JSON, to be
+        /// converted further with e.g. `pandoc -f json -o notes.docx`.
+        #[arg(long, value_name = "FORMAT")]
+        to: String,
+
+        /// Give headings, footnotes and definitions ids derived from their titles, by the same
+        /// rules links find them by.
+        #[arg(long)]
+        heading_ids: bool,
+
+        /// The file the export is written to. Default: stdout.
+        #[arg(long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
+    /// Print the version of norg-fmt and of the grammar it is built with, the revision of the
+    /// specification it supports, its cargo features and where it looks for its configuration, as
+    /// JSON.
+    Info,
+    /// Install a git hook that checks the documents being committed are formatted, or update one
+    /// installed before.
+    InstallHooks {
+        /// Install a pre-commit hook, checking the staged documents. This is the default.
+        #[arg(long, conflicts_with = "pre_push")]
+        pre_commit: bool,
+
+        /// Install a pre-push hook instead, checking every tracked document.
+        #[arg(long)]
+        pre_push: bool,
+
+        /// Make the pre-commit hook format the staged documents and stage the changes, instead of
+        /// only checking them.
+        #[arg(long, conflicts_with = "pre_push")]
+        write: bool,
+
+        /// Replace an existing hook that wasn't installed by norg-fmt.
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+fn parse_tag_handling(value: &str) -> Result<(String, TagHandling), String> {
+    let (name, handling) = value
+        .split_once('=')
+        .ok_or_else(|| format!("`{value}` is not of the form NAME=HANDLING"))?;
+
+    Ok((name.to_string(), handling.parse()?))
+}
+
+fn parse_language_alias(value: &str) -> Result<(String, String), String> {
+    let (alias, language) = value
+        .split_once('=')
+        .ok_or_else(|| format!("`{value}` is not of the form ALIAS=LANGUAGE"))?;
+
+    Ok((alias.to_string(), language.to_string()))
+}
+
+fn parse_construct_line_length(value: &str) -> Result<(Construct, usize), String> {
+    let (construct, length) = value
+        .split_once('=')
+        .ok_or_else(|| format!("`{value}` is not of the form CONSTRUCT=LENGTH"))?;
+    let length = length
+        .parse()
+        .map_err(|_| format!("`{length}` is not a number"))?;
+
+    Ok((construct.parse()?, length))
+}
+
+/// The formatting options given on the command line. Flags that aren't passed leave the option
+/// unset, so that they don't override a configuration file.
+fn cli_options(cli: &NorgFmt) -> FormatOptions {
+    FormatOptions {
+        newline_after_headings: cli.newline_after_headings.then_some(true),
+        indent_headings: cli.indent_headings.then_some(true),
+        line_length: cli.line_length,
+        construct_line_length: (!cli.construct_line_length.is_empty())
+            .then(|| cli.construct_line_length.iter().copied().collect()),
+        wrap_comments: cli.wrap_comments.then_some(true),
+        free_form_conversion: cli.free_form_conversion,
+        indent_style: cli.indent_style,
+        indent_width: cli.indent_width,
+        line_ending: cli.line_ending,
+        continuation_indent: cli.continuation_indent,
+        align_definitions: cli.align_definitions.then_some(true),
+        blank_line_before_lists: cli.blank_line_before_lists.then_some(true),
+        blank_line_between_lists: cli.blank_line_between_lists.then_some(true),
+        link_style: cli.link_style,
+        wikilink_style: cli.wikilink_style,
+        punctuation_spacing: cli.punctuation_spacing.then_some(true),
+        locale: cli.locale,
+        non_breaking_spaces: cli.non_breaking_spaces.then_some(true),
+        typographic_substitution: cli.typographic_substitution.then_some(true),
+        verbatim_blank_lines: cli.verbatim_blank_lines,
+        heading_annotation_markers: (!cli.heading_annotation_markers.is_empty())
+            .then(|| cli.heading_annotation_markers.clone()),
+        template_placeholders: (!cli.template_placeholders.is_empty())
+            .then(|| cli.template_placeholders.clone()),
+        task_roll_up: cli.task_roll_up.then_some(true),
+        journal_date_format: cli.journal_date_format.clone(),
+        sort_journal_entries: cli.sort_journal_entries.then_some(true),
+        metadata_title: cli.metadata_title,
+        fix_metadata: cli.fix_metadata.then_some(true),
+        disabled_lints: (!cli.disabled_lints.is_empty())
+            .then(|| cli.disabled_lints.iter().copied().collect()),
+        normalize_languages: cli.normalize_languages.then_some(true),
+        language_aliases: (!cli.language_aliases.is_empty())
+            .then(|| cli.language_aliases.iter().cloned().collect()),
+        tag_handling: (!cli.tag_handling.is_empty())
+            .then(|| cli.tag_handling.iter().cloned().collect()),
+        include: (!cli.include.is_empty()).then(|| cli.include.clone()),
+        exclude: (!cli.exclude.is_empty()).then(|| cli.exclude.clone()),
+        ..FormatOptions::default()
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match NorgFmt::try_parse() {
+        Ok(cli) => cli,
+        Err(err) => {
+            // Invalid options are configuration errors, whereas `--help` is not an error at all.
+            let _ = err.print();
+
+            return if err.use_stderr() {
+                Status::Failure.into()
+            } else {
+                Status::Clean.into()
+            };
+        }
+    };
+
+    match run(cli) {
+        Ok(status) => status.into(),
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+
+            if let Some(table) = err.downcast_ref().and_then(VerifyError::table) {
+                eprint!("{table}");
+            }
+
+            Status::of(&err).into()
         }
     }
 }
 
-fn main() -> Result<()> {
-    let cli = NorgFmt::parse();
+/// The state shared by every file formatted in a run.
+struct Session {
+    cache: Cache,
+    trace: Option<Box<dyn Write>>,
+    timings: Timings,
+    profile: Option<Rc<RefCell<Profile>>>,
+    /// The inspectors the prose of every file is checked by.
+    inspectors: Vec<Rc<dyn ProseInspector>>,
+    /// The number of warnings reported so far.
+    warnings: usize,
+    /// The links between the files being formatted, if their backlinks are maintained.
+    graph: Option<LinkGraph>,
+    /// Asks for approval before writing each file, with `--interactive`.
+    prompt: Option<Prompt>,
+}
+
+fn run(cli: NorgFmt) -> Result<Status> {
+    #[cfg(feature = "tracing")]
+    if let Some(level) = cli.log_level {
+        logging::init(level, cli.log_format.unwrap_or_default());
+    }
+
+    if cli.version {
+        println!("norg-fmt {}", env!("CARGO_PKG_VERSION"));
+
+        if cli.verbose {
+            println!("grammar: {}", grammar::describe());
+        }
+
+        return Ok(Status::Clean);
+    }
+
+    if let Some(warning) = grammar::check() {
+        eprintln!("warning: {warning}");
+    }
+
+    if let Some(Command::DebugAst {
+        file,
+        format: dump_format,
+    }) = cli.command
+    {
+        let (content, _) = encoding::read(&file, false)?;
+        let ast = parse(&content).map_err(|err| ParseError(format!("{err:?}")))?;
+
+        println!(
+            "{}",
+            dump::dump(&content, &ast, dump_format.unwrap_or_default())
+        );
+
+        return Ok(Status::Clean);
+    }
+
+    if let Some(Command::Graph {
+        dir,
+        format: graph_format,
+    }) = &cli.command
+    {
+        let files = workspace::collect_files(&[dir.clone()], &WalkOptions::default())?;
+        let graph = LinkGraph::build(&files, dir)?;
+
+        print!("{}", graph.export(dir, graph_format.unwrap_or_default())?);
+
+        return Ok(Status::Clean);
+    }
+
+    if let Some(Command::InstallHooks {
+        pre_push,
+        write,
+        force,
+        ..
+    }) = cli.command
+    {
+        let hook = if pre_push {
+            hooks::Hook::PrePush
+        } else {
+            hooks::Hook::PreCommit
+        };
+
+        hooks::install(hook, write, force)?;
+
+        return Ok(Status::Clean);
+    }
+
+    if let Some(Command::Info) = cli.command {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&info::info(Path::new(".")))?
+        );
+
+        return Ok(Status::Clean);
+    }
+
+    let config_path = cli
+        .config
+        .clone()
+        .or_else(|| std::env::var_os(config_file::CONFIG_VAR).map(PathBuf::from));
+    let file_options = match &config_path {
+        Some(path) => config_file::load(path)?,
+        None => FormatOptions::default(),
+    };
+    let env_options = config_file::from_env()?;
+
+    for warning in env_options.warnings() {
+        eprintln!("warning: environment: {warning}");
+    }
+
+    let overrides = env_options.clone().merge(cli_options(&cli));
+    let options = file_options.clone().merge(overrides.clone());
+
+    // The configuration file was validated when it was loaded, so the remaining problems come from
+    // the environment or the command line, possibly in combination with the file.
+    if let Some(error) = options.errors().first() {
+        bail!("invalid options: {error}");
+    }
 
-    let config = Config {
-        newline_after_headings: cli.newline_after_headings,
-        indent_headings: cli.indent_headings,
-        line_length: cli.line_length.unwrap_or(80),
+    // Everything that determines whether a file's content is formatted goes into the cache key,
+    // including the options scoped to some of the files.
+    let scoped_options = serde_json::to_string(&(&file_options.paths, &file_options.profile))?;
+    let configs = Configs::new(file_options, overrides, config_path.as_deref())?;
+    let configs = if cli.no_editorconfig {
+        configs
+    } else {
+        configs.with_editorconfig()
     };
+    let config = configs.default_config().clone();
+
+    if let Some(Command::Serve) = cli.command {
+        let latin1 = cli.latin1;
+
+        Server::new(config, Disk)
+            .with_file_configs(move |file| configs.for_file(file).into_owned())
+            .with_decoder(move |content| {
+                encoding::decode(content, latin1)
+                    .map(|(content, _)| content)
+                    .map_err(|err| err.to_string())
+            })
+            .serve(std::io::stdin().lock(), std::io::stdout())?;
+
+        return Ok(Status::Clean);
+    }
+
+    if let Some(option) = &cli.why {
+        let [file] = cli.files.as_slice() else {
+            bail!("--why can only be used with a single file");
+        };
+        let content = encoding::read(file, cli.latin1)
+            .map(|(content, _)| content)
+            .unwrap_or_default();
+        let mut layers = vec![(
+            "default".to_string(),
+            FormatOptions::from(&Config::default()),
+        )];
+
+        layers.extend(configs.layers(file));
+        layers.push(("environment".to_string(), env_options));
+        layers.push(("command line".to_string(), cli_options(&cli)));
+
+        print!("{}", config_file::why(option, &layers, &content)?);
+
+        return Ok(Status::Clean);
+    }
+
+    if let Some(Command::RenameHeading {
+        file,
+        old,
+        new,
+        workspace,
+        dry_run,
+    }) = &cli.command
+    {
+        let root = workspace.as_deref().unwrap_or(Path::new("."));
+
+        rewrite::rename_heading(file, old, new, root, &config, *dry_run)?;
+
+        return Ok(Status::Clean);
+    }
+
+    if let Some(Command::FixLinks {
+        moved,
+        workspace,
+        dry_run,
+    }) = &cli.command
+    {
+        let root = workspace.as_deref().unwrap_or(Path::new("."));
+
+        rewrite::fix_links(&moved[0], &moved[1], root, &config, *dry_run)?;
+
+        return Ok(Status::Clean);
+    }
+
+    if let Some(Command::Index {
+        dir,
+        output,
+        dry_run,
+    }) = &cli.command
+    {
+        let output = output.clone().unwrap_or_else(|| dir.join("index.norg"));
+
+        index::index(dir, &output, &configs.for_file(&output), *dry_run)?;
+
+        return Ok(Status::Clean);
+    }
+
+    if let Some(Command::Export {
+        file,
+        to,
+        heading_ids,
+        output,
+    }) = &cli.command
+    {
+        let options = ExportOptions {
+            heading_ids: *heading_ids,
+        };
+        let exported = export::export(file, to, &options, &configs.for_file(file))?;
+
+        match output {
+            Some(output) => std::fs::write(output, exported)?,
+            None => print!("{exported}"),
+        }
+
+        return Ok(Status::Clean);
+    }
+
+    let mut paths = cli.files.clone();
+
+    if let Some(list) = &cli.files_from {
+        paths.extend(workspace::read_file_list(list, cli.null)?);
+    }
+
+    if cli.changed {
+        paths.extend(workspace::changed_files(cli.staged)?);
+    }
+
+    // Only lists of files can be empty, and there's nothing to format then.
+    if paths.is_empty() {
+        return Ok(Status::Clean);
+    }
+
+    let max_file_size = cli.max_file_size.unwrap_or(4 * 1024 * 1024);
+    let is_batch = cli.write
+        || cli.check
+        || cli.dry_run
+        || cli.files_from.is_some()
+        || cli.changed
+        || paths.len() > 1
+        || paths[0].is_dir();
+    let walk_options = WalkOptions {
+        follow_symlinks: cli.follow_symlinks,
+        include: configs.include().to_vec(),
+        exclude: configs.exclude().to_vec(),
+        max_depth: cli.max_depth.map(|depth| depth as usize),
+    };
+    let files = workspace::collect_files(&paths, &walk_options)?;
+
+    if let Some(check) = cli.self_check {
+        self_check::run(check, &files, &config)?;
+
+        return Ok(Status::Clean);
+    }
+
+    // Backlinks depend on the other files, so files can't be skipped based on their own content.
+    let graph = cli
+        .backlinks
+        .then(|| LinkGraph::build(&files, Path::new(".")))
+        .transpose()?;
+
+    let mut session = Session {
+        cache: if cli.no_cache || graph.is_some() {
+            Cache::disabled()
+        } else {
+            Cache::open(&(
+                &config,
+                &scoped_options,
+                cli.output_encoding,
+                cli.latin1,
+                cli.minimal_diff,
+                cli.fingerprint,
+                cli.no_fingerprint,
+            ))
+        },
+        trace: cli
+            .trace_transforms
+            .as_deref()
+            .map(trace::open)
+            .transpose()?,
+        timings: Timings::default(),
+        profile: cli.profile.then(Rc::default),
+        inspectors: Vec::new(),
+        warnings: 0,
+        graph,
+        prompt: cli.interactive.then(Prompt::new).transpose()?,
+    };
+
+    #[cfg(feature = "spellcheck")]
+    if let Some(dictionary) = &cli.spellcheck {
+        if !dictionary.with_extension("dic").is_file() {
+            bail!("no Hunspell dictionary found at {}", dictionary.display());
+        }
+
+        session
+            .inspectors
+            .push(Rc::new(norg_fmt::prose::Spellchecker::new(dictionary)));
+    }
+
+    if cli.list_different {
+        let result = list_different(&cli, &configs, &files, max_file_size, &mut session.cache);
+
+        save_cache(&mut session.cache);
+
+        return result;
+    }
+
+    if !is_batch {
+        if cli.verify_report.is_some() {
+            bail!("--verify-report can only be used with --check, --write or a directory");
+        }
+
+        if cli.backlinks {
+            bail!("--backlinks can only be used with --check, --write or a directory");
+        }
+
+        let Some(file) = files.first() else {
+            bail!("{} is excluded from formatting", paths[0].display());
+        };
+
+        if let Some(reason) = guard::check_size(file, max_file_size)? {
+            bail!("{}: {reason}", file.display());
+        }
+
+        let original = std::fs::read(file)?;
+
+        if let Some(reason) = guard::sniff(&original) {
+            bail!("{}: {reason}", file.display());
+        }
+
+        let (content, source_encoding) = encoding::decode(&original, cli.latin1)?;
+        let config = configs.for_file(file);
+
+        if let Some(position) = cli.explain {
+            let ast = parse(&content).map_err(|err| ParseError(format!("{err:?}")))?;
+
+            print!("{}", explain::explain(&content, &ast, position, &config)?);
+
+            return Ok(Status::Clean);
+        }
+
+        let blocks = format_source(&cli, &config, file, &content, &mut session)?;
+
+        if cli.diff {
+            let style = Style::stdout(cli.color.unwrap_or_default());
+
+            print!(
+                "{}",
+                diff::unified_diff(file, &content, &blocks.concat(), style)
+            );
+        } else if let Some(Output::Patch) = cli.output {
+            print!(
+                "{}",
+                diff::patch(file, &content, &blocks.concat(), source_encoding)
+            );
+        } else if let Some(Output::AstJson) = cli.output {
+            let formatted = blocks.concat();
+            let ast = parse(&formatted).map_err(|err| ParseError(format!("{err:?}")))?;
+
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&ast_json::document(&ast, &formatted))?
+            );
+        } else {
+            session
+                .timings
+                .measure(Phase::Emit, || -> std::io::Result<()> {
+                    let mut stdout = BufWriter::new(std::io::stdout().lock());
+
+                    for block in blocks {
+                        stdout.write_all(block.as_bytes())?;
+                    }
+
+                    stdout.flush()
+                })?;
+        }
+
+        print_measurements(&cli, &session);
+
+        return Ok(warnings_status(&cli, session.warnings));
+    }
+
+    if cli.explain.is_some() {
+        bail!("--explain can only be used with a single file");
+    }
+
+    if let Some(Output::AstJson) = cli.output {
+        bail!("--output ast-json can only be used with a single file");
+    }
+
+    let start = Instant::now();
+    let mut summary = Summary::default();
+    let progress = Progress::new(files.len(), !cli.quiet && !cli.interactive);
+
+    let total = files.len();
+
+    let jobs = cli
+        .jobs
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |jobs| jobs.get()));
+
+    prefetch::for_each(&files, jobs, max_file_size, |file, content| {
+        progress.start(file);
+
+        let outcome = format_file(&cli, &configs.for_file(file), file, content, &mut session)
+            .unwrap_or_else(Outcome::Errored);
+        let errored = matches!(outcome, Outcome::Errored(_));
+
+        summary.record(file.to_path_buf(), outcome);
+        progress.advance();
+
+        let has_quit = session.prompt.as_ref().is_some_and(Prompt::has_quit);
+
+        if (errored && cli.fail_fast) || has_quit {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    });
+
+    progress.finish();
+    save_cache(&mut session.cache);
+
+    if !cli.quiet {
+        let style = Style::stderr(cli.color.unwrap_or_default());
+
+        summary.print(
+            start.elapsed(),
+            cli.check || cli.dry_run,
+            cli.verbose,
+            style,
+        );
+    }
+
+    print_measurements(&cli, &session);
+
+    if let Some(report) = &cli.verify_report {
+        summary.write_verify_report(report)?;
+    }
+
+    let has_quit = session.prompt.as_ref().is_some_and(Prompt::has_quit);
+
+    if has_quit && summary.recorded() < total {
+        eprintln!(
+            "stopped early at your request, {} file(s) were left untouched",
+            total - summary.recorded()
+        );
+    }
+
+    if summary.errored() > 0 {
+        eprintln!("{} file(s) could not be formatted", summary.errored());
+
+        if summary.recorded() < total && !has_quit {
+            eprintln!(
+                "stopped early because of --fail-fast, {} file(s) were not processed",
+                total - summary.recorded()
+            );
+        }
+
+        return Ok(summary
+            .errors()
+            .map(Status::of)
+            .max()
+            .unwrap_or(Status::Failure));
+    }
+
+    if cli.check && summary.formatted() > 0 {
+        eprintln!("{} file(s) would be reformatted", summary.formatted());
+
+        return Ok(Status::Unclean);
+    }
+
+    Ok(warnings_status(&cli, session.warnings))
+}
+
+/// The number of entries `--profile` prints.
+const PROFILE_ENTRIES: usize = 10;
+
+/// Prints the time measurements requested with `--timings` and `--profile`.
+fn print_measurements(cli: &NorgFmt, session: &Session) {
+    if cli.timings {
+        session.timings.print();
+    }
+
+    if let Some(profile) = &session.profile {
+        eprintln!("slowest parts of the formatter:");
+
+        for (name, total, count) in profile.borrow().top(PROFILE_ENTRIES) {
+            eprintln!(
+                "{:>10.2}ms {count:>8}x  {name}",
+                total.as_secs_f64() * 1000.0
+            );
+        }
+    }
+}
+
+/// The status of an otherwise successful run that reported the given number of warnings.
+fn warnings_status(cli: &NorgFmt, warnings: usize) -> Status {
+    if cli.error_on_warnings && warnings > 0 {
+        eprintln!("{warnings} warning(s) were reported");
+
+        return Status::Unclean;
+    }
+
+    Status::Clean
+}
+
+/// Saves the cache, leaving a disabled one in its place.
+fn save_cache(cache: &mut Cache) {
+    if let Err(err) = std::mem::replace(cache, Cache::disabled()).save() {
+        eprintln!("warning: unable to save the cache: {err}");
+    }
+}
+
+/// Formats a single file as part of a multi-file run, given its content as read ahead of time,
+/// writing the result back if requested. Files that are too large or appear to be binary are
+/// skipped without being formatted, and so are files the cache knows to be formatted already.
+fn format_file(
+    cli: &NorgFmt,
+    config: &Config,
+    file: &Path,
+    content: Prefetched,
+    session: &mut Session,
+) -> Result<Outcome> {
+    let original = match content? {
+        Ok(original) => original,
+        Err(reason) => return Ok(Outcome::Skipped(reason)),
+    };
+
+    if let Some(reason) = guard::sniff(&original) {
+        return Ok(Outcome::Skipped(reason));
+    }
+
+    if session.cache.is_formatted(&original, config) {
+        return Ok(Outcome::Unchanged);
+    }
+
+    let (content, source_encoding) = encoding::decode(&original, cli.latin1)?;
+
+    if cli.assume_clean && fingerprint::is_clean(&content, config) {
+        return Ok(Outcome::Unchanged);
+    }
+
+    let blocks = format_source(cli, config, file, &content, session)?;
+    let output_encoding = cli.output_encoding.unwrap_or(source_encoding);
+
+    if is_unchanged(&blocks, &content) && output_encoding == source_encoding {
+        session.cache.insert(&original, config);
+
+        return Ok(Outcome::Unchanged);
+    }
+
+    if cli.diff {
+        let style = Style::stdout(cli.color.unwrap_or_default());
+
+        print!(
+            "{}",
+            diff::unified_diff(file, &content, &blocks.concat(), style)
+        );
+    } else if let Some(Output::Patch) = cli.output {
+        print!(
+            "{}",
+            diff::patch(file, &content, &blocks.concat(), source_encoding)
+        );
+    } else if cli.dry_run {
+        println!(
+            "{}: {}",
+            file.display(),
+            diff::stats(&content, &blocks.concat())
+        );
+    }
+
+    let blocks = match &mut session.prompt {
+        None => blocks,
+        Some(prompt) => {
+            let style = Style::stdout(cli.color.unwrap_or_default());
+
+            match prompt.review(file, &content, &blocks.concat(), style)? {
+                Some(approved) => vec![approved],
+                None => return Ok(Outcome::Skipped(guard::SkipReason::Declined)),
+            }
+        }
+    };
+
+    if cli.write {
+        for block in &blocks {
+            encoding::check(block, output_encoding)?;
+        }
+
+        if cli.backup {
+            backup::backup(file, &original, cli.backup_dir.as_deref())?;
+        }
+
+        let generation = persist::Generation::of(&original);
+        let preserve_mtime = cli.preserve_mtime && is_unchanged(&blocks, &content);
+
+        drop(content);
+        drop(original);
+
+        session.timings.measure(Phase::Emit, || {
+            persist::replace(file, generation, preserve_mtime, |writer| {
+                encoding::write(writer, blocks, output_encoding)
+            })
+        })?;
+    }
+
+    Ok(Outcome::Formatted)
+}
+
+/// Prints the path of every file that would be reformatted. Files that would be skipped are left
+/// out, and errors are reported without stopping the run.
+fn list_different(
+    cli: &NorgFmt,
+    configs: &Configs,
+    files: &[PathBuf],
+    max_file_size: u64,
+    cache: &mut Cache,
+) -> Result<Status> {
+    let mut stdout = BufWriter::new(std::io::stdout().lock());
+    let mut status = Status::Clean;
+
+    for file in files {
+        match is_formatted(cli, &configs.for_file(file), file, max_file_size, cache) {
+            Ok(true) => {}
+            Ok(false) => {
+                status = status.max(Status::Unclean);
+                writeln!(stdout, "{}", file.display())?;
+            }
+            Err(err) => {
+                status = status.max(Status::of(&err));
+                eprintln!("error: {}: {err}", file.display());
+
+                if cli.fail_fast {
+                    break;
+                }
+            }
+        }
+    }
+
+    stdout.flush()?;
+
+    Ok(status)
+}
+
+/// Checks whether a file is already formatted. Files that would be skipped count as formatted.
+fn is_formatted(
+    cli: &NorgFmt,
+    config: &Config,
+    file: &Path,
+    max_file_size: u64,
+    cache: &mut Cache,
+) -> Result<bool> {
+    if guard::check_size(file, max_file_size)?.is_some() {
+        return Ok(true);
+    }
+
+    let original = std::fs::read(file)?;
+
+    if guard::sniff(&original).is_some() || cache.is_formatted(&original, config) {
+        return Ok(true);
+    }
+
+    let (content, source_encoding) = encoding::decode(&original, cli.latin1)?;
+
+    if cli.output_encoding.unwrap_or(source_encoding) != source_encoding {
+        return Ok(false);
+    }
+
+    if cli.assume_clean && fingerprint::is_clean(&content, config) {
+        return Ok(true);
+    }
+
+    let blocks = formatter(cli, config).format(&content)?.blocks;
+    let is_formatted = is_unchanged(&blocks, &content);
+
+    if is_formatted {
+        cache.insert(&original, config);
+    }
+
+    Ok(is_formatted)
+}
+
+/// The formatter for the files of a run, fingerprinting, verifying and keeping untouched blocks
+/// as requested on the command line.
+fn formatter(cli: &NorgFmt, config: &Config) -> Formatter {
+    let fingerprint = if cli.fingerprint {
+        Fingerprint::Add
+    } else if cli.no_fingerprint {
+        Fingerprint::Remove
+    } else {
+        Fingerprint::Keep
+    };
+
+    Formatter::new(config.clone())
+        .with_fingerprint(fingerprint)
+        .with_verification(cli.verify)
+        .with_minimal_diff(cli.minimal_diff)
+}
+
+/// Formats the content of a file into its top-level blocks, tracing the result if requested. The
+/// blocks are kept separate so that they can be compared with the content and encoded one at a
+/// time. Diagnostics are reported as warnings.
+fn format_source(
+    cli: &NorgFmt,
+    config: &Config,
+    file: &Path,
+    content: &str,
+    session: &mut Session,
+) -> Result<Vec<String>> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("file", path = %file.display()).entered();
+
+    let formatter = formatter(cli, config);
+    let mut parsed = session
+        .timings
+        .measure(Phase::Parse, || formatter.parse(content))?;
+
+    if let Some(graph) = &session.graph {
+        graph::update_backlinks(&mut parsed.ast, file, graph);
+    }
+
+    if let Some(trace) = &mut session.trace {
+        trace::trace_transforms(trace, file, parsed.source(), &parsed.ast, config)?;
+    }
 
-    let file = cli.file;
-    let content = String::from_utf8(std::fs::read(file)?)?;
+    let formatted = session.timings.measure(Phase::Format, || {
+        formatter.format_parsed(parsed, Some(file), |mut ctx| {
+            if let Some(profile) = &session.profile {
+                ctx = ctx.with_profile(profile.clone());
+            }
 
-    let ast = parse(&content).unwrap();
+            for inspector in &session.inspectors {
+                ctx = ctx.with_inspector(inspector.clone());
+            }
 
-    let (formatted_output, errors) = format().parse_recovery(ast);
+            ctx
+        })
+    })?;
 
-    if let Some(formatted_output) = formatted_output {
-        print!("{}", formatted_output.join(""));
+    for diagnostic in formatted.diagnostics {
+        eprintln!("warning: {}: {}", file.display(), diagnostic.message);
+        session.warnings += 1;
     }
 
-    Ok(())
+    Ok(formatted.blocks)
 }
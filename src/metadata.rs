@@ -0,0 +1,298 @@
+//! Reading the `@document.meta` block of a document, e.g.
+//!
+//! ```norg
+//! @document.meta
+//! title: Launch plan
+//! categories: [
+//!     work
+//!     projects
+//! ]
+//! @end
+//! ```
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use rust_norg::NorgASTFlat;
+
+use crate::MetadataTitle;
+
+/// A value of the metadata: a single string or a list of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    List(Vec<String>),
+}
+
+impl Value {
+    /// The strings of the value, a single one unless it is a list.
+    pub fn items(&self) -> Vec<&str> {
+        match self {
+            Value::String(value) if value.is_empty() => Vec::new(),
+            Value::String(value) => vec![value],
+            Value::List(values) => values.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// The metadata of a document, by key.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Metadata(pub BTreeMap<String, Value>);
+
+impl Metadata {
+    /// Parses the content of a `@document.meta` block: `key: value` lines, where a value of `[`
+    /// starts a list of the lines up to the closing `]`. Lists may also be written on one line,
+    /// e.g. `[work, projects]`. Lines that are neither are ignored.
+    pub fn parse(content: &str) -> Self {
+        let mut metadata = BTreeMap::new();
+        let mut lines = content.lines();
+
+        while let Some(line) = lines.next() {
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (key, value) = (key.trim().to_string(), value.trim());
+
+            let value = if value == "[" {
+                Value::List(
+                    lines
+                        .by_ref()
+                        .map(str::trim)
+                        .take_while(|line| *line != "]")
+                        .filter(|line| !line.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                )
+            } else if let Some(list) = value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+                let separator = if list.contains(',') { ',' } else { ' ' };
+
+                Value::List(
+                    list.split(separator)
+                        .map(str::trim)
+                        .filter(|item| !item.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                )
+            } else {
+                Value::String(value.to_string())
+            };
+
+            metadata.insert(key, value);
+        }
+
+        Self(metadata)
+    }
+
+    /// Reads the `@document.meta` block of a document, if it has one.
+    pub fn of(ast: &[NorgASTFlat]) -> Option<Self> {
+        ast.iter().find_map(|node| match node {
+            NorgASTFlat::VerbatimRangedTag { name, content, .. }
+                if name == &["document", "meta"] =>
+            {
+                Some(Self::parse(content))
+            }
+            _ => None,
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.0.get(key)
+    }
+
+    /// The title of the document, if it has one.
+    pub fn title(&self) -> Option<&str> {
+        match self.get("title")? {
+            Value::String(title) if !title.is_empty() => Some(title),
+            _ => None,
+        }
+    }
+
+    /// The categories the document is filed under.
+    pub fn categories(&self) -> Vec<&str> {
+        self.get("categories").map(Value::items).unwrap_or_default()
+    }
+}
+
+/// Sets `key` to `value` in the content of a `@document.meta` block, replacing the line that sets
+/// it (keeping its indentation) or adding one at the end if there is none.
+pub fn set_field(content: &str, key: &str, value: &str) -> String {
+    let mut found = false;
+    let mut lines = content
+        .lines()
+        .map(|line| match line.split_once(':') {
+            Some((name, _)) if !found && name.trim() == key => {
+                found = true;
+
+                let indent = &line[..line.len() - line.trim_start().len()];
+
+                format!("{indent}{key}: {value}")
+            }
+            _ => line.to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    if !found {
+        lines.push(format!("{key}: {value}"));
+    }
+
+    lines.join("\n") + if content.ends_with('\n') { "\n" } else { "" }
+}
+
+/// The title the metadata of a document should have according to `source`: the title of its first
+/// level-1 heading, or the name of its file without the extension.
+pub fn expected_title(
+    ast: &[NorgASTFlat],
+    source: MetadataTitle,
+    file_stem: Option<&str>,
+) -> Option<String> {
+    match source {
+        MetadataTitle::Heading => ast.iter().find_map(|node| match node {
+            NorgASTFlat::Heading {
+                level: 1, title, ..
+            } => Some(
+                title
+                    .iter()
+                    .cloned()
+                    .map_into::<String>()
+                    .collect::<String>()
+                    .trim()
+                    .to_string(),
+            ),
+            _ => None,
+        }),
+        MetadataTitle::Filename => file_stem.map(str::to_string),
+    }
+}
+
+/// Sets the title in the `@document.meta` block of a document, if it has one.
+pub fn set_title(ast: &mut [NorgASTFlat], title: &str) {
+    for node in ast {
+        if let NorgASTFlat::VerbatimRangedTag { name, content, .. } = node {
+            if name == &["document", "meta"] {
+                *content = set_field(content, "title", title);
+                return;
+            }
+        }
+    }
+}
+
+/// Sets `key` to `value` in the `@document.meta` block of formatted output, if it has one and the
+/// key is set in it already. Returns `None` if there is nothing to update.
+pub fn update_field_in_output(output: &str, key: &str, value: &str) -> Option<String> {
+    let lines = output.split_inclusive('\n').collect::<Vec<_>>();
+    let start = lines
+        .iter()
+        .position(|line| line.trim() == "@document.meta")?;
+    let end = start
+        + lines[start..]
+            .iter()
+            .position(|line| line.trim() == "@end")?;
+    let field = (start + 1..end).find(|&index| {
+        lines[index]
+            .split_once(':')
+            .is_some_and(|(name, _)| name.trim() == key)
+    })?;
+    let line = lines[field];
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let newline = &line[line.trim_end_matches(['\r', '\n']).len()..];
+    let updated = format!("{indent}{key}: {value}{newline}");
+
+    Some(lines[..field].concat() + &updated + &lines[field + 1..].concat())
+}
+
+/// Renders a point in time, given in seconds since the Unix epoch, the way Neorg writes the
+/// `created` and `updated` fields of the metadata, e.g. `2024-05-01T12:30:00+0000`.
+pub fn format_timestamp(seconds: u64) -> String {
+    // Days to civil dates, from Howard Hinnant's `civil_from_days`.
+    let days = (seconds / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    let time = seconds % 86_400;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}+0000",
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_norg::parse;
+
+    use super::*;
+
+    #[test]
+    fn reads_strings_and_lists() {
+        let metadata = Metadata::parse(
+            "title: Launch plan\ncategories: [\n    work\n\n    projects\n]\nauthors: [a, b c]\n\
+             tags: [x y]\ndescription:\nnot a field\n",
+        );
+
+        assert_eq!(metadata.title(), Some("Launch plan"));
+        assert_eq!(metadata.categories(), ["work", "projects"]);
+        assert_eq!(
+            metadata.get("authors"),
+            Some(&Value::List(vec!["a".to_string(), "b c".to_string()]))
+        );
+        assert_eq!(metadata.get("tags").unwrap().items(), ["x", "y"]);
+        assert!(metadata.get("description").unwrap().items().is_empty());
+        assert_eq!(metadata.0.len(), 5);
+    }
+
+    #[test]
+    fn reads_the_metadata_block_of_a_document() {
+        let ast = parse("* Heading\n@document.meta\ncategories: work\n@end\n").unwrap();
+        let metadata = Metadata::of(&ast).unwrap();
+
+        assert_eq!(metadata.categories(), ["work"]);
+        assert_eq!(metadata.title(), None);
+        assert_eq!(Metadata::of(&parse("* Heading\n").unwrap()), None);
+    }
+
+    #[test]
+    fn sets_fields_in_blocks_and_output() {
+        assert_eq!(
+            set_field("  title: Old\nauthor: me\n", "title", "New"),
+            "  title: New\nauthor: me\n"
+        );
+        assert_eq!(
+            set_field("author: me", "title", "New"),
+            "author: me\ntitle: New"
+        );
+
+        let output = "* Notes\n@document.meta\ntitle: Notes\nupdated: old\r\n@end\n";
+
+        assert_eq!(
+            update_field_in_output(output, "updated", "new").as_deref(),
+            Some("* Notes\n@document.meta\ntitle: Notes\nupdated: new\r\n@end\n")
+        );
+        assert_eq!(update_field_in_output(output, "created", "new"), None);
+        assert_eq!(update_field_in_output("* Notes\n", "updated", "new"), None);
+    }
+
+    #[test]
+    fn derives_titles_and_timestamps() {
+        let ast = parse("** Sub\n* Launch plan\n").unwrap();
+
+        assert_eq!(
+            expected_title(&ast, MetadataTitle::Heading, Some("notes")).as_deref(),
+            Some("Launch plan")
+        );
+        assert_eq!(
+            expected_title(&ast, MetadataTitle::Filename, Some("notes")).as_deref(),
+            Some("notes")
+        );
+        assert_eq!(format_timestamp(0), "1970-01-01T00:00:00+0000");
+        assert_eq!(format_timestamp(1_714_566_600), "2024-05-01T12:30:00+0000");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29T00:00:00+0000");
+    }
+}
@@ -0,0 +1,682 @@
+//! The serializable form of the formatting options, shared by the command line, the configuration
+//! file, the `serve` protocol and the library.
+//!
+//! Every option is optional, so that options can be given in layers (e.g. a configuration file
+//! overridden by command line flags) which are combined with [`FormatOptions::merge`]. Options that
+//! are given nowhere take their default, as documented on each field.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use serde::{de::IgnoredAny, Deserialize, Serialize};
+
+use crate::{
+    journal, Config, Construct, ContinuationIndent, FreeFormConversion, IndentStyle, LineEnding,
+    LinkStyle, Lint, Locale, MetadataTitle, TagHandling, VerbatimBlankLines, WikilinkStyle,
+};
+
+/// The version of the options format, increased whenever options change incompatibly.
+pub const OPTIONS_VERSION: u32 = 1;
+
+/// The shortest line length that is accepted. Anything shorter wraps nearly every word onto a line
+/// of its own, which is almost certainly a typo.
+pub const MIN_LINE_LENGTH: usize = 20;
+
+/// The widest indentation level that is accepted when indenting with spaces.
+pub const MAX_INDENT_WIDTH: usize = 16;
+
+/// Something wrong with the options, along with a suggestion on how to fix it if there is one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Problem {
+    /// The option the problem is about, with the keys of maps separated by dots, e.g.
+    /// `construct_line_length.quotes`.
+    pub key: Option<String>,
+    pub message: String,
+    pub help: Option<String>,
+}
+
+impl Problem {
+    fn new(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            key: Some(key.into()),
+            message: message.into(),
+            help: None,
+        }
+    }
+
+    fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+
+        match &self.help {
+            Some(help) => write!(f, "; {help}"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The number of single-character edits turning one string into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+
+        for (j, &b) in b.iter().enumerate() {
+            current.push(
+                (previous[j] + usize::from(a != b))
+                    .min(previous[j + 1] + 1)
+                    .min(current[j] + 1),
+            );
+        }
+
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// The option most likely meant by an unknown key, if any is close enough.
+pub fn suggest(key: &str) -> Option<String> {
+    let key = key.replace('-', "_").to_ascii_lowercase();
+    let known = serde_json::to_value(FormatOptions::from(&Config::default())).ok()?;
+
+    known
+        .as_object()?
+        .keys()
+        .map(|option| (edit_distance(&key, option), option))
+        .filter(|&(distance, option)| distance <= option.len() / 3 || option.contains(&key))
+        .min()
+        .map(|(_, option)| option.clone())
+}
+
+/// Formatting options as found in a configuration file or sent by an editor, e.g.
+/// `{ "version": 1, "line_length": 100, "indent_style": "tabs" }`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FormatOptions {
+    /// The version of the format the options were written for. Default: [`OPTIONS_VERSION`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub newline_after_headings: Option<bool>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indent_headings: Option<bool>,
+    /// The width paragraphs are wrapped to. Default: 80.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_length: Option<usize>,
+    /// Line lengths of specific constructs, e.g. `{ "quotes": 72 }`. Default: none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub construct_line_length: Option<BTreeMap<Construct, usize>>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wrap_comments: Option<bool>,
+    /// Default: `auto`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub free_form_conversion: Option<FreeFormConversion>,
+    /// Default: `spaces`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indent_style: Option<IndentStyle>,
+    /// The number of spaces per indentation level when indenting with spaces, or the number of
+    /// columns a tab counts as towards the line length when indenting with tabs. Default: 4.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indent_width: Option<usize>,
+    /// Default: `lf`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line_ending: Option<LineEnding>,
+    /// Default: `text`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub continuation_indent: Option<ContinuationIndent>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub align_definitions: Option<bool>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blank_line_before_lists: Option<bool>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blank_line_between_lists: Option<bool>,
+    /// Default: `preserve`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub link_style: Option<LinkStyle>,
+    /// Default: `preserve`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wikilink_style: Option<WikilinkStyle>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub punctuation_spacing: Option<bool>,
+    /// The typographic conventions of the prose, `en` or `fr`. Default: `en`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locale: Option<Locale>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub non_breaking_spaces: Option<bool>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typographic_substitution: Option<bool>,
+    /// Default: `preserve`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verbatim_blank_lines: Option<VerbatimBlankLines>,
+    /// Markers starting a trailing annotation of a heading, e.g. `["{{{", "}}}"]`. Default: none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heading_annotation_markers: Option<Vec<String>>,
+    /// Patterns of template placeholders kept as written, e.g. `["{{*}}", "<DATE>"]`, where `*`
+    /// stands for any text within a line. Default: none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template_placeholders: Option<Vec<String>>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task_roll_up: Option<bool>,
+    /// The format headings titled with a date are rewritten in, e.g. `"%Y-%m-%d"`. Default: none,
+    /// leaving dates as written.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journal_date_format: Option<String>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_journal_entries: Option<bool>,
+    /// What the title in `@document.meta` has to match, `heading` or `filename`. Default:
+    /// `heading`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata_title: Option<MetadataTitle>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix_metadata: Option<bool>,
+    /// The lints that are not run, e.g. `["references"]`. Default: none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disabled_lints: Option<BTreeSet<Lint>>,
+    /// Default: false.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub normalize_languages: Option<bool>,
+    /// Aliases of `@code` languages, e.g. `{ "golang": "go" }`, on top of the built-in ones.
+    /// Default: none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language_aliases: Option<BTreeMap<String, String>>,
+    /// How the content of tags is handled by tag name, e.g. `{ "code": "verbatim" }`. Default:
+    /// none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag_handling: Option<BTreeMap<String, TagHandling>>,
+    /// Only the files matching one of these globs are formatted, like with `--include`. The globs
+    /// of later layers replace those of earlier ones. Default: none, selecting every file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<String>>,
+    /// Files matching one of these globs are never formatted, nor directories matching them
+    /// searched, like with `--exclude`. The globs of every layer are combined. Default: none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<String>>,
+    /// Options that only apply to the files matching a glob, e.g. `[{ "glob": "journal/**",
+    /// "sort_journal_entries": true }]`. Entries are applied in order, on top of the other
+    /// options. Default: none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paths: Option<Vec<PathOptions>>,
+    /// Options that only apply to the files matching a glob relative to the directory of the
+    /// configuration file, by glob, e.g. `{ "journal/**": { "sort_journal_entries": true } }`.
+    /// Profiles with longer globs are applied later, before `paths`. Default: none.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<BTreeMap<String, FormatOptions>>,
+    /// Keys that aren't options (yet), kept to warn about instead of rejecting the options, as they
+    /// may have been written for a newer version.
+    #[serde(flatten, skip_serializing)]
+    pub unknown: BTreeMap<String, IgnoredAny>,
+}
+
+/// Options that only apply to the files matching a glob, e.g. `journal/**`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PathOptions {
+    pub glob: String,
+    #[serde(flatten)]
+    pub options: FormatOptions,
+}
+
+impl FormatOptions {
+    /// Combines two layers of options, with the options given in `other` taking precedence. The
+    /// entries of maps are combined rather than replaced.
+    pub fn merge(self, other: Self) -> Self {
+        fn merge_maps<K: Ord, V>(
+            base: Option<BTreeMap<K, V>>,
+            other: Option<BTreeMap<K, V>>,
+        ) -> Option<BTreeMap<K, V>> {
+            match (base, other) {
+                (Some(mut base), Some(other)) => {
+                    base.extend(other);
+                    Some(base)
+                }
+                (base, other) => other.or(base),
+            }
+        }
+
+        fn merge_vecs<T>(base: Option<Vec<T>>, other: Option<Vec<T>>) -> Option<Vec<T>> {
+            match (base, other) {
+                (Some(mut base), Some(other)) => {
+                    base.extend(other);
+                    Some(base)
+                }
+                (base, other) => other.or(base),
+            }
+        }
+
+        let mut unknown = self.unknown;
+        unknown.extend(other.unknown);
+
+        Self {
+            version: other.version.or(self.version),
+            newline_after_headings: other.newline_after_headings.or(self.newline_after_headings),
+            indent_headings: other.indent_headings.or(self.indent_headings),
+            line_length: other.line_length.or(self.line_length),
+            construct_line_length: merge_maps(
+                self.construct_line_length,
+                other.construct_line_length,
+            ),
+            wrap_comments: other.wrap_comments.or(self.wrap_comments),
+            free_form_conversion: other.free_form_conversion.or(self.free_form_conversion),
+            indent_style: other.indent_style.or(self.indent_style),
+            indent_width: other.indent_width.or(self.indent_width),
+            line_ending: other.line_ending.or(self.line_ending),
+            continuation_indent: other.continuation_indent.or(self.continuation_indent),
+            align_definitions: other.align_definitions.or(self.align_definitions),
+            blank_line_before_lists: other
+                .blank_line_before_lists
+                .or(self.blank_line_before_lists),
+            blank_line_between_lists: other
+                .blank_line_between_lists
+                .or(self.blank_line_between_lists),
+            link_style: other.link_style.or(self.link_style),
+            wikilink_style: other.wikilink_style.or(self.wikilink_style),
+            punctuation_spacing: other.punctuation_spacing.or(self.punctuation_spacing),
+            locale: other.locale.or(self.locale),
+            non_breaking_spaces: other.non_breaking_spaces.or(self.non_breaking_spaces),
+            typographic_substitution: other
+                .typographic_substitution
+                .or(self.typographic_substitution),
+            verbatim_blank_lines: other.verbatim_blank_lines.or(self.verbatim_blank_lines),
+            heading_annotation_markers: other
+                .heading_annotation_markers
+                .or(self.heading_annotation_markers),
+            template_placeholders: other.template_placeholders.or(self.template_placeholders),
+            task_roll_up: other.task_roll_up.or(self.task_roll_up),
+            journal_date_format: other.journal_date_format.or(self.journal_date_format),
+            sort_journal_entries: other.sort_journal_entries.or(self.sort_journal_entries),
+            metadata_title: other.metadata_title.or(self.metadata_title),
+            fix_metadata: other.fix_metadata.or(self.fix_metadata),
+            disabled_lints: other.disabled_lints.or(self.disabled_lints),
+            normalize_languages: other.normalize_languages.or(self.normalize_languages),
+            language_aliases: merge_maps(self.language_aliases, other.language_aliases),
+            tag_handling: merge_maps(self.tag_handling, other.tag_handling),
+            include: other.include.or(self.include),
+            exclude: merge_vecs(self.exclude, other.exclude),
+            paths: merge_vecs(self.paths, other.paths),
+            profile: merge_maps(self.profile, other.profile),
+            unknown,
+        }
+    }
+
+    /// Whether any of the options that only apply to the top level, not within `paths` entries and
+    /// profiles, are given.
+    fn has_top_level_options(&self) -> bool {
+        self.paths.is_some()
+            || self.profile.is_some()
+            || self.include.is_some()
+            || self.exclude.is_some()
+    }
+
+    /// Describes what was ignored in the options: unknown keys, and everything that may not be
+    /// understood in options written for a newer version.
+    pub fn warnings(&self) -> Vec<Problem> {
+        let mut warnings = Vec::new();
+
+        if let Some(version) = self.version.filter(|&version| version > OPTIONS_VERSION) {
+            warnings.push(Problem::new(
+                "version",
+                format!(
+                    "the options were written for version {version}, but only version \
+                     {OPTIONS_VERSION} is supported; options that changed since may be misread"
+                ),
+            ));
+        }
+
+        for (index, path) in self.paths.iter().flatten().enumerate() {
+            if path.options.has_top_level_options() {
+                warnings.push(Problem::new(
+                    "paths",
+                    format!(
+                        "the `paths`, `profile`, `include` and `exclude` of `paths[{index}]` ({}) \
+                         were ignored",
+                        path.glob
+                    ),
+                ));
+            }
+
+            warnings.extend(path.options.warnings());
+        }
+
+        for (glob, options) in self.profile.iter().flatten() {
+            if options.has_top_level_options() {
+                warnings.push(Problem::new(
+                    format!("profile.{glob}"),
+                    format!(
+                        "the `paths`, `profile`, `include` and `exclude` of the `{glob}` profile \
+                         were ignored"
+                    ),
+                ));
+            }
+
+            warnings.extend(options.warnings());
+        }
+
+        for key in self.unknown.keys() {
+            let problem = Problem::new(key, format!("`{key}` is not an option and was ignored"));
+
+            warnings.push(match suggest(key) {
+                Some(option) => problem.with_help(format!("did you mean `{option}`?")),
+                None => problem,
+            });
+        }
+
+        warnings
+    }
+
+    /// Describes the options that can't be formatted with: values out of their range.
+    pub fn errors(&self) -> Vec<Problem> {
+        let mut errors = Vec::new();
+        let mut check_line_length = |key: String, length: usize| {
+            if length < MIN_LINE_LENGTH {
+                errors.push(
+                    Problem::new(
+                        key,
+                        format!("a line length of {length} is shorter than {MIN_LINE_LENGTH}"),
+                    )
+                    .with_help(format!(
+                        "use a line length of at least {MIN_LINE_LENGTH}, e.g. the default of {}",
+                        Config::default().line_length
+                    )),
+                );
+            }
+        };
+
+        if let Some(length) = self.line_length {
+            check_line_length("line_length".to_string(), length);
+        }
+
+        for (construct, &length) in self.construct_line_length.iter().flatten() {
+            check_line_length(format!("construct_line_length.{construct}"), length);
+        }
+
+        if let Some(width) = self
+            .indent_width
+            .filter(|width| !(1..=MAX_INDENT_WIDTH).contains(width))
+        {
+            errors.push(
+                Problem::new(
+                    "indent_width",
+                    format!("an indent width of {width} is not between 1 and {MAX_INDENT_WIDTH}"),
+                )
+                .with_help(format!(
+                    "use e.g. the default of {}",
+                    Config::default().indent_width
+                )),
+            );
+        }
+
+        if let Some(format) = &self.journal_date_format {
+            let mut chars = format.chars();
+            let unknown = std::iter::from_fn(|| {
+                chars.by_ref().find(|&c| c == '%')?;
+                Some(chars.next().unwrap_or('%'))
+            })
+            .find(|&directive| !journal::DATE_DIRECTIVES.contains(directive));
+
+            if let Some(directive) = unknown {
+                errors.push(
+                    Problem::new(
+                        "journal_date_format",
+                        format!("`%{directive}` is not a known date directive"),
+                    )
+                    .with_help(
+                        "use `%Y`, `%m`, `%d`, `%e`, `%B`, `%b`, `%A` or `%a`, e.g. `%Y-%m-%d`",
+                    ),
+                );
+            }
+        }
+
+        for path in self.paths.iter().flatten() {
+            errors.extend(path.options.errors());
+        }
+
+        for options in self.profile.iter().flatten().map(|(_, options)| options) {
+            errors.extend(options.errors());
+        }
+
+        errors
+    }
+
+    /// The configuration to format with, using the defaults for every option that isn't given.
+    pub fn config(&self) -> Config {
+        let default = Config::default();
+
+        Config {
+            newline_after_headings: self
+                .newline_after_headings
+                .unwrap_or(default.newline_after_headings),
+            indent_headings: self.indent_headings.unwrap_or(default.indent_headings),
+            preserve_line_breaks: default.preserve_line_breaks,
+            line_length: self.line_length.unwrap_or(default.line_length),
+            construct_line_length: self
+                .construct_line_length
+                .clone()
+                .unwrap_or(default.construct_line_length),
+            wrap_comments: self.wrap_comments.unwrap_or(default.wrap_comments),
+            free_form_conversion: self
+                .free_form_conversion
+                .unwrap_or(default.free_form_conversion),
+            indent_style: self.indent_style.unwrap_or(default.indent_style),
+            indent_width: self.indent_width.unwrap_or(default.indent_width),
+            line_ending: self.line_ending.unwrap_or(default.line_ending),
+            continuation_indent: self
+                .continuation_indent
+                .unwrap_or(default.continuation_indent),
+            align_definitions: self.align_definitions.unwrap_or(default.align_definitions),
+            blank_line_before_lists: self
+                .blank_line_before_lists
+                .unwrap_or(default.blank_line_before_lists),
+            blank_line_between_lists: self
+                .blank_line_between_lists
+                .unwrap_or(default.blank_line_between_lists),
+            link_style: self.link_style.unwrap_or(default.link_style),
+            wikilink_style: self.wikilink_style.unwrap_or(default.wikilink_style),
+            punctuation_spacing: self
+                .punctuation_spacing
+                .unwrap_or(default.punctuation_spacing),
+            locale: self.locale.unwrap_or(default.locale),
+            non_breaking_spaces: self
+                .non_breaking_spaces
+                .unwrap_or(default.non_breaking_spaces),
+            typographic_substitution: self
+                .typographic_substitution
+                .unwrap_or(default.typographic_substitution),
+            verbatim_blank_lines: self
+                .verbatim_blank_lines
+                .unwrap_or(default.verbatim_blank_lines),
+            heading_annotation_markers: self
+                .heading_annotation_markers
+                .clone()
+                .unwrap_or(default.heading_annotation_markers),
+            template_placeholders: self
+                .template_placeholders
+                .clone()
+                .unwrap_or(default.template_placeholders),
+            task_roll_up: self.task_roll_up.unwrap_or(default.task_roll_up),
+            journal_date_format: self
+                .journal_date_format
+                .clone()
+                .or(default.journal_date_format),
+            sort_journal_entries: self
+                .sort_journal_entries
+                .unwrap_or(default.sort_journal_entries),
+            metadata_title: self.metadata_title.unwrap_or(default.metadata_title),
+            fix_metadata: self.fix_metadata.unwrap_or(default.fix_metadata),
+            disabled_lints: self
+                .disabled_lints
+                .clone()
+                .unwrap_or(default.disabled_lints),
+            normalize_languages: self
+                .normalize_languages
+                .unwrap_or(default.normalize_languages),
+            // Aliases are matched against lowercased languages.
+            language_aliases: self
+                .language_aliases
+                .iter()
+                .flatten()
+                .map(|(alias, name)| (alias.to_lowercase(), name.clone()))
+                .collect(),
+            tag_handling: self.tag_handling.clone().unwrap_or(default.tag_handling),
+        }
+    }
+}
+
+impl From<&Config> for FormatOptions {
+    /// Gives every option explicitly, e.g. to show the configuration that is in effect.
+    fn from(config: &Config) -> Self {
+        Self {
+            version: Some(OPTIONS_VERSION),
+            newline_after_headings: Some(config.newline_after_headings),
+            indent_headings: Some(config.indent_headings),
+            line_length: Some(config.line_length),
+            construct_line_length: Some(config.construct_line_length.clone()),
+            wrap_comments: Some(config.wrap_comments),
+            free_form_conversion: Some(config.free_form_conversion),
+            indent_style: Some(config.indent_style),
+            indent_width: Some(config.indent_width),
+            line_ending: Some(config.line_ending),
+            continuation_indent: Some(config.continuation_indent),
+            align_definitions: Some(config.align_definitions),
+            blank_line_before_lists: Some(config.blank_line_before_lists),
+            blank_line_between_lists: Some(config.blank_line_between_lists),
+            link_style: Some(config.link_style),
+            wikilink_style: Some(config.wikilink_style),
+            punctuation_spacing: Some(config.punctuation_spacing),
+            locale: Some(config.locale),
+            non_breaking_spaces: Some(config.non_breaking_spaces),
+            typographic_substitution: Some(config.typographic_substitution),
+            verbatim_blank_lines: Some(config.verbatim_blank_lines),
+            heading_annotation_markers: Some(config.heading_annotation_markers.clone()),
+            template_placeholders: Some(config.template_placeholders.clone()),
+            task_roll_up: Some(config.task_roll_up),
+            journal_date_format: config.journal_date_format.clone(),
+            sort_journal_entries: Some(config.sort_journal_entries),
+            metadata_title: Some(config.metadata_title),
+            fix_metadata: Some(config.fix_metadata),
+            disabled_lints: Some(config.disabled_lints.clone()),
+            normalize_languages: Some(config.normalize_languages),
+            language_aliases: Some(config.language_aliases.clone()),
+            tag_handling: Some(config.tag_handling.clone()),
+            include: None,
+            exclude: None,
+            paths: None,
+            profile: None,
+            unknown: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(json: serde_json::Value) -> FormatOptions {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn keys(problems: Vec<Problem>) -> Vec<String> {
+        problems
+            .into_iter()
+            .filter_map(|problem| problem.key)
+            .collect()
+    }
+
+    #[test]
+    fn later_layers_take_precedence_and_maps_are_combined() {
+        let base = options(serde_json::json!({
+            "line_length": 80,
+            "wrap_comments": true,
+            "construct_line_length": { "quotes": 72, "lists": 60 },
+            "paths": [{ "glob": "a/**", "line_length": 60 }],
+        }));
+        let other = options(serde_json::json!({
+            "line_length": 100,
+            "construct_line_length": { "lists": 90 },
+            "paths": [{ "glob": "b/**", "line_length": 70 }],
+        }));
+        let merged = base.merge(other);
+
+        assert_eq!(merged.line_length, Some(100));
+        assert_eq!(merged.wrap_comments, Some(true));
+        assert_eq!(
+            merged.construct_line_length,
+            Some(BTreeMap::from([
+                (Construct::Quotes, 72),
+                (Construct::Lists, 90)
+            ]))
+        );
+        assert_eq!(
+            merged
+                .paths
+                .into_iter()
+                .flatten()
+                .map(|path| path.glob)
+                .collect::<Vec<_>>(),
+            ["a/**", "b/**"]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_options_within_paths_and_profiles() {
+        let invalid = options(serde_json::json!({
+            "line_length": 10,
+            "journal_date_format": "%Y-%q",
+            "paths": [{ "glob": "a/**", "construct_line_length": { "quotes": 5 } }],
+            "profile": { "b/**": { "indent_width": 0 } },
+        }));
+
+        assert_eq!(
+            keys(invalid.errors()),
+            [
+                "line_length",
+                "journal_date_format",
+                "construct_line_length.quotes",
+                "indent_width"
+            ]
+        );
+        assert!(options(serde_json::json!({ "line_length": 100 }))
+            .errors()
+            .is_empty());
+        // The width of a tab still counts towards the line length.
+        assert!(
+            options(serde_json::json!({ "indent_style": "tabs", "indent_width": 2 }))
+                .errors()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn suggests_options_for_unknown_keys() {
+        let warnings = options(serde_json::json!({ "line_lenght": 100 })).warnings();
+
+        assert_eq!(keys(warnings.clone()), ["line_lenght"]);
+        assert_eq!(
+            warnings[0].help.as_deref(),
+            Some("did you mean `line_length`?")
+        );
+        assert_eq!(suggest("LINE-LENGTH").as_deref(), Some("line_length"));
+        assert_eq!(suggest("bless"), None);
+    }
+}
@@ -0,0 +1,133 @@
+//! Formatting overrides for parts of a document.
+//!
+//! A `+norgfmt` attribute overrides the configuration for the block it is attached to, or for the
+//! whole section when attached to a heading, e.g. `+norgfmt line_length=120 wrap=preserve`.
+
+use crate::{Config, Construct};
+
+/// The name of the carryover attribute holding the overrides.
+pub const ATTRIBUTE: &str = "norgfmt";
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{value}` is neither `true` nor `false`"))
+}
+
+fn parse_number(value: &str) -> Result<usize, String> {
+    value
+        .parse()
+        .map_err(|_| format!("`{value}` is not a number"))
+}
+
+/// Applies the `key=value` overrides given as the parameters of a `+norgfmt` attribute to the
+/// configuration. Keys are named like the command line options, with either dashes or underscores.
+/// `wrap=preserve` keeps paragraphs, list items and quotes as they were written and `wrap=reflow`
+/// wraps them at the line length again. The line length of a single construct is overridden with
+/// e.g. `line_length.quotes=72`.
+pub fn apply(config: &Config, parameters: &[String]) -> Result<Config, String> {
+    let mut config = config.clone();
+
+    for parameter in parameters {
+        let (key, value) = parameter
+            .split_once('=')
+            .ok_or_else(|| format!("`{parameter}` is not of the form key=value"))?;
+
+        let key = key.replace('-', "_");
+
+        if let Some(construct) = key.strip_prefix("line_length.") {
+            let construct = construct
+                .parse::<Construct>()
+                .map_err(|_| format!("`{construct}` has no line length of its own"))?;
+
+            config
+                .construct_line_length
+                .insert(construct, parse_number(value)?);
+
+            continue;
+        }
+
+        match key.as_str() {
+            "line_length" => config.line_length = parse_number(value)?,
+            "wrap" => match value {
+                "preserve" => config.preserve_line_breaks = true,
+                "reflow" => config.preserve_line_breaks = false,
+                _ => return Err(format!("`{value}` is neither `preserve` nor `reflow`")),
+            },
+            "newline_after_headings" => config.newline_after_headings = parse_bool(value)?,
+            "indent_headings" => config.indent_headings = parse_bool(value)?,
+            "wrap_comments" => config.wrap_comments = parse_bool(value)?,
+            "free_form_conversion" => config.free_form_conversion = value.parse()?,
+            "indent_style" => config.indent_style = value.parse()?,
+            "indent_width" => config.indent_width = parse_number(value)?,
+            "continuation_indent" => config.continuation_indent = value.parse()?,
+            "align_definitions" => config.align_definitions = parse_bool(value)?,
+            "blank_line_before_lists" => config.blank_line_before_lists = parse_bool(value)?,
+            "blank_line_between_lists" => config.blank_line_between_lists = parse_bool(value)?,
+            _ => return Err(format!("`{key}` can not be overridden")),
+        }
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::IndentStyle;
+
+    fn apply(parameters: &[&str]) -> Result<Config, String> {
+        let parameters = parameters
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        super::apply(&Config::default(), &parameters)
+    }
+
+    #[test]
+    fn overrides_options_by_their_command_line_names() {
+        let config = apply(&[
+            "line-length=100",
+            "line_length.quotes=72",
+            "wrap=preserve",
+            "indent_style=tabs",
+            "blank-line-before-lists=false",
+        ])
+        .unwrap();
+
+        assert_eq!(config.line_length, 100);
+        assert_eq!(config.line_length_of(Construct::Quotes), 72);
+        assert_eq!(config.line_length_of(Construct::Lists), 100);
+        assert!(config.preserve_line_breaks);
+        assert_eq!(config.indent_style, IndentStyle::Tabs);
+        assert!(!config.blank_line_before_lists);
+        assert!(!apply(&["wrap=reflow"]).unwrap().preserve_line_breaks);
+    }
+
+    #[test]
+    fn rejects_what_can_not_be_overridden() {
+        let error = |parameter| apply(&[parameter]).err();
+
+        assert_eq!(
+            error("line_length"),
+            Some("`line_length` is not of the form key=value".to_string())
+        );
+        assert_eq!(
+            error("line_length=wide"),
+            Some("`wide` is not a number".to_string())
+        );
+        assert_eq!(
+            error("wrap=sometimes"),
+            Some("`sometimes` is neither `preserve` nor `reflow`".to_string())
+        );
+        assert_eq!(
+            error("line_length.tables=72"),
+            Some("`tables` has no line length of its own".to_string())
+        );
+        assert_eq!(
+            error("locale=fr"),
+            Some("`locale` can not be overridden".to_string())
+        );
+    }
+}
@@ -0,0 +1,477 @@
+//! Exporting documents as the JSON of Pandoc's AST, e.g. for `pandoc -f json -o notes.docx`, which
+//! reaches every format Pandoc writes.
+//!
+//! Lists, quotes and definitions map to their Pandoc counterparts and tasks to the ☐/☒ prefixes
+//! Pandoc uses for task lists. Footnote definitions become divs with the `footnote` class, as Norg
+//! writes them apart from where they are referred to. The title of the `@document.meta` block, if
+//! any, goes into the metadata of the document.
+
+use rust_norg::{
+    DetachedModifierExtension, LinkTarget, NorgASTFlat, ParagraphSegment, ParagraphSegmentToken,
+    TodoStatus,
+};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    exporter::{heading_id, link_href, visit, Exporter, Nestable, Rangeable},
+    metadata::Metadata,
+};
+
+/// The version of Pandoc's AST the JSON is written in.
+pub const API_VERSION: [u32; 3] = [1, 23, 1];
+
+/// Exports documents as Pandoc's JSON, with links to other documents pointing to their
+/// counterpart with `extension`, e.g. `html`.
+#[derive(Clone)]
+pub struct PandocJson {
+    extension: String,
+    blocks: Vec<Value>,
+    items: Vec<Item>,
+    definitions: Vec<Value>,
+    title: Option<String>,
+}
+
+impl Default for PandocJson {
+    fn default() -> Self {
+        Self::new("html")
+    }
+}
+
+fn element(tag: &str, content: Value) -> Value {
+    json!({ "t": tag, "c": content })
+}
+
+/// The attributes of an element: its id, classes and key-value pairs.
+fn attributes(id: &str, classes: &[&str]) -> Value {
+    json!([id, classes, []])
+}
+
+fn plain_text(segments: &[ParagraphSegment]) -> String {
+    segments.iter().cloned().map(String::from).collect()
+}
+
+/// A list item or quote, gathered up before its run is turned into a list.
+#[derive(Clone)]
+struct Item {
+    kind: Nestable,
+    level: usize,
+    blocks: Vec<Value>,
+}
+
+/// Turns a run of consecutive list items and quotes into lists and quotes, nesting the items
+/// deeper than the first ones into the item before them.
+fn nest(run: &[Item]) -> Vec<Value> {
+    let level = run.iter().map(|item| item.level).min().unwrap_or_default();
+    let mut blocks = Vec::new();
+    let mut entries: Vec<Vec<Value>> = Vec::new();
+    let mut kind = None;
+    let flush = |kind, entries: Vec<Vec<Value>>, blocks: &mut Vec<Value>| match kind {
+        Some(Nestable::Quote) => blocks.push(element("BlockQuote", entries.concat().into())),
+        Some(Nestable::OrderedList) => blocks.push(element(
+            "OrderedList",
+            json!([[1, { "t": "Decimal" }, { "t": "Period" }], entries]),
+        )),
+        Some(Nestable::UnorderedList) => blocks.push(element("BulletList", entries.into())),
+        None => (),
+    };
+    let mut start = 0;
+
+    while start < run.len() {
+        let end = run[start + 1..]
+            .iter()
+            .position(|item| item.level <= level)
+            .map_or(run.len(), |position| start + 1 + position);
+
+        if kind != Some(run[start].kind) {
+            flush(kind, std::mem::take(&mut entries), &mut blocks);
+            kind = Some(run[start].kind);
+        }
+
+        let mut entry = run[start].blocks.clone();
+
+        entry.extend(nest(&run[start + 1..end]));
+        entries.push(entry);
+        start = end;
+    }
+
+    flush(kind, entries, &mut blocks);
+    blocks
+}
+
+impl PandocJson {
+    pub fn new(extension: &str) -> Self {
+        Self {
+            extension: extension.to_string(),
+            blocks: Vec::new(),
+            items: Vec::new(),
+            definitions: Vec::new(),
+            title: None,
+        }
+    }
+
+    fn inlines(&self, segments: &[ParagraphSegment]) -> Vec<Value> {
+        segments
+            .iter()
+            .filter_map(|segment| self.inline(segment))
+            .collect()
+    }
+
+    fn inline(&self, segment: &ParagraphSegment) -> Option<Value> {
+        Some(match segment {
+            ParagraphSegment::Token(ParagraphSegmentToken::Whitespace) => json!({ "t": "Space" }),
+            ParagraphSegment::AttachedModifier {
+                modifier_type: '%', ..
+            } => return None,
+            ParagraphSegment::AttachedModifier {
+                modifier_type: '`',
+                content,
+            } => element("Code", json!([attributes("", &[]), plain_text(content)])),
+            ParagraphSegment::AttachedModifier {
+                modifier_type: '$',
+                content,
+            } => element("Math", json!([{ "t": "InlineMath" }, plain_text(content)])),
+            ParagraphSegment::AttachedModifier {
+                modifier_type,
+                content,
+            } => {
+                let content = self.inlines(content);
+
+                match modifier_type {
+                    '*' => element("Strong", content.into()),
+                    '/' => element("Emph", content.into()),
+                    '_' => element("Underline", content.into()),
+                    '-' => element("Strikeout", content.into()),
+                    '^' => element("Superscript", content.into()),
+                    ',' => element("Subscript", content.into()),
+                    '&' => element("Span", json!([attributes("", &["variable"]), content])),
+                    '!' => element("Span", json!([attributes("", &["spoiler"]), content])),
+                    _ => element("Span", json!([attributes("", &[]), content])),
+                }
+            }
+            ParagraphSegment::InlineVerbatim(content) => element(
+                "Code",
+                json!([
+                    attributes("", &[]),
+                    content.iter().map(ToString::to_string).collect::<String>()
+                ]),
+            ),
+            ParagraphSegment::Link {
+                filepath,
+                targets,
+                description,
+            } => {
+                let text = match (description, targets.first()) {
+                    (Some(description), _) => self.inlines(description),
+                    (
+                        None,
+                        Some(
+                            LinkTarget::Heading { title, .. }
+                            | LinkTarget::Footnote(title)
+                            | LinkTarget::Definition(title)
+                            | LinkTarget::Generic(title)
+                            | LinkTarget::Wiki(title)
+                            | LinkTarget::Extendable(title),
+                        ),
+                    ) => self.inlines(title),
+                    (None, _) => vec![element(
+                        "Str",
+                        link_href(filepath.as_deref(), targets, &self.extension).into(),
+                    )],
+                };
+
+                element(
+                    "Link",
+                    json!([
+                        attributes("", &[]),
+                        text,
+                        [link_href(filepath.as_deref(), targets, &self.extension), ""]
+                    ]),
+                )
+            }
+            ParagraphSegment::Anchor {
+                content,
+                description,
+            } => element(
+                "Span",
+                json!([
+                    attributes("", &[]),
+                    self.inlines(description.as_deref().unwrap_or(content))
+                ]),
+            ),
+            ParagraphSegment::AnchorDefinition { content, target } => match target.as_ref() {
+                ParagraphSegment::Link {
+                    filepath, targets, ..
+                } => element(
+                    "Link",
+                    json!([
+                        attributes("", &[]),
+                        self.inlines(content),
+                        [link_href(filepath.as_deref(), targets, &self.extension), ""]
+                    ]),
+                ),
+                _ => element("Span", json!([attributes("", &[]), self.inlines(content)])),
+            },
+            ParagraphSegment::InlineLinkTarget(content) => element(
+                "Span",
+                json!([attributes(&heading_id(content), &[]), self.inlines(content)]),
+            ),
+            segment => element("Str", String::from(segment.clone()).into()),
+        })
+    }
+
+    /// Exports a part of the document on its own, e.g. the content of a definition.
+    fn nested_blocks(&self, ast: &[NorgASTFlat]) -> Vec<Value> {
+        let mut nested = PandocJson::new(&self.extension);
+
+        for node in ast {
+            visit(&mut nested, node);
+        }
+
+        nested.flush();
+        nested.blocks
+    }
+
+    /// Turns the list items and definitions gathered so far into blocks.
+    fn flush(&mut self) {
+        if !self.items.is_empty() {
+            self.blocks.extend(nest(&std::mem::take(&mut self.items)));
+        }
+
+        if !self.definitions.is_empty() {
+            let definitions = std::mem::take(&mut self.definitions);
+
+            self.blocks
+                .push(element("DefinitionList", definitions.into()));
+        }
+    }
+
+    fn push(&mut self, block: Value) {
+        self.flush();
+        self.blocks.push(block);
+    }
+}
+
+impl Exporter for PandocJson {
+    fn paragraph(&mut self, content: &[ParagraphSegment]) {
+        let inlines = self.inlines(content);
+
+        self.push(element("Para", inlines.into()));
+    }
+
+    fn heading(
+        &mut self,
+        level: usize,
+        title: &[ParagraphSegment],
+        _extensions: &[DetachedModifierExtension],
+    ) {
+        let header = element(
+            "Header",
+            json!([
+                level,
+                attributes(&heading_id(title), &[]),
+                self.inlines(title)
+            ]),
+        );
+
+        self.push(header);
+    }
+
+    fn nestable(
+        &mut self,
+        kind: Nestable,
+        level: usize,
+        extensions: &[DetachedModifierExtension],
+        content: &NorgASTFlat,
+    ) {
+        let task = extensions.iter().find_map(|extension| match extension {
+            DetachedModifierExtension::Todo(TodoStatus::Done) => Some("☒"),
+            DetachedModifierExtension::Todo(_) => Some("☐"),
+            _ => None,
+        });
+        let blocks = match content {
+            NorgASTFlat::Paragraph(content) => {
+                let mut inlines = self.inlines(content);
+
+                if let Some(task) = task {
+                    inlines.splice(0..0, [element("Str", task.into()), json!({ "t": "Space" })]);
+                }
+
+                // Pandoc renders list items as tight lists, without paragraphs.
+                let tag = if kind == Nestable::Quote {
+                    "Para"
+                } else {
+                    "Plain"
+                };
+
+                vec![element(tag, inlines.into())]
+            }
+            node => self.nested_blocks(std::slice::from_ref(node)),
+        };
+
+        if !self.definitions.is_empty() {
+            self.flush();
+        }
+
+        self.items.push(Item {
+            kind,
+            level,
+            blocks,
+        });
+    }
+
+    fn rangeable(
+        &mut self,
+        kind: Rangeable,
+        title: &[ParagraphSegment],
+        _extensions: &[DetachedModifierExtension],
+        content: &[NorgASTFlat],
+    ) {
+        let id = heading_id(title);
+        let title = self.inlines(title);
+        let content = self.nested_blocks(content);
+
+        match kind {
+            Rangeable::Definition => {
+                if !self.items.is_empty() {
+                    self.flush();
+                }
+
+                self.definitions.push(json!([
+                    [element("Span", json!([attributes(&id, &[]), title]))],
+                    [content]
+                ]));
+            }
+            Rangeable::Footnote => self.push(element(
+                "Div",
+                json!([
+                    attributes(&id, &["footnote"]),
+                    [element("Para", title.into())]
+                        .into_iter()
+                        .chain(content)
+                        .collect::<Vec<_>>()
+                ]),
+            )),
+            Rangeable::Table => {
+                self.push(element("Para", title.into()));
+                self.blocks.extend(content);
+            }
+        }
+    }
+
+    fn verbatim_tag(&mut self, name: &[String], parameters: &[String], content: &str) {
+        let classes = match (name.join(".").as_str(), parameters.first()) {
+            ("document.meta", _) => {
+                self.title = Metadata::parse(content).title().map(str::to_string);
+                return;
+            }
+            ("code", Some(language)) => vec![language.as_str()],
+            _ => Vec::new(),
+        };
+
+        self.push(element(
+            "CodeBlock",
+            json!([attributes("", &classes), content.trim_end_matches('\n')]),
+        ));
+    }
+
+    fn ranged_tag(&mut self, name: &[String], _parameters: &[String], content: &[NorgASTFlat]) {
+        if name != ["comment"] {
+            let blocks = self.nested_blocks(content);
+
+            self.flush();
+            self.blocks.extend(blocks);
+        }
+    }
+
+    fn infirm_tag(&mut self, name: &[String], parameters: &[String]) {
+        if let Some(source) = parameters.first().filter(|_| name == ["image"]) {
+            self.push(element(
+                "Para",
+                json!([element(
+                    "Image",
+                    json!([attributes("", &[]), [], [source, ""]])
+                )]),
+            ));
+        }
+    }
+
+    fn finish(&mut self) -> String {
+        self.flush();
+
+        let mut meta = Map::new();
+
+        if let Some(title) = self.title.take() {
+            meta.insert("title".to_string(), element("MetaString", title.into()));
+        }
+
+        let document = json!({
+            "pandoc-api-version": API_VERSION,
+            "meta": meta,
+            "blocks": std::mem::take(&mut self.blocks),
+        });
+
+        format!("{document}\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_norg::parse;
+
+    use super::*;
+    use crate::exporter::export;
+
+    fn str(text: &str) -> Value {
+        element("Str", text.into())
+    }
+
+    #[test]
+    fn exports_blocks_nested_lists_and_the_title() {
+        let ast = parse(
+            "@document.meta\ntitle: Notes\n@end\n* Notes\n- ( ) one\n-- nested\n- two\n\
+             @code rust\nfn main() {}\n@end\n",
+        )
+        .unwrap();
+        let document =
+            serde_json::from_str::<Value>(&export(&mut PandocJson::default(), &ast)).unwrap();
+        let plain = |inlines| element("Plain", inlines);
+        let nested = element("BulletList", json!([[plain(json!([str("nested")]))]]));
+
+        assert_eq!(
+            document,
+            json!({
+                "pandoc-api-version": API_VERSION,
+                "meta": { "title": element("MetaString", "Notes".into()) },
+                "blocks": [
+                    element("Header", json!([1, attributes("notes", &[]), [str("Notes")]])),
+                    element("BulletList", json!([
+                        [plain(json!([str("☐"), { "t": "Space" }, str("one")])), nested],
+                        [plain(json!([str("two")]))],
+                    ])),
+                    element("CodeBlock", json!([attributes("", &["rust"]), "fn main() {}"])),
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn links_to_the_counterparts_of_documents() {
+        let ast = parse("See {:notes:* Some Heading} and {https://neorg.org}[Neorg].\n").unwrap();
+        let document =
+            serde_json::from_str::<Value>(&export(&mut PandocJson::new("docx"), &ast)).unwrap();
+        let para = &document["blocks"][0]["c"];
+
+        assert_eq!(
+            para[2],
+            element(
+                "Link",
+                json!([
+                    attributes("", &[]),
+                    [str("Some"), { "t": "Space" }, str("Heading")],
+                    ["notes.docx#some-heading", ""]
+                ])
+            )
+        );
+        assert_eq!(para[6]["c"][2], json!(["https://neorg.org", ""]));
+    }
+}
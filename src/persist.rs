@@ -0,0 +1,193 @@
+use std::{
+    fs::{File, Metadata, OpenOptions, TryLockError},
+    hash::Hasher as _,
+    io::{BufWriter, IntoInnerError, Read},
+    path::{Path, PathBuf},
+};
+
+use eyre::{bail, Result, WrapErr};
+use norg_fmt::stable_hash::StableHasher;
+
+/// Identifies the content a file had when it was read, so that it can be told whether someone else
+/// modified the file in the meantime without keeping the whole content around.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Generation {
+    length: usize,
+    hash: u64,
+}
+
+impl Generation {
+    pub fn of(content: &[u8]) -> Self {
+        let mut hasher = StableHasher::default();
+        hasher.write(content);
+
+        Self {
+            length: content.len(),
+            hash: hasher.finish(),
+        }
+    }
+}
+
+/// The temporary file the new content of `file` is written to before it replaces it, next to it so
+/// that it is on the same file system.
+fn temporary_path(file: &Path) -> PathBuf {
+    let name = file.file_name().unwrap_or_default().to_string_lossy();
+
+    file.with_file_name(format!(".{name}.norg-fmt-{}.tmp", std::process::id()))
+}
+
+/// Replaces the content of an existing file with whatever `write` emits, buffering it on the way.
+///
+/// The content is written to a temporary file in the same directory, which is then renamed over the
+/// file, so that the file is never left half-written if writing fails or the process is killed.
+/// Symbolic links are followed, replacing the file they point to, and the file's permissions (and
+/// on Unix its ownership, where allowed) carry over, but hard links to it are broken. If
+/// `preserve_mtime` is set the modification time is carried over as well.
+///
+/// The file is locked while it is checked and replaced, and is only replaced if its content is
+/// still that of `expected`. This keeps concurrent runs (e.g. an editor's save hook and a commit
+/// hook) from overwriting each other's output or changes made after the file was read.
+pub fn replace(
+    file: &Path,
+    expected: Generation,
+    preserve_mtime: bool,
+    write: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+) -> Result<()> {
+    let file = &std::fs::canonicalize(file)?;
+    let metadata = std::fs::metadata(file)?;
+
+    let mut handle = File::open(file)
+        .wrap_err_with(|| format!("unable to open {} for writing", file.display()))?;
+
+    match handle.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            bail!("the file is being written by another process, leaving it alone")
+        }
+        Err(TryLockError::Error(err)) => return Err(err.into()),
+    }
+
+    let mut current = Vec::new();
+    handle.read_to_end(&mut current)?;
+
+    if Generation::of(&current) != expected {
+        bail!("the file was modified while it was being formatted, leaving it alone");
+    }
+
+    drop(current);
+
+    let temporary = temporary_path(file);
+    let result = write_temporary(&temporary, &metadata, preserve_mtime, write)
+        .and_then(|()| std::fs::rename(&temporary, file));
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+
+    result.wrap_err_with(|| format!("unable to replace {}", file.display()))
+}
+
+/// Writes the new content of a file to the temporary file replacing it, giving it the permissions
+/// and ownership of the file.
+fn write_temporary(
+    temporary: &Path,
+    metadata: &Metadata,
+    preserve_mtime: bool,
+    write: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let handle = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temporary)?;
+
+    handle.set_permissions(metadata.permissions())?;
+
+    // Only the superuser may give a file away, everyone else keeps owning the files they write.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+
+        let _ = std::os::unix::fs::fchown(&handle, Some(metadata.uid()), Some(metadata.gid()));
+    }
+
+    let mut writer = BufWriter::new(handle);
+
+    write(&mut writer)?;
+
+    let handle = writer.into_inner().map_err(IntoInnerError::into_error)?;
+
+    if preserve_mtime {
+        handle.set_modified(metadata.modified()?)?;
+    }
+
+    handle.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write as _;
+
+    use super::*;
+
+    /// A file of its own in a directory of its own, so that no temporary files are left behind.
+    fn file(name: &str, content: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("norg-fmt-persist-{name}-{}", std::process::id()));
+
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("notes.norg"), content).unwrap();
+
+        directory.join("notes.norg")
+    }
+
+    fn entries(file: &Path) -> usize {
+        std::fs::read_dir(file.parent().unwrap()).unwrap().count()
+    }
+
+    #[test]
+    fn replaces_files_that_are_unchanged() -> Result<()> {
+        let file = file("unchanged", "*  Heading\n");
+
+        replace(&file, Generation::of(b"*  Heading\n"), false, |writer| {
+            writer.write_all(b"* Heading\n")
+        })?;
+
+        let content = std::fs::read_to_string(&file)?;
+        let entries = entries(&file);
+        std::fs::remove_dir_all(file.parent().unwrap())?;
+
+        assert_eq!(content, "* Heading\n");
+        assert_eq!(entries, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn leaves_modified_and_locked_files_alone() -> Result<()> {
+        let file = file("modified", "* Edited\n");
+        let modified = replace(&file, Generation::of(b"* Heading\n"), false, |writer| {
+            writer.write_all(b"* Formatted\n")
+        });
+        let lock = File::open(&file)?;
+        lock.lock()?;
+        let locked = replace(&file, Generation::of(b"* Edited\n"), false, |writer| {
+            writer.write_all(b"* Formatted\n")
+        });
+        drop(lock);
+        let failed = replace(&file, Generation::of(b"* Edited\n"), false, |writer| {
+            writer.write_all(b"* Half")?;
+            Err(std::io::Error::other("disk full"))
+        });
+        let content = std::fs::read_to_string(&file)?;
+        let entries = entries(&file);
+        std::fs::remove_dir_all(file.parent().unwrap())?;
+
+        assert!(modified.unwrap_err().to_string().contains("was modified"));
+        assert!(locked.unwrap_err().to_string().contains("another process"));
+        assert!(failed.is_err());
+        assert_eq!(content, "* Edited\n");
+        assert_eq!(entries, 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,170 @@
+//! Template placeholders, e.g. `{{name}}` or `<DATE>`, which are kept exactly as written.
+//!
+//! Placeholders are swapped for opaque tokens before a document is parsed, and swapped back once it
+//! is formatted. A token is a single word as wide as its placeholder, so a placeholder is never
+//! wrapped, respaced or escaped, while lines are still measured as they will be written.
+
+use crate::converter::text_width;
+
+/// Starts a token. Tokens are made of private use characters, which never occur in prose.
+const TOKEN_START: char = '\u{E010}';
+/// Pads a token to the width of its placeholder.
+const TOKEN_FILL: char = '\u{E011}';
+/// The first of the 16 characters the index of a placeholder is written in.
+const TOKEN_DIGITS: u32 = 0xE020;
+
+/// Finds the first placeholder matching `pattern` in `text`, returning its byte range. A `*` in the
+/// pattern stands for any text within a line, e.g. `{{*}}`; patterns without one match literally.
+fn find(text: &str, pattern: &str) -> Option<(usize, usize)> {
+    match pattern.split_once('*') {
+        None => text
+            .find(pattern)
+            .map(|start| (start, start + pattern.len())),
+        Some((open, close)) => {
+            let mut from = 0;
+
+            loop {
+                let start = from + text[from..].find(open)?;
+                let inner = start + open.len();
+                let line = &text[inner
+                    ..text[inner..]
+                        .find('\n')
+                        .map_or(text.len(), |end| inner + end)];
+
+                match line.find(close) {
+                    Some(end) => return Some((start, inner + end + close.len())),
+                    // Without an opening part, the placeholder can only start on the next line.
+                    None if open.is_empty() => from = inner + line.len() + 1,
+                    None => from = inner,
+                }
+
+                if from > text.len() {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+fn token(index: usize, width: usize) -> String {
+    let digits = format!("{index:x}")
+        .chars()
+        .filter_map(|digit| char::from_u32(TOKEN_DIGITS + digit.to_digit(16)?))
+        .collect::<String>();
+    let length = 1 + digits.chars().count();
+
+    format!(
+        "{TOKEN_START}{digits}{}",
+        TOKEN_FILL.to_string().repeat(width.saturating_sub(length))
+    )
+}
+
+/// A document with its placeholders swapped for tokens.
+pub struct Masked {
+    pub source: String,
+    placeholders: Vec<String>,
+}
+
+impl Masked {
+    /// Swaps every placeholder matching one of the `patterns` in `source` for a token.
+    pub fn new(source: &str, patterns: &[String]) -> Self {
+        let mut masked = Self {
+            source: source.to_string(),
+            placeholders: Vec::new(),
+        };
+
+        for pattern in patterns
+            .iter()
+            .filter(|pattern| !pattern.is_empty() && *pattern != "*")
+        {
+            let mut output = String::with_capacity(masked.source.len());
+            let mut rest = masked.source.as_str();
+
+            while let Some((start, end)) = find(rest, pattern) {
+                let placeholder = &rest[start..end];
+
+                output += &rest[..start];
+                output += &token(masked.placeholders.len(), text_width(placeholder));
+                masked.placeholders.push(placeholder.to_string());
+                rest = &rest[end..];
+            }
+
+            output += rest;
+            masked.source = output;
+        }
+
+        masked
+    }
+
+    /// Swaps the tokens in formatted text back for their placeholders.
+    pub fn unmask(&self, text: &str) -> String {
+        if self.placeholders.is_empty() {
+            return text.to_string();
+        }
+
+        let mut output = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if c != TOKEN_START {
+                output.push(c);
+                continue;
+            }
+
+            let mut index = 0;
+
+            while let Some(digit) = chars
+                .peek()
+                .and_then(|&c| (c as u32).checked_sub(TOKEN_DIGITS))
+                .filter(|&digit| digit < 16)
+            {
+                index = index * 16 + digit as usize;
+                chars.next();
+            }
+
+            while chars.next_if_eq(&TOKEN_FILL).is_some() {}
+
+            output += self.placeholders.get(index).map_or("", String::as_str);
+        }
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{formatter::Formatter, Config};
+
+    #[test]
+    fn finds_placeholders_within_a_line() {
+        assert_eq!(find("a {{x}} b", "{{*}}"), Some((2, 7)));
+        assert_eq!(find("a {{\n}} {{y}}", "{{*}}"), Some((8, 13)));
+        assert_eq!(find("a {{\n}}", "{{*}}"), None);
+        assert_eq!(find("a\nb}}", "*}}"), Some((2, 5)));
+        assert_eq!(find("on <DATE>", "<DATE>"), Some((3, 9)));
+    }
+
+    #[test]
+    fn masks_placeholders_with_tokens_of_the_same_width() {
+        let source = "Dear {{name}}, on <DATE> and <DATE>.";
+        let masked = Masked::new(source, &["{{*}}".to_string(), "<DATE>".to_string()]);
+
+        assert!(!masked.source.contains("{{") && !masked.source.contains("<DATE>"));
+        assert_eq!(masked.source.chars().count(), source.chars().count());
+        assert_eq!(masked.unmask(&masked.source), source);
+    }
+
+    #[test]
+    fn keeps_placeholders_as_written_while_formatting() {
+        let formatter = Formatter::new(Config {
+            template_placeholders: vec!["{{*}}".to_string()],
+            ..Config::default()
+        });
+
+        assert_eq!(
+            formatter.format("*  {{ title }}\n").unwrap().output(),
+            "* {{ title }}\n"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Reading files ahead of formatting them, on a bounded pool of threads, so that waiting for the
+//! disk overlaps with formatting when working through long lists of files.
+
+use std::{
+    collections::VecDeque,
+    ops::ControlFlow,
+    path::{Path, PathBuf},
+    sync::{mpsc, Mutex},
+};
+
+use eyre::{eyre, Result};
+
+use crate::guard::{self, SkipReason};
+
+/// The content of a file, or why it is skipped without being read.
+pub type Prefetched = Result<Result<Vec<u8>, SkipReason>>;
+
+/// Reads a file unless it is larger than `max_file_size`.
+fn read(file: &Path, max_file_size: u64) -> Prefetched {
+    if let Some(reason) = guard::check_size(file, max_file_size)? {
+        return Ok(Err(reason));
+    }
+
+    Ok(Ok(std::fs::read(file)?))
+}
+
+/// Reads the files on `jobs` threads and hands them to `consume` one at a time, in order, on the
+/// calling thread. No more than twice as many files as there are threads are read ahead of the one
+/// being consumed. Stops early if `consume` breaks.
+pub fn for_each(
+    files: &[PathBuf],
+    jobs: usize,
+    max_file_size: u64,
+    mut consume: impl FnMut(&Path, Prefetched) -> ControlFlow<()>,
+) {
+    let jobs = jobs.max(1);
+    let (queue, requests) = mpsc::channel::<(&Path, mpsc::SyncSender<Prefetched>)>();
+    let requests = Mutex::new(requests);
+
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| {
+                while let Ok((file, reply)) = requests
+                    .lock()
+                    .map_err(drop)
+                    .and_then(|requests| requests.recv().map_err(drop))
+                {
+                    // The consumer may have stopped early and no longer be waiting for the file.
+                    let _ = reply.send(read(file, max_file_size));
+                }
+            });
+        }
+
+        let mut pending = VecDeque::new();
+        let mut files = files.iter();
+
+        loop {
+            while pending.len() < 2 * jobs {
+                let Some(file) = files.next() else {
+                    break;
+                };
+                let (reply, content) = mpsc::sync_channel(1);
+
+                if queue.send((file.as_path(), reply)).is_err() {
+                    break;
+                }
+
+                pending.push_back((file.as_path(), content));
+            }
+
+            let Some((file, content)) = pending.pop_front() else {
+                break;
+            };
+            let content = content
+                .recv()
+                .unwrap_or_else(|_| Err(eyre!("unable to read {}", file.display())));
+
+            if consume(file, content).is_break() {
+                break;
+            }
+        }
+
+        // Lets the threads finish once the files they were asked for are read.
+        drop(queue);
+    });
+}
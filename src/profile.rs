@@ -0,0 +1,35 @@
+//! Measuring where formatting spends its time, by node kind and by transformation.
+
+use std::{collections::BTreeMap, time::Duration};
+
+/// The time spent in every measured part of the formatter, accumulated across a whole run.
+///
+/// Node kinds are measured inclusively, so the time of a list contains that of the paragraph inside
+/// of it.
+#[derive(Default)]
+pub struct Profile {
+    entries: BTreeMap<String, (Duration, usize)>,
+}
+
+impl Profile {
+    /// Adds a measurement to the entry with the given name.
+    pub fn record(&mut self, name: &str, duration: Duration) {
+        let (total, count) = self.entries.entry(name.to_string()).or_default();
+
+        *total += duration;
+        *count += 1;
+    }
+
+    /// The entries that took the most time in total, along with how often they were measured.
+    pub fn top(&self, limit: usize) -> Vec<(&str, Duration, usize)> {
+        let mut entries = self
+            .entries
+            .iter()
+            .map(|(name, &(total, count))| (name.as_str(), total, count))
+            .collect::<Vec<_>>();
+
+        entries.sort_by(|(a, a_total, _), (b, b_total, _)| b_total.cmp(a_total).then(a.cmp(b)));
+        entries.truncate(limit);
+        entries
+    }
+}
@@ -0,0 +1,45 @@
+use std::{io::IsTerminal as _, path::Path};
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// The amount of files above which a progress bar is shown.
+const PROGRESS_THRESHOLD: usize = 50;
+
+/// A progress bar for multi-file runs. It is only shown for runs large enough to warrant one and
+/// when both stdout and stderr are attached to a terminal.
+pub struct Progress(Option<ProgressBar>);
+
+impl Progress {
+    pub fn new(total: usize, enabled: bool) -> Self {
+        let is_interactive = std::io::stdout().is_terminal() && std::io::stderr().is_terminal();
+
+        if !enabled || !is_interactive || total <= PROGRESS_THRESHOLD {
+            return Self(None);
+        }
+
+        let bar = ProgressBar::new(total as u64).with_style(
+            ProgressStyle::with_template("{bar:40} {pos}/{len} {wide_msg}")
+                .unwrap_or_else(|_| ProgressStyle::default_bar()),
+        );
+
+        Self(Some(bar))
+    }
+
+    pub fn start(&self, file: &Path) {
+        if let Some(bar) = &self.0 {
+            bar.set_message(file.display().to_string());
+        }
+    }
+
+    pub fn advance(&self) {
+        if let Some(bar) = &self.0 {
+            bar.inc(1);
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Some(bar) = &self.0 {
+            bar.finish_and_clear();
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Hooks for checking the prose of a document, e.g. its spelling.
+//!
+//! Prose is the text of paragraphs and headings stripped of everything that isn't prose: markup
+//! characters, inline verbatim and math, comments, link targets and verbatim blocks. Inspectors are
+//! registered on the context with [`FormatCtx::with_inspector`](crate::context::FormatCtx) and are
+//! run by [`lint`](crate::lint::lint), which reports their findings as diagnostics.
+
+use std::ops::Range;
+
+use rust_norg::{NorgASTFlat, ParagraphSegment, ParagraphSegmentToken};
+
+/// A paragraph or heading title as plain text.
+pub struct Prose<'a> {
+    pub text: &'a str,
+    /// The lines of the source (zero-based, end exclusive) of the top-level block the prose is
+    /// part of, if the source is known.
+    pub lines: Option<Range<usize>>,
+}
+
+/// Something an inspector found in a piece of prose.
+pub struct Finding {
+    /// The byte range of the text the finding is about.
+    pub range: Range<usize>,
+    pub message: String,
+}
+
+/// Inspects the prose of a document, e.g. to check its spelling or style.
+pub trait ProseInspector {
+    fn inspect(&self, prose: &Prose) -> Vec<Finding>;
+}
+
+/// Renders the prose within paragraph segments, leaving out anything that isn't prose. Links and
+/// anchors are represented by their description, as that is what is read.
+pub fn plain_text(segments: &[ParagraphSegment]) -> String {
+    use ParagraphSegment::*;
+
+    let mut text = String::new();
+
+    for segment in segments {
+        match segment {
+            Token(ParagraphSegmentToken::Whitespace) => text.push(' '),
+            Token(token) => text.push_str(&token.to_string()),
+            // Verbatim, math, comments and variables.
+            AttachedModifier {
+                modifier_type: '`' | '$' | '%' | '&',
+                ..
+            }
+            | InlineVerbatim(_) => text.push(' '),
+            AttachedModifier { content, .. }
+            | AttachedModifierCandidate { content, .. }
+            | InlineLinkTarget(content)
+            | AnchorDefinition { content, .. }
+            | Anchor {
+                description: None,
+                content,
+            }
+            | Anchor {
+                description: Some(content),
+                ..
+            }
+            | Link {
+                description: Some(content),
+                ..
+            } => text.push_str(&plain_text(content)),
+            Link {
+                description: None, ..
+            } => text.push(' '),
+            AttachedModifierOpener(_)
+            | AttachedModifierOpenerFail(_)
+            | AttachedModifierCloserCandidate(_)
+            | AttachedModifierCloser(_) => {}
+        }
+    }
+
+    text
+}
+
+/// Calls `visit` with the segments of every paragraph and heading title within a node. Comments and
+/// verbatim content are left out.
+pub fn visit_prose(node: &NorgASTFlat, visit: &mut impl FnMut(&[ParagraphSegment])) {
+    match node {
+        NorgASTFlat::Paragraph(content) => visit(content),
+        NorgASTFlat::Heading { title, .. } => visit(title),
+        NorgASTFlat::NestableDetachedModifier { content, .. } => visit_prose(content, visit),
+        NorgASTFlat::RangeableDetachedModifier { title, content, .. } => {
+            visit(title);
+            content.iter().for_each(|node| visit_prose(node, visit));
+        }
+        NorgASTFlat::RangedTag { name, .. } if name == &["comment"] => {}
+        NorgASTFlat::RangedTag { content, .. } => {
+            content.iter().for_each(|node| visit_prose(node, visit))
+        }
+        NorgASTFlat::CarryoverTag { next_object, .. } => visit_prose(next_object, visit),
+        NorgASTFlat::VerbatimRangedTag { .. } | NorgASTFlat::InfirmTag { .. } => {}
+    }
+}
+
+/// Checks the spelling of prose with a Hunspell dictionary, reporting every word it doesn't know.
+#[cfg(feature = "spellcheck")]
+pub struct Spellchecker {
+    hunspell: hunspell_rs::Hunspell,
+}
+
+#[cfg(feature = "spellcheck")]
+impl Spellchecker {
+    /// Loads the dictionary at the given path without an extension, e.g.
+    /// `/usr/share/hunspell/en_US` for `en_US.aff` and `en_US.dic`.
+    pub fn new(dictionary: &std::path::Path) -> Self {
+        let path = |extension: &str| {
+            dictionary
+                .with_extension(extension)
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        Self {
+            hunspell: hunspell_rs::Hunspell::new(&path("aff"), &path("dic")),
+        }
+    }
+}
+
+#[cfg(feature = "spellcheck")]
+impl ProseInspector for Spellchecker {
+    fn inspect(&self, prose: &Prose) -> Vec<Finding> {
+        let mut findings = Vec::new();
+
+        for word in prose
+            .text
+            .split(|char: char| !char.is_alphanumeric() && char != '\'')
+        {
+            let word = word.trim_matches('\'');
+            // Every word is a slice of the text, so its offset within it is known.
+            let start = word.as_ptr() as usize - prose.text.as_ptr() as usize;
+            let range = start..start + word.len();
+
+            if word.is_empty() || word.chars().any(|char| char.is_numeric()) {
+                continue;
+            }
+
+            if self.hunspell.check(word) == hunspell_rs::CheckResult::FoundInDictionary {
+                continue;
+            }
+
+            let message = match self.hunspell.suggest(word).first() {
+                Some(suggestion) => {
+                    format!("`{word}` may be misspelled; did you mean `{suggestion}`?")
+                }
+                None => format!("`{word}` may be misspelled"),
+            };
+
+            findings.push(Finding { range, message });
+        }
+
+        findings
+    }
+}
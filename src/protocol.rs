@@ -0,0 +1,552 @@
+//! The JSON-RPC protocol spoken by `norg-fmt serve`, for integrations that format many documents
+//! without starting a process for each of them.
+//!
+//! Every line of input is a JSON-RPC 2.0 request and is answered by a single line holding the
+//! response. The methods are:
+//!
+//! - `version`: returns `{ "protocol": <PROTOCOL_VERSION>, "norgFmt": "<version>" }`. Clients
+//!   should call it first and refuse to continue on a protocol version they don't know.
+//! - `initialize`: takes `{ "options": { ... } }` with [`FormatOptions`] replacing the server's
+//!   configuration for every following request, and returns `{ "warnings": ["..."] }` describing
+//!   the options that were ignored, e.g. because they are unknown. Invalid options, such as a line
+//!   length that is too short, are rejected with [`INVALID_PARAMS`].
+//! - `formatText`: takes `{ "text": "...", "options"?: { ... } }` and returns
+//!   `{ "formatted": "...", "changed": <bool>, "diagnostics": ["..."] }`.
+//! - `formatFile`: takes `{ "path": "...", "options"?: { ... } }` and returns the same as
+//!   `formatText` for the content of the file, formatted with the configuration of the file until
+//!   the server is initialized. The file is not written. Links to documents that neither exist
+//!   nor are open as buffers are reported as diagnostics.
+//! - `openBuffer`: takes `{ "path": "...", "text": "..." }` and returns `null`. Until the buffer
+//!   is closed, `formatFile` reads the document from it instead of the file, and links to it
+//!   resolve even if the file doesn't exist yet. Editors should send it whenever a buffer changes.
+//! - `closeBuffer`: takes `{ "path": "..." }` and returns `null`, after which the document is read
+//!   from its file again.
+//! - `checkText`: takes the same parameters as `formatText` and returns
+//!   `{ "formatted": <bool>, "diagnostics": ["..."] }`, where `formatted` tells whether the text is
+//!   formatted already.
+//! - `shutdown`: returns `null`, after which the server stops.
+//!
+//! `options` override the server's configuration for a single request, with the same keys and
+//! values as a `+norgfmt` attribute, e.g. `{ "line_length": 100 }`. Errors use the codes defined by
+//! JSON-RPC, plus [`DOCUMENT_ERROR`] for documents that can't be read or parsed.
+
+use std::{
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use rust_norg::parse;
+
+use crate::{
+    formatter::Formatter,
+    grammar,
+    options::FormatOptions,
+    overrides,
+    refactor::{normalize_path, resolve_link_path, visit_links_mut},
+    vfs::{Overlay, SourceProvider},
+    wikilinks, Config,
+};
+
+/// The version of the protocol, increased whenever it changes incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// A document could not be read or parsed.
+pub const DOCUMENT_ERROR: i64 = 1;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Serialize)]
+struct Error {
+    code: i64,
+    message: String,
+}
+
+impl Error {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct TextParams {
+    text: String,
+    #[serde(default)]
+    options: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct InitializeParams {
+    #[serde(default)]
+    options: FormatOptions,
+}
+
+#[derive(Deserialize)]
+struct FileParams {
+    path: PathBuf,
+    #[serde(default)]
+    options: Map<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct BufferParams {
+    path: PathBuf,
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Serialize)]
+struct FormatResult {
+    formatted: String,
+    changed: bool,
+    diagnostics: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CheckResult {
+    formatted: bool,
+    diagnostics: Vec<String>,
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, Error> {
+    serde_json::from_value(params).map_err(|err| Error::new(INVALID_PARAMS, err.to_string()))
+}
+
+/// Resolves the configuration of a file, e.g. from the configuration files applying to it.
+pub type FileConfigs = Box<dyn Fn(&Path) -> Config>;
+
+/// Decodes the raw content of a file into text.
+pub type Decoder = Box<dyn Fn(&[u8]) -> Result<String, String>>;
+
+/// Buffers and files are looked up by absolute path, however clients refer to them.
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).map_or_else(|_| path.to_path_buf(), |path| normalize_path(&path))
+}
+
+/// Answers requests with a base configuration, reading files through a [`SourceProvider`] with the
+/// buffers the client opened on top.
+pub struct Server<P> {
+    config: Config,
+    file_configs: Option<FileConfigs>,
+    decode: Decoder,
+    sources: Overlay<P>,
+    is_shut_down: bool,
+}
+
+impl<P: SourceProvider> Server<P> {
+    pub fn new(config: Config, sources: P) -> Self {
+        Self {
+            config,
+            file_configs: None,
+            decode: Box::new(|content| {
+                String::from_utf8(content.to_vec()).map_err(|_| "file is not UTF-8".to_string())
+            }),
+            sources: Overlay::new(sources),
+            is_shut_down: false,
+        }
+    }
+
+    /// Formats files with the configuration `configs` resolves for them instead of the base
+    /// configuration, until the server is initialized with a configuration of its own.
+    pub fn with_file_configs(self, configs: impl Fn(&Path) -> Config + 'static) -> Self {
+        Self {
+            file_configs: Some(Box::new(configs)),
+            ..self
+        }
+    }
+
+    /// Decodes files with `decode`, e.g. to detect their encoding, instead of requiring UTF-8.
+    pub fn with_decoder(self, decode: impl Fn(&[u8]) -> Result<String, String> + 'static) -> Self {
+        Self {
+            decode: Box::new(decode),
+            ..self
+        }
+    }
+
+    /// The provider files are read through, e.g. to open buffers without a request.
+    pub fn sources_mut(&mut self) -> &mut Overlay<P> {
+        &mut self.sources
+    }
+
+    /// Describes the links in a document at `path` to documents that neither exist nor are open.
+    /// Links into other workspaces (`$name/`, `$/`) or the home directory are not checked.
+    fn missing_links(&self, path: &Path, text: &str, config: &Config) -> Vec<String> {
+        let Ok(mut ast) = parse(&wikilinks::expand(text, config.wikilink_style)) else {
+            return Vec::new();
+        };
+        let mut missing = Vec::new();
+
+        visit_links_mut(&mut ast, &mut |filepath, _| {
+            let Some(filepath) = filepath.as_deref() else {
+                return;
+            };
+
+            if filepath.starts_with(['$', '~']) {
+                return;
+            }
+
+            let target = normalize_path(&resolve_link_path(path, Path::new(""), filepath));
+
+            if !self.sources.exists(&target) {
+                missing.push(format!(
+                    "a link refers to the document `{filepath}`, which does not exist"
+                ));
+            }
+        });
+
+        missing
+    }
+
+    fn formatter(&self, config: &Config, options: &Map<String, Value>) -> Result<Formatter, Error> {
+        let parameters = options
+            .iter()
+            .map(|(key, value)| match value {
+                Value::String(value) => format!("{key}={value}"),
+                value => format!("{key}={value}"),
+            })
+            .collect::<Vec<_>>();
+
+        overrides::apply(config, &parameters)
+            .map(Formatter::new)
+            .map_err(|err| Error::new(INVALID_PARAMS, err))
+    }
+
+    fn format(
+        &self,
+        config: &Config,
+        text: &str,
+        options: &Map<String, Value>,
+    ) -> Result<FormatResult, Error> {
+        let formatted = self
+            .formatter(config, options)?
+            .format(text)
+            .map_err(|err| Error::new(DOCUMENT_ERROR, err.to_string()))?;
+
+        let output = formatted.output();
+
+        Ok(FormatResult {
+            changed: output != text,
+            formatted: output,
+            diagnostics: formatted
+                .diagnostics
+                .into_iter()
+                .map(|diagnostic| diagnostic.message)
+                .collect(),
+        })
+    }
+
+    fn dispatch(&mut self, method: &str, parameters: Value) -> Result<Value, Error> {
+        let result = match method {
+            "version" => json!({
+                "protocol": PROTOCOL_VERSION,
+                "norgFmt": env!("CARGO_PKG_VERSION"),
+                "grammar": {
+                    "version": grammar::VERSION,
+                    "revision": grammar::REVISION,
+                    "supported": grammar::is_supported(grammar::VERSION),
+                },
+            }),
+            "initialize" => {
+                let InitializeParams { options } = params(parameters)?;
+
+                if let Some(error) = options.errors().first() {
+                    return Err(Error::new(INVALID_PARAMS, error.to_string()));
+                }
+
+                self.config = options.config();
+                self.file_configs = None;
+
+                let warnings = options.warnings().iter().map(ToString::to_string);
+
+                json!({ "warnings": warnings.collect::<Vec<_>>() })
+            }
+            "formatText" => {
+                let TextParams { text, options } = params(parameters)?;
+
+                json!(self.format(&self.config, &text, &options)?)
+            }
+            "formatFile" => {
+                let FileParams { path, options } = params(parameters)?;
+                let path = absolute(&path);
+                let content = self
+                    .sources
+                    .read(&path)
+                    .map_err(|err| Error::new(DOCUMENT_ERROR, err.to_string()))?;
+                let text = (self.decode)(&content).map_err(|err| {
+                    Error::new(DOCUMENT_ERROR, format!("{}: {err}", path.display()))
+                })?;
+                let config = match &self.file_configs {
+                    Some(configs) => configs(&path),
+                    None => self.config.clone(),
+                };
+
+                let mut result = self.format(&config, &text, &options)?;
+
+                result
+                    .diagnostics
+                    .extend(self.missing_links(&path, &text, &config));
+
+                json!(result)
+            }
+            "openBuffer" => {
+                let BufferParams { path, text } = params(parameters)?;
+
+                self.sources.open(absolute(&path), text);
+
+                Value::Null
+            }
+            "closeBuffer" => {
+                let BufferParams { path, .. } = params(parameters)?;
+
+                self.sources.close(&absolute(&path));
+
+                Value::Null
+            }
+            "checkText" => {
+                let TextParams { text, options } = params(parameters)?;
+                let result = self.format(&self.config, &text, &options)?;
+
+                json!(CheckResult {
+                    formatted: !result.changed,
+                    diagnostics: result.diagnostics,
+                })
+            }
+            "shutdown" => {
+                self.is_shut_down = true;
+
+                Value::Null
+            }
+            method => {
+                return Err(Error::new(
+                    METHOD_NOT_FOUND,
+                    format!("unknown method `{method}`"),
+                ))
+            }
+        };
+
+        Ok(result)
+    }
+
+    /// Answers a single request, given as a line of JSON. Notifications (requests without an id)
+    /// are handled but not answered.
+    pub fn handle(&mut self, line: &str) -> Option<String> {
+        let (id, result) = match serde_json::from_str::<Request>(line) {
+            Err(err) => (Value::Null, Err(Error::new(PARSE_ERROR, err.to_string()))),
+            Ok(request) if request.jsonrpc != "2.0" => (
+                request.id,
+                Err(Error::new(
+                    INVALID_REQUEST,
+                    "only JSON-RPC 2.0 is supported",
+                )),
+            ),
+            Ok(request) => {
+                let result = self.dispatch(&request.method, request.params);
+
+                if request.id.is_null() {
+                    return None;
+                }
+
+                (request.id, result)
+            }
+        };
+
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+        };
+
+        Some(response.to_string())
+    }
+
+    /// Answers requests line by line until a `shutdown` request or the end of the input.
+    pub fn serve(mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(response) = self.handle(&line) {
+                writeln!(output, "{response}")?;
+                output.flush()?;
+            }
+
+            if self.is_shut_down {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file system without any documents, so that only buffers can be formatted.
+    struct NoFiles;
+
+    impl SourceProvider for NoFiles {
+        fn read(&self, _: &Path) -> io::Result<Vec<u8>> {
+            Err(io::ErrorKind::NotFound.into())
+        }
+
+        fn exists(&self, _: &Path) -> bool {
+            false
+        }
+    }
+
+    fn request(server: &mut Server<NoFiles>, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let response = server.handle(&request.to_string()).unwrap();
+
+        serde_json::from_str(&response).unwrap()
+    }
+
+    #[test]
+    fn formats_and_checks_text() {
+        let mut server = Server::new(Config::default(), NoFiles);
+
+        assert_eq!(
+            request(&mut server, "version", Value::Null)["result"]["protocol"],
+            PROTOCOL_VERSION
+        );
+        assert_eq!(
+            request(&mut server, "formatText", json!({ "text": "*  Heading\n" }))["result"],
+            json!({ "formatted": "* Heading\n", "changed": true, "diagnostics": [] })
+        );
+        assert_eq!(
+            request(&mut server, "checkText", json!({ "text": "* Heading\n" }))["result"],
+            json!({ "formatted": true, "diagnostics": [] })
+        );
+
+        let text = "A paragraph that is long enough to wrap at twenty columns.\n";
+        let narrow = json!({ "text": text, "options": { "line_length": 20 } });
+
+        assert_eq!(
+            request(&mut server, "formatText", narrow)["result"]["formatted"],
+            "A paragraph that is\nlong enough to wrap\nat twenty columns.\n"
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_json_rpc_codes() {
+        let mut server = Server::new(Config::default(), NoFiles);
+        let code = |response: Option<String>| {
+            serde_json::from_str::<Value>(&response.unwrap()).unwrap()["error"]["code"].clone()
+        };
+
+        assert_eq!(code(server.handle("{")), PARSE_ERROR);
+        assert_eq!(
+            code(server.handle(r#"{ "jsonrpc": "1.0", "id": 1, "method": "version" }"#)),
+            INVALID_REQUEST
+        );
+        assert_eq!(
+            request(&mut server, "format", Value::Null)["error"]["code"],
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(
+            request(
+                &mut server,
+                "initialize",
+                json!({ "options": { "line_length": 5 } })
+            )["error"]["code"],
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            request(&mut server, "formatFile", json!({ "path": "missing.norg" }))["error"]["code"],
+            DOCUMENT_ERROR
+        );
+        assert_eq!(
+            server.handle(r#"{ "jsonrpc": "2.0", "method": "version" }"#),
+            None
+        );
+    }
+
+    #[test]
+    fn formats_open_buffers_and_checks_their_links() {
+        let mut server = Server::new(Config::default(), NoFiles);
+        let text = "See {:other:} and {:missing:}.\n";
+
+        request(
+            &mut server,
+            "openBuffer",
+            json!({ "path": "notes/index.norg", "text": text }),
+        );
+        request(
+            &mut server,
+            "openBuffer",
+            json!({ "path": "notes/other.norg", "text": "" }),
+        );
+
+        let result = &request(
+            &mut server,
+            "formatFile",
+            json!({ "path": "notes/index.norg" }),
+        )["result"];
+
+        assert_eq!(result["formatted"], text);
+        assert_eq!(
+            result["diagnostics"],
+            json!(["a link refers to the document `missing`, which does not exist"])
+        );
+
+        request(
+            &mut server,
+            "closeBuffer",
+            json!({ "path": "notes/index.norg" }),
+        );
+
+        assert_eq!(
+            request(
+                &mut server,
+                "formatFile",
+                json!({ "path": "notes/index.norg" })
+            )["error"]["code"],
+            DOCUMENT_ERROR
+        );
+    }
+
+    #[test]
+    fn serves_until_shut_down() {
+        let input = [
+            r#"{ "jsonrpc": "2.0", "id": 1, "method": "version" }"#,
+            "",
+            r#"{ "jsonrpc": "2.0", "id": 2, "method": "shutdown" }"#,
+            r#"{ "jsonrpc": "2.0", "id": 3, "method": "version" }"#,
+        ]
+        .join("\n");
+        let mut output = Vec::new();
+
+        Server::new(Config::default(), NoFiles)
+            .serve(input.as_bytes(), &mut output)
+            .unwrap();
+
+        let ids = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["id"].clone())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, [1, 2]);
+    }
+}
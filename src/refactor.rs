@@ -0,0 +1,567 @@
+//! Structural changes to documents that go beyond formatting, such as renaming a heading along with
+//! every link to it.
+//!
+//! The changes are made to the AST, which is formatted again afterwards, so they can't break the
+//! syntax of a document the way editing its text with search and replace could.
+
+use std::{
+    collections::BTreeSet,
+    path::{Component, Path, PathBuf},
+};
+
+use rust_norg::{parse, LinkTarget, NorgASTFlat, ParagraphSegment};
+
+use crate::{
+    journal,
+    slug::{slug, slug_segments},
+    tags, tasks, typography, Config, LinkStyle,
+};
+
+/// Calls `visit` for every paragraph segment of a node, including those nested in other segments.
+fn visit_segments_mut(node: &mut NorgASTFlat, visit: &mut impl FnMut(&mut ParagraphSegment)) {
+    fn visit_all(segments: &mut [ParagraphSegment], visit: &mut impl FnMut(&mut ParagraphSegment)) {
+        for segment in segments {
+            visit(segment);
+
+            match segment {
+                ParagraphSegment::AttachedModifier { content, .. }
+                | ParagraphSegment::AttachedModifierCandidate { content, .. }
+                | ParagraphSegment::InlineLinkTarget(content) => visit_all(content, visit),
+                ParagraphSegment::Link {
+                    description: Some(description),
+                    ..
+                } => visit_all(description, visit),
+                ParagraphSegment::AnchorDefinition { content, target } => {
+                    visit_all(content, visit);
+                    visit_all(std::slice::from_mut(target.as_mut()), visit);
+                }
+                ParagraphSegment::Anchor {
+                    content,
+                    description,
+                } => {
+                    visit_all(content, visit);
+                    visit_all(description.as_deref_mut().unwrap_or_default(), visit);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    match node {
+        NorgASTFlat::Paragraph(content) => visit_all(content, visit),
+        NorgASTFlat::Heading { title, .. } => visit_all(title, visit),
+        NorgASTFlat::NestableDetachedModifier { content, .. } => visit_segments_mut(content, visit),
+        NorgASTFlat::RangeableDetachedModifier { title, content, .. } => {
+            visit_all(title, visit);
+            content
+                .iter_mut()
+                .for_each(|node| visit_segments_mut(node, visit));
+        }
+        NorgASTFlat::RangedTag { content, .. } => content
+            .iter_mut()
+            .for_each(|node| visit_segments_mut(node, visit)),
+        NorgASTFlat::CarryoverTag { next_object, .. } => visit_segments_mut(next_object, visit),
+        NorgASTFlat::VerbatimRangedTag { .. } | NorgASTFlat::InfirmTag { .. } => {}
+    }
+}
+
+/// Calls `visit` for every link in a document, with the file it refers to (if any) and its
+/// targets.
+pub fn visit_links_mut(
+    ast: &mut [NorgASTFlat],
+    visit: &mut impl FnMut(&mut Option<String>, &mut Vec<LinkTarget>),
+) {
+    for node in ast {
+        visit_segments_mut(node, &mut |segment| {
+            if let ParagraphSegment::Link {
+                filepath, targets, ..
+            } = segment
+            {
+                visit(filepath, targets);
+            }
+        });
+    }
+}
+
+/// Parses text into the segments of a title, e.g. the new title of a heading. Returns `None` if
+/// the text is not a single line of inline content.
+pub fn parse_title(text: &str) -> Option<Vec<ParagraphSegment>> {
+    if text.contains('\n') {
+        return None;
+    }
+
+    match parse(text).ok()?.as_slice() {
+        [NorgASTFlat::Paragraph(segments)] => Some(segments.clone()),
+        _ => None,
+    }
+}
+
+/// Resolves the file a link's `{:path:}` refers to, given the file the link is in and the root of
+/// the workspace, which paths starting with `$/` are relative to. Other paths are relative to the
+/// directory of the file the link is in. The `.norg` extension is implied.
+pub fn resolve_link_path(from: &Path, root: &Path, path: &str) -> PathBuf {
+    let path = path.strip_suffix(".norg").unwrap_or(path);
+    let mut resolved = match path.strip_prefix("$/") {
+        Some(path) => root.join(path),
+        None => from.parent().unwrap_or(Path::new("")).join(path),
+    }
+    .into_os_string();
+
+    resolved.push(".norg");
+
+    PathBuf::from(resolved)
+}
+
+/// Renames every heading titled `old` (compared by [`slug`]) to `new`, returning the levels of the
+/// headings that were renamed.
+pub fn rename_headings(
+    ast: &mut [NorgASTFlat],
+    old: &str,
+    new: &[ParagraphSegment],
+) -> BTreeSet<u16> {
+    fn rename(node: &mut NorgASTFlat, old: &str, new: &[ParagraphSegment]) -> Option<u16> {
+        match node {
+            NorgASTFlat::Heading { level, title, .. } if slug_segments(title) == old => {
+                *title = new.to_vec();
+                Some(*level)
+            }
+            NorgASTFlat::CarryoverTag { next_object, .. } => rename(next_object, old, new),
+            _ => None,
+        }
+    }
+
+    let old = slug(old);
+
+    ast.iter_mut()
+        .filter_map(|node| rename(node, &old, new))
+        .collect()
+}
+
+/// Points every link to a heading of one of the given levels titled `old` to `new` instead,
+/// provided the link refers to the file the heading is in according to `is_heading_file`, which is
+/// given the `{:path:}` of the link. Returns the number of links that were changed.
+pub fn rename_heading_links(
+    ast: &mut [NorgASTFlat],
+    old: &str,
+    levels: &BTreeSet<u16>,
+    new: &[ParagraphSegment],
+    is_heading_file: impl Fn(Option<&str>) -> bool,
+) -> usize {
+    let old = slug(old);
+    let mut changed = 0;
+
+    visit_links_mut(ast, &mut |filepath, targets| {
+        if !is_heading_file(filepath.as_deref()) {
+            return;
+        }
+
+        for target in targets {
+            if let LinkTarget::Heading { level, title } = target {
+                if levels.contains(level) && slug_segments(title) == old {
+                    *title = new.to_vec();
+                    changed += 1;
+                }
+            }
+        }
+    });
+
+    changed
+}
+
+/// Removes `.` and `..` components from a path without consulting the file system, as the file a
+/// link refers to may not exist (anymore).
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// Renders `to` relative to the directory `from`, with forward slashes as used in links.
+pub fn relative_path(from: &Path, to: &Path) -> String {
+    let from = from.components().collect::<Vec<_>>();
+    let to = to.components().collect::<Vec<_>>();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    std::iter::repeat_n("..".into(), from.len() - common)
+        .chain(
+            to[common..]
+                .iter()
+                .map(|component| component.as_os_str().to_string_lossy()),
+        )
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// A file that was moved within a workspace. Every path is absolute and normalized with
+/// [`normalize_path`].
+pub struct Move<'a> {
+    pub root: &'a Path,
+    pub from: &'a Path,
+    pub to: &'a Path,
+}
+
+impl Move<'_> {
+    /// The path a link in `file` should have, given its current path. Links to the moved file are
+    /// pointed to its new location, and relative links in the moved file itself are adjusted to
+    /// still refer to the same files. Links into other workspaces (`$name/`) or the home directory
+    /// are left alone.
+    fn update(&self, file: &Path, path: &str, is_norg: bool) -> Option<String> {
+        if path.starts_with('~') || (path.starts_with('$') && !path.starts_with("$/")) {
+            return None;
+        }
+
+        let extension = if is_norg && !path.ends_with(".norg") {
+            ".norg"
+        } else {
+            ""
+        };
+        let was_at = if file == self.to { self.from } else { file };
+        let mut resolved = match path.strip_prefix("$/") {
+            Some(path) => self.root.join(path),
+            None => was_at.parent().unwrap_or(Path::new("")).join(path),
+        }
+        .into_os_string();
+
+        resolved.push(extension);
+
+        let resolved = normalize_path(Path::new(&resolved));
+
+        let destination = if resolved == self.from {
+            self.to
+        } else if file == self.to && !path.starts_with("$/") && !path.starts_with('/') {
+            &resolved
+        } else {
+            return None;
+        };
+
+        let updated = if path.starts_with('/') {
+            destination.to_string_lossy().into_owned()
+        } else if path.starts_with("$/") {
+            format!("$/{}", relative_path(self.root, destination))
+        } else {
+            relative_path(file.parent().unwrap_or(Path::new("")), destination)
+        };
+        let updated = updated
+            .strip_suffix(extension)
+            .unwrap_or(&updated)
+            .to_string();
+
+        (updated != path).then_some(updated)
+    }
+}
+
+/// Updates the `{:path:}` and `{/ path}` links of the document at `file` after a file was moved,
+/// see [`Move`]. Returns the number of links that were changed.
+pub fn fix_moved_links(ast: &mut [NorgASTFlat], file: &Path, moved: &Move) -> usize {
+    let mut changed = 0;
+
+    visit_links_mut(ast, &mut |filepath, targets| {
+        if let Some(path) = filepath {
+            if let Some(updated) = moved.update(file, path, true) {
+                *path = updated;
+                changed += 1;
+            }
+        }
+
+        for target in targets {
+            if let LinkTarget::Path(path) = target {
+                if let Some(updated) = moved.update(file, path, false) {
+                    *path = updated;
+                    changed += 1;
+                }
+            }
+        }
+    });
+
+    changed
+}
+
+/// Converts inline links that are repeated within a document into anchors: the first link to a
+/// target becomes an anchor definition named after its description (`[Neorg]{https://neorg.org}`),
+/// and every later link to the same target an anchor referring to it (`[Neorg]`). Links without a
+/// description, and targets whose anchor name would be taken already, are left inline. Returns the
+/// number of links that were converted.
+pub fn anchor_repeated_links(ast: &mut [NorgASTFlat]) -> usize {
+    type Target = (Option<String>, Vec<LinkTarget>);
+
+    let mut names = BTreeSet::new();
+    let mut targets = Vec::<(Target, usize)>::new();
+
+    for node in ast.iter_mut() {
+        visit_segments_mut(node, &mut |segment| match segment {
+            ParagraphSegment::Link {
+                filepath,
+                targets: link_targets,
+                description: Some(_),
+            } => {
+                let target = (filepath.clone(), link_targets.clone());
+
+                match targets.iter_mut().find(|(known, _)| *known == target) {
+                    Some((_, count)) => *count += 1,
+                    None => targets.push((target, 1)),
+                }
+            }
+            ParagraphSegment::AnchorDefinition { content, .. } => {
+                names.insert(slug_segments(content));
+            }
+            _ => {}
+        });
+    }
+
+    // The name of the anchor each repeated target is converted to, once its definition was placed.
+    let mut anchors = Vec::<(Target, Option<Vec<ParagraphSegment>>)>::new();
+    let mut converted = 0;
+
+    for node in ast.iter_mut() {
+        visit_segments_mut(node, &mut |segment| {
+            let ParagraphSegment::Link {
+                filepath,
+                targets: link_targets,
+                description: Some(description),
+            } = segment
+            else {
+                return;
+            };
+
+            let target = (filepath.clone(), link_targets.clone());
+
+            if !targets
+                .iter()
+                .any(|(known, count)| *known == target && *count > 1)
+            {
+                return;
+            }
+
+            let replacement = match anchors.iter().find(|(known, _)| *known == target) {
+                Some((_, None)) => return,
+                Some((_, Some(name))) => ParagraphSegment::Anchor {
+                    content: name.clone(),
+                    description: (slug_segments(description) != slug_segments(name))
+                        .then(|| description.clone()),
+                },
+                None if !names.insert(slug_segments(description)) => {
+                    anchors.push((target, None));
+                    return;
+                }
+                None => {
+                    anchors.push((target, Some(description.clone())));
+
+                    ParagraphSegment::AnchorDefinition {
+                        content: description.clone(),
+                        target: Box::new(ParagraphSegment::Link {
+                            filepath: filepath.clone(),
+                            targets: link_targets.clone(),
+                            description: None,
+                        }),
+                    }
+                }
+            };
+
+            *segment = replacement;
+            converted += 1;
+        });
+    }
+
+    converted
+}
+
+/// Converts anchors back into inline links: anchor definitions (`[Neorg]{https://neorg.org}`)
+/// become links described by their name (`{https://neorg.org}[Neorg]`), as do the anchors referring
+/// to them (`[Neorg]`). Anchors without a definition in the document are left alone. Returns the
+/// number of anchors that were converted.
+pub fn inline_anchors(ast: &mut [NorgASTFlat]) -> usize {
+    let mut definitions = Vec::<(String, ParagraphSegment)>::new();
+
+    for node in ast.iter_mut() {
+        visit_segments_mut(node, &mut |segment| {
+            if let ParagraphSegment::AnchorDefinition { content, target } = segment {
+                definitions.push((slug_segments(content), target.as_ref().clone()));
+            }
+        });
+    }
+
+    let mut converted = 0;
+
+    for node in ast.iter_mut() {
+        visit_segments_mut(node, &mut |segment| {
+            let (name, description) = match &*segment {
+                ParagraphSegment::AnchorDefinition { content, .. } => (content, None),
+                ParagraphSegment::Anchor {
+                    content,
+                    description,
+                } => (content, description.as_ref()),
+                _ => return,
+            };
+
+            let Some((
+                _,
+                ParagraphSegment::Link {
+                    filepath, targets, ..
+                },
+            )) = definitions
+                .iter()
+                .find(|(known, _)| *known == slug_segments(name))
+            else {
+                return;
+            };
+
+            let link = ParagraphSegment::Link {
+                filepath: filepath.clone(),
+                targets: targets.clone(),
+                description: Some(description.unwrap_or(name).clone()),
+            };
+
+            *segment = link;
+            converted += 1;
+        });
+    }
+
+    converted
+}
+
+/// Applies the structural changes the configuration asks for to a document before it is formatted,
+/// such as converting between inline links and anchors according to
+/// [`link_style`](Config::link_style) or the opt-in normalizations of [`typography`].
+pub fn restyle(ast: &mut [NorgASTFlat], config: &Config) {
+    if config.punctuation_spacing {
+        typography::space_punctuation(ast, config.locale);
+    }
+
+    if config.non_breaking_spaces {
+        typography::insert_non_breaking_spaces(ast, config.locale);
+    }
+
+    if config.typographic_substitution {
+        typography::substitute_typography(ast);
+    }
+
+    tasks::normalize_extensions(ast);
+    tags::normalize_verbatim_blank_lines(ast, config);
+    tags::normalize_languages(ast, config);
+
+    if let Some(format) = &config.journal_date_format {
+        journal::normalize_dates(ast, format);
+    }
+
+    if config.sort_journal_entries {
+        journal::sort_entries(ast);
+    }
+
+    if config.task_roll_up {
+        tasks::roll_up(ast, &config.heading_annotation_markers);
+    }
+
+    match config.link_style {
+        LinkStyle::Preserve => {}
+        LinkStyle::Anchors => {
+            anchor_repeated_links(ast);
+        }
+        LinkStyle::Inline => {
+            inline_anchors(ast);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renames_headings_and_the_links_to_them() {
+        let mut ast =
+            parse("* Old Title\n** Old Title\nSee {* Old Title}, {** old title} and {* Other}.\n")
+                .unwrap();
+        let new = parse_title("New Title").unwrap();
+        let levels = rename_headings(&mut ast, "old title", &new);
+        let links = rename_heading_links(&mut ast, "Old Title", &levels, &new, |filepath| {
+            filepath.is_none()
+        });
+
+        assert_eq!(levels, BTreeSet::from([1, 2]));
+        assert_eq!(links, 2);
+        assert_eq!(
+            ast,
+            parse("* New Title\n** New Title\nSee {* New Title}, {** New Title} and {* Other}.\n")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn renames_links_only_to_the_file_and_levels_of_the_heading() {
+        let mut ast = parse("See {:a:* Title}, {:b:* Title} and {:a:** Title}.\n").unwrap();
+        let new = parse_title("Renamed").unwrap();
+        let links = rename_heading_links(&mut ast, "title", &BTreeSet::from([1]), &new, |path| {
+            path == Some("a")
+        });
+
+        assert_eq!(links, 1);
+        assert_eq!(
+            ast,
+            parse("See {:a:* Renamed}, {:b:* Title} and {:a:** Title}.\n").unwrap()
+        );
+        assert!(parse_title("two\nlines").is_none());
+    }
+
+    #[test]
+    fn points_links_to_a_moved_file() {
+        let moved = Move {
+            root: Path::new("/w"),
+            from: Path::new("/w/notes/a.norg"),
+            to: Path::new("/w/archive/a.norg"),
+        };
+        let mut index =
+            parse("See {:notes/a:}, {:$/notes/a:* Heading}, {/ notes/a.norg} and {:other:}.\n")
+                .unwrap();
+        let mut a = parse("See {:b:}, {:$/b:} and {:$notes/b:}.\n").unwrap();
+
+        assert_eq!(
+            fix_moved_links(&mut index, Path::new("/w/index.norg"), &moved),
+            3
+        );
+        assert_eq!(
+            index,
+            parse(
+                "See {:archive/a:}, {:$/archive/a:* Heading}, {/ archive/a.norg} and {:other:}.\n"
+            )
+            .unwrap()
+        );
+        assert_eq!(fix_moved_links(&mut a, moved.to, &moved), 1);
+        assert_eq!(
+            a,
+            parse("See {:../notes/b:}, {:$/b:} and {:$notes/b:}.\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn converts_between_repeated_links_and_anchors() {
+        let links = "Use {https://neorg.org}[Neorg], {https://neorg.org}[Neorg] and \
+                     {https://neorg.org}[the site], but {https://norg.org}[once].\n";
+        let anchors = "Use [Neorg]{https://neorg.org}, [Neorg] and [Neorg][the site], but \
+                       {https://norg.org}[once].\n";
+        let mut ast = parse(links).unwrap();
+
+        assert_eq!(anchor_repeated_links(&mut ast), 3);
+        assert_eq!(ast, parse(anchors).unwrap());
+        assert_eq!(inline_anchors(&mut ast), 3);
+        assert_eq!(ast, parse(links).unwrap());
+    }
+
+    #[test]
+    fn leaves_anchors_without_a_definition_alone() {
+        let source = "See [Neorg] and {https://neorg.org}[Neorg].\n";
+        let mut ast = parse(source).unwrap();
+
+        assert_eq!(inline_anchors(&mut ast), 0);
+        assert_eq!(ast, parse(source).unwrap());
+    }
+}
@@ -0,0 +1,56 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use rust_norg::NorgASTFlat;
+
+use crate::{
+    context::{FormatCtx, NodeKind},
+    converter::format_node,
+};
+
+/// A function formatting a single node into Norg source, including its trailing newline. It can be
+/// shared across threads along with the [`Formatter`](crate::formatter::Formatter) using it.
+pub type NodeFormatter = Arc<dyn Fn(NorgASTFlat, &FormatCtx) -> String + Send + Sync>;
+
+/// Maps node kinds to the functions formatting them.
+///
+/// Every kind is formatted by the built-in [`format_node`] unless a formatter has been registered
+/// for it, which allows syntax extensions to be formatted without changes to this crate. A
+/// registered formatter can still delegate to [`format_node`] for the nodes it does not care
+/// about.
+#[derive(Clone, Default)]
+pub struct Registry {
+    formatters: BTreeMap<NodeKind, NodeFormatter>,
+}
+
+impl Registry {
+    /// Registers a formatter for a kind of node, replacing the one registered before, if any.
+    pub fn register(
+        &mut self,
+        kind: NodeKind,
+        formatter: impl Fn(NorgASTFlat, &FormatCtx) -> String + Send + Sync + 'static,
+    ) -> &mut Self {
+        self.formatters.insert(kind, Arc::new(formatter));
+        self
+    }
+
+    /// Whether the built-in formatter for a kind of node has been replaced.
+    pub fn overrides(&self, kind: NodeKind) -> bool {
+        self.formatters.contains_key(&kind)
+    }
+
+    /// Formats a node with the formatter registered for its kind.
+    pub fn format(&self, node: NorgASTFlat, ctx: &FormatCtx) -> String {
+        let kind = NodeKind::of(&node);
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("node", %kind, depth = ctx.depth).entered();
+
+        ctx.profiled(
+            || format!("node: {kind}"),
+            || match self.formatters.get(&kind) {
+                Some(formatter) => formatter(node, ctx),
+                None => format_node(node, ctx),
+            },
+        )
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use eyre::{Report, Result, WrapErr};
+use norg_fmt::verify::{Mismatch, VerifyError};
+use serde_json::json;
+
+use crate::{guard::SkipReason, term::Style};
+
+/// What happened to a single file during a run.
+pub enum Outcome {
+    /// The file was reformatted (or, in check mode, would have been).
+    Formatted,
+    Unchanged,
+    /// The file was not formatted because it is too large or not a text file.
+    Skipped(SkipReason),
+    Errored(Report),
+}
+
+/// Collects the outcome of every file in a multi-file run.
+#[derive(Default)]
+pub struct Summary {
+    files: Vec<(PathBuf, Outcome)>,
+}
+
+impl Summary {
+    pub fn record(&mut self, file: PathBuf, outcome: Outcome) {
+        self.files.push((file, outcome));
+    }
+
+    /// The number of files recorded so far.
+    pub fn recorded(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn formatted(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Formatted))
+    }
+
+    pub fn unchanged(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Unchanged))
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Skipped(_)))
+    }
+
+    pub fn errored(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Errored(_)))
+    }
+
+    /// The errors of every file that could not be formatted.
+    pub fn errors(&self) -> impl Iterator<Item = &Report> {
+        self.files.iter().filter_map(|(_, outcome)| match outcome {
+            Outcome::Errored(err) => Some(err),
+            _ => None,
+        })
+    }
+
+    /// Every file that failed verification, along with why.
+    pub fn verification_failures(&self) -> impl Iterator<Item = (&Path, &VerifyError)> {
+        self.files
+            .iter()
+            .filter_map(|(file, outcome)| match outcome {
+                Outcome::Errored(err) => Some((file.as_path(), err.downcast_ref()?)),
+                _ => None,
+            })
+    }
+
+    /// Writes a report of every file that failed verification, with the path and an excerpt of
+    /// each node that changed, as HTML if the report file ends in `.html` and as JSON otherwise.
+    pub fn write_verify_report(&self, path: &Path) -> Result<()> {
+        let report = if path
+            .extension()
+            .is_some_and(|extension| extension == "html")
+        {
+            self.verify_report_html()
+        } else {
+            self.verify_report_json()
+        };
+
+        std::fs::write(path, report)
+            .wrap_err_with(|| format!("unable to write the report to {}", path.display()))
+    }
+
+    fn verify_report_json(&self) -> String {
+        let files = self
+            .verification_failures()
+            .map(|(file, err)| {
+                let mismatches: &[Mismatch] = match err {
+                    VerifyError::Unparsable(_) => &[],
+                    VerifyError::Changed { mismatches, .. } => mismatches,
+                };
+
+                json!({
+                    "file": file,
+                    "error": err.to_string(),
+                    "mismatches": mismatches,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let report = json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "checked": self.recorded(),
+            "failed": files.len(),
+            "files": files,
+        });
+
+        serde_json::to_string_pretty(&report).unwrap_or_default() + "\n"
+    }
+
+    fn verify_report_html(&self) -> String {
+        fn escape(text: &str) -> String {
+            text.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        }
+
+        let mut failed = 0;
+        let mut rows = String::new();
+
+        for (file, err) in self.verification_failures() {
+            let file = escape(&file.display().to_string());
+
+            failed += 1;
+
+            match err {
+                VerifyError::Unparsable(_) => rows.push_str(&format!(
+                    "<tr><td>{file}</td><td colspan=\"3\">{}</td></tr>\n",
+                    escape(&err.to_string())
+                )),
+                VerifyError::Changed { mismatches, .. } => {
+                    for mismatch in mismatches {
+                        let excerpt = |excerpt: &Option<String>| {
+                            excerpt.as_deref().map_or("(missing)".to_string(), escape)
+                        };
+
+                        rows.push_str(&format!(
+                            "<tr><td>{file}</td><td>{}</td><td><code>{}</code></td>\
+                             <td><code>{}</code></td></tr>\n",
+                            escape(&mismatch.path),
+                            excerpt(&mismatch.original),
+                            excerpt(&mismatch.formatted)
+                        ));
+                    }
+                }
+            }
+        }
+
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>norg-fmt verification report</title>\n</head>\n<body>\n\
+             <h1>norg-fmt {} verification report</h1>\n\
+             <p>{failed} of {} file(s) failed verification.</p>\n\
+             <table>\n<tr><th>File</th><th>Node</th><th>Original</th><th>Formatted</th></tr>\n\
+             {rows}</table>\n</body>\n</html>\n",
+            env!("CARGO_PKG_VERSION"),
+            self.recorded()
+        )
+    }
+
+    fn count(&self, predicate: impl Fn(&Outcome) -> bool) -> usize {
+        self.files
+            .iter()
+            .filter(|(_, outcome)| predicate(outcome))
+            .count()
+    }
+
+    /// Prints the summary to stderr. Errors and skipped files are always detailed, and so are the
+    /// files that would be reformatted in check mode. Every other file is only listed when
+    /// `verbose` is set.
+    pub fn print(&self, elapsed: Duration, check: bool, verbose: bool, style: Style) {
+        let formatted_label = if check {
+            "would be formatted"
+        } else {
+            "formatted"
+        };
+
+        for (file, outcome) in &self.files {
+            match outcome {
+                Outcome::Formatted if verbose || check => {
+                    eprintln!("{}: {}", style.yellow(formatted_label), file.display())
+                }
+                Outcome::Unchanged if verbose => eprintln!("unchanged: {}", file.display()),
+                Outcome::Skipped(reason) => {
+                    eprintln!("{}: {}: {reason}", style.yellow("skipped"), file.display())
+                }
+                Outcome::Errored(err) => {
+                    eprintln!("{}: {}: {err}", style.red("error"), file.display());
+
+                    if let Some(table) = err.downcast_ref().and_then(VerifyError::table) {
+                        eprint!("{table}");
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        eprintln!(
+            "{}",
+            style.bold(&format!(
+                "{} {formatted_label}, {} unchanged, {} skipped, {} errored in {:.2}s",
+                self.formatted(),
+                self.unchanged(),
+                self.skipped(),
+                self.errored(),
+                elapsed.as_secs_f64()
+            ))
+        );
+    }
+}
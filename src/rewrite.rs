@@ -0,0 +1,291 @@
+//! Rewriting the documents of a workspace after a structural change, e.g. renaming a heading.
+
+use std::path::{Path, PathBuf};
+
+use eyre::{bail, eyre, Result, WrapErr};
+use norg_fmt::{
+    context::FormatCtx,
+    converter::format_document,
+    refactor::{
+        fix_moved_links, normalize_path, parse_title, rename_heading_links, rename_headings,
+        resolve_link_path, Move,
+    },
+    verify, wikilinks, Config,
+};
+use rust_norg::{parse, NorgASTFlat};
+
+use crate::{
+    encoding::{self, Encoding},
+    persist::{self, Generation},
+    status::ParseError,
+    workspace::{self, WalkOptions},
+};
+
+/// A file changed by [`rewrite_workspace`].
+pub struct Rewritten {
+    pub file: PathBuf,
+    /// The number of changes made to the file, e.g. links that were updated.
+    pub changes: usize,
+}
+
+struct Pending {
+    file: PathBuf,
+    original: Generation,
+    formatted: String,
+    encoding: Encoding,
+    changes: usize,
+}
+
+/// Lets `transform` change the AST of every Norg file below `root`, returning the number of changes
+/// it made, and writes the files it changed back formatted. Files that can't be read or parsed are
+/// skipped with a warning, as they can't be changed safely.
+///
+/// Every changed file is formatted and verified before any of them is written, so that a failure
+/// leaves the whole workspace untouched rather than half of it changed. With `dry_run` nothing is
+/// written at all.
+pub fn rewrite_workspace(
+    root: &Path,
+    config: &Config,
+    dry_run: bool,
+    mut transform: impl FnMut(&Path, &mut Vec<NorgASTFlat>) -> usize,
+) -> Result<Vec<Rewritten>> {
+    let mut pending = Vec::new();
+
+    for file in workspace::collect_files(&[root.to_path_buf()], &WalkOptions::default())? {
+        let bytes =
+            std::fs::read(&file).wrap_err_with(|| format!("unable to read {}", file.display()))?;
+        let Ok((content, encoding)) = encoding::decode(&bytes, false) else {
+            eprintln!("warning: {}: unable to decode, skipping it", file.display());
+            continue;
+        };
+        let content = wikilinks::expand(&content, config.wikilink_style);
+        let Ok(mut ast) = parse(&content) else {
+            eprintln!("warning: {}: unable to parse, skipping it", file.display());
+            continue;
+        };
+
+        let changes = transform(&file, &mut ast);
+
+        if changes == 0 {
+            continue;
+        }
+
+        let ctx = FormatCtx::new(config).with_source(&content);
+        let formatted = format_document(ast.clone(), &ctx)
+            .and_then(|blocks| {
+                let formatted = blocks.concat();
+
+                verify::verify(&ast, &formatted, config)?;
+                encoding::check(&formatted, encoding)?;
+
+                Ok(formatted)
+            })
+            .wrap_err_with(|| format!("unable to rewrite {}", file.display()))?;
+
+        let formatted = wikilinks::collapse(formatted, config.wikilink_style);
+
+        pending.push(Pending {
+            file,
+            original: Generation::of(&bytes),
+            formatted,
+            encoding,
+            changes,
+        });
+    }
+
+    let mut rewritten = Vec::with_capacity(pending.len());
+
+    for file in pending {
+        if !dry_run {
+            persist::replace(&file.file, file.original, false, |sink| {
+                encoding::write(sink, [file.formatted], file.encoding)
+            })
+            .wrap_err_with(|| format!("unable to write {}", file.file.display()))?;
+        }
+
+        rewritten.push(Rewritten {
+            file: file.file,
+            changes: file.changes,
+        });
+    }
+
+    Ok(rewritten)
+}
+
+/// Renames the heading titled `old` in `file` to `new`, along with every link to it in the
+/// workspace below `root`, and prints the files that were changed.
+pub fn rename_heading(
+    file: &Path,
+    old: &str,
+    new: &str,
+    root: &Path,
+    config: &Config,
+    dry_run: bool,
+) -> Result<()> {
+    let new_title =
+        parse_title(new).ok_or_else(|| eyre!("`{new}` is not a valid heading title"))?;
+    let target = file
+        .canonicalize()
+        .wrap_err_with(|| format!("unable to find {}", file.display()))?;
+
+    if !target.starts_with(root.canonicalize()?) {
+        bail!(
+            "{} is not part of the workspace in {}",
+            file.display(),
+            root.display()
+        );
+    }
+
+    // Links are only renamed if they refer to a heading of the same level, which has to be known
+    // before the first file linking to it is rewritten.
+    let (content, _) = encoding::read(file, false)?;
+    let mut ast = parse(&content).map_err(|err| ParseError(format!("{err:?}")))?;
+    let levels = rename_headings(&mut ast, old, &new_title);
+
+    if levels.is_empty() {
+        bail!("{} has no heading titled `{old}`", file.display());
+    }
+
+    let is_target = |path: &Path| path.canonicalize().is_ok_and(|path| path == target);
+    let rewritten = rewrite_workspace(root, config, dry_run, |path, ast| {
+        let in_target = is_target(path);
+        let headings = if in_target {
+            rename_headings(ast, old, &new_title).len()
+        } else {
+            0
+        };
+
+        headings
+            + rename_heading_links(ast, old, &levels, &new_title, |filepath| match filepath {
+                None => in_target,
+                Some(filepath) => is_target(&resolve_link_path(path, root, filepath)),
+            })
+    })?;
+
+    for Rewritten { file, changes } in rewritten {
+        println!("{}: {changes} change(s)", file.display());
+    }
+
+    Ok(())
+}
+
+/// Updates the links across the workspace below `root` after the file `from` was moved to `to`,
+/// and prints the files that were changed.
+pub fn fix_links(
+    from: &Path,
+    to: &Path,
+    root: &Path,
+    config: &Config,
+    dry_run: bool,
+) -> Result<()> {
+    if !to.is_file() {
+        bail!(
+            "{} does not exist; move the file before fixing the links to it",
+            to.display()
+        );
+    }
+
+    if from.exists() {
+        bail!(
+            "{} still exists; move the file before fixing the links to it",
+            from.display()
+        );
+    }
+
+    let absolute =
+        |path: &Path| Ok::<_, std::io::Error>(normalize_path(&std::path::absolute(path)?));
+    let (root, from, to) = (absolute(root)?, absolute(from)?, absolute(to)?);
+    let moved = Move {
+        root: &root,
+        from: &from,
+        to: &to,
+    };
+
+    let rewritten = rewrite_workspace(&root, config, dry_run, |path, ast| {
+        absolute(path).map_or(0, |path| fix_moved_links(ast, &path, &moved))
+    })?;
+
+    for Rewritten { file, changes } in rewritten {
+        println!("{}: {changes} link(s) updated", file.display());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A workspace of its own with the given files, which the caller removes again.
+    fn workspace(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("norg-fmt-rewrite-{name}-{}", std::process::id()));
+
+        for (file, content) in files {
+            let file = root.join(file);
+
+            std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+            std::fs::write(file, content).unwrap();
+        }
+
+        root
+    }
+
+    #[test]
+    fn renames_a_heading_across_the_workspace() -> Result<()> {
+        let root = workspace(
+            "rename",
+            &[
+                ("a.norg", "* Old\nSee {* Old}.\n"),
+                ("notes/b.norg", "See {:../a:* Old} and {:c:* Old}.\n"),
+                ("notes/c.norg", "* Old\n"),
+            ],
+        );
+        let config = Config::default();
+        let renamed = rename_heading(&root.join("a.norg"), "old", "New", &root, &config, false);
+        let missing = rename_heading(&root.join("a.norg"), "old", "New", &root, &config, false);
+        let read = |file: &str| std::fs::read_to_string(root.join(file));
+        let (a, b, c) = (
+            read("a.norg")?,
+            read("notes/b.norg")?,
+            read("notes/c.norg")?,
+        );
+        std::fs::remove_dir_all(&root)?;
+
+        renamed?;
+        assert!(missing
+            .unwrap_err()
+            .to_string()
+            .contains("no heading titled `old`"));
+        assert_eq!(a, "* New\nSee {* New}.\n");
+        assert!(b.contains("{:../a:* New}") && b.contains("{:c:* Old}"));
+        assert_eq!(c, "* Old\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn fixes_the_links_to_a_moved_file() -> Result<()> {
+        let root = workspace(
+            "move",
+            &[
+                ("index.norg", "See {:notes/a:} and {:other:}.\n"),
+                ("archive/a.norg", "See {:b:}.\n"),
+            ],
+        );
+        let config = Config::default();
+        let (from, to) = (root.join("notes/a.norg"), root.join("archive/a.norg"));
+        let fixed = fix_links(&from, &to, &root, &config, false);
+        let unmoved = fix_links(&to, &to, &root, &config, false);
+        let read = |file: &str| std::fs::read_to_string(root.join(file));
+        let (index, a) = (read("index.norg")?, read("archive/a.norg")?);
+        std::fs::remove_dir_all(&root)?;
+
+        fixed?;
+        assert!(unmoved.unwrap_err().to_string().contains("still exists"));
+        assert_eq!(index, "See {:archive/a:} and {:other:}.\n");
+        assert_eq!(a, "See {:../notes/b:}.\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use eyre::{bail, eyre, Result};
+use norg_fmt::{context::FormatCtx, converter::format_document, Config};
+use rust_norg::parse;
+
+use crate::encoding;
+
+/// The checks the formatter can run against itself.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SelfCheck {
+    /// Format every file twice, the second time in shuffled order, and compare the output.
+    Determinism,
+}
+
+/// Runs a self-check over the given files, failing if the formatter misbehaves on any of them.
+pub fn run(check: SelfCheck, files: &[PathBuf], config: &Config) -> Result<()> {
+    match check {
+        SelfCheck::Determinism => determinism(files, config),
+    }
+}
+
+fn format_file(file: &Path, config: &Config) -> Result<String> {
+    let (content, _) = encoding::read(file, false)?;
+    let ast = parse(&content).map_err(|err| eyre!("unable to parse file: {err:?}"))?;
+
+    Ok(format_document(ast, &FormatCtx::new(config).with_source(&content))?.concat())
+}
+
+/// Shuffles the items with a small xorshift generator, so that the order can be reproduced from
+/// the seed alone.
+fn shuffle<T>(items: &mut [T], mut seed: u64) {
+    for index in (1..items.len()).rev() {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+
+        items.swap(index, (seed % (index as u64 + 1)) as usize);
+    }
+}
+
+fn determinism(files: &[PathBuf], config: &Config) -> Result<()> {
+    let first_pass = files
+        .iter()
+        .map(|file| format_file(file, config).map_err(|err| eyre!("{}: {err}", file.display())))
+        .collect::<Result<Vec<_>>>()?;
+
+    let seed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |elapsed| elapsed.as_nanos() as u64)
+        | 1;
+    let mut order: Vec<usize> = (0..files.len()).collect();
+
+    shuffle(&mut order, seed);
+
+    let mut mismatches = Vec::new();
+
+    for index in order {
+        if format_file(&files[index], config)? != first_pass[index] {
+            mismatches.push(&files[index]);
+        }
+    }
+
+    if mismatches.is_empty() {
+        eprintln!("determinism: {} file(s) formatted identically", files.len());
+
+        return Ok(());
+    }
+
+    for file in &mismatches {
+        eprintln!("nondeterministic output: {}", file.display());
+    }
+
+    bail!(
+        "{} file(s) were formatted differently on the second pass (shuffle seed {seed})",
+        mismatches.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shuffles_reproducibly_from_the_seed() {
+        let shuffled = |seed| {
+            let mut items = (0..10).collect::<Vec<_>>();
+
+            shuffle(&mut items, seed);
+            items
+        };
+        let mut sorted = shuffled(42);
+
+        sorted.sort_unstable();
+
+        assert_eq!(shuffled(42), shuffled(42));
+        assert_ne!(shuffled(42), (0..10).collect::<Vec<_>>());
+        assert_eq!(sorted, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn formats_files_the_same_way_twice() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("norg-fmt-self-check-{}", std::process::id()));
+        let files = [root.join("a.norg"), root.join("b.norg")];
+
+        std::fs::create_dir_all(&root)?;
+        std::fs::write(&files[0], "*  Heading\n-  Item\n")?;
+        std::fs::write(&files[1], "Some   text.\n")?;
+
+        let checked = run(SelfCheck::Determinism, &files, &Config::default());
+        std::fs::remove_dir_all(&root)?;
+
+        checked
+    }
+}
@@ -0,0 +1,73 @@
+//! The rules by which links find what they refer to, for tools that need to produce or resolve
+//! link targets the same way the formatter does.
+//!
+//! Titles of headings, footnotes and definitions as well as the targets of links (`{* Title}`,
+//! `{# Title}`, ...) are matched regardless of case and of how whitespace is laid out. The
+//! formatter keeps the case a target was written in and only collapses its whitespace, so that
+//! `{#  Some Title }` becomes `{# Some Title}`; [`slug`] gives the key both sides are compared by.
+//! Inline comments, such as the roll-up of the tasks beneath a heading, aren't part of a title.
+
+use itertools::Itertools as _;
+use rust_norg::ParagraphSegment;
+
+/// Renders text the way it is written in a link target: trimmed, with every run of whitespace
+/// collapsed into a single space, keeping its case.
+pub fn normalize(text: &str) -> String {
+    text.split_whitespace().join(" ")
+}
+
+/// The key a title or link target is matched by: normalized as by [`normalize`] and lowercased, so
+/// that e.g. `Some  Title` and `some title` refer to the same heading.
+pub fn slug(text: &str) -> String {
+    normalize(text).to_lowercase()
+}
+
+/// [`normalize`] for the segments of a parsed title, leaving out inline comments.
+pub fn normalize_segments(title: &[ParagraphSegment]) -> String {
+    normalize(
+        &title
+            .iter()
+            .filter(|segment| {
+                !matches!(
+                    segment,
+                    ParagraphSegment::AttachedModifier {
+                        modifier_type: '%',
+                        ..
+                    }
+                )
+            })
+            .cloned()
+            .map_into::<String>()
+            .collect::<String>(),
+    )
+}
+
+/// [`slug`] for the segments of a parsed title.
+pub fn slug_segments(title: &[ParagraphSegment]) -> String {
+    slug(&normalize_segments(title))
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_norg::{parse, NorgASTFlat};
+
+    use super::*;
+
+    #[test]
+    fn matches_titles_regardless_of_case_and_whitespace() {
+        assert_eq!(normalize("  Some \t Title "), "Some Title");
+        assert_eq!(slug("Some  TITLE"), slug("some title"));
+        assert_ne!(slug("Some Title"), slug("SomeTitle"));
+    }
+
+    #[test]
+    fn leaves_inline_comments_out_of_titles() {
+        let ast = parse("* Some  Tasks %2/3%\n").unwrap();
+        let [NorgASTFlat::Heading { title, .. }] = ast.as_slice() else {
+            panic!("expected a single heading");
+        };
+
+        assert_eq!(normalize_segments(title), "Some Tasks");
+        assert_eq!(slug_segments(title), "some tasks");
+    }
+}
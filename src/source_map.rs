@@ -0,0 +1,296 @@
+use std::ops::Range;
+
+use rust_norg::parse;
+
+use crate::slug::normalize;
+
+/// Determines the range of lines (zero-based, end exclusive) occupied by each top-level block of
+/// `source`, excluding surrounding blank lines.
+///
+/// The parser does not report spans, so block boundaries are recovered by parsing windows of the
+/// source: a block ends right before the line at which a window starting at the block first
+/// contains two blocks. That line is found by doubling the window until it contains two blocks and
+/// then bisecting the last doubling, so that every window parsed is at most about twice as long as
+/// the block and the whole source is mapped in close to linear time. Returns `None` if the
+/// recovered blocks do not line up with the expected block count.
+pub fn block_ranges(source: &str, block_count: usize) -> Option<Vec<Range<usize>>> {
+    let lines = source.lines().collect::<Vec<_>>();
+    let is_blank = |line: usize| lines[line].trim().is_empty();
+    let blocks_in = |range: Range<usize>| {
+        parse(&lines[range].join("\n"))
+            .map(|ast| ast.len())
+            .unwrap_or(1)
+    };
+
+    let mut ranges = Vec::with_capacity(block_count);
+    let mut start = 0;
+
+    while ranges.len() < block_count {
+        while start < lines.len() && is_blank(start) {
+            start += 1;
+        }
+
+        if start >= lines.len() {
+            return None;
+        }
+
+        // The block ends within `(low, high]`: the window up to `low` holds a single block, the one
+        // up to `high` holds two or runs to the end of the source.
+        let (mut low, mut high) = (start + 1, start + 2);
+
+        while high <= lines.len() && blocks_in(start..high) < 2 {
+            low = high;
+            high = start + (high - start) * 2;
+        }
+
+        let mut high = high.min(lines.len() + 1);
+
+        while high - low > 1 {
+            let middle = low + (high - low) / 2;
+
+            if blocks_in(start..middle) < 2 {
+                low = middle;
+            } else {
+                high = middle;
+            }
+        }
+
+        let end = high - 1;
+        let mut trimmed_end = end;
+
+        while trimmed_end > start + 1 && is_blank(trimmed_end - 1) {
+            trimmed_end -= 1;
+        }
+
+        ranges.push(start..trimmed_end);
+        start = end;
+    }
+
+    Some(ranges)
+}
+
+/// Returns the name of the tag a line opens if it starts with the given tag character, e.g.
+/// `chart` for `#chart bar`.
+fn tag_name(line: &str, prefix: char) -> Option<&str> {
+    let rest = line.trim().strip_prefix(prefix)?;
+
+    rest.starts_with(char::is_alphabetic)
+        .then(|| rest.split_whitespace().next().unwrap_or(rest))
+}
+
+/// Returns the index of the line closing the ranged tag opened at `start`, taking tags of the same
+/// type nested inside of it into account.
+fn closing_line(lines: &[&str], start: usize, prefix: char) -> usize {
+    let mut depth = 0;
+
+    for (index, line) in lines.iter().enumerate().skip(start) {
+        match tag_name(line, prefix) {
+            Some("end") if depth == 1 => return index,
+            Some("end") => depth -= 1,
+            // Verbatim ranged tags can't be nested, their content is never parsed.
+            Some(_) if prefix == '@' && depth == 1 => {}
+            Some(_) => depth += 1,
+            None => {}
+        }
+    }
+
+    lines.len()
+}
+
+/// Collects the source text belonging to every tag for which `wanted` returns true, in document
+/// order: the body of a ranged tag (`|name ... |end`), or the object a carryover tag (`#name` and
+/// `+name`) applies to. Every line of the text ends with a newline.
+///
+/// Like [`block_ranges`], this makes up for the missing spans in the AST so that tag content can be
+/// reproduced exactly as written.
+pub fn tag_bodies(source: &str, wanted: impl Fn(&str) -> bool) -> Vec<(String, String)> {
+    let lines = source.lines().collect::<Vec<_>>();
+    let text = |range: Range<usize>| -> String {
+        lines[range.start..range.end.min(lines.len())]
+            .iter()
+            .map(|line| format!("{line}\n"))
+            .collect()
+    };
+
+    let mut bodies = Vec::new();
+    let mut index = 0;
+
+    while index < lines.len() {
+        let line = lines[index];
+
+        if tag_name(line, '@').is_some_and(|name| name != "end") {
+            index = closing_line(&lines, index, '@') + 1;
+            continue;
+        }
+
+        if let Some(name) = tag_name(line, '|').filter(|name| *name != "end" && wanted(name)) {
+            let end = closing_line(&lines, index, '|');
+
+            bodies.push((name.to_string(), text(index + 1..end)));
+            index = end + 1;
+            continue;
+        }
+
+        let carryover = tag_name(line, '#').or_else(|| tag_name(line, '+'));
+
+        if let Some(name) = carryover.filter(|name| wanted(name)) {
+            let start = index + 1;
+            let next = lines.get(start).copied().unwrap_or_default();
+            let end = if tag_name(next, '|').is_some() {
+                closing_line(&lines, start, '|') + 1
+            } else if tag_name(next, '@').is_some() {
+                closing_line(&lines, start, '@') + 1
+            } else {
+                (start..lines.len())
+                    .find(|&line| lines[line].trim().is_empty())
+                    .unwrap_or(lines.len())
+            };
+
+            bodies.push((name.to_string(), text(start..end)));
+            index = end;
+            continue;
+        }
+
+        index += 1;
+    }
+
+    bodies
+}
+
+/// Splits a heading line at the trailing annotation starting with the earliest of the `markers`
+/// that follows whitespace, e.g. a `{{{` fold marker, returning the heading before it and the
+/// annotation along with the whitespace in front of it.
+pub fn split_heading_annotation<'a>(
+    line: &'a str,
+    markers: &[String],
+) -> Option<(&'a str, &'a str)> {
+    let start = markers
+        .iter()
+        .filter(|marker| !marker.is_empty())
+        .filter_map(|marker| {
+            line.match_indices(marker.as_str())
+                .map(|(index, _)| index)
+                .find(|&index| line[..index].ends_with(char::is_whitespace))
+        })
+        .min()?;
+    let heading = line[..start].trim_end();
+
+    Some((heading, &line[heading.len()..]))
+}
+
+/// Finds the trailing annotations of the headings in `source` (see [`split_heading_annotation`]) in
+/// the order they appear in, along with the heading they belong to, whitespace normalized.
+///
+/// Like [`tag_bodies`], this makes up for the missing spans in the AST, as the spacing in front of
+/// an annotation, e.g. to line up fold markers, is lost when parsing.
+pub fn heading_annotations(source: &str, markers: &[String]) -> Vec<(String, String)> {
+    if markers.is_empty() {
+        return Vec::new();
+    }
+
+    source
+        .lines()
+        .filter(|line| {
+            let stars = line.trim_start().trim_start_matches('*');
+
+            stars.len() < line.trim_start().len() && stars.starts_with(char::is_whitespace)
+        })
+        .filter_map(|line| split_heading_annotation(line.trim_start(), markers))
+        .map(|(heading, annotation)| (normalize(heading), annotation.trim_end().to_string()))
+        .collect()
+}
+
+/// Takes every formatted block that formatting left untouched from `original`, the source it was
+/// formatted from, so that only changed blocks are regenerated. Lines are compared without their
+/// line endings, so that untouched blocks are kept byte-for-byte, `\r\n` line endings included.
+/// Blocks are looked up in order, each after the last one found.
+pub fn keep_untouched(original: &str, blocks: Vec<String>) -> Vec<String> {
+    let content = |line: &str| (line.trim_end_matches(['\r', '\n']), line.ends_with('\n'));
+    let lines = original
+        .split_inclusive('\n')
+        .map(|line| (line, content(line)))
+        .collect::<Vec<_>>();
+    let mut start = 0;
+
+    blocks
+        .into_iter()
+        .map(|block| {
+            let block_lines = block.split_inclusive('\n').map(content).collect::<Vec<_>>();
+            let count = block_lines.len();
+
+            if count == 0 || start + count > lines.len() {
+                return block;
+            }
+
+            let position = (start..=lines.len() - count).find(|&position| {
+                lines[position..position + count]
+                    .iter()
+                    .map(|(_, content)| content)
+                    .eq(&block_lines)
+            });
+
+            match position {
+                Some(position) => {
+                    start = position + count;
+
+                    lines[position..start]
+                        .iter()
+                        .map(|(line, _)| *line)
+                        .collect()
+                }
+                None => block,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locates_blocks_by_line() {
+        let source = "* Heading\n\nFirst paragraph\nstill first.\n\n@code\na\n\nb\n@end\n\nLast.\n";
+
+        assert_eq!(
+            block_ranges(source, 4),
+            Some(vec![0..1, 2..4, 5..10, 11..12])
+        );
+        assert_eq!(block_ranges(source, 5), None);
+    }
+
+    #[test]
+    fn keeps_untouched_blocks_byte_for_byte() {
+        let blocks = ["a\nb\n", "\n", "C\n"].map(String::from).to_vec();
+
+        assert_eq!(
+            keep_untouched("a\r\nb\r\n\r\nc\r\n", blocks),
+            ["a\r\nb\r\n", "\r\n", "C\n"]
+        );
+    }
+
+    #[test]
+    fn collects_tag_bodies() {
+        let source =
+            "|example\nkept  as\n|end\n#name value\nnext  object\n\n@code\n|example\n@end\n";
+
+        assert_eq!(
+            tag_bodies(source, |name| name == "example" || name == "name"),
+            [
+                ("example".to_string(), "kept  as\n".to_string()),
+                ("name".to_string(), "next  object\n".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_heading_annotations() {
+        let markers = ["{{{".to_string()];
+
+        assert_eq!(
+            split_heading_annotation("* Title   {{{1", &markers),
+            Some(("* Title", "   {{{1"))
+        );
+        assert_eq!(split_heading_annotation("* Title{{{1", &markers), None);
+    }
+}
@@ -0,0 +1,26 @@
+//! Hashing whose output can be stored, e.g. in caches and fingerprints.
+
+use std::hash::Hasher;
+
+/// A 64-bit FNV-1a hasher. Unlike the standard library's hasher its output is stable across Rust
+/// releases, which matters for hashes that are stored on disk.
+pub struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
@@ -0,0 +1,36 @@
+use std::process::ExitCode;
+
+use eyre::Report;
+pub use norg_fmt::formatter::ParseError;
+
+/// How a run ended, which determines the exit code of the process. The exit codes are part of the
+/// command line interface, so existing variants must keep their values.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    /// Every file is formatted, or was formatted successfully.
+    Clean = 0,
+    /// Files would be reformatted in check mode, or warnings were reported with
+    /// `--error-on-warnings`.
+    Unclean = 1,
+    /// A document could not be parsed.
+    ParseError = 2,
+    /// Any other failure, such as an IO error or an invalid configuration.
+    Failure = 3,
+}
+
+impl Status {
+    /// The status of a run that failed with the given error.
+    pub fn of(err: &Report) -> Self {
+        if err.downcast_ref::<ParseError>().is_some() {
+            Self::ParseError
+        } else {
+            Self::Failure
+        }
+    }
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    io::Write as _,
+    process::{Command, Stdio},
+};
+
+use eyre::{bail, eyre, Result};
+use rust_norg::{parse, NorgASTFlat};
+
+use crate::{
+    context::{FormatCtx, NodeKind},
+    converter::format_nodes,
+    Config, TagHandling, VerbatimBlankLines,
+};
+
+/// Formats the content of the tag called `name` according to the handling configured for it.
+/// `format_norg` formats the content the regular way, for tags that are formatted as Norg and as a
+/// fallback whenever the content can not be handled as configured.
+pub fn tag_content(name: &str, ctx: &FormatCtx, format_norg: impl FnOnce() -> String) -> String {
+    match ctx.config.tag_handling.get(name) {
+        None | Some(TagHandling::Norg) => format_norg(),
+        Some(TagHandling::Verbatim) => match ctx.take_tag_body(name) {
+            Some(body) => body,
+            None => {
+                ctx.diagnose(format!(
+                    "the content of `{name}` could not be found in the source, formatting it as \
+                     Norg instead"
+                ));
+
+                format_norg()
+            }
+        },
+        Some(TagHandling::Command(command)) => {
+            let input = match ctx.take_tag_body(name) {
+                Some(body) => body,
+                None => format_norg(),
+            };
+
+            let output = ctx.profiled(|| "tag-command".into(), || run(command, &input));
+
+            output.unwrap_or_else(|err| {
+                ctx.diagnose(format!(
+                    "`{command}` failed on the content of `{name}`, leaving it as-is: {err}"
+                ));
+
+                input
+            })
+        }
+    }
+}
+
+/// Whether the content of a verbatim ranged tag is Norg source. This is the case for `@norg`,
+/// `@code norg` and any verbatim tag explicitly configured to be formatted as Norg.
+pub fn embeds_norg(name: &str, parameters: &[String], config: &Config) -> bool {
+    match config.tag_handling.get(name) {
+        Some(handling) => handling == &TagHandling::Norg,
+        None => {
+            name == "norg"
+                || (name == "code"
+                    && parameters
+                        .first()
+                        .is_some_and(|language| language == "norg"))
+        }
+    }
+}
+
+/// Formats the content of a verbatim ranged tag. Content is left as-is unless the tag embeds Norg,
+/// in which case it is formatted as a nested document, or is configured to be piped through a
+/// command.
+pub fn verbatim_content(
+    name: &str,
+    parameters: &[String],
+    content: String,
+    ctx: &FormatCtx,
+) -> String {
+    if embeds_norg(name, parameters, ctx.config) {
+        let formatted = ctx.profiled(|| "embedded-norg".into(), || format_embedded(&content, ctx));
+
+        if formatted.is_some() {
+            ctx.apply_rule("embedded-norg");
+        }
+
+        return formatted.unwrap_or_else(|| {
+            ctx.diagnose(format!(
+                "the content of `@{name}` could not be parsed as Norg, leaving it as-is"
+            ));
+
+            content
+        });
+    }
+
+    match ctx.config.tag_handling.get(name) {
+        Some(TagHandling::Command(command)) => {
+            let output = ctx.profiled(|| "tag-command".into(), || run(command, &content));
+
+            output.unwrap_or_else(|err| {
+                ctx.diagnose(format!(
+                    "`{command}` failed on the content of `@{name}`, leaving it as-is: {err}"
+                ));
+
+                content
+            })
+        }
+        _ => content,
+    }
+}
+
+/// Removes the blank lines at the start and end of the content of a verbatim tag, leaving a single
+/// one before `@end` with [`VerbatimBlankLines::Spaced`]. Every other line is kept as written.
+fn trim_blank_lines(content: &str, blank_lines: VerbatimBlankLines) -> String {
+    let lines = content.split_inclusive('\n').collect::<Vec<_>>();
+    let is_blank = |line: &&str| line.trim().is_empty();
+    let start = lines.iter().take_while(is_blank).count();
+    let end = lines.len() - lines[start..].iter().rev().take_while(is_blank).count();
+
+    if start == end {
+        return String::new();
+    }
+
+    let mut trimmed = lines[start..end].concat();
+
+    if !trimmed.ends_with('\n') {
+        trimmed.push('\n');
+    }
+
+    if blank_lines == VerbatimBlankLines::Spaced {
+        trimmed.push('\n');
+    }
+
+    trimmed
+}
+
+/// Calls `visit` with the name, parameters and content of every verbatim tag in a document.
+fn visit_verbatim_tags_mut(
+    ast: &mut [NorgASTFlat],
+    visit: &mut impl FnMut(&str, &mut Vec<String>, &mut String),
+) {
+    for node in ast {
+        match node {
+            NorgASTFlat::VerbatimRangedTag {
+                name,
+                parameters,
+                content,
+            } => visit(&name.join("."), parameters, content),
+            NorgASTFlat::NestableDetachedModifier { content, .. } => {
+                visit_verbatim_tags_mut(std::slice::from_mut(content.as_mut()), visit)
+            }
+            NorgASTFlat::CarryoverTag { next_object, .. } => {
+                visit_verbatim_tags_mut(std::slice::from_mut(next_object.as_mut()), visit)
+            }
+            NorgASTFlat::RangeableDetachedModifier { content, .. }
+            | NorgASTFlat::RangedTag { content, .. } => visit_verbatim_tags_mut(content, visit),
+            _ => {}
+        }
+    }
+}
+
+/// Normalizes the blank lines around the content of every verbatim tag in a document according to
+/// [`verbatim_blank_lines`](Config::verbatim_blank_lines). Tags whose content is formatted, i.e.
+/// embedded Norg or tags piped through a command, are left alone.
+pub fn normalize_verbatim_blank_lines(ast: &mut [NorgASTFlat], config: &Config) {
+    if config.verbatim_blank_lines == VerbatimBlankLines::Preserve {
+        return;
+    }
+
+    visit_verbatim_tags_mut(ast, &mut |name, parameters, content| {
+        if !embeds_norg(name, parameters, config) && !config.tag_handling.contains_key(name) {
+            *content = trim_blank_lines(content, config.verbatim_blank_lines);
+        }
+    });
+}
+
+/// Common abbreviations of the languages of `@code` blocks and the names they stand for.
+const LANGUAGE_ALIASES: [(&str, &str); 12] = [
+    ("c++", "cpp"),
+    ("hs", "haskell"),
+    ("js", "javascript"),
+    ("kt", "kotlin"),
+    ("md", "markdown"),
+    ("py", "python"),
+    ("rb", "ruby"),
+    ("rs", "rust"),
+    ("sh", "bash"),
+    ("ts", "typescript"),
+    ("vimscript", "vim"),
+    ("yml", "yaml"),
+];
+
+/// The name a language of a `@code` block is normalized to: its lowercase form, resolved through
+/// the configured [`language_aliases`](Config::language_aliases) and the built-in ones.
+pub fn normalize_language(language: &str, config: &Config) -> String {
+    let language = language.to_lowercase();
+
+    if let Some(name) = config.language_aliases.get(&language) {
+        return name.clone();
+    }
+
+    LANGUAGE_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == language)
+        .map_or(language, |(_, name)| name.to_string())
+}
+
+/// Normalizes the language of every `@code` block in a document with [`normalize_language`], if
+/// [`normalize_languages`](Config::normalize_languages) is enabled.
+pub fn normalize_languages(ast: &mut [NorgASTFlat], config: &Config) {
+    if !config.normalize_languages {
+        return;
+    }
+
+    visit_verbatim_tags_mut(ast, &mut |name, parameters, _| {
+        if let (Some(language), "code") = (parameters.first_mut(), name) {
+            *language = normalize_language(language, config);
+        }
+    });
+}
+
+/// The whitespace every non-blank line of `text` starts with.
+pub(crate) fn common_indent(text: &str) -> &str {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .reduce(|common, indent| {
+            let length = common
+                .chars()
+                .zip(indent.chars())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a.len_utf8())
+                .sum();
+
+            &common[..length]
+        })
+        .unwrap_or_default()
+}
+
+/// Parses the Norg embedded in a verbatim tag, ignoring the indentation shared by all of its lines.
+pub(crate) fn embedded_ast(content: &str) -> Option<Vec<NorgASTFlat>> {
+    let indent = common_indent(content);
+    let dedented = content
+        .lines()
+        .map(|line| line.strip_prefix(indent).unwrap_or(line.trim_start()))
+        .map(|line| format!("{line}\n"))
+        .collect::<String>();
+
+    parse(&dedented).ok()
+}
+
+/// Formats the Norg embedded in a verbatim tag as a document of its own, keeping the indentation
+/// shared by all of its lines.
+fn format_embedded(content: &str, ctx: &FormatCtx) -> Option<String> {
+    if content.trim().is_empty() {
+        return Some(content.to_string());
+    }
+
+    let indent = common_indent(content);
+    let formatted = format_nodes(
+        embedded_ast(content)?,
+        &ctx.nested(NodeKind::VerbatimRangedTag),
+    )
+    .ok()?
+    .concat();
+
+    Some(
+        formatted
+            .lines()
+            .map(|line| {
+                if line.is_empty() {
+                    "\n".to_string()
+                } else {
+                    format!("{indent}{line}\n")
+                }
+            })
+            .collect(),
+    )
+}
+
+/// Pipes `input` through the command, returning its output with a trailing newline.
+fn run(command: &str, input: &str) -> Result<String> {
+    let mut words = command.split_whitespace();
+    let program = words.next().ok_or_else(|| eyre!("no program given"))?;
+
+    let mut child = Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Writing from another thread keeps a program that doesn't read all of its input before
+    // writing output from blocking on a full pipe.
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| eyre!("unable to write to the program"))?;
+    let input = input.to_string();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child.wait_with_output()?;
+
+    writer
+        .join()
+        .map_err(|_| eyre!("writing to the program panicked"))??;
+
+    if !output.status.success() {
+        bail!(
+            "{}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let mut output = String::from_utf8(output.stdout)?;
+
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+
+    Ok(output)
+}
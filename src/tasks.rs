@@ -0,0 +1,386 @@
+//! Task metadata: the canonical form of the extensions of detached modifiers (statuses,
+//! priorities, dates and recurrences) and roll-up annotations summarizing the tasks beneath each
+//! heading, e.g. `* Launch %3/7%`.
+
+use itertools::Itertools;
+use rust_norg::{parse, DetachedModifierExtension, NorgASTFlat, TodoStatus};
+
+use crate::source_map::split_heading_annotation;
+
+/// The status of the task a node is, looking through any carryover tags attached to it.
+fn task_status(node: &NorgASTFlat) -> Option<&TodoStatus> {
+    let extensions = match node {
+        NorgASTFlat::Heading { extensions, .. }
+        | NorgASTFlat::NestableDetachedModifier { extensions, .. }
+        | NorgASTFlat::RangeableDetachedModifier { extensions, .. } => extensions,
+        NorgASTFlat::CarryoverTag { next_object, .. } => return task_status(next_object),
+        _ => return None,
+    };
+
+    extensions.iter().find_map(|extension| match extension {
+        DetachedModifierExtension::Todo(status) => Some(status),
+        _ => None,
+    })
+}
+
+/// The statuses of the tasks among the nodes, including those nested in list items.
+fn task_statuses<'a>(nodes: &'a [NorgASTFlat], statuses: &mut Vec<&'a TodoStatus>) {
+    for node in nodes {
+        statuses.extend(task_status(node));
+
+        match node {
+            NorgASTFlat::NestableDetachedModifier { content, .. } => {
+                task_statuses(std::slice::from_ref(content), statuses);
+            }
+            NorgASTFlat::RangeableDetachedModifier { content, .. } => {
+                task_statuses(content, statuses);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn heading_level(node: &NorgASTFlat) -> Option<u16> {
+    match node {
+        NorgASTFlat::Heading { level, .. } => Some(*level),
+        NorgASTFlat::CarryoverTag { next_object, .. } => heading_level(next_object),
+        _ => None,
+    }
+}
+
+fn heading_mut(node: &mut NorgASTFlat) -> Option<&mut NorgASTFlat> {
+    match node {
+        NorgASTFlat::Heading { .. } => Some(node),
+        NorgASTFlat::CarryoverTag { next_object, .. } => heading_mut(next_object),
+        _ => None,
+    }
+}
+
+/// Removes a trailing roll-up such as ` %3/7%` from the title of a heading. Only inline comments
+/// made up of the counts alone are roll-ups, so that counts written as part of a title, e.g.
+/// `* Part (1/2)`, are left alone.
+fn strip_roll_up(title: &str) -> &str {
+    let Some(rest) = title.strip_suffix('%') else {
+        return title;
+    };
+    let Some((before, counts)) = rest.rsplit_once('%') else {
+        return title;
+    };
+    let is_count = |count: &str| !count.is_empty() && count.chars().all(|c| c.is_ascii_digit());
+
+    match counts.split_once('/') {
+        Some((done, total))
+            if is_count(done) && is_count(total) && before.ends_with(char::is_whitespace) =>
+        {
+            before.trim_end()
+        }
+        _ => title,
+    }
+}
+
+/// Writes a `%done/total%` annotation at the end of every heading with tasks beneath it, counting
+/// the tasks of its whole section, subsections included. Canceled tasks are left out, and stale
+/// annotations of headings without any tasks are removed. A trailing annotation such as a fold
+/// marker stays at the very end.
+///
+/// The annotation is an inline comment, so that it isn't part of the title links refer to the
+/// heading by (see [`slug`](crate::slug)).
+pub fn roll_up(ast: &mut [NorgASTFlat], annotation_markers: &[String]) {
+    for index in 0..ast.len() {
+        let Some(level) = heading_level(&ast[index]) else {
+            continue;
+        };
+        let end = ast[index + 1..]
+            .iter()
+            .position(|node| heading_level(node).is_some_and(|other| other <= level))
+            .map_or(ast.len(), |position| index + 1 + position);
+
+        let mut statuses = Vec::new();
+        task_statuses(&ast[index + 1..end], &mut statuses);
+
+        let done = statuses
+            .iter()
+            .filter(|status| matches!(status, TodoStatus::Done))
+            .count();
+        let total = statuses
+            .iter()
+            .filter(|status| !matches!(status, TodoStatus::Canceled))
+            .count();
+
+        let Some(NorgASTFlat::Heading { title, .. }) = heading_mut(&mut ast[index]) else {
+            continue;
+        };
+        let text = title
+            .iter()
+            .cloned()
+            .map_into::<String>()
+            .collect::<String>();
+        let (text, annotation) =
+            split_heading_annotation(&text, annotation_markers).unwrap_or((&text, ""));
+        let stripped = strip_roll_up(text.trim_end());
+        let rolled_up = match total {
+            0 => format!("{stripped}{annotation}"),
+            _ => format!("{stripped} %{done}/{total}%{annotation}"),
+        };
+
+        if rolled_up == text.to_string() + annotation {
+            continue;
+        }
+
+        // Parsed again, so that the title is made up of the same segments as when the formatted
+        // document is parsed.
+        let heading = format!("{} {rolled_up}\n", "*".repeat(level.into()));
+
+        if let Ok([NorgASTFlat::Heading { title: parsed, .. }]) = parse(&heading).as_deref() {
+            *title = parsed.clone();
+        }
+    }
+}
+
+const WEEKDAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+const MONTHS: [&str; 12] = [
+    "january",
+    "february",
+    "march",
+    "april",
+    "may",
+    "june",
+    "july",
+    "august",
+    "september",
+    "october",
+    "november",
+    "december",
+];
+
+/// Words with a meaning in recurrences and timestamps, written in lowercase.
+const KEYWORDS: [&str; 18] = [
+    "every", "other", "and", "on", "at", "day", "days", "weekday", "weekdays", "week", "weeks",
+    "month", "months", "year", "years", "daily", "weekly", "monthly",
+];
+
+/// The canonical spelling of a weekday or month, written in full or abbreviated to at least three
+/// letters, e.g. `Monday` for `monday` and `Tue` for `TUE`.
+fn calendar_name(word: &str) -> Option<String> {
+    let lowercase = word.to_lowercase();
+
+    WEEKDAYS
+        .iter()
+        .chain(&MONTHS)
+        .any(|name| lowercase.len() >= 3 && name.starts_with(&lowercase))
+        .then(|| {
+            let mut chars = lowercase.chars();
+
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        })
+}
+
+/// Whether a word is a number, an ordinal such as `5th`, a time such as `10:30`, a date such as
+/// `2024-05-01` or a time zone offset such as `+02:00`.
+fn is_numeric(word: &str) -> bool {
+    let digits = word.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let suffix = &word[digits.len()..].to_lowercase();
+
+    !digits.is_empty()
+        && digits
+            .trim_start_matches(['+', '-'])
+            .starts_with(|c: char| c.is_ascii_digit())
+        && digits
+            .chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, ':' | '-' | '/' | '.' | '+'))
+        && matches!(
+            suffix.as_str(),
+            "" | "st" | "nd" | "rd" | "th" | "am" | "pm"
+        )
+}
+
+/// Rewrites a recurrence, date or timestamp with single spaces between its words, weekdays and
+/// months capitalized and keywords such as `every` in lowercase. Other words are kept as written.
+fn normalize_when(when: &str) -> String {
+    when.split_whitespace()
+        .map(|word| {
+            let (word, comma) = match word.strip_suffix(',') {
+                Some(word) => (word, ","),
+                None => (word, ""),
+            };
+            let lowercase = word.to_lowercase();
+            let word = if KEYWORDS.contains(&lowercase.as_str()) {
+                lowercase
+            } else {
+                calendar_name(word).unwrap_or_else(|| word.to_string())
+            };
+
+            word + comma
+        })
+        .join(" ")
+}
+
+/// Whether a recurrence or timestamp is made up of words that can be understood: weekdays, months,
+/// numbers, times, dates, keywords such as `every`, and time zones such as `UTC`.
+pub fn is_valid_when(when: &str) -> bool {
+    let is_time_zone =
+        |word: &str| (2..=5).contains(&word.len()) && word.chars().all(|c| c.is_ascii_uppercase());
+
+    !when.trim().is_empty()
+        && when.split_whitespace().all(|word| {
+            let word = word.trim_end_matches(',');
+
+            is_numeric(word)
+                || is_time_zone(word)
+                || calendar_name(word).is_some()
+                || KEYWORDS.contains(&word.to_lowercase().as_str())
+        })
+}
+
+/// Calls `visit` for the extensions of every detached modifier, including nested ones.
+pub fn visit_extensions(node: &NorgASTFlat, visit: &mut impl FnMut(&[DetachedModifierExtension])) {
+    match node {
+        NorgASTFlat::Heading { extensions, .. } => visit(extensions),
+        NorgASTFlat::NestableDetachedModifier {
+            extensions,
+            content,
+            ..
+        } => {
+            visit(extensions);
+            visit_extensions(content, visit);
+        }
+        NorgASTFlat::RangeableDetachedModifier {
+            extensions,
+            content,
+            ..
+        } => {
+            visit(extensions);
+            content
+                .iter()
+                .for_each(|node| visit_extensions(node, visit));
+        }
+        NorgASTFlat::RangedTag { content, .. } => {
+            content
+                .iter()
+                .for_each(|node| visit_extensions(node, visit));
+        }
+        NorgASTFlat::CarryoverTag { next_object, .. } => visit_extensions(next_object, visit),
+        _ => {}
+    }
+}
+
+fn visit_extensions_mut(
+    node: &mut NorgASTFlat,
+    visit: &mut impl FnMut(&mut Vec<DetachedModifierExtension>),
+) {
+    match node {
+        NorgASTFlat::Heading { extensions, .. } => visit(extensions),
+        NorgASTFlat::NestableDetachedModifier {
+            extensions,
+            content,
+            ..
+        } => {
+            visit(extensions);
+            visit_extensions_mut(content, visit);
+        }
+        NorgASTFlat::RangeableDetachedModifier {
+            extensions,
+            content,
+            ..
+        } => {
+            visit(extensions);
+            content
+                .iter_mut()
+                .for_each(|node| visit_extensions_mut(node, visit));
+        }
+        NorgASTFlat::RangedTag { content, .. } => {
+            content
+                .iter_mut()
+                .for_each(|node| visit_extensions_mut(node, visit));
+        }
+        NorgASTFlat::CarryoverTag { next_object, .. } => visit_extensions_mut(next_object, visit),
+        _ => {}
+    }
+}
+
+/// Brings the extensions of detached modifiers into their canonical form: priorities in uppercase,
+/// and recurrences, dates and timestamps with single spaces and canonical casing (see
+/// [`normalize_when`]).
+pub fn normalize_extensions(ast: &mut [NorgASTFlat]) {
+    for node in ast {
+        visit_extensions_mut(node, &mut |extensions| {
+            for extension in extensions {
+                match extension {
+                    DetachedModifierExtension::Priority(priority) => {
+                        *priority = priority.split_whitespace().join(" ").to_uppercase();
+                    }
+                    DetachedModifierExtension::Todo(TodoStatus::Recurring(Some(when)))
+                    | DetachedModifierExtension::DueDate(when)
+                    | DetachedModifierExtension::StartDate(when)
+                    | DetachedModifierExtension::Timestamp(when) => *when = normalize_when(when),
+                    DetachedModifierExtension::Todo(_) => {}
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_only_roll_ups() {
+        assert_eq!(strip_roll_up("Launch %3/7%"), "Launch");
+        assert_eq!(strip_roll_up("Part (1/2)"), "Part (1/2)");
+        assert_eq!(strip_roll_up("Part %1/2"), "Part %1/2");
+        assert_eq!(strip_roll_up("Part%1/2%"), "Part%1/2%");
+        assert_eq!(strip_roll_up("Part %one/2%"), "Part %one/2%");
+    }
+
+    #[test]
+    fn rolls_up_tasks_beneath_headings() {
+        let rolled_up = |source: &str| {
+            let mut ast = parse(source).unwrap();
+
+            roll_up(&mut ast, &[]);
+            ast
+        };
+
+        assert_eq!(
+            rolled_up("* Launch\n** Tasks\n- ( ) one\n- (x) two\n- (_) three\n"),
+            parse("* Launch %1/2%\n** Tasks %1/2%\n- ( ) one\n- (x) two\n- (_) three\n").unwrap()
+        );
+        assert_eq!(
+            rolled_up("* Part (1/2) %1/2%\nNo tasks.\n"),
+            parse("* Part (1/2)\nNo tasks.\n").unwrap()
+        );
+    }
+
+    #[test]
+    fn normalizes_recurrences_and_dates() {
+        assert_eq!(
+            normalize_when("EVERY  other tue,  at 10:30"),
+            "every other Tue, at 10:30"
+        );
+        assert_eq!(normalize_when("1 jan 2024"), "1 Jan 2024");
+        assert_eq!(normalize_when("Someday"), "Someday");
+    }
+
+    #[test]
+    fn validates_recurrences_and_dates() {
+        assert!(is_valid_when("every other Friday"));
+        assert!(is_valid_when("2024-05-01 10:30 UTC"));
+        assert!(is_valid_when("5th, 10am +02:00"));
+        assert!(!is_valid_when("someday"));
+        assert!(!is_valid_when("1x"));
+        assert!(!is_valid_when("  "));
+    }
+}
@@ -0,0 +1,70 @@
+use std::io::IsTerminal;
+
+use clap::ValueEnum;
+
+/// Determines whether output is colored.
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum ColorChoice {
+    /// Color output written to a terminal, unless `NO_COLOR` is set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Applies ANSI styles to text written to a specific stream, or leaves it untouched if that stream
+/// should not be colored.
+#[derive(Clone, Copy)]
+pub struct Style {
+    enabled: bool,
+}
+
+impl Style {
+    pub fn new(choice: ColorChoice, stream: &impl IsTerminal) -> Self {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+
+        let enabled = match choice {
+            ColorChoice::Auto => !no_color && stream.is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        };
+
+        Self { enabled }
+    }
+
+    pub fn stdout(choice: ColorChoice) -> Self {
+        Self::new(choice, &std::io::stdout())
+    }
+
+    pub fn stderr(choice: ColorChoice) -> Self {
+        Self::new(choice, &std::io::stderr())
+    }
+
+    fn paint(self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn bold(self, text: &str) -> String {
+        self.paint("1", text)
+    }
+
+    pub fn red(self, text: &str) -> String {
+        self.paint("31", text)
+    }
+
+    pub fn green(self, text: &str) -> String {
+        self.paint("32", text)
+    }
+
+    pub fn yellow(self, text: &str) -> String {
+        self.paint("33", text)
+    }
+
+    pub fn cyan(self, text: &str) -> String {
+        self.paint("36", text)
+    }
+}
@@ -0,0 +1,160 @@
+//! Helpers for testing that formatting never changes the meaning of a document.
+//!
+//! Enabled by the `testing` feature, so that both this crate's tests and downstream tools can
+//! exercise the formatter against generated documents.
+
+use proptest::{
+    collection::vec,
+    prelude::*,
+    sample::select,
+    test_runner::{Config as ProptestConfig, RngAlgorithm, TestRng, TestRunner},
+};
+use rust_norg::parse;
+
+use crate::{
+    context::FormatCtx,
+    converter::format_nodes,
+    verify::{verify, VerifyError},
+    Config,
+};
+
+/// Formats `input` and panics if the output does not parse to the same AST as the input.
+pub fn assert_format_preserves_ast(input: &str) {
+    assert_format_preserves_ast_with(input, &Config::default());
+}
+
+/// Same as [`assert_format_preserves_ast`], but formats with the given configuration.
+pub fn assert_format_preserves_ast_with(input: &str, config: &Config) {
+    let ast = parse(input).unwrap_or_else(|err| panic!("input does not parse: {err:?}"));
+    let formatted = format_nodes(ast.clone(), &FormatCtx::new(config))
+        .unwrap_or_else(|errors| panic!("input could not be formatted: {errors:?}"))
+        .concat();
+
+    if let Err(err) = verify(&ast, &formatted, config) {
+        let table = err
+            .downcast_ref()
+            .and_then(VerifyError::table)
+            .unwrap_or_default();
+
+        panic!("{err}\n{table}\ninput:\n{input}\n\nformatted:\n{formatted}");
+    }
+}
+
+/// Formats `input` twice and panics if formatting the formatted output changes it again.
+pub fn assert_format_is_idempotent_with(input: &str, config: &Config) {
+    let format_source = |source: &str| {
+        let ast = parse(source).unwrap_or_else(|err| panic!("source does not parse: {err:?}"));
+
+        format_nodes(ast, &FormatCtx::new(config))
+            .unwrap_or_else(|errors| panic!("source could not be formatted: {errors:?}"))
+            .concat()
+    };
+
+    let once = format_source(input);
+    let twice = format_source(&once);
+
+    if once != twice {
+        panic!("formatting is not idempotent\n\nonce:\n{once}\n\ntwice:\n{twice}");
+    }
+}
+
+fn word() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9]{1,12}"
+}
+
+/// A word, possibly wrapped in attached modifier markup or inline verbatim.
+fn inline() -> impl Strategy<Value = String> {
+    prop_oneof![
+        4 => word(),
+        1 => (select(vec!['*', '/', '_', '-', '!', '^', ',', '`']), word())
+            .prop_map(|(modifier, word)| format!("{modifier}{word}{modifier}")),
+        1 => (word(), word()).prop_map(|(text, target)| format!("{{* {target}}}[{text}]")),
+    ]
+}
+
+/// A single line of text, without a trailing newline.
+fn line() -> impl Strategy<Value = String> {
+    vec(inline(), 1..12).prop_map(|words| words.join(" "))
+}
+
+fn paragraph() -> impl Strategy<Value = String> {
+    vec(line(), 1..4).prop_map(|lines| lines.join("\n") + "\n")
+}
+
+fn heading() -> impl Strategy<Value = String> {
+    (1..=6usize, line()).prop_map(|(level, title)| format!("{} {title}\n", "*".repeat(level)))
+}
+
+fn todo_status() -> impl Strategy<Value = String> {
+    prop_oneof![
+        3 => Just(String::new()),
+        1 => select(vec![" ", "x", "?", "=", "!", "+", "-", "_"])
+            .prop_map(|status| format!("({status}) ")),
+    ]
+}
+
+fn nestable() -> impl Strategy<Value = String> {
+    (
+        select(vec!['-', '~', '>']),
+        1..=6usize,
+        todo_status(),
+        line(),
+    )
+        .prop_map(|(modifier, level, status, content)| {
+            let status = if modifier == '>' {
+                String::new()
+            } else {
+                status
+            };
+
+            format!("{} {status}{content}\n", modifier.to_string().repeat(level))
+        })
+}
+
+fn definition() -> impl Strategy<Value = String> {
+    (select(vec!['$', '^']), line(), line())
+        .prop_map(|(modifier, title, content)| format!("{modifier} {title}\n{content}\n"))
+}
+
+fn verbatim() -> impl Strategy<Value = String> {
+    (word(), vec("[ -~]{0,30}", 0..4)).prop_map(|(language, lines)| {
+        let content: String = lines.into_iter().map(|line| line + "\n").collect();
+
+        format!("@code {language}\n{content}@end\n")
+    })
+}
+
+/// Generates random, valid Norg documents made up of the constructs the formatter handles.
+pub fn document() -> impl Strategy<Value = String> {
+    vec(
+        prop_oneof![
+            4 => paragraph(),
+            2 => heading(),
+            3 => nestable(),
+            1 => definition(),
+            1 => verbatim(),
+        ],
+        1..16,
+    )
+    .prop_map(|blocks| blocks.join("\n"))
+}
+
+/// Generates a document with [`document`] from a seed, e.g. the input of a fuzzer, so that the same
+/// seed always generates the same document. Only the first 32 bytes of the seed are used.
+pub fn document_from_seed(seed: &[u8]) -> String {
+    let mut bytes = [0; 32];
+
+    for (byte, seed) in bytes.iter_mut().zip(seed) {
+        *byte = *seed;
+    }
+
+    let mut runner = TestRunner::new_with_rng(
+        ProptestConfig::default(),
+        TestRng::from_seed(RngAlgorithm::ChaCha, &bytes),
+    );
+
+    document()
+        .new_tree(&mut runner)
+        .expect("documents can always be generated")
+        .current()
+}
@@ -0,0 +1,42 @@
+use std::time::{Duration, Instant};
+
+/// The phases a file goes through while being formatted.
+#[derive(Clone, Copy, Debug)]
+pub enum Phase {
+    Parse,
+    Format,
+    Emit,
+}
+
+/// Accumulates the time spent in every phase across all files of a run.
+#[derive(Default)]
+pub struct Timings {
+    durations: [Duration; 3],
+}
+
+impl Timings {
+    /// Runs `f`, adding the time it took to the given phase.
+    pub fn measure<T>(&mut self, phase: Phase, f: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("phase", ?phase).entered();
+
+        let start = Instant::now();
+        let result = f();
+
+        self.durations[phase as usize] += start.elapsed();
+
+        result
+    }
+
+    /// Prints the duration of every phase to stderr.
+    pub fn print(&self) {
+        let [parse, format, emit] = self.durations.map(|duration| duration.as_secs_f64());
+
+        eprintln!(
+            "parse: {:.2}ms, format: {:.2}ms, emit: {:.2}ms",
+            parse * 1000.0,
+            format * 1000.0,
+            emit * 1000.0
+        );
+    }
+}
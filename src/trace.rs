@@ -0,0 +1,110 @@
+use std::{io::Write, path::Path};
+
+use eyre::Result;
+use rust_norg::NorgASTFlat;
+use serde::Serialize;
+
+use crate::{
+    explain::{blocks, transformations},
+    Config,
+};
+
+#[derive(Serialize)]
+struct Lines {
+    start: usize,
+    end: usize,
+}
+
+/// A single entry of the transformation log. Entries are written as one JSON object per line.
+#[derive(Serialize)]
+struct TraceEntry<'a> {
+    file: &'a Path,
+    rules: Vec<&'static str>,
+    block: usize,
+    /// The one-based, inclusive range of lines the block occupied in the input.
+    lines: Lines,
+    before: &'a str,
+    after: &'a str,
+}
+
+/// Opens the destination of the transformation log, which is stderr if the destination is `-`.
+pub fn open(destination: &Path) -> Result<Box<dyn Write>> {
+    if destination == Path::new("-") {
+        Ok(Box::new(std::io::stderr()))
+    } else {
+        Ok(Box::new(std::fs::File::create(destination)?))
+    }
+}
+
+/// Writes a log entry for every block of `file` the formatter changed.
+pub fn trace_transforms(
+    output: &mut dyn Write,
+    file: &Path,
+    source: &str,
+    ast: &[NorgASTFlat],
+    config: &Config,
+) -> Result<()> {
+    for block in blocks(source, ast, config)? {
+        if block.before == block.after {
+            continue;
+        }
+
+        let entry = TraceEntry {
+            file,
+            rules: transformations(&block, ast, config)
+                .into_iter()
+                .map(|transformation| transformation.rule)
+                .collect(),
+            block: block.index,
+            lines: Lines {
+                start: block.lines.start + 1,
+                end: block.lines.end,
+            },
+            before: &block.before,
+            after: &block.after,
+        };
+
+        writeln!(output, "{}", serde_json::to_string(&entry)?)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_norg::parse;
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    #[test]
+    fn logs_the_blocks_that_changed() -> Result<()> {
+        let source = "*  Heading\nText.\n-  Item\n";
+        let mut output = Vec::new();
+
+        trace_transforms(
+            &mut output,
+            Path::new("notes.norg"),
+            source,
+            &parse(source).unwrap(),
+            &Config::default(),
+        )?;
+
+        let entries = String::from_utf8(output)?
+            .lines()
+            .map(serde_json::from_str::<Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["file"], "notes.norg");
+        assert_eq!(entries[0]["block"], 0);
+        assert_eq!(entries[0]["lines"], json!({ "start": 1, "end": 1 }));
+        assert_eq!(entries[0]["before"], "*  Heading");
+        assert_eq!(entries[0]["after"], "* Heading");
+        assert_eq!(entries[1]["block"], 2);
+        assert_eq!(entries[1]["after"], "- Item");
+        assert!(entries[1]["rules"].is_array());
+
+        Ok(())
+    }
+}
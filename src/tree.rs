@@ -0,0 +1,137 @@
+//! Support for the recursive [`NorgAST`] produced by [`rust_norg::parse_tree`].
+//!
+//! The formatter operates on the flat sequence of blocks produced by [`rust_norg::parse`], in which
+//! the content of a heading or list item simply follows it. A tree is formatted by flattening it
+//! into that sequence first, which yields the same output as formatting the flat AST directly.
+//!
+//! The hierarchy of headings isn't lost by flattening: the formatter recovers the sections of a
+//! document from the levels of its headings, which is where `indent_headings` and
+//! `newline_after_headings` are applied, with every subsection indented by one more unit.
+
+use eyre::Result;
+use rust_norg::{NorgAST, NorgASTFlat};
+
+use crate::{context::FormatCtx, converter::format_document};
+
+fn flatten_into(node: NorgAST, output: &mut Vec<NorgASTFlat>) {
+    match node {
+        NorgAST::Paragraph(content) => output.push(NorgASTFlat::Paragraph(content)),
+        NorgAST::NestableDetachedModifier {
+            modifier_type,
+            level,
+            extensions,
+            text,
+            content,
+        } => {
+            output.push(NorgASTFlat::NestableDetachedModifier {
+                modifier_type,
+                level,
+                extensions,
+                content: text,
+            });
+            output.extend(flatten(content));
+        }
+        NorgAST::RangeableDetachedModifier {
+            modifier_type,
+            title,
+            extensions,
+            content,
+        } => output.push(NorgASTFlat::RangeableDetachedModifier {
+            modifier_type,
+            title,
+            extensions,
+            content,
+        }),
+        NorgAST::Heading {
+            level,
+            title,
+            extensions,
+            content,
+        } => {
+            output.push(NorgASTFlat::Heading {
+                level,
+                title,
+                extensions,
+            });
+            output.extend(flatten(content));
+        }
+        NorgAST::CarryoverTag {
+            tag_type,
+            name,
+            parameters,
+            next_object,
+        } => {
+            // The tag only applies to the first block of the flattened object. The rest of it, e.g.
+            // the content of a heading, follows the tag.
+            let mut next_objects = flatten(vec![*next_object]).into_iter();
+
+            if let Some(next_object) = next_objects.next() {
+                output.push(NorgASTFlat::CarryoverTag {
+                    tag_type,
+                    name,
+                    parameters,
+                    next_object: Box::new(next_object),
+                });
+            }
+
+            output.extend(next_objects);
+        }
+        NorgAST::VerbatimRangedTag {
+            name,
+            parameters,
+            content,
+        } => output.push(NorgASTFlat::VerbatimRangedTag {
+            name,
+            parameters,
+            content,
+        }),
+        NorgAST::RangedTag {
+            name,
+            parameters,
+            content,
+        } => output.push(NorgASTFlat::RangedTag {
+            name,
+            parameters,
+            content,
+        }),
+        NorgAST::InfirmTag { name, parameters } => {
+            output.push(NorgASTFlat::InfirmTag { name, parameters })
+        }
+    }
+}
+
+/// Flattens a tree into the sequence of blocks it was built from, in document order.
+pub fn flatten(tree: Vec<NorgAST>) -> Vec<NorgASTFlat> {
+    let mut output = Vec::new();
+
+    for node in tree {
+        flatten_into(node, &mut output);
+    }
+
+    output
+}
+
+/// Formats a document given as a tree, see [`format_document`].
+pub fn format_tree(tree: Vec<NorgAST>, ctx: &FormatCtx) -> Result<Vec<String>> {
+    format_document(flatten(tree), ctx)
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_norg::{parse, parse_tree};
+
+    use super::*;
+    use crate::Config;
+
+    #[test]
+    fn formats_trees_like_the_flat_ast() {
+        let source = "* Heading\nText.\n** Subheading\n- Item\n-- Nested\n> Quote\n* Other\n";
+        let config = Config::default();
+        let ctx = || FormatCtx::new(&config).with_source(source);
+
+        assert_eq!(
+            format_tree(parse_tree(source).unwrap(), &ctx()).unwrap(),
+            format_document(parse(source).unwrap(), &ctx()).unwrap()
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! Opt-in normalizations of prose, such as the spacing around punctuation or typographic
+//! substitutions.
+//!
+//! They are applied to the AST before it is formatted, and only to the text of paragraphs and
+//! headings: verbatim and math, comments, links and anchors are never touched.
+
+use rust_norg::{parse, NorgASTFlat, ParagraphSegment, ParagraphSegmentToken};
+
+use crate::Locale;
+
+/// Punctuation that is never preceded by a space.
+const PUNCTUATION: [char; 6] = [',', '.', ';', ':', '!', '?'];
+
+/// Punctuation that is always followed by a space when followed by a word. Colons and full stops
+/// are left out, as they appear within words too often (`3.14`, `e.g.`, `12:30`, `file.norg`).
+const SPACED_PUNCTUATION: [char; 4] = [',', ';', '!', '?'];
+
+/// Punctuation that French typography separates from the preceding word.
+pub const FRENCH_SPACED_PUNCTUATION: [char; 4] = [';', ':', '!', '?'];
+
+/// Spaces that glue the words around them together: the no-break space, the narrow no-break space
+/// and the figure space. They are never turned into line breaks nor collapsed into regular spaces.
+pub const NON_BREAKING_SPACES: [char; 3] = ['\u{A0}', '\u{202F}', '\u{2007}'];
+
+/// Whether a character is whitespace a line may be broken at, which is any whitespace apart from
+/// [`NON_BREAKING_SPACES`].
+pub fn is_breaking_whitespace(char: char) -> bool {
+    char.is_whitespace() && !NON_BREAKING_SPACES.contains(&char)
+}
+
+/// Calls `visit` with every run of prose segments in a node: the content of paragraphs and titles,
+/// as well as the content of markup within them, apart from verbatim, math, comments and variables.
+fn visit_prose_mut(node: &mut NorgASTFlat, visit: &mut impl FnMut(&mut Vec<ParagraphSegment>)) {
+    fn visit_segments(
+        segments: &mut Vec<ParagraphSegment>,
+        visit: &mut impl FnMut(&mut Vec<ParagraphSegment>),
+    ) {
+        visit(segments);
+
+        for segment in segments {
+            match segment {
+                ParagraphSegment::AttachedModifier {
+                    modifier_type: '`' | '$' | '%' | '&',
+                    ..
+                } => {}
+                ParagraphSegment::AttachedModifier { content, .. } => {
+                    visit_segments(content, visit)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    match node {
+        NorgASTFlat::Paragraph(content) => visit_segments(content, visit),
+        NorgASTFlat::Heading { title, .. } => visit_segments(title, visit),
+        NorgASTFlat::NestableDetachedModifier { content, .. } => visit_prose_mut(content, visit),
+        NorgASTFlat::RangeableDetachedModifier { title, content, .. } => {
+            visit_segments(title, visit);
+            content
+                .iter_mut()
+                .for_each(|node| visit_prose_mut(node, visit));
+        }
+        NorgASTFlat::RangedTag { name, .. } if name == &["comment"] => {}
+        NorgASTFlat::RangedTag { content, .. } => content
+            .iter_mut()
+            .for_each(|node| visit_prose_mut(node, visit)),
+        NorgASTFlat::CarryoverTag { next_object, .. } => visit_prose_mut(next_object, visit),
+        NorgASTFlat::VerbatimRangedTag { .. } | NorgASTFlat::InfirmTag { .. } => {}
+    }
+}
+
+fn is_whitespace(segment: &ParagraphSegment) -> bool {
+    matches!(
+        segment,
+        ParagraphSegment::Token(ParagraphSegmentToken::Whitespace)
+    )
+}
+
+/// The first character of a plain text segment.
+fn first_char(segment: Option<&ParagraphSegment>) -> Option<char> {
+    match segment? {
+        ParagraphSegment::Token(ParagraphSegmentToken::Special(char)) => Some(*char),
+        ParagraphSegment::Token(ParagraphSegmentToken::Text(text)) => text.chars().next(),
+        _ => None,
+    }
+}
+
+/// The last character of a plain text segment.
+fn last_char(segment: &ParagraphSegment) -> Option<char> {
+    match segment {
+        ParagraphSegment::Token(ParagraphSegmentToken::Special(char)) => Some(*char),
+        ParagraphSegment::Token(ParagraphSegmentToken::Text(text)) => text.chars().last(),
+        _ => None,
+    }
+}
+
+/// Removes the whitespace before punctuation and adds a space after it where a word follows it
+/// directly, e.g. `one ,two` becomes `one, two`. With the French locale, the space before `;`, `:`,
+/// `!` and `?` is kept.
+fn space_segments(segments: &mut Vec<ParagraphSegment>, locale: Locale) {
+    let mut i = 0;
+
+    while i < segments.len() {
+        if i > 0 && is_whitespace(&segments[i]) {
+            let end = i + segments[i..]
+                .iter()
+                .take_while(|segment| is_whitespace(segment))
+                .count();
+            let next = first_char(segments.get(end));
+            // A leading ellipsis (`and ...`) is not a full stop.
+            let is_ellipsis = next == Some('.') && first_char(segments.get(end + 1)) == Some('.');
+            let keeps_space = locale == Locale::French
+                && next.is_some_and(|char| FRENCH_SPACED_PUNCTUATION.contains(&char));
+
+            if next.is_some_and(|char| PUNCTUATION.contains(&char)) && !is_ellipsis && !keeps_space
+            {
+                segments.drain(i..end);
+                continue;
+            }
+        }
+
+        if last_char(&segments[i]).is_some_and(|char| SPACED_PUNCTUATION.contains(&char))
+            && first_char(segments.get(i + 1)).is_some_and(char::is_alphabetic)
+        {
+            segments.insert(
+                i + 1,
+                ParagraphSegment::Token(ParagraphSegmentToken::Whitespace),
+            );
+        }
+
+        i += 1;
+    }
+}
+
+/// Normalizes the spacing around punctuation in the prose of a document: removes whitespace before
+/// punctuation and adds a space after `,`, `;`, `!` and `?` where a word follows them directly.
+pub fn space_punctuation(ast: &mut [NorgASTFlat], locale: Locale) {
+    for node in ast {
+        visit_prose_mut(node, &mut |segments| space_segments(segments, locale));
+    }
+}
+
+/// Replaces runs of two or three hyphens with an en or em dash, leaving longer runs alone.
+fn substitute_dashes(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        if char != '-' {
+            output.push(char);
+            continue;
+        }
+
+        let mut hyphens = 1;
+
+        while chars.next_if_eq(&'-').is_some() {
+            hyphens += 1;
+        }
+
+        match hyphens {
+            2 => output.push('–'),
+            3 => output.push('—'),
+            _ => output.extend(std::iter::repeat_n('-', hyphens)),
+        }
+    }
+
+    output
+}
+
+/// Applies typographic substitutions to a run of text: dashes, ellipses and curly quotes. Quotes
+/// open after whitespace, opening brackets and dashes (or at the start of the paragraph, where
+/// `previous` is `None`) and close everywhere else, which turns apostrophes into `’` as well.
+fn substitute(text: &str, mut previous: Option<char>) -> String {
+    let text = substitute_dashes(text).replace("...", "…");
+    let mut output = String::with_capacity(text.len());
+
+    for char in text.chars() {
+        let opens = previous.is_none_or(|c| c.is_whitespace() || "([{–—".contains(c));
+
+        output.push(match (char, opens) {
+            ('"', true) => '“',
+            ('"', false) => '”',
+            ('\'', true) => '‘',
+            ('\'', false) => '’',
+            (char, _) => char,
+        });
+        previous = Some(char);
+    }
+
+    output
+}
+
+/// Applies `rewrite` to every run of plain text among the segments, given the character preceding
+/// the run (`None` at the start of the paragraph). Runs include the whitespace between words, but
+/// never start or end with it, and end at escape sequences, which are never rewritten. Changed runs
+/// are parsed again, so that they are made up of the same tokens as when the formatted document is
+/// parsed.
+fn rewrite_text(
+    segments: &mut Vec<ParagraphSegment>,
+    rewrite: impl Fn(&str, Option<char>) -> String,
+) {
+    let is_text = |segment: &ParagraphSegment| {
+        matches!(
+            segment,
+            ParagraphSegment::Token(
+                ParagraphSegmentToken::Text(_)
+                    | ParagraphSegmentToken::Special(_)
+                    | ParagraphSegmentToken::Whitespace
+            )
+        )
+    };
+    let is_word = |segment: &ParagraphSegment| {
+        matches!(
+            segment,
+            ParagraphSegment::Token(
+                ParagraphSegmentToken::Text(_) | ParagraphSegmentToken::Special(_)
+            )
+        )
+    };
+    let mut i = 0;
+
+    while i < segments.len() {
+        if !is_word(&segments[i]) {
+            i += 1;
+            continue;
+        }
+
+        let mut end = i + segments[i..]
+            .iter()
+            .take_while(|segment| is_text(segment))
+            .count();
+
+        while !is_word(&segments[end - 1]) {
+            end -= 1;
+        }
+
+        let text = segments[i..end]
+            .iter()
+            .cloned()
+            .map(String::from)
+            .collect::<String>();
+        let previous = match i.checked_sub(1).map(|previous| &segments[previous]) {
+            None => None,
+            Some(segment) if is_whitespace(segment) => Some(' '),
+            Some(ParagraphSegment::Token(ParagraphSegmentToken::Escape(char))) => Some(*char),
+            // The end of markup, a link or the like.
+            Some(_) => Some('x'),
+        };
+        let rewritten = rewrite(&text, previous);
+
+        let replacement = match parse(&rewritten).ok().as_deref() {
+            Some([NorgASTFlat::Paragraph(replacement)]) if rewritten != text => replacement.clone(),
+            _ => {
+                i = end;
+                continue;
+            }
+        };
+
+        let length = replacement.len();
+
+        segments.splice(i..end, replacement);
+        i += length;
+    }
+}
+
+/// Applies typographic substitutions to the prose of a document: `--` and `---` become en and em
+/// dashes, `...` an ellipsis, and straight quotes curly ones. Escaped characters are left alone.
+pub fn substitute_typography(ast: &mut [NorgASTFlat]) {
+    for node in ast {
+        visit_prose_mut(node, &mut |segments| rewrite_text(segments, substitute));
+    }
+}
+
+/// Glues `;`, `:`, `!` and `?` to the preceding word with a no-break space, as French typography
+/// requires: a narrow one before `;`, `!` and `?` and a regular one before `:`. Existing spaces
+/// before them are replaced, and colons within words (`12:30`, `https://`) are left alone.
+fn insert_french_spaces(text: &str, previous: Option<char>) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut output = String::with_capacity(text.len());
+
+    for (i, &char) in chars.iter().enumerate() {
+        let before = i.checked_sub(1).map(|i| chars[i]).or(previous);
+        let after = chars.get(i + 1).copied();
+        let space = match char {
+            ';' | '!' | '?' => '\u{202F}',
+            ':' if after.is_none_or(char::is_whitespace) => '\u{A0}',
+            _ => {
+                output.push(char);
+                continue;
+            }
+        };
+
+        match before {
+            Some(' ') if output.ends_with(' ') => {
+                output.pop();
+                output.push(space);
+            }
+            Some(before)
+                if !before.is_whitespace() && !FRENCH_SPACED_PUNCTUATION.contains(&before) =>
+            {
+                output.push(space)
+            }
+            _ => {}
+        }
+
+        output.push(char);
+    }
+
+    output
+}
+
+/// Inserts the no-break spaces the typography of the locale requires in the prose of a document.
+/// Only French has any, see [`FRENCH_SPACED_PUNCTUATION`].
+pub fn insert_non_breaking_spaces(ast: &mut [NorgASTFlat], locale: Locale) {
+    if locale != Locale::French {
+        return;
+    }
+
+    for node in ast {
+        visit_prose_mut(node, &mut |segments| {
+            rewrite_text(segments, insert_french_spaces)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn restyled(source: &str, restyle: impl Fn(&mut [NorgASTFlat])) -> Vec<NorgASTFlat> {
+        let mut ast = parse(source).unwrap();
+
+        restyle(&mut ast);
+        ast
+    }
+
+    #[test]
+    fn substitutes_dashes_ellipses_and_quotes() {
+        assert_eq!(
+            restyled(r#"wait -- or --- "not" ..."#, substitute_typography),
+            parse("wait – or — “not” …").unwrap()
+        );
+    }
+
+    #[test]
+    fn leaves_escape_sequences_alone() {
+        let source = r#"an \-- dash, \... and \"quotes\" ; kept \, as is"#;
+
+        assert_eq!(
+            restyled(source, substitute_typography),
+            parse(source).unwrap()
+        );
+        assert_eq!(
+            restyled(source, |ast| space_punctuation(ast, Locale::English)),
+            parse(r#"an \-- dash, \... and \"quotes\"; kept \, as is"#).unwrap()
+        );
+    }
+}
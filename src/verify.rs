@@ -0,0 +1,278 @@
+use std::fmt;
+
+use eyre::Result;
+use itertools::{EitherOrBoth, Itertools as _};
+use rust_norg::{parse, NorgASTFlat};
+use serde::Serialize;
+
+use crate::{
+    context::{FormatCtx, NodeKind},
+    converter::format_nodes,
+    tags::{embedded_ast, embeds_norg},
+    Config,
+};
+
+const EXCERPT_LENGTH: usize = 40;
+
+/// Renders the first line of a node as Norg source, truncated to [`EXCERPT_LENGTH`] characters.
+fn excerpt(node: &NorgASTFlat, config: &Config) -> String {
+    let source = format_nodes(vec![node.clone()], &FormatCtx::new(config))
+        .map(|output| output.join(""))
+        .unwrap_or_default();
+    let first_line = source.lines().next().unwrap_or_default();
+
+    if first_line.chars().count() > EXCERPT_LENGTH {
+        first_line
+            .chars()
+            .take(EXCERPT_LENGTH - 1)
+            .collect::<String>()
+            + "…"
+    } else {
+        first_line.to_string()
+    }
+}
+
+fn describe(node: Option<&NorgASTFlat>, config: &Config) -> String {
+    match node {
+        Some(node) => format!("{:<26} {:?}", NodeKind::of(node), excerpt(node, config)),
+        None => "(missing)".to_string(),
+    }
+}
+
+/// Whether two nodes are the same. Verbatim tags embedding Norg are compared by the AST of their
+/// content, as the embedded document is formatted as well.
+fn same_node(original: &NorgASTFlat, formatted: &NorgASTFlat, config: &Config) -> bool {
+    match (original, formatted) {
+        (
+            NorgASTFlat::VerbatimRangedTag {
+                name,
+                parameters,
+                content,
+            },
+            NorgASTFlat::VerbatimRangedTag {
+                name: formatted_name,
+                parameters: formatted_parameters,
+                content: formatted_content,
+            },
+        ) if name == formatted_name
+            && parameters == formatted_parameters
+            && embeds_norg(&name.join("."), parameters, config) =>
+        {
+            content == formatted_content
+                || embedded_ast(content)
+                    .is_some_and(|ast| embedded_ast(formatted_content) == Some(ast))
+        }
+        _ => original == formatted,
+    }
+}
+
+/// A node that differs between the original document and the formatted output.
+#[derive(Debug, Serialize)]
+pub struct Mismatch {
+    /// Where the node is in the document: the index and kind of the top-level node, followed by
+    /// those of the nested nodes down to the innermost one that differs, e.g.
+    /// `3:NestableDetachedModifier/0:Paragraph`.
+    pub path: String,
+    /// The first line of the original node, if there is one.
+    pub original: Option<String>,
+    /// The first line of the formatted node, if there is one.
+    pub formatted: Option<String>,
+}
+
+/// Why the formatted output of a document failed verification.
+#[derive(Debug)]
+pub enum VerifyError {
+    /// The formatted output could not be parsed at all.
+    Unparsable(String),
+    /// Nodes changed during formatting.
+    Changed {
+        mismatches: Vec<Mismatch>,
+        /// A side-by-side listing of the top-level nodes that changed, one per line, under a
+        /// header.
+        table: String,
+    },
+}
+
+impl VerifyError {
+    /// The listing of the nodes that changed, if any did, to be shown along with the error.
+    pub fn table(&self) -> Option<&str> {
+        match self {
+            Self::Unparsable(_) => None,
+            Self::Changed { table, .. } => Some(table),
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unparsable(err) => write!(f, "the formatted output could not be parsed: {err}"),
+            Self::Changed { mismatches, .. } => write!(
+                f,
+                "verification failed: {} top-level node(s) changed during formatting",
+                mismatches.len()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// The nodes nested within a node, if it has any.
+fn children(node: &NorgASTFlat) -> Option<Vec<&NorgASTFlat>> {
+    match node {
+        NorgASTFlat::NestableDetachedModifier { content, .. } => Some(vec![content]),
+        NorgASTFlat::CarryoverTag { next_object, .. } => Some(vec![next_object]),
+        NorgASTFlat::RangeableDetachedModifier { content, .. }
+        | NorgASTFlat::RangedTag { content, .. } => Some(content.iter().collect()),
+        _ => None,
+    }
+}
+
+/// Follows two differing nodes down to the innermost nested nodes that differ, building the path
+/// to them.
+fn locate(
+    index: usize,
+    original: Option<&NorgASTFlat>,
+    formatted: Option<&NorgASTFlat>,
+    config: &Config,
+) -> Mismatch {
+    let kind = original.or(formatted).map(NodeKind::of).expect("a node");
+    let path = format!("{index}:{kind}");
+
+    let nested = original
+        .zip(formatted)
+        .filter(|(original, formatted)| NodeKind::of(original) == NodeKind::of(formatted))
+        .and_then(|(original, formatted)| children(original).zip(children(formatted)))
+        .and_then(|(original, formatted)| {
+            original
+                .into_iter()
+                .zip_longest(formatted)
+                .enumerate()
+                .find_map(|(index, pair)| match pair {
+                    EitherOrBoth::Both(original, formatted)
+                        if same_node(original, formatted, config) =>
+                    {
+                        None
+                    }
+                    pair => {
+                        let (original, formatted) = pair.left_and_right();
+
+                        Some(locate(index, original, formatted, config))
+                    }
+                })
+        });
+
+    match nested {
+        Some(nested) => Mismatch {
+            path: format!("{path}/{}", nested.path),
+            ..nested
+        },
+        None => Mismatch {
+            path,
+            original: original.map(|node| excerpt(node, config)),
+            formatted: formatted.map(|node| excerpt(node, config)),
+        },
+    }
+}
+
+/// Builds a side-by-side listing of every top-level node that differs between the two ASTs, along
+/// with where they differ.
+fn diff(
+    original: &[NorgASTFlat],
+    formatted: &[NorgASTFlat],
+    config: &Config,
+) -> Vec<(String, Mismatch)> {
+    original
+        .iter()
+        .zip_longest(formatted)
+        .enumerate()
+        .filter_map(|(index, pair)| {
+            let (original, formatted) = match pair {
+                EitherOrBoth::Both(original, formatted)
+                    if same_node(original, formatted, config) =>
+                {
+                    return None
+                }
+                EitherOrBoth::Both(original, formatted) => (Some(original), Some(formatted)),
+                EitherOrBoth::Left(original) => (Some(original), None),
+                EitherOrBoth::Right(formatted) => (None, Some(formatted)),
+            };
+
+            let listing = format!(
+                "{index:>4}  {:<70} | {}",
+                describe(original, config),
+                describe(formatted, config)
+            );
+
+            Some((listing, locate(index, original, formatted, config)))
+        })
+        .collect()
+}
+
+/// Ensures that the formatted output parses to the same AST as the original document. Fails with a
+/// [`VerifyError`] holding a structural diff of the two if it does not.
+pub fn verify(original: &[NorgASTFlat], formatted: &str, config: &Config) -> Result<()> {
+    let formatted = parse(formatted).map_err(|err| VerifyError::Unparsable(format!("{err:?}")))?;
+
+    let differences = diff(original, &formatted, config);
+
+    if differences.is_empty() {
+        return Ok(());
+    }
+
+    let mut table = format!("{:>4}  {:<70} | formatted\n", "node", "original");
+    let mut mismatches = Vec::new();
+
+    for (listing, mismatch) in differences {
+        table += &listing;
+        table.push('\n');
+        mismatches.push(mismatch);
+    }
+
+    Err(VerifyError::Changed { mismatches, table }.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mismatches(original: &str, formatted: &str) -> Vec<Mismatch> {
+        let err = verify(&parse(original).unwrap(), formatted, &Config::default()).unwrap_err();
+
+        match err.downcast::<VerifyError>().unwrap() {
+            VerifyError::Changed { mismatches, .. } => mismatches,
+            err => panic!("unexpected error: {err}"),
+        }
+    }
+
+    #[test]
+    fn accepts_output_with_the_same_ast() {
+        let config = Config::default();
+
+        assert!(verify(&parse("*  Heading\n").unwrap(), "* Heading\n", &config).is_ok());
+    }
+
+    #[test]
+    fn locates_the_innermost_node_that_changed() {
+        let mismatches = mismatches(
+            "* Heading\n- one\n-- two\n",
+            "* Heading\n- one\n-- changed\n",
+        );
+
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].path, "2:NestableDetachedModifier/0:Paragraph");
+        assert_eq!(mismatches[0].original.as_deref(), Some("two"));
+        assert_eq!(mismatches[0].formatted.as_deref(), Some("changed"));
+    }
+
+    #[test]
+    fn reports_nodes_missing_from_the_output() {
+        let mismatches = mismatches("* Heading\nText.\n", "* Heading\n");
+
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].path, "1:Paragraph");
+        assert_eq!(mismatches[0].original.as_deref(), Some("Text."));
+        assert_eq!(mismatches[0].formatted, None);
+    }
+}
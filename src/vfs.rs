@@ -0,0 +1,128 @@
+//! Where the content of documents comes from.
+//!
+//! Editors hold unsaved changes in buffers, so anything looking at documents on behalf of an editor
+//! (e.g. a language server) should read them through a [`SourceProvider`] that prefers those
+//! buffers over the files on disk.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Provides the content of documents by path.
+pub trait SourceProvider {
+    /// Reads the raw content of a document.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Whether a document exists, e.g. to check whether a link to it resolves.
+    fn exists(&self, path: &Path) -> bool;
+}
+
+/// Reads documents straight from the file system.
+#[derive(Clone, Copy, Default)]
+pub struct Disk;
+
+impl SourceProvider for Disk {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+}
+
+/// Serves documents from in-memory buffers where there are any, and from another provider
+/// otherwise.
+///
+/// Buffers are looked up by the exact path they were opened with, so callers should normalize paths
+/// (e.g. make them absolute) consistently.
+#[derive(Clone, Default)]
+pub struct Overlay<P> {
+    base: P,
+    buffers: BTreeMap<PathBuf, String>,
+}
+
+impl<P: SourceProvider> Overlay<P> {
+    pub fn new(base: P) -> Self {
+        Self {
+            base,
+            buffers: BTreeMap::new(),
+        }
+    }
+
+    /// Sets the content of a buffer, replacing the previous one, e.g. whenever an editor changes
+    /// it.
+    pub fn open(&mut self, path: impl Into<PathBuf>, content: impl Into<String>) {
+        self.buffers.insert(path.into(), content.into());
+    }
+
+    /// Drops a buffer, e.g. when an editor closes it, so that the document is read from the base
+    /// provider again.
+    pub fn close(&mut self, path: &Path) {
+        self.buffers.remove(path);
+    }
+
+    /// The content of a buffer, if the document is open.
+    pub fn buffer(&self, path: &Path) -> Option<&str> {
+        self.buffers.get(path).map(String::as_str)
+    }
+}
+
+impl<P: SourceProvider> SourceProvider for Overlay<P> {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.buffer(path) {
+            Some(content) => Ok(content.as_bytes().to_vec()),
+            None => self.base.read(path),
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.buffers.contains_key(path) || self.base.exists(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A file system holding a single document.
+    struct Single;
+
+    impl SourceProvider for Single {
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            match path == Path::new("saved.norg") {
+                true => Ok(b"* Saved\n".to_vec()),
+                false => Err(io::ErrorKind::NotFound.into()),
+            }
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            path == Path::new("saved.norg")
+        }
+    }
+
+    #[test]
+    fn prefers_open_buffers_over_the_base_provider() {
+        let mut overlay = Overlay::new(Single);
+
+        overlay.open("saved.norg", "* Unsaved\n");
+        overlay.open("new.norg", "* New\n");
+
+        assert_eq!(
+            overlay.read(Path::new("saved.norg")).unwrap(),
+            b"* Unsaved\n"
+        );
+        assert_eq!(overlay.buffer(Path::new("new.norg")), Some("* New\n"));
+        assert!(overlay.exists(Path::new("new.norg")));
+        assert!(!overlay.exists(Path::new("other.norg")));
+
+        overlay.close(Path::new("saved.norg"));
+        overlay.close(Path::new("new.norg"));
+
+        assert_eq!(overlay.read(Path::new("saved.norg")).unwrap(), b"* Saved\n");
+        assert!(overlay.read(Path::new("new.norg")).is_err());
+        assert!(!overlay.exists(Path::new("new.norg")));
+    }
+}
@@ -0,0 +1,265 @@
+//! Normalizing the form of links to other documents, e.g. `{:note:}`.
+//!
+//! Documents imported from wiki-style tools often link to other documents as `{:note}`, without
+//! the colon that ends the path, or with spacing variants such as `{: note :}`. The parser doesn't
+//! take these for links, so they are rewritten in the source before it is parsed: every link to a
+//! document gets the canonical full form `{:note:}`, targets within the document
+//! (`{:note:* Title}`) included. With [`WikilinkStyle::Collapse`] links to a whole document are
+//! written back in the shorthand `{:note}` once formatted.
+//!
+//! Verbatim ranged tags (`@code` up to `@end`) and inline verbatim are left untouched. Like for the
+//! parser, a line such as `@alice see {:note}` only opens a tag if an `@end` follows it.
+
+use std::borrow::Cow;
+
+use crate::WikilinkStyle;
+
+/// The length of a link to a document starting at `text`, which starts with `{`, along with its
+/// path and whatever follows the path up to and including the closing `}` (e.g. `* Title}`).
+fn file_link(text: &str) -> Option<(usize, &str, &str)> {
+    let inner = text.strip_prefix('{')?.trim_start_matches([' ', '\t']);
+    let inner = inner.strip_prefix(':')?;
+    let end = inner.find(['}', '\n'])?;
+
+    if !inner[end..].starts_with('}') {
+        return None;
+    }
+
+    let (path, rest) = match inner[..end].find(':') {
+        Some(colon) => (&inner[..colon], inner[colon + 1..=end].trim_start()),
+        None => (&inner[..end], "}"),
+    };
+    let path = path.trim_matches([' ', '\t']);
+
+    if path.is_empty() || path.contains(['{', '[']) {
+        return None;
+    }
+
+    Some((text.len() - inner.len() + end + 1, path, rest))
+}
+
+/// Whether a trimmed line has the form of the first line of a verbatim ranged tag, e.g.
+/// `@code rust`.
+fn is_verbatim_tag(line: &str) -> bool {
+    let Some(rest) = line.strip_prefix('@') else {
+        return false;
+    };
+    let name = rest.split_whitespace().next().unwrap_or_default();
+
+    !name.is_empty()
+        && name != "end"
+        && rest.starts_with(name)
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+/// Calls `rewrite` with every link to a document outside of verbatim text, replacing the link with
+/// what it returns, if anything.
+fn rewrite_links<'a>(
+    source: &'a str,
+    mut rewrite: impl FnMut(&str, &str) -> Option<String>,
+) -> Cow<'a, str> {
+    let mut output = String::new();
+    let mut copied = 0;
+    let mut in_verbatim_tag = false;
+    let mut offset = 0;
+    // A tag without an `@end` after it is prose, so it matters whether there is one at all.
+    let last_end = source
+        .split_inclusive('\n')
+        .scan(0, |offset, line| {
+            let start = *offset;
+
+            *offset += line.len();
+
+            Some((start, line))
+        })
+        .filter(|(_, line)| line.trim() == "@end")
+        .map(|(start, _)| start)
+        .last();
+
+    for line in source.split_inclusive('\n') {
+        let start = offset;
+
+        offset += line.len();
+
+        let trimmed = line.trim();
+
+        if in_verbatim_tag {
+            in_verbatim_tag = trimmed != "@end";
+            continue;
+        }
+
+        if is_verbatim_tag(trimmed) && last_end.is_some_and(|end| end > start) {
+            in_verbatim_tag = true;
+            continue;
+        }
+
+        let mut in_inline_verbatim = false;
+        let mut escaped = false;
+
+        for (index, c) in line.char_indices() {
+            if escaped {
+                escaped = false;
+                continue;
+            }
+
+            match c {
+                '\\' => escaped = true,
+                '`' => in_inline_verbatim = !in_inline_verbatim,
+                '{' if !in_inline_verbatim && start + index >= copied => {
+                    let at = start + index;
+                    let Some((length, path, rest)) = file_link(&source[at..]) else {
+                        continue;
+                    };
+                    let Some(replacement) = rewrite(path, rest) else {
+                        continue;
+                    };
+
+                    if source[at..at + length] != replacement {
+                        output += &source[copied..at];
+                        output += &replacement;
+                        copied = at + length;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if copied == 0 {
+        return Cow::Borrowed(source);
+    }
+
+    output += &source[copied..];
+
+    Cow::Owned(output)
+}
+
+/// Rewrites every link to a document in `source` in its full form, e.g. `{:note}` and `{: note :}`
+/// as `{:note:}`, unless links are to be kept as they were written.
+pub fn expand(source: &str, style: WikilinkStyle) -> Cow<'_, str> {
+    if style == WikilinkStyle::Preserve {
+        return Cow::Borrowed(source);
+    }
+
+    rewrite_links(source, |path, rest| Some(format!("{{:{path}:{rest}")))
+}
+
+/// Rewrites the links to a whole document in formatted output in their shorthand form, e.g.
+/// `{:note:}` as `{:note}`, if links are to be collapsed.
+pub fn collapse(output: String, style: WikilinkStyle) -> String {
+    if style != WikilinkStyle::Collapse {
+        return output;
+    }
+
+    match rewrite_links(&output, |path, rest| {
+        (rest == "}").then(|| format!("{{:{path}}}"))
+    }) {
+        Cow::Borrowed(_) => output,
+        Cow::Owned(collapsed) => collapsed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_links_to_documents() {
+        assert_eq!(
+            expand(
+                "See {:note}, {: other :} and {:doc:* Title}.",
+                WikilinkStyle::Expand
+            ),
+            "See {:note:}, {:other:} and {:doc:* Title}."
+        );
+        assert!(matches!(
+            expand("See {:note}.", WikilinkStyle::Preserve),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn leaves_verbatim_escapes_and_other_braces_alone() {
+        let source = "`{:note}` \\{:note} {:} {* Title} {:a\nb}\n@code\n{:note}\n@end\n{:note}\n";
+
+        assert_eq!(
+            expand(source, WikilinkStyle::Expand),
+            source.replace("@end\n{:note}", "@end\n{:note:}")
+        );
+    }
+
+    #[test]
+    fn collapses_links_to_whole_documents() {
+        let output = "{:note:} {:note:* Title}\n".to_string();
+
+        assert_eq!(
+            collapse(output.clone(), WikilinkStyle::Collapse),
+            "{:note} {:note:* Title}\n"
+        );
+        assert_eq!(collapse(output.clone(), WikilinkStyle::Expand), output);
+    }
+
+    #[test]
+    fn only_takes_closed_tags_for_verbatim() {
+        let source = "@alice see {:note}\nand {:other}\n@ {:third}\n";
+
+        assert_eq!(
+            expand(source, WikilinkStyle::Expand),
+            "@alice see {:note:}\nand {:other:}\n@ {:third:}\n"
+        );
+
+        let source = "@alice see {:note}\nand {:other}\n@end\n{:third}\n";
+
+        assert_eq!(
+            expand(source, WikilinkStyle::Expand),
+            source.replace("{:third}", "{:third:}")
+        );
+    }
+
+    #[test]
+    fn leaves_metadata_and_nested_verbatim_alone() {
+        let source = "@document.meta\ntitle: {:a}\n@end\n|example\n{:b}\n@code norg\n{:c}\n@end\n\
+                      {:d}\n|end\n";
+
+        assert_eq!(
+            expand(source, WikilinkStyle::Expand),
+            source.replace("{:b}", "{:b:}").replace("{:d}", "{:d:}")
+        );
+    }
+
+    #[test]
+    fn stops_escapes_at_the_end_of_the_line() {
+        let source = "ends with \\{\n:note}\nand with \\\n{:note}\n\\{:note}\n";
+
+        assert_eq!(
+            expand(source, WikilinkStyle::Expand),
+            "ends with \\{\n:note}\nand with \\\n{:note:}\n\\{:note}\n"
+        );
+    }
+
+    #[test]
+    fn collapsing_expanded_links_restores_them() {
+        let source = "See {:note} and {:other:* Title}.\n@code\n{:note:}\n@end\n";
+        let expanded = expand(source, WikilinkStyle::Collapse).into_owned();
+
+        assert_ne!(expanded, source);
+        assert_eq!(collapse(expanded, WikilinkStyle::Collapse), source);
+    }
+
+    #[test]
+    fn formatting_collapsed_links_again_changes_nothing() {
+        let formatter = crate::formatter::Formatter::new(crate::Config {
+            wikilink_style: WikilinkStyle::Collapse,
+            ..crate::Config::default()
+        });
+        let formatted = formatter
+            .format("* Notes\nSee {: note :}, {:other:}\nand {:other:* Title}.\n")
+            .unwrap()
+            .output();
+
+        assert!(formatted.contains("{:note}"), "{formatted}");
+        assert_eq!(formatter.format(&formatted).unwrap().output(), formatted);
+    }
+}
@@ -0,0 +1,378 @@
+use std::{
+    collections::BTreeSet,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use eyre::{bail, eyre, Result, WrapErr};
+use regex::Regex;
+use walkdir::WalkDir;
+
+/// A shell-style glob matched against paths. `*` and `?` match within a single path component, `**`
+/// matches across components and `[...]` matches a class of characters.
+#[derive(Clone)]
+pub struct Glob(Regex);
+
+impl FromStr for Glob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pattern = String::from("^");
+        let mut chars = s.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '*' if chars.peek() == Some(&'*') => {
+                    chars.next();
+
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        pattern.push_str("(?:.*/)?");
+                    } else {
+                        pattern.push_str(".*");
+                    }
+                }
+                '*' => pattern.push_str("[^/]*"),
+                '?' => pattern.push_str("[^/]"),
+                '[' => {
+                    pattern.push('[');
+
+                    for c in chars.by_ref() {
+                        match c {
+                            ']' => break,
+                            '!' if pattern.ends_with('[') => pattern.push('^'),
+                            '\\' | '^' => pattern.push_str(&regex::escape(&c.to_string())),
+                            c => pattern.push(c),
+                        }
+                    }
+
+                    pattern.push(']');
+                }
+                c => pattern.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+
+        pattern.push('$');
+
+        Regex::new(&pattern)
+            .map(Self)
+            .map_err(|_| format!("`{s}` is not a valid glob"))
+    }
+}
+
+impl Glob {
+    fn matches(&self, path: &str) -> bool {
+        self.0.is_match(path)
+    }
+
+    /// Whether the glob matches a path, written the way it was given on the command line.
+    pub fn matches_path(&self, path: &Path) -> bool {
+        self.matches(&glob_path(path))
+    }
+}
+
+/// Determines how directories are searched for files to format.
+#[derive(Default)]
+pub struct WalkOptions {
+    /// Whether symbolic links inside of directories are followed.
+    pub follow_symlinks: bool,
+    /// If not empty, only files matching one of these globs are formatted.
+    pub include: Vec<Glob>,
+    /// Files and directories matching one of these globs are never formatted or searched.
+    pub exclude: Vec<Glob>,
+    /// How many levels of directories are searched, where 1 only searches the given directories
+    /// themselves. Unlimited if not set.
+    pub max_depth: Option<usize>,
+}
+
+/// Renders a path the way globs are matched against it: with forward slashes and without leading
+/// `./` components.
+fn glob_path(path: &Path) -> String {
+    path.components()
+        .skip_while(|component| matches!(component, Component::CurDir))
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+        .replace("//", "/")
+}
+
+impl WalkOptions {
+    fn is_excluded(&self, path: &str) -> bool {
+        self.exclude.iter().any(|glob| glob.matches(path))
+    }
+
+    fn is_selected(&self, file: &Path) -> bool {
+        let path = glob_path(file);
+
+        (self.include.is_empty() || self.include.iter().any(|glob| glob.matches(&path)))
+            && !self.is_excluded(&path)
+    }
+
+    /// Whether the content of a directory is excluded as a whole, e.g. by `**/archive/**`.
+    fn is_excluded_dir(&self, directory: &Path) -> bool {
+        let path = glob_path(directory);
+
+        self.is_excluded(&path) || self.is_excluded(&format!("{path}/"))
+    }
+}
+
+fn is_norg_file(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension == "norg")
+}
+
+/// Reads a list of paths from a file, or from stdin if `source` is `-`, with one path per line or,
+/// if `null_separated`, separated by NUL bytes. Empty entries are skipped.
+pub fn read_file_list(source: &Path, null_separated: bool) -> Result<Vec<PathBuf>> {
+    let content = if source == Path::new("-") {
+        let mut content = Vec::new();
+        std::io::stdin().lock().read_to_end(&mut content)?;
+        content
+    } else {
+        std::fs::read(source).wrap_err_with(|| format!("unable to read {}", source.display()))?
+    };
+
+    split_file_list(&content, null_separated)
+}
+
+fn split_file_list(content: &[u8], null_separated: bool) -> Result<Vec<PathBuf>> {
+    let separator = if null_separated { b'\0' } else { b'\n' };
+
+    content
+        .split(|&byte| byte == separator)
+        .map(|entry| {
+            if null_separated {
+                entry
+            } else {
+                entry.strip_suffix(b"\r").unwrap_or(entry)
+            }
+        })
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            String::from_utf8(entry.to_vec())
+                .map(PathBuf::from)
+                .map_err(|_| eyre!("the list of files holds a path that isn't valid UTF-8"))
+        })
+        .collect()
+}
+
+/// Lists the paths of the `.norg` files git reports as changed in the repository in the working
+/// directory, relative to it: those that are added, copied, modified or renamed by staged or
+/// unstaged changes, and untracked ones. With `staged`, only those changed by staged changes.
+pub fn changed_files(staged: bool) -> Result<Vec<PathBuf>> {
+    let git = |args: &[&str]| -> Result<Vec<PathBuf>> {
+        let output = Command::new("git")
+            .args(args)
+            .output()
+            .wrap_err("unable to run git")?;
+
+        if !output.status.success() {
+            bail!(
+                "unable to list the changed files: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        split_file_list(&output.stdout, true)
+    };
+    let diff = |options: &[&str]| {
+        let filter = ["--name-only", "-z", "--relative", "--diff-filter=ACMR"];
+
+        git(&[&["diff"][..], &filter[..], options, &["--", "*.norg"][..]].concat())
+    };
+    let mut files = diff(&["--cached"])?;
+
+    if !staged {
+        files.extend(diff(&[])?);
+        files.extend(git(&[
+            "ls-files",
+            "-z",
+            "--others",
+            "--exclude-standard",
+            "--",
+            "*.norg",
+        ])?);
+    }
+
+    Ok(files
+        .into_iter()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect())
+}
+
+/// Expands the given paths into the list of files to format. Files are taken as-is, whereas
+/// directories are searched recursively for `.norg` files in a stable order.
+///
+/// Both the files found in directories and the ones given explicitly are filtered by the globs of
+/// the options. Every file is only listed once, even if it can be reached through multiple paths
+/// (e.g. through symbolic links), so that it is never formatted twice in one run.
+pub fn collect_files(paths: &[PathBuf], options: &WalkOptions) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut seen = BTreeSet::new();
+    let mut push = |file: PathBuf| {
+        let real = file.canonicalize().unwrap_or_else(|_| file.clone());
+
+        if seen.insert(real) {
+            files.push(file);
+        }
+    };
+
+    for path in paths {
+        if !path.is_dir() {
+            if options.is_selected(path) {
+                push(path.clone());
+            }
+
+            continue;
+        }
+
+        for entry in WalkDir::new(path)
+            .follow_links(options.follow_symlinks)
+            .max_depth(options.max_depth.unwrap_or(usize::MAX))
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| {
+                !entry.file_type().is_dir() || !options.is_excluded_dir(entry.path())
+            })
+        {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(err) if err.loop_ancestor().is_some() => {
+                    eprintln!("warning: {err}, not following it");
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            if entry.file_type().is_file()
+                && is_norg_file(entry.path())
+                && options.is_selected(entry.path())
+            {
+                push(entry.into_path());
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glob(glob: &str) -> Glob {
+        glob.parse().unwrap()
+    }
+
+    fn globs(globs: &[&str]) -> Vec<Glob> {
+        globs.iter().map(|glob| glob.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn matches_within_and_across_components() {
+        assert!(glob("*.norg").matches("index.norg"));
+        assert!(!glob("*.norg").matches("notes/index.norg"));
+        assert!(glob("notes/*").matches("notes/index.norg"));
+        assert!(!glob("notes/*").matches("notes/2024/index.norg"));
+
+        assert!(glob("**/archive/**").matches("archive/a.norg"));
+        assert!(glob("**/archive/**").matches("notes/archive/2024/a.norg"));
+        assert!(!glob("**/archive/**").matches("notes/archived/a.norg"));
+        assert!(glob("**/journal/**.norg").matches("journal/2024/05/01.norg"));
+        assert!(glob("notes**").matches("notes/a/b.norg"));
+
+        assert!(glob("a?.norg").matches("ab.norg"));
+        assert!(!glob("a?.norg").matches("a.norg"));
+        assert!(!glob("a?.norg").matches("a/.norg"));
+    }
+
+    #[test]
+    fn matches_classes_of_characters() {
+        assert!(glob("[ab].norg").matches("b.norg"));
+        assert!(!glob("[ab].norg").matches("c.norg"));
+        assert!(glob("[a-c].norg").matches("c.norg"));
+        assert!(glob("[!ab].norg").matches("c.norg"));
+        assert!(!glob("[!ab].norg").matches("a.norg"));
+        assert!(glob("[^a].norg").matches("^.norg"));
+        assert!(!glob("[^a].norg").matches("b.norg"));
+        assert!("[]".parse::<Glob>().is_err());
+    }
+
+    #[test]
+    fn matches_whole_paths_and_escapes_everything_else() {
+        assert!(!glob("*.norg").matches("index.norg.bak"));
+        assert!(!glob("notes/*.norg").matches("old/notes/index.norg"));
+        assert!(glob("a+(b)|c.norg").matches("a+(b)|c.norg"));
+        assert!(!glob("a.norg").matches("abnorg"));
+        assert!(glob("$HOME/{a}.norg").matches("$HOME/{a}.norg"));
+    }
+
+    #[test]
+    fn matches_paths_the_way_they_were_given() {
+        assert_eq!(glob_path(Path::new("./notes/a.norg")), "notes/a.norg");
+        assert_eq!(glob_path(Path::new("notes//a.norg")), "notes/a.norg");
+        assert_eq!(glob_path(Path::new("/notes/a.norg")), "/notes/a.norg");
+        assert!(glob("notes/*.norg").matches_path(Path::new("./notes/a.norg")));
+    }
+
+    #[test]
+    fn selects_included_files_unless_excluded() {
+        let options = WalkOptions {
+            include: globs(&["**/journal/**.norg"]),
+            exclude: globs(&["**/archive/**"]),
+            ..WalkOptions::default()
+        };
+
+        assert!(options.is_selected(Path::new("./journal/2024/a.norg")));
+        assert!(!options.is_selected(Path::new("journal/archive/a.norg")));
+        assert!(!options.is_selected(Path::new("notes/a.norg")));
+        assert!(WalkOptions::default().is_selected(Path::new("notes/a.norg")));
+    }
+
+    #[test]
+    fn prunes_excluded_directories() {
+        let options = WalkOptions {
+            exclude: globs(&["**/archive/**", "drafts"]),
+            ..WalkOptions::default()
+        };
+
+        assert!(options.is_excluded_dir(Path::new("notes/archive")));
+        assert!(options.is_excluded_dir(Path::new("./drafts")));
+        assert!(!options.is_excluded_dir(Path::new("notes")));
+        assert!(!options.is_excluded_dir(Path::new("notes/drafts")));
+    }
+
+    #[test]
+    fn collects_norg_files_outside_of_excluded_directories() -> Result<()> {
+        let root = std::env::temp_dir().join(format!("norg-fmt-walk-{}", std::process::id()));
+
+        for dir in ["notes/archive", "notes/drafts"] {
+            std::fs::create_dir_all(root.join(dir))?;
+        }
+
+        for file in [
+            "index.norg",
+            "todo.txt",
+            "notes/b.norg",
+            "notes/archive/c.norg",
+            "notes/drafts/d.norg",
+        ] {
+            std::fs::write(root.join(file), "")?;
+        }
+
+        // Only directories match `**/drafts`, so `d.norg` is only left out by pruning them.
+        let options = WalkOptions {
+            exclude: globs(&["**/archive/**", "**/drafts"]),
+            ..WalkOptions::default()
+        };
+        let files = collect_files(&[root.clone()], &options);
+        std::fs::remove_dir_all(&root)?;
+
+        assert_eq!(files?, [root.join("index.norg"), root.join("notes/b.norg")]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,20 @@
+use norg_fmt::{
+    testing::{assert_format_preserves_ast, assert_format_preserves_ast_with, document},
+    Config,
+};
+use proptest::prelude::*;
+
+proptest! {
+    #[test]
+    fn formatting_preserves_ast(input in document()) {
+        assert_format_preserves_ast(&input);
+    }
+
+    #[test]
+    fn formatting_preserves_ast_at_any_line_length(
+        input in document(),
+        line_length in 10..120usize
+    ) {
+        assert_format_preserves_ast_with(&input, &Config { line_length, ..Config::default() });
+    }
+}
@@ -0,0 +1,79 @@
+//! Snapshot tests over the documents in `tests/corpus/`.
+//!
+//! Every `<name>.norg` file is formatted with a [`Formatter`] using the default configuration, the
+//! way the command line formats files, and compared against `<name>.expected.norg`. Run with
+//! `NORG_FMT_BLESS=1` to (re)generate the expected files from the current output instead, then
+//! review the changes before committing them.
+//!
+//! The output is verified to parse to the same AST as the input, as with `--verify`. The debugging
+//! aids (`debug-ast`, `--explain` and `--trace-transforms`) leave the output alone and can't be set
+//! through a `+norgfmt` attribute, so they are covered by the unit tests of their modules instead.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use norg_fmt::{formatter::Formatter, Config};
+use similar::TextDiff;
+
+const EXPECTED_SUFFIX: &str = ".expected.norg";
+
+fn inputs(corpus: &Path) -> Vec<PathBuf> {
+    let mut inputs: Vec<PathBuf> = fs::read_dir(corpus)
+        .expect("the corpus directory should exist")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            let name = path.file_name().unwrap().to_string_lossy();
+
+            name.ends_with(".norg") && !name.ends_with(EXPECTED_SUFFIX)
+        })
+        .collect();
+
+    inputs.sort();
+    inputs
+}
+
+#[test]
+fn corpus() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let bless = std::env::var_os("NORG_FMT_BLESS").is_some();
+    let formatter = Formatter::new(Config::default()).with_verification(true);
+    let mut failures = Vec::new();
+
+    for input in inputs(&corpus) {
+        let name = input.file_stem().unwrap().to_string_lossy().into_owned();
+        let expected_path = corpus.join(format!("{name}{EXPECTED_SUFFIX}"));
+        let source = fs::read_to_string(&input).unwrap();
+        let formatted = formatter
+            .format(&source)
+            .unwrap_or_else(|err| panic!("{name}: unable to format: {err:?}"))
+            .output();
+
+        if bless {
+            fs::write(&expected_path, &formatted).unwrap();
+            continue;
+        }
+
+        let Ok(expected) = fs::read_to_string(&expected_path) else {
+            failures.push(format!("{name}: missing {}", expected_path.display()));
+            continue;
+        };
+
+        if formatted != expected {
+            let diff = TextDiff::from_lines(&expected, &formatted)
+                .unified_diff()
+                .header("expected", "formatted")
+                .to_string();
+
+            failures.push(format!("{name}:\n{diff}"));
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} corpus case(s) failed, rerun with NORG_FMT_BLESS=1 to accept the new output:\n\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}